@@ -8,6 +8,10 @@
 ## The favicon to be loaded. This file should be a PNG.
 #favicon: DATADIR/longboard/favicon.png
 
+## The URL the site is served at. Link previews for threads only include the
+## thread's URL and image if this is set.
+#base_url: https://example.com
+
 ## The address that the server binds to.
 #address: 0.0.0.0
 
@@ -38,6 +42,12 @@
 ## Whether or not to allow users to upload files.
 #allow_uploads: true
 
+## Mark every uploaded file as a spoiler.
+#force_spoiler: false
+
+## Whether users may upload files that aren't marked as a spoiler.
+#spoiler_optional: true
+
 ## The allowed file types for file uploads.
 #allow_file_types:
 #   - image/png
@@ -127,3 +137,259 @@
 
 ## The maximum number of threads per board.
 # thread_limit: 36
+
+## The maximum number of replies before a thread is locked.
+# reply_cap: 500
+
+## Warn users before creating a thread with a similar subject to an existing
+## thread.
+# dedup_threads: false
+
+## Allow users to create cyclical threads, which delete their oldest replies
+## instead of being locked when they reach the reply cap.
+# allow_cyclical: false
+
+## The Content-Security-Policy header sent with each page.
+# content_security_policy: "default-src 'self'; script-src 'self' cdnjs.cloudflare.com; style-src 'self' 'unsafe-inline' cdnjs.cloudflare.com; img-src 'self' data:; media-src 'self'; object-src 'none'; base-uri 'none'; form-action 'self'; frame-ancestors 'none'"
+
+## Require the first post of a new thread to have a file attached.
+# op_requires_file: true
+
+## The hours during which users can post. Reading is always allowed.
+# posting_hours:
+#   utc_offset: "+00:00"
+#   open:
+#     - "08:00-12:00"
+#     - "18:00-23:00"
+
+## Have the front-end web server (nginx or apache) serve files.
+# accel_redirect:
+#   header: nginx
+#   upload_location: /internal/upload/
+#   resource_location: /internal/resource/
+
+## How long browsers should cache uploaded files.
+# upload_cache_age: 365d
+
+## How long browsers should cache styles, scripts, and other site resources.
+# resource_cache_age: 1h
+
+## Where to keep the files of posts deleted by staff members.
+# quarantine_dir: /var/lib/longboard/quarantine
+
+## How many leading bits of an IPv6 address identify a user.
+# ipv6_prefix: 64
+
+## Also rate limit posts that are similar to a recent post (from 0 to 1).
+# content_similarity: 0.8
+
+## How staff roles are shown on posts.
+# role_flair:
+#   administrator:
+#     text: "## Admin"
+#     class: role-administrator
+
+## How many files to show at a time in the gallery.
+# gallery_page_size: 48
+
+## The most boards that can be created.
+# max_boards: 20
+
+## Where to store uploads while they are being received.
+# upload_temp_dir: /var/tmp/longboard
+
+## How long to keep reports after they're closed.
+# report_retention: 90 days
+
+## How long to keep entries in the staff log.
+# log_retention: 1 year
+
+## The least time between loading a posting form and submitting it.
+# min_form_time: 3 seconds
+
+## The secret used to sign posting form and preview tokens.
+# form_secret: change me to something random
+
+## A hidden field in posting forms that must be left empty.
+# honeypot_field: email2
+
+## The least number of seconds between new threads on a board.
+# min_seconds_between_threads: 30
+
+## HTML tags allowed in posts on boards that use sanitized HTML.
+# sanitizer_allowed_tags: [a, b, blockquote, br, code, em, i, p, pre, strong]
+
+## The most lines a post can have.
+# max_lines: 100
+
+## The most quote lines in a row a post can have.
+# max_quote_lines: 20
+
+## Which view of a board is shown at the board's URI.
+# default_views:
+#   b: catalog
+
+## HTML tags (and their attributes) that are allowed in the notice and custom
+## pages.
+# page_allowed_html:
+#   iframe: [src, width, height, allowfullscreen]
+
+## Show post and thread IDs prefixed with their board name, like /tech/#123.
+# board_id_prefixes: false
+
+## Collapse posts with more lines than this.
+# post_collapse_lines: 30
+
+## Collapse posts with more characters than this.
+# post_collapse_chars: 3000
+
+## Host names that videos can be embedded from, on boards that allow embeds.
+## The players also need to be allowed by frame-src in content_security_policy.
+# embed_hosts: [youtube.com, www.youtube.com, youtu.be, vimeo.com]
+
+## How long after making a post users can delete it.
+# self_delete_window: 1 hour
+
+## Hold posts from new users until staff approve them.
+# hold_first_posts: false
+
+## The most threads a single user can have open on each board.
+# max_open_threads_per_user:
+#   b: 3
+
+## Show the first line of referenced posts when hovering over post references.
+# ref_snippets: false
+
+## How lines of text in posts are separated, either paragraph or break-tags.
+# line_break_mode: paragraph
+
+## Save uploaded files under (a cleaned up version of) their original name.
+# preserve_filename: false
+
+## The icon shown in place of a thumbnail for uploads that aren't images or
+## videos. This file should be a PNG.
+#file_icon: DATADIR/longboard/file-icon.png
+
+## How many replies will be displayed per page of a thread.
+# posts_per_thread_page: 200
+
+## How many of the newest replies the "Last" link on threads shows. Set this to
+## 0 to hide the link.
+# last_posts_count: 50
+
+## Filter rules that only apply to posts shown in board previews and the catalog.
+# preview_filter_rules:
+#   - pattern: (?i)\bheck\b
+#     replace_with: h*ck
+
+## How threads are ordered on each board, either by bump or by creation.
+# sort_modes:
+#   archive: created
+
+## Let logged in staff skip block lists and rate limits.
+# exempt_staff: false
+
+## Allow threads without a subject. The first line of the post is used instead.
+# subject_optional: false
+
+## How much of the first line of the post to use as a missing subject.
+# subject_fallback_chars: 60
+
+## A file of IP ranges to block, one per line in CIDR notation.
+# blocked_ranges_path: /etc/longboard/blocked-ranges.txt
+
+## Boards where posts must be previewed before they're submitted.
+# require_preview: []
+
+## The rel attribute of links in posts. Empty to leave it out.
+# link_rel: "nofollow noopener"
+
+## The target attribute of links in posts. Empty to leave it out.
+# link_target: "_blank"
+
+## The maximum number of uploads sent to clients at once.
+# max_downloads: 100
+
+## The maximum number of uploads sent to a single user at once.
+# max_downloads_per_user: 4
+
+## Ask crawlers not to index pages on NSFW boards.
+# nsfw_noindex: false
+
+## Blur thumbnails on NSFW boards until they're hovered over.
+# nsfw_blur_thumbnails: false
+
+## How long a thread subject is reserved after a thread is created with it.
+#duplicate_subject_cooldown: 1h
+
+## How to format log messages (text or json).
+#log_format: text
+
+## The size limit for the whole body of a post request.
+#request_size_limit: 5M
+
+## Whether link previews of threads on NSFW boards are shown without an image.
+#nsfw_hide_share_image: false
+
+## The secret used to hash users' IP addresses.
+#hash_secret: a-long-random-string
+
+## The largest width and height of the thumbnails shown in catalogs.
+#catalog_thumb_size: 120
+
+## The most pinned threads shown at the top of a board.
+#max_pinned_on_page: 3
+
+## Hold or reject posts with links from users with fewer than this many posts.
+# first_link_min_posts: 3
+
+## What happens to posts caught by first_link_min_posts, either hold or reject.
+# first_link_action: hold
+
+## Load thumbnails on board pages and catalogs lazily.
+# lazy_thumbnails: true
+
+## Lay catalogs out in a fixed number of columns.
+# catalog_columns: 6
+
+## The most thumbnails that can be generated at once.
+# max_concurrent_thumbnails: 4
+
+## The image format that thumbnails are saved in, either png or webp.
+# thumbnail_format: png
+
+## Rotate and flip images according to their EXIF orientation when making
+## thumbnails.
+# auto_orient_images: true
+
+## Check that all of the templates exist when starting up.
+# check_templates: true
+
+## Shorten links in posts that are longer than this when showing them.
+# link_display_chars: 60
+
+## Give users who post with JSON responses a token for deleting their post.
+# delete_tokens: false
+
+## Trusted HTML added to the <head> of every page. Never put user input here.
+## Scripts must also be allowed by content_security_policy.
+# extra_head_html: '<meta name="theme-color" content="#222222">'
+
+## Trusted HTML added to the end of every page. Never put user input here.
+# extra_footer_html: '<p class="site-notice">Be excellent to each other.</p>'
+
+## Whether extra_head_html and extra_footer_html are added to pages.
+# allow_extra_html: true
+
+## Redirect paths like /Tech/123/ to their canonical path, like /tech/123.
+# canonical_redirects: true
+
+## Convert uploaded videos to a format that browsers can play.
+# transcode_uploads: false
+
+## The formats that uploaded videos are converted to, by content type.
+# transcode_types:
+#   video/quicktime: mp4
+
+## Keep the original files of converted uploads.
+# keep_transcoded_originals: false
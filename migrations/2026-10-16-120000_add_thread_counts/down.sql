@@ -0,0 +1,2 @@
+ALTER TABLE thread DROP COLUMN post_count;
+ALTER TABLE thread DROP COLUMN file_count;
@@ -0,0 +1,10 @@
+ALTER TABLE thread ADD COLUMN post_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE thread ADD COLUMN file_count INTEGER NOT NULL DEFAULT 0;
+
+UPDATE thread T SET post_count = (
+    SELECT COUNT(*) FROM post P WHERE P.thread = T.id);
+
+UPDATE thread T SET file_count = (
+    SELECT COUNT(*) FROM file F
+    INNER JOIN post P ON F.post = P.id
+    WHERE P.thread = T.id);
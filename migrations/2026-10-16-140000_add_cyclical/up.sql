@@ -0,0 +1 @@
+ALTER TABLE thread ADD COLUMN cyclical BOOLEAN NOT NULL DEFAULT false;
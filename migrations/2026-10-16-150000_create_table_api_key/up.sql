@@ -0,0 +1,6 @@
+CREATE TABLE api_key (
+    id SERIAL PRIMARY KEY,
+    key_hash TEXT NOT NULL UNIQUE,
+    staff_name TEXT NOT NULL REFERENCES staff,
+    scope TEXT NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW());
@@ -0,0 +1,10 @@
+CREATE TABLE deleted_file (
+    save_name TEXT PRIMARY KEY,
+    thumb_name TEXT NOT NULL,
+    orig_name TEXT,
+    content_type TEXT NOT NULL,
+    post INTEGER NOT NULL,
+    is_spoiler BOOL NOT NULL,
+    quarantine_path TEXT NOT NULL,
+    deleted_by TEXT NOT NULL,
+    time_stamp TIMESTAMPTZ NOT NULL DEFAULT NOW());
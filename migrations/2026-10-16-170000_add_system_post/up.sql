@@ -0,0 +1 @@
+ALTER TABLE post ADD COLUMN system BOOL NOT NULL DEFAULT FALSE;
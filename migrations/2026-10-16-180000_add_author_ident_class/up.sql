@@ -0,0 +1 @@
+ALTER TABLE post ADD COLUMN author_ident_class TEXT;
@@ -0,0 +1,4 @@
+DELETE FROM staff_action WHERE done_by NOT IN (SELECT name FROM staff);
+ALTER TABLE staff_action
+    ADD CONSTRAINT staff_action_done_by_fkey
+    FOREIGN KEY (done_by) REFERENCES staff;
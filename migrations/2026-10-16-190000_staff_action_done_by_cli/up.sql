@@ -0,0 +1 @@
+ALTER TABLE staff_action DROP CONSTRAINT staff_action_done_by_fkey;
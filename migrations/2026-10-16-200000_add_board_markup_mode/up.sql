@@ -0,0 +1 @@
+ALTER TABLE board ADD COLUMN markup_mode TEXT NOT NULL DEFAULT 'longboard';
@@ -0,0 +1 @@
+ALTER TABLE post DROP COLUMN truncated_html;
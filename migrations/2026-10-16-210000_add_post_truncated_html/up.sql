@@ -0,0 +1 @@
+ALTER TABLE post ADD COLUMN truncated_html TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE post DROP COLUMN embeds;
+ALTER TABLE board DROP COLUMN allow_embeds;
@@ -0,0 +1,2 @@
+ALTER TABLE board ADD COLUMN allow_embeds BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE post ADD COLUMN embeds TEXT;
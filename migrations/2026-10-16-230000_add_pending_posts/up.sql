@@ -0,0 +1,2 @@
+ALTER TABLE post ADD COLUMN pending BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE thread ADD COLUMN pending BOOLEAN NOT NULL DEFAULT false;
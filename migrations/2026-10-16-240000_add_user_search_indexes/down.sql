@@ -0,0 +1,2 @@
+DROP INDEX anon_user_note_idx;
+DROP INDEX anon_user_short_hash_idx;
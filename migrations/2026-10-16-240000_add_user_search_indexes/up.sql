@@ -0,0 +1,2 @@
+CREATE INDEX anon_user_short_hash_idx ON anon_user (substring(hash FROM '[^$]*$') text_pattern_ops);
+CREATE INDEX anon_user_note_idx ON anon_user USING gin (note gin_trgm_ops);
@@ -0,0 +1 @@
+ALTER TABLE board DROP COLUMN allow_new_threads;
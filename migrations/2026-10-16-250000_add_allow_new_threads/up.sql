@@ -0,0 +1 @@
+ALTER TABLE board ADD COLUMN allow_new_threads BOOLEAN NOT NULL DEFAULT true;
@@ -0,0 +1 @@
+ALTER TABLE board ADD COLUMN listed BOOLEAN NOT NULL DEFAULT true;
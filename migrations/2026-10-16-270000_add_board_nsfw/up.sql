@@ -0,0 +1 @@
+ALTER TABLE board ADD COLUMN nsfw BOOLEAN NOT NULL DEFAULT false;
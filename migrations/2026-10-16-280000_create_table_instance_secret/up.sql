@@ -0,0 +1,3 @@
+CREATE TABLE instance_secret (
+    name TEXT PRIMARY KEY,
+    value TEXT NOT NULL);
@@ -0,0 +1 @@
+ALTER TABLE file DROP COLUMN catalog_thumb_name;
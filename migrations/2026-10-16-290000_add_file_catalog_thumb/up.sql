@@ -0,0 +1 @@
+ALTER TABLE file ADD COLUMN catalog_thumb_name TEXT;
@@ -0,0 +1 @@
+ALTER TABLE post DROP COLUMN delete_token_hash;
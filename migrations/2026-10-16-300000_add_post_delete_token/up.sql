@@ -0,0 +1 @@
+ALTER TABLE post ADD COLUMN delete_token_hash TEXT;
@@ -0,0 +1 @@
+ALTER TABLE report DROP COLUMN closed_at;
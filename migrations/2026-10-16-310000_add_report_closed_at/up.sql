@@ -0,0 +1 @@
+ALTER TABLE report ADD COLUMN closed_at TIMESTAMPTZ;
@@ -0,0 +1,6 @@
+CREATE TABLE deleted_thread (
+    id SERIAL PRIMARY KEY,
+    board TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    time_stamp TIMESTAMPTZ NOT NULL);
+CREATE INDEX deleted_thread_board_idx ON deleted_thread (board, time_stamp);
@@ -77,10 +77,26 @@ function onLoadPostImage(ev) {
     }
 }
 
+/* Get the ID of the post that a post reference points to. */
+function postRefId(elem) {
+    // References may be prefixed with their board, like ">>/tech/#123".
+    return elem.textContent.replace(">>", "").replace(/.*#/, "");
+}
+
+/* Mark a post reference if it points to one of the user's own posts. */
+function markOwnPostRef(elem) {
+    var id = postRefId(elem);
+    var selector = '.post.own-post[data-post-id="' + id + '"]';
+
+    if (document.querySelector(selector) !== null) {
+        elem.classList.add('own-post-ref');
+    }
+}
+
 /* Display a preview of a post when the cursor hovers over a post reference. */
 function onMouseEnterPostRef(ev) {
-    var id = ev.target.textContent.replace(">>", "");
-    var post = document.getElementById(id);
+    var id = postRefId(ev.target);
+    var post = document.querySelector('.post[data-post-id="' + id + '"]');
 
     var targetRect = ev.target.getBoundingClientRect();
 
@@ -142,6 +158,67 @@ function onMouseLeavePostRef(ev) {
     });
 }
 
+/* Hide the beginning of a collapsed post while the full post is shown. */
+function onToggleFullBody(ev) {
+    var truncated = ev.target.previousElementSibling;
+
+    if (truncated !== null) {
+        truncated.hidden = ev.target.open;
+    }
+}
+
+/* Replace the link to an embedded video with the video's player. */
+function onClickEmbed(ev) {
+    ev.preventDefault();
+
+    var frame = document.createElement('iframe');
+    frame.className = 'embed-frame';
+    frame.src = ev.target.href;
+    frame.allowFullscreen = true;
+    frame.referrerPolicy = 'no-referrer';
+    frame.setAttribute('sandbox',
+        'allow-scripts allow-same-origin allow-presentation');
+
+    ev.target.replaceWith(frame);
+}
+
+/* Preview the body of a new post, and keep the token that shows the server that
+ * it was previewed. */
+function onClickPreview(ev) {
+    var form = ev.target.form;
+    var output = form.querySelector('.body-preview');
+
+    var data = new URLSearchParams();
+    data.append('body', form.elements['body'].value);
+
+    fetch(ev.target.dataset.uri, { method: 'POST', body: data })
+        .then((response) => {
+            if (!response.ok) {
+                throw new Error("Couldn't preview the post.");
+            }
+
+            return response.json();
+        })
+        .then((preview) => {
+            form.elements['preview-token'].value = preview.token;
+            output.innerHTML = preview.html;
+            output.hidden = false;
+        })
+        .catch((err) => {
+            output.textContent = err.message;
+            output.hidden = false;
+        });
+}
+
+/* Forget the preview token once the body is edited, since it's only good for
+ * the body that was previewed. */
+function onEditPreviewedBody(ev) {
+    var form = ev.target.form;
+
+    form.elements['preview-token'].value = "";
+    form.querySelector('.body-preview').hidden = true;
+}
+
 document.addEventListener('DOMContentLoaded', () => {
     document.querySelectorAll('.post-image img').forEach((elem) => {
         elem.addEventListener('click', onClickPostImage);
@@ -155,4 +232,20 @@ document.addEventListener('DOMContentLoaded', () => {
     document.querySelectorAll('.post .post-ref').forEach((elem) => {
         elem.addEventListener('mouseleave', onMouseLeavePostRef);
     });
+
+    document.querySelectorAll('.post .post-ref').forEach(markOwnPostRef);
+
+    document.querySelectorAll('.post .full-body').forEach((elem) => {
+        elem.addEventListener('toggle', onToggleFullBody);
+    });
+
+    document.querySelectorAll('.post .embed-play').forEach((elem) => {
+        elem.addEventListener('click', onClickEmbed);
+    });
+
+    document.querySelectorAll('.preview-button').forEach((elem) => {
+        elem.addEventListener('click', onClickPreview);
+        elem.form.elements['body'].addEventListener('input',
+            onEditPreviewedBody);
+    });
 })
@@ -0,0 +1,44 @@
+/* Load more files when scrolling to the bottom of the gallery. */
+document.addEventListener('DOMContentLoaded', (event) => {
+    const files = document.querySelector('.gallery-files');
+    let loading = false;
+
+    const loadMore = () => {
+        const next = document.querySelector('.gallery-next');
+
+        if (loading || next === null) {
+            return;
+        }
+
+        const bottom = next.getBoundingClientRect().top;
+        if (bottom > window.innerHeight * 2) {
+            return;
+        }
+
+        loading = true;
+
+        fetch(next.href)
+            .then((response) => response.text())
+            .then((text) => {
+                const page = new DOMParser().parseFromString(text, 'text/html');
+
+                page.querySelectorAll('.gallery-files .file-preview')
+                    .forEach((preview) => files.appendChild(preview));
+
+                const newNext = page.querySelector('.gallery-next');
+                if (newNext === null) {
+                    next.remove();
+                } else {
+                    next.href = newNext.getAttribute('href');
+                }
+
+                loading = false;
+            })
+            .catch(() => {
+                loading = false;
+            });
+    };
+
+    window.addEventListener('scroll', loadMore);
+    loadMore();
+});
@@ -0,0 +1,8 @@
+/* Highlight code blocks in posts with highlight.js. */
+document.addEventListener('DOMContentLoaded', (event) => {
+    document
+        .querySelectorAll('pre.blockcode code')
+        .forEach((block) => {
+            hljs.highlightBlock(block);
+        });
+});
@@ -3,7 +3,7 @@
 function onClickPostId(ev) {
     ev.preventDefault()
 
-    var id = ev.target.textContent.replace("#", "")
+    var id = ev.target.closest('.post').dataset.postId
     var reference = ">>" + id + "\n\n"
 
     var textarea = document.querySelector('.new-item-form textarea')
@@ -17,3 +17,38 @@ document.addEventListener('DOMContentLoaded', () => {
         elem.addEventListener('click', onClickPostId)
     })
 })
+
+/* Threads are split into pages, so a link to a post might land on a page that
+ * doesn't have it, or on the last posts of the thread. In that case, ask the
+ * server which page the post is on. */
+function findLinkedPost() {
+    var anchor = decodeURIComponent(location.hash.slice(1))
+    var match = anchor.match(/(\d+)$/)
+
+    if (!match || document.getElementById(anchor)) {
+        return
+    }
+
+    // Anchors used to be prefixed with the board name, like `tech-123`, and
+    // older links still have them.
+    if (anchor !== match[1] && document.getElementById(match[1])) {
+        location.replace('#' + match[1])
+        return
+    }
+
+    // The page we're sent to always has a page number, so a post that's gone
+    // doesn't send us around in circles.
+    if (new URLSearchParams(location.search).has('page')) {
+        return
+    }
+
+    // The last posts of a thread are at `/<board>/<thread>/last/<count>`, but
+    // the post might be anywhere in the thread.
+    var path = location.pathname
+        .replace(/\/$/, '')
+        .replace(/\/last\/\d+$/, '')
+    location.replace(path + '/post/' + match[1] + location.hash)
+}
+
+document.addEventListener('DOMContentLoaded', findLinkedPost)
+window.addEventListener('hashchange', findLinkedPost)
@@ -2,15 +2,72 @@
 
 use std::fs::OpenOptions;
 use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 
 use clap::{Arg, Command};
 
 use fern::colors::{Color, ColoredLevelConfig};
 
+use log::kv::{self, Key, Value, VisitSource};
 use log::{debug, info, log_enabled};
 
-use longboard::config::{Config, ExtensionConfig, GlobalConfig};
-use longboard::{new_instance, Error, Result};
+use serde_json::{Map, Value as JsonValue};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use signal_hook::low_level::signal_name;
+
+use longboard::config::{Config, ExtensionConfig, GlobalConfig, LogFormat};
+use longboard::{new_instance, Error, Result, Shutdown};
+
+/// How long to wait for requests to finish when shutting down. This is a bit
+/// less than the 10 seconds that Docker waits before killing a container.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// The fields of a log message in JSON format.
+struct JsonFields(Map<String, JsonValue>);
+
+impl JsonFields {
+    fn insert(&mut self, key: &str, value: impl Into<JsonValue>) {
+        self.0.insert(key.to_string(), value.into());
+    }
+}
+
+impl<'kvs> VisitSource<'kvs> for JsonFields {
+    fn visit_pair(
+        &mut self,
+        key: Key<'kvs>,
+        value: Value<'kvs>,
+    ) -> std::result::Result<(), kv::Error> {
+        let value = serde_json::to_value(value).map_err(kv::Error::boxed)?;
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Shut down cleanly when the process is asked to stop. See `Shutdown`.
+fn handle_signals(shutdown: Shutdown) -> Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            info!(
+                "Received {}, shutting down",
+                signal_name(signal).unwrap_or("signal")
+            );
+
+            shutdown.run(SHUTDOWN_TIMEOUT);
+
+            info!("Stopped");
+            log::logger().flush();
+
+            std::process::exit(0);
+        }
+    });
+
+    Ok(())
+}
 
 fn main_res() -> Result<()> {
     let matches = Command::new(env!("CARGO_PKG_NAME"))
@@ -92,6 +149,8 @@ fn main_res() -> Result<()> {
 
     let log_all = matches.contains_id("log-all");
 
+    let log_format = config.global().log_format;
+
     let dispatch = fern::Dispatch::new()
         .format(move |out, message, record| {
             let colors = ColoredLevelConfig::new()
@@ -100,7 +159,19 @@ fn main_res() -> Result<()> {
                 .warn(Color::Yellow)
                 .error(Color::Red);
 
-            if log_to_file {
+            if log_format == LogFormat::Json {
+                let mut fields = JsonFields(Map::new());
+                fields.insert("timestamp", chrono::Local::now().to_rfc3339());
+                fields.insert("level", record.level().as_str());
+                fields.insert("target", record.target());
+                fields.insert("message", message.to_string());
+
+                // A field that can't be serialized is left out rather than
+                // losing the whole message.
+                let _ = record.key_values().visit(&mut fields);
+
+                out.finish(format_args!("{}", JsonValue::Object(fields.0)))
+            } else if log_to_file {
                 out.finish(format_args!(
                     "{} [{}] {:>5}",
                     chrono::Local::now().format("%F %T%.3f"),
@@ -147,7 +218,15 @@ fn main_res() -> Result<()> {
         }
     }
 
-    Err(Error::from(new_instance(config)?.launch()))
+    let rocket = new_instance(config)?;
+
+    let shutdown = rocket
+        .state::<Shutdown>()
+        .expect("expected shutdown handle to be managed")
+        .clone();
+    handle_signals(shutdown)?;
+
+    Err(Error::from(rocket.launch()))
 }
 
 fn main() {
@@ -1,14 +1,70 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use clap::{builder::PossibleValuesParser, Arg, Command};
+use chrono::{Duration, Utc};
 
-use rand::{thread_rng, Rng};
+use clap::{
+    builder::PossibleValuesParser, Arg, ArgAction, ArgGroup, ArgMatches,
+    Command,
+};
 
-use longboard::config::{Config, ExtensionConfig, GlobalConfig};
-use longboard::models::staff::{Role, Staff};
-use longboard::models::SingleConnection;
-use longboard::Result;
+use diesel::result::Error as DieselError;
+
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+use longboard::config::{Conf, Config, ExtensionConfig, GlobalConfig};
+use longboard::models::staff::{
+    normalize_ip, ApiKeyId, ApiScope, NewStaffAction, NewUser, Role, Staff,
+    User, UserId, CLI_STAFF_NAME,
+};
+use longboard::models::{BoardExport, SingleConnection};
+use longboard::routes::new::{create_catalog_thumbnail, create_thumbnail};
+use longboard::{Error, Result};
+
+/// Make a file's thumbnail again in the configured format, along with its
+/// catalog thumbnail if it has one. Returns the names of the new thumbnails.
+fn remake_thumbs(
+    conf: &Conf,
+    file: &longboard::models::File,
+) -> Result<(String, Option<String>)> {
+    let file_name = |path: &Path| {
+        path.file_name()
+            .expect("bad thumb path")
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    let save_path = conf.upload_dir.join(&file.save_name);
+    let thumb_path = create_thumbnail(
+        &save_path,
+        &file.content_type,
+        conf.thumbnail_format,
+        conf.auto_orient_images,
+    )?;
+
+    let catalog_name = match (&file.catalog_thumb_name, conf.catalog_thumb_size)
+    {
+        (Some(_), Some(max_size)) => {
+            let catalog_path = create_catalog_thumbnail(
+                &save_path,
+                &thumb_path,
+                &file.content_type,
+                max_size,
+                conf.thumbnail_format,
+                conf.auto_orient_images,
+            )?;
+
+            Some(file_name(&catalog_path))
+        }
+        (catalog_name, _) => catalog_name.clone(),
+    };
+
+    Ok((file_name(&thumb_path), catalog_name))
+}
 
 fn main_res() -> Result<()> {
     let matches = Command::new("longctl")
@@ -87,10 +143,235 @@ fn main_res() -> Result<()> {
                         .num_args(1),
                 ),
         )
+        .subcommand(
+            Command::new("set-password")
+                .about("Change a staff member's password")
+                .arg(
+                    Arg::new("name")
+                        .short('u')
+                        .long("name")
+                        .help("The login name of the staff member")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("pass")
+                        .short('p')
+                        .long("pass")
+                        .help("The new password for the staff member")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("keep-api-keys")
+                        .long("keep-api-keys")
+                        .help("Don't revoke the staff member's API keys")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("verify-password")
+                .about("Check a staff member's password")
+                .arg(
+                    Arg::new("name")
+                        .short('u')
+                        .long("name")
+                        .help("The login name of the staff member")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("pass")
+                        .short('p')
+                        .long("pass")
+                        .help("The password to check")
+                        .required(true)
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("add-api-key")
+                .about("Add an API key for a staff member")
+                .arg(
+                    Arg::new("name")
+                        .short('u')
+                        .long("name")
+                        .help("The login name of the staff member")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("scope")
+                        .short('s')
+                        .long("scope")
+                        .help("The actions the key may be used for")
+                        .required(true)
+                        .num_args(1)
+                        .value_parser(PossibleValuesParser::new([
+                            "delete", "ban", "all",
+                        ])),
+                ),
+        )
+        .subcommand(
+            Command::new("remove-api-key")
+                .about("Revoke an API key")
+                .arg(
+                    Arg::new("id")
+                        .short('i')
+                        .long("id")
+                        .help("The ID of the API key")
+                        .required(true)
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(ApiKeyId)),
+                ),
+        )
+        .subcommand(
+            Command::new("ban")
+                .about("Ban a user")
+                .arg(
+                    Arg::new("ip")
+                        .short('i')
+                        .long("ip")
+                        .help("The IP address of the user")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(IpAddr)),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .short('H')
+                        .long("hash")
+                        .help("The start of the user's hash")
+                        .num_args(1),
+                )
+                .group(
+                    ArgGroup::new("user")
+                        .args(["ip", "hash"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("duration")
+                        .short('d')
+                        .long("duration")
+                        .help("How long the ban lasts, e.g. \"3 days\"")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("reason")
+                        .short('r')
+                        .long("reason")
+                        .help("The reason for the action")
+                        .num_args(1)
+                        .default_value(""),
+                ),
+        )
+        .subcommand(
+            Command::new("unban")
+                .about("Unban a user")
+                .arg(
+                    Arg::new("ip")
+                        .short('i')
+                        .long("ip")
+                        .help("The IP address of the user")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(IpAddr)),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .short('H')
+                        .long("hash")
+                        .help("The start of the user's hash")
+                        .num_args(1),
+                )
+                .group(
+                    ArgGroup::new("user")
+                        .args(["ip", "hash"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("reason")
+                        .short('r')
+                        .long("reason")
+                        .help("The reason for the action")
+                        .num_args(1)
+                        .default_value(""),
+                ),
+        )
+        .subcommand(
+            Command::new("prune-old").about(
+                "Delete reports and staff log entries older than the \
+                 configured retention",
+            ),
+        )
+        .subcommand(
+            Command::new("prune-expired-bans")
+                .about("Clear bans that have run out, listing their users"),
+        )
         .subcommand(
             Command::new("check-config")
                 .about("Check configuration file for errors"),
         )
+        .subcommand(
+            Command::new("rehash-users").about(
+                "Hash every user's IP address again with the current hash \
+                 secret and IPv6 prefix",
+            ),
+        )
+        .subcommand(
+            Command::new("recount")
+                .about("Check and repair cached thread post and file counts"),
+        )
+        .subcommand(
+            Command::new("fix-bump-dates")
+                .about("Check and repair thread bump dates"),
+        )
+        .subcommand(
+            Command::new("make-catalog-thumbs").about(
+                "Make catalog thumbnails for files uploaded without one",
+            ),
+        )
+        .subcommand(
+            Command::new("remake-thumbs").about(
+                "Make thumbnails again for files whose thumbnails aren't in \
+                 the configured format",
+            ),
+        )
+        .subcommand(
+            Command::new("export-board")
+                .about("Export a board with all of its threads as JSON")
+                .arg(
+                    Arg::new("name")
+                        .short('n')
+                        .long("name")
+                        .help("The name of the board")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("out")
+                        .short('o')
+                        .long("out")
+                        .value_name("FILE")
+                        .help("The file to write the export to")
+                        .required(true)
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("import-board")
+                .about("Import a board exported with export-board")
+                .arg(
+                    Arg::new("in")
+                        .short('i')
+                        .long("in")
+                        .value_name("FILE")
+                        .help("The file to read the export from")
+                        .required(true)
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
+        )
         .get_matches();
 
     let conf_path = GlobalConfig::default_path();
@@ -112,13 +393,11 @@ fn main_res() -> Result<()> {
 
     let mut db =
         SingleConnection::establish(&config.global_config.database_uri)?;
+    config.load_secrets(&mut db)?;
 
     if let Some(matches) = matches.subcommand_matches("add-staff") {
-        let pass = matches.get_one::<String>("pass").unwrap().as_bytes();
-        let salt: [u8; 20] = thread_rng().gen();
-
-        let argon_config = argon2::Config::default();
-        let password_hash = argon2::hash_encoded(pass, &salt, &argon_config)?;
+        let password_hash =
+            hash_password(matches.get_one::<String>("pass").unwrap())?;
 
         db.insert_staff(&Staff {
             name: matches.get_one::<String>("name").unwrap().to_owned(),
@@ -131,8 +410,326 @@ fn main_res() -> Result<()> {
         db.delete_staff(matches.get_one::<String>("name").unwrap())?;
     }
 
+    if let Some(matches) = matches.subcommand_matches("set-password") {
+        let name = matches.get_one::<String>("name").unwrap();
+        let password_hash =
+            hash_password(matches.get_one::<String>("pass").unwrap())?;
+
+        let keep_api_keys = matches.get_flag("keep-api-keys");
+
+        db.update_staff_password(name, password_hash, keep_api_keys)?;
+
+        println!("Changed the password for {}.", name);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("verify-password") {
+        let name = matches.get_one::<String>("name").unwrap();
+        let pass = matches.get_one::<String>("pass").unwrap();
+
+        let staff = match db.staff(name) {
+            Err(Error::DatabaseError(DieselError::NotFound)) => {
+                return Err(Error::StaffInvalidUsername {
+                    staff_name: name.to_owned(),
+                });
+            }
+            result => result?,
+        };
+
+        if !argon2::verify_encoded(&staff.password_hash, pass.as_bytes())? {
+            return Err(Error::StaffInvalidPassword {
+                staff_name: name.to_owned(),
+            });
+        }
+
+        println!("The password for {} is correct.", name);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("add-api-key") {
+        let key: String = thread_rng()
+            .sample_iter(Alphanumeric)
+            .map(char::from)
+            .take(42)
+            .collect();
+
+        let id = db.insert_api_key(
+            matches.get_one::<String>("name").unwrap(),
+            ApiScope::from_str(matches.get_one::<String>("scope").unwrap())?,
+            &key,
+        )?;
+
+        println!("Created API key {}: {}", id, key);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("remove-api-key") {
+        db.delete_api_key(*matches.get_one::<ApiKeyId>("id").unwrap())?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("ban") {
+        let user = find_user(&mut db, &config, matches, true)?;
+        let duration = matches.get_one::<String>("duration").unwrap();
+        let duration = Duration::from_std(parse_duration::parse(duration)?)?;
+
+        db.ban_user(user.id, duration)?;
+
+        db.insert_staff_action(NewStaffAction {
+            done_by: CLI_STAFF_NAME.to_string(),
+            action: format!("Banned user {}", user.id),
+            reason: matches.get_one::<String>("reason").unwrap().to_owned(),
+        })?;
+
+        println!("Banned user {} ({}).", user.id, user.short_hash());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("unban") {
+        let user = find_user(&mut db, &config, matches, false)?;
+
+        db.unban_user(user.id)?;
+
+        db.insert_staff_action(NewStaffAction {
+            done_by: CLI_STAFF_NAME.to_string(),
+            action: format!("Unbanned user {}", user.id),
+            reason: matches.get_one::<String>("reason").unwrap().to_owned(),
+        })?;
+
+        println!("Unbanned user {} ({}).", user.id, user.short_hash());
+    }
+
+    if matches.subcommand_matches("prune-old").is_some() {
+        let conf = &config.global_config;
+
+        if let Some(retention) = conf.report_retention {
+            let count = db.prune_reports_before(Utc::now() - retention)?;
+
+            println!("Pruned {} report(s).", count);
+        }
+
+        if let Some(retention) = conf.log_retention {
+            let count = db.prune_actions_before(Utc::now() - retention)?;
+
+            println!("Pruned {} staff log entries.", count);
+        }
+    }
+
+    if matches.subcommand_matches("prune-expired-bans").is_some() {
+        for user in db.expired_bans()? {
+            println!(
+                "Clearing ban for user {} ({}).",
+                user.id,
+                user.short_hash()
+            );
+        }
+
+        let count = db.clear_expired_bans()?;
+
+        println!("Cleared {} expired ban(s).", count);
+    }
+
+    if matches.subcommand_matches("rehash-users").is_some() {
+        let conf = config.global();
+        let mut count = 0;
+        let mut merged = 0;
+        // Users whose addresses are in the same network are merged into the
+        // oldest of them, so that their bans and notes apply to the network.
+        let mut networks: HashMap<IpAddr, UserId> = HashMap::new();
+
+        let mut users = db.all_users()?;
+        users.sort_by_key(|user| user.id);
+
+        for user in users {
+            let ip: IpAddr = match user.ip.parse() {
+                Ok(ip) => ip,
+                Err(_) => {
+                    eprintln!(
+                        "Warning: user {} has an invalid IP address.",
+                        user.id
+                    );
+                    continue;
+                }
+            };
+            let network = normalize_ip(ip, conf.ipv6_prefix);
+
+            if let Some(&kept_id) = networks.get(&network) {
+                db.merge_user(user.id, kept_id)?;
+                merged += 1;
+                continue;
+            }
+
+            let hash = NewUser::hash_ip(ip, conf.ipv6_prefix, conf.hash_secret);
+            db.set_user_ip(user.id, network, hash)?;
+            networks.insert(network, user.id);
+            count += 1;
+        }
+
+        println!("Rehashed {} user(s).", count);
+        if merged > 0 {
+            println!("Merged {} user(s) into others in their network.", merged);
+        }
+    }
+
+    if matches.subcommand_matches("recount").is_some() {
+        let count = db.recount_threads()?;
+
+        println!("Fixed counts for {} thread(s).", count);
+    }
+
+    if matches.subcommand_matches("fix-bump-dates").is_some() {
+        let count = db.fix_bump_dates()?;
+
+        println!("Fixed bump dates for {} thread(s).", count);
+    }
+
+    if matches.subcommand_matches("make-catalog-thumbs").is_some() {
+        let conf = config.global();
+
+        let max_size = match conf.catalog_thumb_size {
+            Some(max_size) => max_size,
+            None => return Err(Error::CatalogThumbsDisabled),
+        };
+
+        let mut count = 0;
+
+        for file in db.files_without_catalog_thumb()? {
+            let save_path = conf.upload_dir.join(&file.save_name);
+            let thumb_path = conf.upload_dir.join(&file.thumb_name);
+
+            let catalog_path = match create_catalog_thumbnail(
+                &save_path,
+                &thumb_path,
+                &file.content_type,
+                max_size,
+                conf.thumbnail_format,
+                conf.auto_orient_images,
+            ) {
+                Ok(path) => path,
+                Err(err) => {
+                    eprintln!(
+                        "Warning: couldn't make a catalog thumbnail for {}: {}",
+                        file.save_name, err
+                    );
+                    continue;
+                }
+            };
+
+            let catalog_name = catalog_path
+                .file_name()
+                .expect("bad thumb path")
+                .to_string_lossy();
+
+            db.set_catalog_thumb_name(&file.save_name, catalog_name)?;
+            count += 1;
+        }
+
+        println!("Made catalog thumbnails for {} file(s).", count);
+    }
+
+    if matches.subcommand_matches("remake-thumbs").is_some() {
+        let conf = config.global();
+        let extension = conf.thumbnail_format.extension();
+
+        let mut count = 0;
+
+        for file in db.files_with_thumbnails()? {
+            let thumb_ext = Path::new(&file.thumb_name).extension();
+            if thumb_ext.map_or(false, |ext| ext == extension) {
+                continue;
+            }
+
+            let (thumb_name, catalog_name) = match remake_thumbs(&conf, &file)
+            {
+                Ok(names) => names,
+                Err(err) => {
+                    eprintln!(
+                        "Warning: couldn't make thumbnails for {}: {}",
+                        file.save_name, err
+                    );
+                    continue;
+                }
+            };
+
+            db.set_thumb_names(
+                &file.save_name,
+                &thumb_name,
+                catalog_name.as_deref(),
+            )?;
+
+            // Remove the old thumbnails, unless they're still in use.
+            let mut old_names = vec![&file.thumb_name];
+            old_names.extend(&file.catalog_thumb_name);
+            old_names.dedup();
+
+            for old_name in old_names {
+                let in_use = *old_name == file.save_name
+                    || *old_name == thumb_name
+                    || catalog_name.as_ref() == Some(old_name);
+
+                if in_use {
+                    continue;
+                }
+
+                let old_path = conf.upload_dir.join(old_name);
+                if let Err(err) = std::fs::remove_file(&old_path) {
+                    eprintln!(
+                        "Warning: couldn't remove old thumbnail {}: {}",
+                        old_path.display(),
+                        err
+                    );
+                }
+            }
+
+            count += 1;
+        }
+
+        println!("Made thumbnails again for {} file(s).", count);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("export-board") {
+        let export =
+            db.export_board(matches.get_one::<String>("name").unwrap())?;
+        let path = matches.get_one::<PathBuf>("out").unwrap();
+
+        let file = File::create(path).map_err(|cause| Error::IoErrorMsg {
+            cause,
+            msg: format!("Couldn't create export file at {}", path.display()),
+        })?;
+        serde_json::to_writer(BufWriter::new(file), &export)?;
+
+        warn_missing_files(&config, &export);
+
+        println!(
+            "Exported {} thread(s) and {} post(s) to {}.",
+            export.threads.len(),
+            export.post_count(),
+            path.display()
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("import-board") {
+        let path = matches.get_one::<PathBuf>("in").unwrap();
+
+        let file = File::open(path).map_err(|cause| Error::IoErrorMsg {
+            cause,
+            msg: format!("Couldn't open export file at {}", path.display()),
+        })?;
+        let export: BoardExport =
+            serde_json::from_reader(BufReader::new(file))?;
+
+        warn_missing_files(&config, &export);
+
+        db.import_board(&export)?;
+
+        println!(
+            "Imported {} thread(s) and {} post(s) into /{}/.",
+            export.threads.len(),
+            export.post_count(),
+            export.board.name
+        );
+    }
+
     if matches.subcommand_matches("check-config").is_some() {
-        // We've already loaded all the config files, so we know they're good.
+        // We've already loaded all the config files, so we know they're good,
+        // except for the parts that refer to the database and the templates.
+        config.check_home_redirects(&mut db)?;
+        config.check_templates()?;
 
         println!("Global configuration: {}", conf_path.display());
 
@@ -153,6 +750,71 @@ fn main_res() -> Result<()> {
     Ok(())
 }
 
+/// Hash a staff member's password for storing in the database.
+fn hash_password(pass: &str) -> Result<String> {
+    let salt: [u8; 20] = thread_rng().gen();
+
+    let argon_config = argon2::Config::default();
+    Ok(argon2::hash_encoded(pass.as_bytes(), &salt, &argon_config)?)
+}
+
+/// Find the user given by the `--ip` or `--hash` argument. If `create` is
+/// true, a user is created for an IP address that hasn't been seen before.
+fn find_user(
+    db: &mut SingleConnection,
+    config: &Config,
+    matches: &ArgMatches,
+    create: bool,
+) -> Result<User> {
+    if let Some(hash) = matches.get_one::<String>("hash") {
+        return db.user_by_hash(hash);
+    }
+
+    let ip = *matches.get_one::<IpAddr>("ip").unwrap();
+    let conf = config.global();
+
+    match db.user(normalize_ip(ip, conf.ipv6_prefix)) {
+        Err(Error::DatabaseError(DieselError::NotFound)) if create => db
+            .insert_user(&NewUser::from_ip(
+                ip,
+                conf.ipv6_prefix,
+                conf.hash_secret,
+            )),
+        Err(Error::DatabaseError(DieselError::NotFound)) => {
+            Err(Error::UserNotFound { ip_addr: ip })
+        }
+        result => result,
+    }
+}
+
+/// Print a warning for each file in a board export that isn't in the upload
+/// directory.
+fn warn_missing_files(config: &Config, export: &BoardExport) {
+    let upload_dir = &config.global_config.upload_dir;
+
+    for file in export.files() {
+        let mut names = vec![&file.save_name];
+
+        if file.thumb_name != file.save_name {
+            names.push(&file.thumb_name);
+        }
+
+        if let Some(ref name) = file.catalog_thumb_name {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        for name in names {
+            let path = upload_dir.join(name);
+
+            if !path.exists() {
+                eprintln!("Warning: file {} is missing.", path.display());
+            }
+        }
+    }
+}
+
 fn main() {
     if let Err(e) = main_res() {
         eprintln!("{}", e);
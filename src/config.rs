@@ -1,5 +1,6 @@
 //! App configuration.
 
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::ffi::OsStr;
 use std::fs::{read_dir, read_to_string, File};
@@ -8,11 +9,13 @@ use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::string::ToString;
 
-use chrono::Duration;
+use chrono::{
+    DateTime, Duration, FixedOffset, NaiveTime, TimeZone, Timelike, Utc,
+};
 
-use mime::Mime;
+use log::warn;
 
-use pulldown_cmark::{html::push_html, Parser};
+use mime::Mime;
 
 use rand::{thread_rng, Rng};
 
@@ -23,6 +26,10 @@ use rocket::{uri, State};
 
 use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::models::staff::Role;
+use crate::models::{Connection, InnerConnection};
+use crate::parse::{render_admin_markdown, LineBreakMode};
+use crate::views::TEMPLATES;
 use crate::{Error, Result};
 
 /// Longboard configuration.
@@ -89,16 +96,26 @@ impl Config {
         Conf {
             site_name: self.global_config.site_name.as_ref(),
             favicon_path: self.global_config.favicon_path.as_ref(),
+            base_url: self
+                .global_config
+                .base_url
+                .as_deref()
+                .map(|url| url.trim_end_matches('/')),
             address: self.global_config.address.as_ref(),
             port: self.global_config.port,
             log_file: self.global_config.log_file.as_deref(),
+            log_format: self.global_config.log_format,
             database_uri: self.global_config.database_uri.as_ref(),
             resource_dir: self.global_config.resource_dir.as_ref(),
             upload_dir: self.global_config.upload_dir.as_ref(),
+            banner_dir: None,
             pages_dir: self.global_config.pages_dir.as_deref(),
+            pages: &self.global_config.pages,
             names_path: self.global_config.names_path.as_deref(),
             notice_path: self.global_config.notice_path.as_deref(),
             allow_uploads: self.global_config.allow_uploads,
+            force_spoiler: self.global_config.force_spoiler,
+            spoiler_optional: self.global_config.spoiler_optional,
             allow_file_types: self.global_config.allow_file_types.as_ref(),
             rate_limit_same_user: &self.global_config.rate_limit_same_user,
             rate_limit_same_content: &self
@@ -111,14 +128,193 @@ impl Config {
             block_list: self.global_config.block_list.as_ref(),
             dns_block_list: self.global_config.dns_block_list.as_slice(),
             extension_name: None,
+            home_redirect: None,
             threads_per_page: self.global_config.threads_per_page,
             preview_limit: self.global_config.preview_limit,
             num_recent_files: self.global_config.num_recent_files,
             num_recent_posts: self.global_config.num_recent_posts,
             thread_limit: self.global_config.thread_limit,
+            reply_cap: self.global_config.reply_cap,
+            dedup_threads: self.global_config.dedup_threads,
+            allow_cyclical: self.global_config.allow_cyclical,
+            content_security_policy: self
+                .global_config
+                .content_security_policy
+                .as_ref(),
+            op_requires_file: self.global_config.op_requires_file,
+            posting_hours: self.global_config.posting_hours.as_ref(),
+            accel_redirect: self.global_config.accel_redirect.as_ref(),
+            upload_cache_age: &self.global_config.upload_cache_age,
+            resource_cache_age: &self.global_config.resource_cache_age,
+            quarantine_dir: self.global_config.quarantine_dir.as_deref(),
+            ipv6_prefix: self.global_config.ipv6_prefix,
+            content_similarity: self.global_config.content_similarity,
+            role_flair: &self.global_config.role_flair,
+            gallery_page_size: self.global_config.gallery_page_size,
+            max_boards: self.global_config.max_boards,
+            upload_temp_dir: self.global_config.upload_temp_dir.as_deref(),
+            report_retention: self.global_config.report_retention.as_ref(),
+            log_retention: self.global_config.log_retention.as_ref(),
+            min_form_time: self.global_config.min_form_time.as_ref(),
+            form_secret: self
+                .global_config
+                .form_secret
+                .as_deref()
+                .unwrap_or_default(),
+            honeypot_field: self.global_config.honeypot_field.as_deref(),
+            min_seconds_between_threads: self
+                .global_config
+                .min_seconds_between_threads,
+            sanitizer_allowed_tags: &self.global_config.sanitizer_allowed_tags,
+            max_lines: self.global_config.max_lines,
+            max_quote_lines: self.global_config.max_quote_lines,
+            default_views: &self.global_config.default_views,
+            page_allowed_html: &self.global_config.page_allowed_html,
+            board_id_prefixes: self.global_config.board_id_prefixes,
+            post_collapse_lines: self.global_config.post_collapse_lines,
+            post_collapse_chars: self.global_config.post_collapse_chars,
+            embed_hosts: &self.global_config.embed_hosts,
+            self_delete_window: self.global_config.self_delete_window.as_ref(),
+            hold_first_posts: self.global_config.hold_first_posts,
+            max_open_threads_per_user: &self
+                .global_config
+                .max_open_threads_per_user,
+            ref_snippets: self.global_config.ref_snippets,
+            line_break_mode: self.global_config.line_break_mode,
+            preserve_filename: self.global_config.preserve_filename,
+            file_icon_path: self.global_config.file_icon_path.as_ref(),
+            posts_per_thread_page: self.global_config.posts_per_thread_page,
+            last_posts_count: self.global_config.last_posts_count,
+            preview_filter_rules: self
+                .global_config
+                .preview_filter_rules
+                .as_ref(),
+            sort_modes: &self.global_config.sort_modes,
+            exempt_staff: self.global_config.exempt_staff,
+            subject_optional: self.global_config.subject_optional,
+            subject_fallback_chars: self.global_config.subject_fallback_chars,
+            blocked_ranges: &self.global_config.blocked_ranges,
+            require_preview: self.global_config.require_preview.as_slice(),
+            link_rel: &self.global_config.link_rel,
+            link_target: &self.global_config.link_target,
+            max_downloads: self.global_config.max_downloads,
+            max_downloads_per_user: self.global_config.max_downloads_per_user,
+            nsfw_noindex: self.global_config.nsfw_noindex,
+            nsfw_blur_thumbnails: self.global_config.nsfw_blur_thumbnails,
+            duplicate_subject_cooldown: self
+                .global_config
+                .duplicate_subject_cooldown
+                .as_ref(),
+            request_size_limit: self
+                .global_config
+                .request_size_limit
+                .unwrap_or_else(|| {
+                    default_request_size_limit(
+                        self.global_config.file_size_limit,
+                    )
+                }),
+            nsfw_hide_share_image: self.global_config.nsfw_hide_share_image,
+            hash_secret: self
+                .global_config
+                .hash_secret
+                .as_deref()
+                .unwrap_or_default(),
+            catalog_thumb_size: self.global_config.catalog_thumb_size,
+            max_pinned_on_page: self.global_config.max_pinned_on_page,
+            first_link_min_posts: self.global_config.first_link_min_posts,
+            first_link_action: self.global_config.first_link_action,
+            lazy_thumbnails: self.global_config.lazy_thumbnails,
+            catalog_columns: self.global_config.catalog_columns,
+            max_concurrent_thumbnails: self
+                .global_config
+                .max_concurrent_thumbnails,
+            thumbnail_format: self.global_config.thumbnail_format,
+            auto_orient_images: self.global_config.auto_orient_images,
+            link_display_chars: self.global_config.link_display_chars,
+            delete_tokens: self.global_config.delete_tokens,
+            extra_head_html: self.global_config.extra_head_html.as_deref(),
+            extra_footer_html: self.global_config.extra_footer_html.as_deref(),
+            allow_extra_html: self.global_config.allow_extra_html,
+            canonical_redirects: self.global_config.canonical_redirects,
+            transcode_uploads: self.global_config.transcode_uploads,
+            transcode_types: &self.global_config.transcode_types,
+            keep_transcoded_originals: self
+                .global_config
+                .keep_transcoded_originals,
         }
     }
 
+    /// Check that the boards that extensions redirect to from the home page
+    /// exist.
+    pub fn check_home_redirects<C, M>(
+        &self,
+        db: &mut Connection<C, M>,
+    ) -> Result<()>
+    where
+        C: InnerConnection<M> + diesel::connection::LoadConnection,
+        M: diesel::connection::TransactionManager<C>,
+    {
+        for ext in &self.extension_configs {
+            if let Some(board_name) = &ext.home_redirect {
+                match db.board(board_name) {
+                    Ok(_) => (),
+                    Err(Error::BoardNotFound { .. }) => {
+                        return Err(Error::HomeRedirectBoardNotFound {
+                            extension: ext.name.clone(),
+                            board_name: board_name.clone(),
+                        });
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that the templates which are rendered by name exist in the
+    /// resource directory.
+    pub fn check_templates(&self) -> Result<()> {
+        let template_dir = self.global_config.resource_dir.join("templates");
+
+        for name in TEMPLATES {
+            let path = template_dir.join(format!("{}.html.hbs", name));
+
+            if !path.exists() {
+                return Err(Error::ConfigPathNotFound {
+                    description: format!("'{}' template", name),
+                    path: path.display().to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load the secrets used to hash users' IP addresses and to sign posting
+    /// forms from the database, if they aren't configured. Each secret is
+    /// generated and stored the first time.
+    pub fn load_secrets<C, M>(
+        &mut self,
+        db: &mut Connection<C, M>,
+    ) -> Result<()>
+    where
+        C: InnerConnection<M> + diesel::connection::LoadConnection,
+        M: diesel::connection::TransactionManager<C>,
+    {
+        if self.global_config.hash_secret.is_none() {
+            let secret = db.instance_secret("hash_secret", random_secret())?;
+            self.global_config.hash_secret = Some(secret);
+        }
+
+        if self.global_config.form_secret.is_none() {
+            let secret = db.instance_secret("form_secret", random_secret())?;
+            self.global_config.form_secret = Some(secret);
+        }
+
+        Ok(())
+    }
+
     /// Get the extension config with the given name, if it exists.
     pub fn extension<S>(&self, name: S) -> Option<Conf>
     where
@@ -135,22 +331,82 @@ impl Config {
         ext_conf.map(|ext_conf| Conf {
             site_name: self.global_config.site_name.as_ref(),
             favicon_path: self.global_config.favicon_path.as_ref(),
+            base_url: self
+                .global_config
+                .base_url
+                .as_deref()
+                .map(|url| url.trim_end_matches('/')),
             address: self.global_config.address.as_ref(),
             port: self.global_config.port,
             resource_dir: self.global_config.resource_dir.as_ref(),
             upload_dir: self.global_config.upload_dir.as_ref(),
             database_uri: self.global_config.database_uri.as_ref(),
             log_file: self.global_config.log_file.as_deref(),
+            log_format: self.global_config.log_format,
             threads_per_page: self.global_config.threads_per_page,
             preview_limit: self.global_config.preview_limit,
             num_recent_files: self.global_config.num_recent_files,
             num_recent_posts: self.global_config.num_recent_posts,
             thread_limit: self.global_config.thread_limit,
+            content_security_policy: self
+                .global_config
+                .content_security_policy
+                .as_ref(),
+            accel_redirect: self.global_config.accel_redirect.as_ref(),
+            upload_cache_age: &self.global_config.upload_cache_age,
+            resource_cache_age: &self.global_config.resource_cache_age,
+            quarantine_dir: self.global_config.quarantine_dir.as_deref(),
+            ipv6_prefix: self.global_config.ipv6_prefix,
+            role_flair: &self.global_config.role_flair,
+            gallery_page_size: self.global_config.gallery_page_size,
+            max_boards: self.global_config.max_boards,
+            upload_temp_dir: self.global_config.upload_temp_dir.as_deref(),
+            report_retention: self.global_config.report_retention.as_ref(),
+            log_retention: self.global_config.log_retention.as_ref(),
+            form_secret: self
+                .global_config
+                .form_secret
+                .as_deref()
+                .unwrap_or_default(),
+            sanitizer_allowed_tags: &self.global_config.sanitizer_allowed_tags,
+            page_allowed_html: &self.global_config.page_allowed_html,
+            embed_hosts: &self.global_config.embed_hosts,
+            file_icon_path: self.global_config.file_icon_path.as_ref(),
+            posts_per_thread_page: self.global_config.posts_per_thread_page,
+            last_posts_count: self.global_config.last_posts_count,
+            exempt_staff: self.global_config.exempt_staff,
+            blocked_ranges: &self.global_config.blocked_ranges,
+            max_downloads: self.global_config.max_downloads,
+            max_downloads_per_user: self.global_config.max_downloads_per_user,
+            hash_secret: self
+                .global_config
+                .hash_secret
+                .as_deref()
+                .unwrap_or_default(),
+            catalog_thumb_size: self.global_config.catalog_thumb_size,
+            max_concurrent_thumbnails: self
+                .global_config
+                .max_concurrent_thumbnails,
+            thumbnail_format: self.global_config.thumbnail_format,
+            auto_orient_images: self.global_config.auto_orient_images,
+            allow_extra_html: self.global_config.allow_extra_html,
+            canonical_redirects: self.global_config.canonical_redirects,
+            transcode_uploads: self.global_config.transcode_uploads,
+            transcode_types: &self.global_config.transcode_types,
+            keep_transcoded_originals: self
+                .global_config
+                .keep_transcoded_originals,
 
+            banner_dir: ext_conf.banner_dir.as_deref(),
             pages_dir: ext_conf
                 .pages_dir
                 .as_deref()
                 .or(self.global_config.pages_dir.as_deref()),
+            pages: if ext_conf.pages_dir.is_some() {
+                &ext_conf.pages
+            } else {
+                &self.global_config.pages
+            },
             names_path: ext_conf
                 .names_path
                 .as_deref()
@@ -162,6 +418,12 @@ impl Config {
             allow_uploads: ext_conf
                 .allow_uploads
                 .unwrap_or(self.global_config.allow_uploads),
+            force_spoiler: ext_conf
+                .force_spoiler
+                .unwrap_or(self.global_config.force_spoiler),
+            spoiler_optional: ext_conf
+                .spoiler_optional
+                .unwrap_or(self.global_config.spoiler_optional),
             allow_file_types: ext_conf
                 .allow_file_types
                 .as_ref()
@@ -174,7 +436,7 @@ impl Config {
                 .as_ref()
                 .unwrap_or(&self.global_config.rate_limit_same_user),
             rate_limit_same_content: ext_conf
-                .rate_limit_same_user
+                .rate_limit_same_content
                 .as_ref()
                 .unwrap_or(&self.global_config.rate_limit_same_content),
             filter_rules: ext_conf
@@ -197,12 +459,170 @@ impl Config {
                 .dns_block_list
                 .as_deref()
                 .unwrap_or(self.global_config.dns_block_list.as_slice()),
+            reply_cap: ext_conf
+                .reply_cap
+                .or(self.global_config.reply_cap),
+            dedup_threads: ext_conf
+                .dedup_threads
+                .unwrap_or(self.global_config.dedup_threads),
+            allow_cyclical: ext_conf
+                .allow_cyclical
+                .unwrap_or(self.global_config.allow_cyclical),
+            op_requires_file: ext_conf
+                .op_requires_file
+                .unwrap_or(self.global_config.op_requires_file),
+            posting_hours: ext_conf
+                .posting_hours
+                .as_ref()
+                .or(self.global_config.posting_hours.as_ref()),
+            content_similarity: ext_conf
+                .content_similarity
+                .or(self.global_config.content_similarity),
+            min_form_time: ext_conf
+                .min_form_time
+                .as_ref()
+                .or(self.global_config.min_form_time.as_ref()),
+            honeypot_field: ext_conf
+                .honeypot_field
+                .as_deref()
+                .or(self.global_config.honeypot_field.as_deref()),
+            min_seconds_between_threads: ext_conf
+                .min_seconds_between_threads
+                .or(self.global_config.min_seconds_between_threads),
+            max_lines: ext_conf.max_lines.or(self.global_config.max_lines),
+            max_quote_lines: ext_conf
+                .max_quote_lines
+                .or(self.global_config.max_quote_lines),
+            default_views: ext_conf
+                .default_views
+                .as_ref()
+                .unwrap_or(&self.global_config.default_views),
+            board_id_prefixes: self.global_config.board_id_prefixes,
+            post_collapse_lines: ext_conf
+                .post_collapse_lines
+                .or(self.global_config.post_collapse_lines),
+            post_collapse_chars: ext_conf
+                .post_collapse_chars
+                .or(self.global_config.post_collapse_chars),
+            self_delete_window: ext_conf
+                .self_delete_window
+                .as_ref()
+                .or(self.global_config.self_delete_window.as_ref()),
+            hold_first_posts: ext_conf
+                .hold_first_posts
+                .unwrap_or(self.global_config.hold_first_posts),
+            max_open_threads_per_user: ext_conf
+                .max_open_threads_per_user
+                .as_ref()
+                .unwrap_or(&self.global_config.max_open_threads_per_user),
+            ref_snippets: ext_conf
+                .ref_snippets
+                .unwrap_or(self.global_config.ref_snippets),
+            line_break_mode: ext_conf
+                .line_break_mode
+                .unwrap_or(self.global_config.line_break_mode),
+            preserve_filename: ext_conf
+                .preserve_filename
+                .unwrap_or(self.global_config.preserve_filename),
+            preview_filter_rules: ext_conf
+                .preview_filter_rules
+                .as_ref()
+                .unwrap_or(self.global_config.preview_filter_rules.as_ref()),
+            sort_modes: ext_conf
+                .sort_modes
+                .as_ref()
+                .unwrap_or(&self.global_config.sort_modes),
+            subject_optional: ext_conf
+                .subject_optional
+                .unwrap_or(self.global_config.subject_optional),
+            subject_fallback_chars: ext_conf
+                .subject_fallback_chars
+                .unwrap_or(self.global_config.subject_fallback_chars),
+            require_preview: ext_conf
+                .require_preview
+                .as_deref()
+                .unwrap_or(self.global_config.require_preview.as_slice()),
+            link_rel: ext_conf
+                .link_rel
+                .as_deref()
+                .unwrap_or(&self.global_config.link_rel),
+            link_target: ext_conf
+                .link_target
+                .as_deref()
+                .unwrap_or(&self.global_config.link_target),
+            nsfw_noindex: ext_conf
+                .nsfw_noindex
+                .unwrap_or(self.global_config.nsfw_noindex),
+            nsfw_blur_thumbnails: ext_conf
+                .nsfw_blur_thumbnails
+                .unwrap_or(self.global_config.nsfw_blur_thumbnails),
+            duplicate_subject_cooldown: ext_conf
+                .duplicate_subject_cooldown
+                .as_ref()
+                .or(self.global_config.duplicate_subject_cooldown.as_ref()),
+            request_size_limit: ext_conf
+                .request_size_limit
+                .or(self.global_config.request_size_limit)
+                .unwrap_or_else(|| {
+                    default_request_size_limit(
+                        ext_conf
+                            .file_size_limit
+                            .unwrap_or(self.global_config.file_size_limit),
+                    )
+                }),
+            nsfw_hide_share_image: ext_conf
+                .nsfw_hide_share_image
+                .unwrap_or(self.global_config.nsfw_hide_share_image),
+            max_pinned_on_page: ext_conf
+                .max_pinned_on_page
+                .or(self.global_config.max_pinned_on_page),
+            first_link_min_posts: ext_conf
+                .first_link_min_posts
+                .or(self.global_config.first_link_min_posts),
+            first_link_action: ext_conf
+                .first_link_action
+                .unwrap_or(self.global_config.first_link_action),
+            lazy_thumbnails: ext_conf
+                .lazy_thumbnails
+                .unwrap_or(self.global_config.lazy_thumbnails),
+            catalog_columns: ext_conf
+                .catalog_columns
+                .or(self.global_config.catalog_columns),
+            link_display_chars: ext_conf
+                .link_display_chars
+                .or(self.global_config.link_display_chars),
+            delete_tokens: ext_conf
+                .delete_tokens
+                .unwrap_or(self.global_config.delete_tokens),
+            extra_head_html: ext_conf
+                .extra_head_html
+                .as_deref()
+                .or(self.global_config.extra_head_html.as_deref()),
+            extra_footer_html: ext_conf
+                .extra_footer_html
+                .as_deref()
+                .or(self.global_config.extra_footer_html.as_deref()),
 
             extension_name: Some(ext_conf.name.as_ref()),
+            home_redirect: ext_conf.home_redirect.as_deref(),
         })
     }
 }
 
+/// The default Content-Security-Policy.
+///
+/// Scripts and styles are allowed from cdnjs so that code highlighting works.
+const DEFAULT_CSP: &str = "\
+    default-src 'self'; \
+    script-src 'self' cdnjs.cloudflare.com; \
+    style-src 'self' 'unsafe-inline' cdnjs.cloudflare.com; \
+    img-src 'self' data:; \
+    media-src 'self'; \
+    object-src 'none'; \
+    base-uri 'none'; \
+    form-action 'self'; \
+    frame-ancestors 'none'";
+
 /// Global site configuration options loaded from a file.
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
@@ -212,12 +632,17 @@ pub struct GlobalConfig {
     /// Path to the favicon.
     #[serde(rename = "favicon")]
     pub favicon_path: PathBuf,
+    /// The URL the site is served at, such as `https://example.com`, for
+    /// links that must be absolute.
+    pub base_url: Option<String>,
     /// Address to bind to.
     pub address: String,
     /// Port to bind to.
     pub port: u16,
     /// File to log to.
     pub log_file: Option<PathBuf>,
+    /// How to format log messages.
+    pub log_format: LogFormat,
     /// URL to connect to the database.
     pub database_uri: String,
     /// Where the site resources (styles, templates, ...) are.
@@ -226,6 +651,9 @@ pub struct GlobalConfig {
     pub upload_dir: PathBuf,
     /// Where the staff-added pages are.
     pub pages_dir: Option<PathBuf>,
+    /// The pages loaded from `pages_dir`.
+    #[serde(skip)]
+    pub pages: Vec<Page>,
     /// The path to a list of user names.
     #[serde(rename = "names")]
     pub names_path: Option<PathBuf>,
@@ -234,6 +662,10 @@ pub struct GlobalConfig {
     pub notice_path: Option<PathBuf>,
     /// Allow users to upload files.
     pub allow_uploads: bool,
+    /// Mark every uploaded file as a spoiler.
+    pub force_spoiler: bool,
+    /// Whether users may upload files that aren't marked as a spoiler.
+    pub spoiler_optional: bool,
     /// Allow these file types for file uploads.
     #[serde(deserialize_with = "de_allow_file_types")]
     pub allow_file_types: Vec<Mime>,
@@ -267,6 +699,211 @@ pub struct GlobalConfig {
     pub num_recent_posts: u32,
     /// The maximum number of threads per board.
     pub thread_limit: u32,
+    /// The maximum number of replies a thread can have before it is locked.
+    pub reply_cap: Option<u32>,
+    /// Warn users before creating a thread with a similar subject to an
+    /// existing thread.
+    pub dedup_threads: bool,
+    /// Allow users to create cyclical threads.
+    pub allow_cyclical: bool,
+    /// The Content-Security-Policy header to send with pages.
+    pub content_security_policy: String,
+    /// Require the first post in a thread to have a file.
+    pub op_requires_file: bool,
+    /// The hours during which users can post.
+    pub posting_hours: Option<PostingHours>,
+    /// Have the front-end web server serve files for us.
+    pub accel_redirect: Option<AccelRedirect>,
+    /// How long browsers should cache uploaded files.
+    #[serde(deserialize_with = "de_duration")]
+    pub upload_cache_age: Duration,
+    /// How long browsers should cache site resources, like styles and scripts.
+    #[serde(deserialize_with = "de_duration")]
+    pub resource_cache_age: Duration,
+    /// Where to move the files of posts deleted by staff members, instead of
+    /// leaving them in the upload directory.
+    pub quarantine_dir: Option<PathBuf>,
+    /// How many bits of an IPv6 address identify a user. Addresses in the same
+    /// network prefix are treated as the same user.
+    pub ipv6_prefix: u8,
+    /// How similar a post must be to a recent post to be rate limited,
+    /// from 0 to 1.
+    pub content_similarity: Option<f64>,
+    /// How staff roles are shown on posts.
+    #[serde(default)]
+    pub role_flair: RoleFlair,
+    /// How many files to show at a time in the gallery.
+    pub gallery_page_size: u32,
+    /// The most boards that can be created.
+    pub max_boards: Option<u32>,
+    /// The directory to store uploads in while they are being received. If not
+    /// set, the system temporary directory is used.
+    pub upload_temp_dir: Option<PathBuf>,
+    /// How long to keep reports after a staff member closes them. Reports that
+    /// haven't been closed are always kept.
+    #[serde(deserialize_with = "de_option_duration")]
+    pub report_retention: Option<Duration>,
+    /// How long to keep entries in the staff log.
+    #[serde(deserialize_with = "de_option_duration")]
+    pub log_retention: Option<Duration>,
+    /// The least time that must pass between loading a posting form and
+    /// submitting it.
+    #[serde(deserialize_with = "de_option_duration")]
+    pub min_form_time: Option<Duration>,
+    /// The secret used to sign the tokens in posting forms and of previewed
+    /// posts. If not set, one is generated and stored in the database.
+    pub form_secret: Option<String>,
+    /// The name of a hidden field in posting forms that must be left empty.
+    pub honeypot_field: Option<String>,
+    /// The least number of seconds between new threads on a board, across all
+    /// users.
+    pub min_seconds_between_threads: Option<u32>,
+    /// The HTML tags allowed in posts on boards that use sanitized HTML.
+    pub sanitizer_allowed_tags: Vec<String>,
+    /// The most lines a post can have.
+    pub max_lines: Option<u32>,
+    /// The most quote lines in a row a post can have.
+    pub max_quote_lines: Option<u32>,
+    /// Which view of a board is shown at the board's bare URI, by board name.
+    pub default_views: HashMap<String, DefaultView>,
+    /// HTML tags that are allowed in the notice and custom pages, mapped to the
+    /// attributes allowed on each. If this is empty, all HTML in them is
+    /// escaped.
+    pub page_allowed_html: HashMap<String, Vec<String>>,
+    /// Whether to show post and thread IDs prefixed with their board name, like
+    /// `/tech/#123`.
+    pub board_id_prefixes: bool,
+    /// The most lines a post can have before it's collapsed.
+    pub post_collapse_lines: Option<u32>,
+    /// The most characters a post can have before it's collapsed.
+    pub post_collapse_chars: Option<u32>,
+    /// The host names that links can be embedded from, on boards that allow
+    /// embeds.
+    pub embed_hosts: Vec<String>,
+    /// How long after making a post users can delete it with its password.
+    #[serde(deserialize_with = "de_option_duration")]
+    pub self_delete_window: Option<Duration>,
+    /// Whether posts from users who have no approved posts are held until staff
+    /// approve them.
+    pub hold_first_posts: bool,
+    /// The most threads a user can have open on a board, by board name. Boards
+    /// that aren't listed have no limit.
+    pub max_open_threads_per_user: HashMap<String, u32>,
+    /// Whether post references show the start of the referenced post when
+    /// hovered over.
+    pub ref_snippets: bool,
+    /// How lines of text in posts are separated.
+    pub line_break_mode: LineBreakMode,
+    /// Whether uploaded files are saved under a sanitized version of their
+    /// original name, instead of the time they were uploaded.
+    pub preserve_filename: bool,
+    /// Path to the icon shown in place of a thumbnail for non-media uploads.
+    #[serde(rename = "file_icon")]
+    pub file_icon_path: PathBuf,
+    /// How many replies should be displayed per page of a thread.
+    pub posts_per_thread_page: u32,
+    /// How many of the newest replies the "last posts" link on threads shows.
+    pub last_posts_count: u32,
+    /// Filter rules to apply to posts shown in board previews and the catalog.
+    pub preview_filter_rules: Vec<FilterRule>,
+    /// How threads are ordered on each board, by board name.
+    pub sort_modes: HashMap<String, SortMode>,
+    /// Whether logged in staff members skip the IP block lists and the user
+    /// rate limits when posting.
+    pub exempt_staff: bool,
+    /// Allow threads to be created without a subject.
+    pub subject_optional: bool,
+    /// The most characters of the first line of the first post used as the
+    /// subject of a thread made without one.
+    pub subject_fallback_chars: u32,
+    /// Path to a file of IP ranges to block, one per line in CIDR notation.
+    pub blocked_ranges_path: Option<PathBuf>,
+    /// The IP ranges loaded from `blocked_ranges_path`.
+    #[serde(skip)]
+    pub blocked_ranges: IpRanges,
+    /// The boards where posts must be previewed before they can be submitted.
+    pub require_preview: Vec<String>,
+    /// The `rel` attribute of links in posts. Links have no `rel` attribute if
+    /// this is empty.
+    pub link_rel: String,
+    /// The `target` attribute of links in posts. Links have no `target`
+    /// attribute if this is empty.
+    pub link_target: String,
+    /// The maximum number of uploads that can be sent to clients at once.
+    pub max_downloads: Option<u32>,
+    /// The maximum number of uploads that can be sent to a single user at once.
+    pub max_downloads_per_user: Option<u32>,
+    /// Whether crawlers are asked not to index pages on NSFW boards.
+    pub nsfw_noindex: bool,
+    /// Whether thumbnails on NSFW boards are blurred until they're hovered
+    /// over.
+    pub nsfw_blur_thumbnails: bool,
+    /// How long after a thread is made that another thread with the same
+    /// subject can't be made on the same board.
+    #[serde(deserialize_with = "de_option_duration")]
+    pub duplicate_subject_cooldown: Option<Duration>,
+    /// The size limit for the whole body of a post request. Defaults to enough
+    /// for a full-size file and the rest of the form.
+    #[serde(deserialize_with = "de_option_file_size_limit")]
+    pub request_size_limit: Option<u64>,
+    /// Whether to leave the preview image out of the link preview tags of
+    /// threads on NSFW boards.
+    pub nsfw_hide_share_image: bool,
+    /// The secret used to hash users' IP addresses. If not set, one is
+    /// generated and stored in the database.
+    pub hash_secret: Option<String>,
+    /// The largest width and height of the thumbnails shown in catalogs. If
+    /// not set, catalogs show the regular thumbnails.
+    pub catalog_thumb_size: Option<u32>,
+    /// The most pinned threads shown at the top of a board. Other pinned
+    /// threads are sorted in with the rest of the threads.
+    pub max_pinned_on_page: Option<u32>,
+    /// If set, posts with links from users with fewer than this many posts
+    /// are held or rejected, see `first_link_action`.
+    pub first_link_min_posts: Option<u32>,
+    /// What happens to posts caught by `first_link_min_posts`.
+    pub first_link_action: FirstLinkAction,
+    /// Whether thumbnails on board pages and catalogs are marked to be loaded
+    /// lazily, once they're scrolled into view.
+    pub lazy_thumbnails: bool,
+    /// If set, catalogs are laid out in this many columns, instead of as many
+    /// as fit on the screen.
+    pub catalog_columns: Option<u32>,
+    /// The maximum number of thumbnails that can be generated at once.
+    pub max_concurrent_thumbnails: Option<u32>,
+    /// The image format that new thumbnails are saved in.
+    pub thumbnail_format: ThumbnailFormat,
+    /// Whether to rotate and flip images according to their EXIF orientation
+    /// when making thumbnails.
+    pub auto_orient_images: bool,
+    /// Whether to check that all of the templates exist when starting up.
+    pub check_templates: bool,
+    /// Links in posts that are longer than this are shown shortened, down to
+    /// their host and the start of their path.
+    pub link_display_chars: Option<u32>,
+    /// Whether users who post with JSON responses are given a token which can
+    /// delete the post in place of a delete password.
+    pub delete_tokens: bool,
+    /// HTML added to the end of the `<head>` of every page. This is trusted and
+    /// shown as it is, so it must only ever come from the site's admins.
+    pub extra_head_html: Option<String>,
+    /// HTML added to the end of the `<body>` of every page. This is trusted and
+    /// shown as it is, so it must only ever come from the site's admins.
+    pub extra_footer_html: Option<String>,
+    /// Whether `extra_head_html` and `extra_footer_html` are added to pages.
+    pub allow_extra_html: bool,
+    /// Whether requests for pages at paths that aren't canonical, like
+    /// `/Tech/123/`, are redirected to their canonical path.
+    pub canonical_redirects: bool,
+    /// Whether uploaded videos of the types in `transcode_types` are converted
+    /// with ffmpeg to a format that browsers can play.
+    pub transcode_uploads: bool,
+    /// The formats that uploaded videos are converted to, by their content
+    /// type. See `transcode_uploads`.
+    pub transcode_types: HashMap<String, TranscodeFormat>,
+    /// Whether the original files of converted uploads are kept in the upload
+    /// directory.
+    pub keep_transcoded_originals: bool,
 }
 
 impl GlobalConfig {
@@ -282,7 +919,7 @@ impl GlobalConfig {
             msg: format!("Couldn't open config file at {}", path.display()),
         })?;
 
-        let conf: GlobalConfig =
+        let mut conf: GlobalConfig =
             serde_yaml::from_reader(file).map_err(Error::from)?;
 
         if !conf.resource_dir.exists() {
@@ -299,6 +936,32 @@ impl GlobalConfig {
             });
         }
 
+        if conf.ipv6_prefix > 128 {
+            return Err(Error::InvalidIpv6Prefix {
+                prefix: conf.ipv6_prefix,
+            });
+        }
+
+        conf.check_values()?;
+
+        if let Some(path) = &conf.quarantine_dir {
+            if !path.exists() {
+                return Err(Error::ConfigPathNotFound {
+                    description: "quarantine directory".to_string(),
+                    path: path.display().to_string(),
+                });
+            }
+        }
+
+        if let Some(path) = &conf.upload_temp_dir {
+            if !path.exists() {
+                return Err(Error::ConfigPathNotFound {
+                    description: "upload temp directory".to_string(),
+                    path: path.display().to_string(),
+                });
+            }
+        }
+
         if let Some(path) = &conf.pages_dir {
             if !path.exists() {
                 return Err(Error::ConfigPathNotFound {
@@ -306,6 +969,8 @@ impl GlobalConfig {
                     path: path.display().to_string(),
                 });
             }
+
+            conf.pages = load_pages(path)?;
         }
 
         if let Some(path) = &conf.names_path {
@@ -326,9 +991,36 @@ impl GlobalConfig {
             }
         }
 
+        if let Some(path) = &conf.blocked_ranges_path {
+            conf.blocked_ranges = IpRanges::load(path)?;
+        }
+
         Ok(conf)
     }
 
+    /// Check options whose values can't be checked by their type alone.
+    fn check_values(&self) -> Result<()> {
+        if self.max_concurrent_thumbnails == Some(0) {
+            return Err(Error::InvalidConfigValue {
+                option: "max_concurrent_thumbnails".into(),
+                reason: "no uploads could be made if it's 0".into(),
+            });
+        }
+
+        if let Some(url) = &self.base_url {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(Error::InvalidConfigValue {
+                    option: "base_url".into(),
+                    reason: "it must start with https:// or http://".into(),
+                });
+            }
+        }
+
+        check_content_similarity(self.content_similarity)?;
+
+        Ok(())
+    }
+
     /// Get the default location of the config file.
     pub fn default_path() -> PathBuf {
         if cfg!(debug_assertions) {
@@ -353,18 +1045,23 @@ impl Default for GlobalConfig {
         if cfg!(debug_assertions) {
             GlobalConfig {
                 site_name: "LONGBOARD".into(),
+                base_url: None,
                 favicon_path: PathBuf::from("res/favicon.png"),
                 address: "0.0.0.0".into(),
                 port: 8000,
                 resource_dir: PathBuf::from("res"),
                 upload_dir: PathBuf::from("uploads"),
                 pages_dir: None,
+                pages: Vec::new(),
                 database_uri: "postgres://longboard:@localhost/longboard"
                     .into(),
                 log_file: None,
+                log_format: LogFormat::default(),
                 names_path: None,
                 notice_path: None,
                 allow_uploads: false,
+                force_spoiler: false,
+                spoiler_optional: true,
                 allow_file_types: Vec::new(),
                 filter_rules: Vec::new(),
                 custom_styles: Vec::new(),
@@ -379,10 +1076,88 @@ impl Default for GlobalConfig {
                 num_recent_files: 4,
                 num_recent_posts: 6,
                 thread_limit: 36,
+                reply_cap: None,
+                dedup_threads: false,
+                allow_cyclical: false,
+                content_security_policy: DEFAULT_CSP.into(),
+                op_requires_file: true,
+                posting_hours: None,
+                accel_redirect: None,
+                upload_cache_age: Duration::days(365),
+                resource_cache_age: Duration::zero(),
+                quarantine_dir: None,
+                ipv6_prefix: 64,
+                content_similarity: None,
+                role_flair: RoleFlair::default(),
+                gallery_page_size: 48,
+                max_boards: None,
+                upload_temp_dir: None,
+                report_retention: None,
+                log_retention: None,
+                min_form_time: None,
+                form_secret: None,
+                honeypot_field: None,
+                min_seconds_between_threads: None,
+                sanitizer_allowed_tags: default_sanitizer_allowed_tags(),
+                max_lines: None,
+                max_quote_lines: None,
+                default_views: HashMap::new(),
+                page_allowed_html: HashMap::new(),
+                board_id_prefixes: false,
+                post_collapse_lines: None,
+                post_collapse_chars: None,
+                embed_hosts: default_embed_hosts(),
+                self_delete_window: None,
+                hold_first_posts: false,
+                max_open_threads_per_user: HashMap::new(),
+                ref_snippets: false,
+                line_break_mode: LineBreakMode::default(),
+                preserve_filename: false,
+                file_icon_path: PathBuf::from("res/file-icon.png"),
+                posts_per_thread_page: 200,
+                last_posts_count: 50,
+                preview_filter_rules: Vec::new(),
+                sort_modes: HashMap::new(),
+                exempt_staff: false,
+                subject_optional: false,
+                subject_fallback_chars: 60,
+                blocked_ranges_path: None,
+                blocked_ranges: IpRanges::default(),
+                require_preview: Vec::new(),
+                link_rel: "nofollow noopener".to_string(),
+                link_target: "_blank".to_string(),
+                max_downloads: None,
+                max_downloads_per_user: None,
+                nsfw_noindex: false,
+                nsfw_blur_thumbnails: false,
+                duplicate_subject_cooldown: None,
+                request_size_limit: None,
+                nsfw_hide_share_image: false,
+                hash_secret: None,
+                catalog_thumb_size: None,
+                max_pinned_on_page: None,
+                first_link_min_posts: None,
+                first_link_action: FirstLinkAction::default(),
+                lazy_thumbnails: true,
+                catalog_columns: None,
+                max_concurrent_thumbnails: None,
+                thumbnail_format: ThumbnailFormat::default(),
+                auto_orient_images: true,
+                check_templates: true,
+                link_display_chars: None,
+                delete_tokens: false,
+                extra_head_html: None,
+                extra_footer_html: None,
+                allow_extra_html: true,
+                canonical_redirects: true,
+                transcode_uploads: false,
+                transcode_types: default_transcode_types(),
+                keep_transcoded_originals: false,
             }
         } else {
             GlobalConfig {
                 site_name: "LONGBOARD".into(),
+                base_url: None,
                 favicon_path: PathBuf::from(datadir)
                     .join("longboard")
                     .join("favicon.png"),
@@ -391,6 +1166,7 @@ impl Default for GlobalConfig {
                 resource_dir: PathBuf::from(datadir).join("longboard"),
                 upload_dir: PathBuf::from(persistdir).join("longboard"),
                 pages_dir: None,
+                pages: Vec::new(),
                 database_uri: "postgres://longboard:@localhost/longboard"
                     .into(),
                 log_file: Some(
@@ -398,9 +1174,12 @@ impl Default for GlobalConfig {
                         .join("longboard")
                         .join("longboard.log"),
                 ),
+                log_format: LogFormat::default(),
                 names_path: None,
                 notice_path: None,
                 allow_uploads: false,
+                force_spoiler: false,
+                spoiler_optional: true,
                 allow_file_types: Vec::new(),
                 filter_rules: Vec::new(),
                 custom_styles: Vec::new(),
@@ -415,11 +1194,119 @@ impl Default for GlobalConfig {
                 num_recent_files: 4,
                 num_recent_posts: 6,
                 thread_limit: 36,
+                reply_cap: None,
+                dedup_threads: false,
+                allow_cyclical: false,
+                content_security_policy: DEFAULT_CSP.into(),
+                op_requires_file: true,
+                posting_hours: None,
+                accel_redirect: None,
+                upload_cache_age: Duration::days(365),
+                resource_cache_age: Duration::hours(1),
+                quarantine_dir: None,
+                ipv6_prefix: 64,
+                content_similarity: None,
+                role_flair: RoleFlair::default(),
+                gallery_page_size: 48,
+                max_boards: None,
+                upload_temp_dir: None,
+                report_retention: None,
+                log_retention: None,
+                min_form_time: None,
+                form_secret: None,
+                honeypot_field: None,
+                min_seconds_between_threads: None,
+                sanitizer_allowed_tags: default_sanitizer_allowed_tags(),
+                max_lines: None,
+                max_quote_lines: None,
+                default_views: HashMap::new(),
+                page_allowed_html: HashMap::new(),
+                board_id_prefixes: false,
+                post_collapse_lines: None,
+                post_collapse_chars: None,
+                embed_hosts: default_embed_hosts(),
+                self_delete_window: None,
+                hold_first_posts: false,
+                max_open_threads_per_user: HashMap::new(),
+                ref_snippets: false,
+                line_break_mode: LineBreakMode::default(),
+                preserve_filename: false,
+                file_icon_path: PathBuf::from(datadir)
+                    .join("longboard")
+                    .join("file-icon.png"),
+                posts_per_thread_page: 200,
+                last_posts_count: 50,
+                preview_filter_rules: Vec::new(),
+                sort_modes: HashMap::new(),
+                exempt_staff: false,
+                subject_optional: false,
+                subject_fallback_chars: 60,
+                blocked_ranges_path: None,
+                blocked_ranges: IpRanges::default(),
+                require_preview: Vec::new(),
+                link_rel: "nofollow noopener".to_string(),
+                link_target: "_blank".to_string(),
+                max_downloads: None,
+                max_downloads_per_user: None,
+                nsfw_noindex: false,
+                nsfw_blur_thumbnails: false,
+                duplicate_subject_cooldown: None,
+                request_size_limit: None,
+                nsfw_hide_share_image: false,
+                hash_secret: None,
+                catalog_thumb_size: None,
+                max_pinned_on_page: None,
+                first_link_min_posts: None,
+                first_link_action: FirstLinkAction::default(),
+                lazy_thumbnails: true,
+                catalog_columns: None,
+                max_concurrent_thumbnails: None,
+                thumbnail_format: ThumbnailFormat::default(),
+                auto_orient_images: true,
+                check_templates: true,
+                link_display_chars: None,
+                delete_tokens: false,
+                extra_head_html: None,
+                extra_footer_html: None,
+                allow_extra_html: true,
+                canonical_redirects: true,
+                transcode_uploads: false,
+                transcode_types: default_transcode_types(),
+                keep_transcoded_originals: false,
             }
         }
     }
 }
 
+/// The HTML tags allowed in sanitized HTML posts by default.
+fn default_sanitizer_allowed_tags() -> Vec<String> {
+    [
+        "a", "b", "blockquote", "br", "code", "del", "em", "h1", "h2", "h3",
+        "hr", "i", "li", "ol", "p", "pre", "s", "strong", "sub", "sup", "u",
+        "ul",
+    ]
+    .iter()
+    .map(ToString::to_string)
+    .collect()
+}
+
+/// The host names that links can be embedded from by default.
+fn default_embed_hosts() -> Vec<String> {
+    ["youtube.com", "www.youtube.com", "youtu.be", "vimeo.com"]
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Generate a random secret, for when one isn't configured.
+fn random_secret() -> String {
+    thread_rng()
+        .sample_iter(rand::distributions::Alphanumeric)
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
 /// Helper for deserializing durations.
 fn de_duration<'de, D>(de: D) -> std::result::Result<Duration, D::Error>
 where
@@ -467,8 +1354,15 @@ pub struct ExtensionConfig {
     /// The name of this extension.
     #[serde(skip)]
     pub name: String,
+    /// The board to redirect to from the home page.
+    pub home_redirect: Option<String>,
+    /// Where this extension's page banners are.
+    pub banner_dir: Option<PathBuf>,
     /// Where the staff-added pages are.
     pub pages_dir: Option<PathBuf>,
+    /// The pages loaded from `pages_dir`.
+    #[serde(skip)]
+    pub pages: Vec<Page>,
     /// The path to a list of user names.
     #[serde(rename = "names")]
     pub names_path: Option<PathBuf>,
@@ -477,6 +1371,10 @@ pub struct ExtensionConfig {
     pub notice_path: Option<PathBuf>,
     /// Whether to allow user file uploads.
     pub allow_uploads: Option<bool>,
+    /// Whether to mark every uploaded file as a spoiler.
+    pub force_spoiler: Option<bool>,
+    /// Whether users may upload files that aren't marked as a spoiler.
+    pub spoiler_optional: Option<bool>,
     /// Allowed file types for file uploads.
     #[serde(deserialize_with = "de_option_allow_file_types")]
     pub allow_file_types: Option<Vec<Mime>>,
@@ -500,6 +1398,115 @@ pub struct ExtensionConfig {
     pub block_list: Option<Vec<IpAddr>>,
     /// The list of DNSBLs to use.
     pub dns_block_list: Option<Vec<String>>,
+    /// The maximum number of replies a thread can have before it is locked.
+    pub reply_cap: Option<u32>,
+    /// Whether to warn users before creating a thread with a similar subject
+    /// to an existing thread.
+    pub dedup_threads: Option<bool>,
+    /// Whether to allow users to create cyclical threads.
+    pub allow_cyclical: Option<bool>,
+    /// Whether to require the first post in a thread to have a file.
+    pub op_requires_file: Option<bool>,
+    /// The hours during which users can post.
+    pub posting_hours: Option<PostingHours>,
+    /// How similar a post must be to a recent post to be rate limited,
+    /// from 0 to 1.
+    pub content_similarity: Option<f64>,
+    /// The least time that must pass between loading a posting form and
+    /// submitting it.
+    #[serde(deserialize_with = "de_option_duration")]
+    pub min_form_time: Option<Duration>,
+    /// The name of a hidden field in posting forms that must be left empty.
+    pub honeypot_field: Option<String>,
+    /// The least number of seconds between new threads on a board, across all
+    /// users.
+    pub min_seconds_between_threads: Option<u32>,
+    /// The most lines a post can have.
+    pub max_lines: Option<u32>,
+    /// The most quote lines in a row a post can have.
+    pub max_quote_lines: Option<u32>,
+    /// Which view of a board is shown at the board's bare URI, by board name.
+    pub default_views: Option<HashMap<String, DefaultView>>,
+    /// The most lines a post can have before it's collapsed.
+    pub post_collapse_lines: Option<u32>,
+    /// The most characters a post can have before it's collapsed.
+    pub post_collapse_chars: Option<u32>,
+    /// How long after making a post users can delete it with its password.
+    #[serde(deserialize_with = "de_option_duration")]
+    pub self_delete_window: Option<Duration>,
+    /// Whether posts from users who have no approved posts are held until staff
+    /// approve them.
+    pub hold_first_posts: Option<bool>,
+    /// The most threads a user can have open on a board, by board name. Boards
+    /// that aren't listed have no limit.
+    pub max_open_threads_per_user: Option<HashMap<String, u32>>,
+    /// Whether post references show the start of the referenced post when
+    /// hovered over.
+    pub ref_snippets: Option<bool>,
+    /// How lines of text in posts are separated.
+    pub line_break_mode: Option<LineBreakMode>,
+    /// Whether uploaded files are saved under a sanitized version of their
+    /// original name, instead of the time they were uploaded.
+    pub preserve_filename: Option<bool>,
+    /// Filter rules to apply to posts shown in board previews and the catalog.
+    pub preview_filter_rules: Option<Vec<FilterRule>>,
+    /// How threads are ordered on each board, by board name.
+    pub sort_modes: Option<HashMap<String, SortMode>>,
+    /// Whether to allow threads to be created without a subject.
+    pub subject_optional: Option<bool>,
+    /// The most characters of the first line of the first post used as the
+    /// subject of a thread made without one.
+    pub subject_fallback_chars: Option<u32>,
+    /// The boards where posts must be previewed before they can be submitted.
+    pub require_preview: Option<Vec<String>>,
+    /// The `rel` attribute of links in posts. Links have no `rel` attribute if
+    /// this is empty.
+    pub link_rel: Option<String>,
+    /// The `target` attribute of links in posts. Links have no `target`
+    /// attribute if this is empty.
+    pub link_target: Option<String>,
+    /// Whether crawlers are asked not to index pages on NSFW boards.
+    pub nsfw_noindex: Option<bool>,
+    /// Whether thumbnails on NSFW boards are blurred until they're hovered
+    /// over.
+    pub nsfw_blur_thumbnails: Option<bool>,
+    /// How long after a thread is made that another thread with the same
+    /// subject can't be made on the same board.
+    #[serde(deserialize_with = "de_option_duration")]
+    pub duplicate_subject_cooldown: Option<Duration>,
+    /// The size limit for the whole body of a post request. Defaults to enough
+    /// for a full-size file and the rest of the form.
+    #[serde(deserialize_with = "de_option_file_size_limit")]
+    pub request_size_limit: Option<u64>,
+    /// Whether to leave the preview image out of the link preview tags of
+    /// threads on NSFW boards.
+    pub nsfw_hide_share_image: Option<bool>,
+    /// The most pinned threads shown at the top of a board. Other pinned
+    /// threads are sorted in with the rest of the threads.
+    pub max_pinned_on_page: Option<u32>,
+    /// If set, posts with links from users with fewer than this many posts
+    /// are held or rejected, see `first_link_action`.
+    pub first_link_min_posts: Option<u32>,
+    /// What happens to posts caught by `first_link_min_posts`.
+    pub first_link_action: Option<FirstLinkAction>,
+    /// Whether thumbnails on board pages and catalogs are marked to be loaded
+    /// lazily, once they're scrolled into view.
+    pub lazy_thumbnails: Option<bool>,
+    /// If set, catalogs are laid out in this many columns, instead of as many
+    /// as fit on the screen.
+    pub catalog_columns: Option<u32>,
+    /// Links in posts that are longer than this are shown shortened, down to
+    /// their host and the start of their path.
+    pub link_display_chars: Option<u32>,
+    /// Whether users who post with JSON responses are given a token which can
+    /// delete the post in place of a delete password.
+    pub delete_tokens: Option<bool>,
+    /// HTML added to the end of the `<head>` of every page. This is trusted and
+    /// shown as it is, so it must only ever come from the site's admins.
+    pub extra_head_html: Option<String>,
+    /// HTML added to the end of the `<body>` of every page. This is trusted and
+    /// shown as it is, so it must only ever come from the site's admins.
+    pub extra_footer_html: Option<String>,
 }
 
 impl ExtensionConfig {
@@ -524,6 +1531,15 @@ impl ExtensionConfig {
 
         extension.name = name;
 
+        if let Some(path) = &extension.banner_dir {
+            if !path.is_dir() {
+                return Err(Error::ConfigPathNotFound {
+                    description: "banner dir".to_string(),
+                    path: path.display().to_string(),
+                });
+            }
+        }
+
         if let Some(path) = &extension.pages_dir {
             if !path.exists() {
                 return Err(Error::ConfigPathNotFound {
@@ -531,8 +1547,12 @@ impl ExtensionConfig {
                     path: path.display().to_string(),
                 });
             }
+
+            extension.pages = load_pages(path)?;
         }
 
+        check_content_similarity(extension.content_similarity)?;
+
         if let Some(path) = &extension.names_path {
             if !path.exists() {
                 return Err(Error::ConfigPathNotFound {
@@ -571,10 +1591,15 @@ impl Default for ExtensionConfig {
     fn default() -> ExtensionConfig {
         ExtensionConfig {
             name: String::new(),
+            home_redirect: None,
+            banner_dir: None,
             pages_dir: None,
+            pages: Vec::new(),
             names_path: None,
             notice_path: None,
             allow_uploads: None,
+            force_spoiler: None,
+            spoiler_optional: None,
             allow_file_types: None,
             file_size_limit: None,
             rate_limit_same_user: None,
@@ -584,6 +1609,47 @@ impl Default for ExtensionConfig {
             allow_list: None,
             block_list: None,
             dns_block_list: None,
+            reply_cap: None,
+            dedup_threads: None,
+            allow_cyclical: None,
+            op_requires_file: None,
+            posting_hours: None,
+            content_similarity: None,
+            min_form_time: None,
+            honeypot_field: None,
+            min_seconds_between_threads: None,
+            max_lines: None,
+            max_quote_lines: None,
+            default_views: None,
+            post_collapse_lines: None,
+            post_collapse_chars: None,
+            self_delete_window: None,
+            hold_first_posts: None,
+            max_open_threads_per_user: None,
+            ref_snippets: None,
+            line_break_mode: None,
+            preserve_filename: None,
+            preview_filter_rules: None,
+            sort_modes: None,
+            subject_optional: None,
+            subject_fallback_chars: None,
+            require_preview: None,
+            link_rel: None,
+            link_target: None,
+            nsfw_noindex: None,
+            nsfw_blur_thumbnails: None,
+            duplicate_subject_cooldown: None,
+            request_size_limit: None,
+            nsfw_hide_share_image: None,
+            max_pinned_on_page: None,
+            first_link_min_posts: None,
+            first_link_action: None,
+            lazy_thumbnails: None,
+            catalog_columns: None,
+            link_display_chars: None,
+            delete_tokens: None,
+            extra_head_html: None,
+            extra_footer_html: None,
         }
     }
 }
@@ -642,25 +1708,676 @@ pub struct FilterRule {
     pub replace_with: String,
 }
 
+/// How a staff role is shown on posts.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Flair {
+    /// The text shown as the post's identity.
+    pub text: String,
+    /// The CSS class given to the post's identity.
+    pub class: String,
+}
+
+impl Flair {
+    fn new<S1, S2>(text: S1, class: S2) -> Flair
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        Flair {
+            text: text.into(),
+            class: class.into(),
+        }
+    }
+}
+
+/// Which view of a board is shown at the board's bare URI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultView {
+    /// The paginated list of threads.
+    #[default]
+    Board,
+    /// The catalog.
+    Catalog,
+}
+
+/// How the threads on a board are ordered.
+///
+/// Pinned threads come first either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortMode {
+    /// Threads that were bumped most recently come first.
+    #[default]
+    Bump,
+    /// Threads that were created most recently come first.
+    Created,
+}
+
+/// How log messages are formatted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines, colored when logging to a terminal.
+    #[default]
+    Text,
+    /// One JSON object per line, for log aggregators.
+    Json,
+}
+
+/// The image format that thumbnails are saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailFormat {
+    /// Supported by every image library build.
+    #[default]
+    Png,
+    /// Smaller than PNG, but needs an image library built with WebP encoding.
+    Webp,
+}
+
+impl ThumbnailFormat {
+    /// Every thumbnail format.
+    pub const ALL: [ThumbnailFormat; 2] =
+        [ThumbnailFormat::Png, ThumbnailFormat::Webp];
+
+    /// The file extension of thumbnails in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Png => "png",
+            ThumbnailFormat::Webp => "webp",
+        }
+    }
+}
+
+/// A format that uploaded videos can be converted to. See
+/// `transcode_uploads`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscodeFormat {
+    /// H.264 video and AAC audio in an MP4 container.
+    Mp4,
+    /// VP9 video and Opus audio in a WebM container.
+    Webm,
+}
+
+impl TranscodeFormat {
+    /// The file extension of videos in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            TranscodeFormat::Mp4 => "mp4",
+            TranscodeFormat::Webm => "webm",
+        }
+    }
+
+    /// The content type of videos in this format.
+    pub fn content_type(self) -> Mime {
+        match self {
+            TranscodeFormat::Mp4 => "video/mp4".parse().unwrap(),
+            TranscodeFormat::Webm => "video/webm".parse().unwrap(),
+        }
+    }
+
+    /// The arguments that tell ffmpeg to encode video in this format.
+    pub fn ffmpeg_args(self) -> &'static [&'static str] {
+        match self {
+            TranscodeFormat::Mp4 => &[
+                "-c:v",
+                "libx264",
+                "-pix_fmt",
+                "yuv420p",
+                "-c:a",
+                "aac",
+                "-movflags",
+                "+faststart",
+            ],
+            TranscodeFormat::Webm => &["-c:v", "libvpx-vp9", "-c:a", "libopus"],
+        }
+    }
+}
+
+/// The formats that uploaded videos are converted to by default. Videos from
+/// phones are often QuickTime files, which most browsers can't play.
+fn default_transcode_types() -> HashMap<String, TranscodeFormat> {
+    HashMap::from([(String::from("video/quicktime"), TranscodeFormat::Mp4)])
+}
+
+/// What happens to a post with links from a user with too few posts. See
+/// `first_link_min_posts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirstLinkAction {
+    /// The post is held until staff approve it.
+    #[default]
+    Hold,
+    /// The post is rejected.
+    Reject,
+}
+
+/// The flair for each staff role.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RoleFlair {
+    pub janitor: Flair,
+    pub moderator: Flair,
+    pub administrator: Flair,
+}
+
+impl RoleFlair {
+    /// Get the flair for a role.
+    pub fn get(&self, role: Role) -> &Flair {
+        match role {
+            Role::Janitor => &self.janitor,
+            Role::Moderator => &self.moderator,
+            Role::Administrator => &self.administrator,
+        }
+    }
+}
+
+impl Default for RoleFlair {
+    fn default() -> Self {
+        RoleFlair {
+            janitor: Flair::new("Janitor", "role-janitor"),
+            moderator: Flair::new("Moderator", "role-moderator"),
+            administrator: Flair::new("Administrator", "role-administrator"),
+        }
+    }
+}
+
+/// How to have the front-end web server serve files for us.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccelRedirect {
+    /// Which header to send.
+    pub header: AccelRedirectHeader,
+    /// The internal location that uploads are served from, for
+    /// `X-Accel-Redirect`.
+    #[serde(default = "default_upload_location")]
+    pub upload_location: String,
+    /// The internal location that site resources are served from, for
+    /// `X-Accel-Redirect`.
+    #[serde(default = "default_resource_location")]
+    pub resource_location: String,
+}
+
+/// A header for having the front-end web server serve a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccelRedirectHeader {
+    /// `X-Accel-Redirect`, used by nginx. The header is set to a URI under an
+    /// internal location.
+    Nginx,
+    /// `X-Sendfile`, used by Apache and lighttpd. The header is set to the
+    /// path of the file.
+    Apache,
+}
+
+fn default_upload_location() -> String {
+    String::from("/internal/upload/")
+}
+
+fn default_resource_location() -> String {
+    String::from("/internal/resource/")
+}
+
+/// The hours during which users can post.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostingHours {
+    /// The UTC offset that the hours are given in.
+    #[serde(default = "utc_offset_zero", deserialize_with = "de_utc_offset")]
+    pub utc_offset: FixedOffset,
+    /// The ranges of time during which posting is open.
+    #[serde(deserialize_with = "de_time_ranges")]
+    pub open: Vec<TimeRange>,
+}
+
+impl PostingHours {
+    /// Check whether posting is open at the given time.
+    pub fn is_open(&self, now: DateTime<Utc>) -> bool {
+        let time = now.with_timezone(&self.utc_offset).time();
+
+        self.open.iter().any(|range| range.contains(time))
+    }
+
+    /// Get the next time after `now` that posting opens.
+    pub fn next_open(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<FixedOffset>> {
+        let now = now.with_timezone(&self.utc_offset);
+
+        self.open
+            .iter()
+            .filter_map(|range| {
+                let naive = now.date_naive().and_time(range.start);
+                let mut start =
+                    self.utc_offset.from_local_datetime(&naive).single()?;
+
+                if start <= now {
+                    start = start + Duration::days(1);
+                }
+
+                Some(start)
+            })
+            .min()
+    }
+}
+
+/// A range of time during a day.
+///
+/// If the end of the range is before the start, the range wraps around
+/// midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeRange {
+    /// Check whether a time falls within this range.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// Parse a time range like "08:00-17:30".
+fn parse_time_range<S>(range: S) -> std::result::Result<TimeRange, String>
+where
+    S: AsRef<str>,
+{
+    let range = range.as_ref();
+
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| format!("expected time range, got '{}'", range))?;
+
+    let parse_time = |time: &str| {
+        NaiveTime::parse_from_str(time.trim(), "%H:%M")
+            .map_err(|err| format!("invalid time '{}': {}", time, err))
+    };
+
+    Ok(TimeRange {
+        start: parse_time(start)?,
+        end: parse_time(end)?,
+    })
+}
+
+/// Parse a UTC offset like "+02:00" or "-05:30".
+pub(crate) fn parse_utc_offset<S>(
+    offset: S,
+) -> std::result::Result<FixedOffset, String>
+where
+    S: AsRef<str>,
+{
+    let re = Regex::new("^([+-])(\\d{2}):(\\d{2})$").unwrap();
+
+    let offset = offset.as_ref();
+
+    let captures = re
+        .captures(offset)
+        .ok_or_else(|| format!("expected UTC offset, got '{}'", offset))?;
+
+    let time = NaiveTime::parse_from_str(
+        &format!("{}:{}", &captures[2], &captures[3]),
+        "%H:%M",
+    )
+    .map_err(|err| format!("invalid UTC offset: {}", err))?;
+
+    let seconds: i32 = time.num_seconds_from_midnight().try_into().unwrap();
+
+    let offset = match &captures[1] {
+        "+" => FixedOffset::east_opt(seconds),
+        _ => FixedOffset::west_opt(seconds),
+    };
+
+    offset.ok_or(String::from("UTC offset out of range"))
+}
+
+/// The default UTC offset for posting hours.
+pub(crate) fn utc_offset_zero() -> FixedOffset {
+    FixedOffset::east_opt(0).unwrap()
+}
+
+/// Helper for deserializing UTC offsets.
+fn de_utc_offset<'de, D>(de: D) -> std::result::Result<FixedOffset, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(de).and_then(|offset| {
+        parse_utc_offset(offset).map_err(serde::de::Error::custom)
+    })
+}
+
+/// Helper for deserializing time ranges.
+fn de_time_ranges<'de, D>(
+    de: D,
+) -> std::result::Result<Vec<TimeRange>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(de).and_then(|ranges| {
+        ranges
+            .into_iter()
+            .map(parse_time_range)
+            .collect::<std::result::Result<_, String>>()
+            .map_err(serde::de::Error::custom)
+    })
+}
+
+/// A set of IP address ranges, which can be quickly checked for an address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpRanges {
+    /// IPv4 ranges as their first and last address, sorted and without any
+    /// overlaps.
+    v4: Vec<(u32, u32)>,
+    /// IPv6 ranges, like `v4`.
+    v6: Vec<(u128, u128)>,
+}
+
+impl IpRanges {
+    /// Load IP ranges from a file with one range per line, in CIDR notation
+    /// like "192.0.2.0/24" or "2001:db8::/32". A lone address is a range of
+    /// one address. Blank lines and lines starting with "#" are skipped.
+    pub fn load<P>(path: P) -> Result<IpRanges>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        let contents =
+            read_to_string(path).map_err(|cause| Error::IoErrorMsg {
+                cause,
+                msg: format!(
+                    "Couldn't open blocked ranges file at {}",
+                    path.display()
+                ),
+            })?;
+
+        IpRanges::parse(contents).map_err(|range| Error::InvalidIpRange {
+            range,
+            path: path.display().to_string(),
+        })
+    }
+
+    /// Parse IP ranges in the format of `IpRanges::load`. If a range can't be
+    /// parsed, it's returned as the error.
+    pub fn parse<S>(list: S) -> std::result::Result<IpRanges, String>
+    where
+        S: AsRef<str>,
+    {
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+
+        for line in list.as_ref().lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (addr, prefix) = match line.split_once('/') {
+                Some((addr, prefix)) => (addr, Some(prefix)),
+                None => (line, None),
+            };
+
+            let addr: IpAddr = addr.parse().map_err(|_| line.to_string())?;
+            let prefix: Option<u32> = prefix
+                .map(|prefix| prefix.parse().map_err(|_| line.to_string()))
+                .transpose()?;
+
+            match addr {
+                IpAddr::V4(addr) => {
+                    let prefix = prefix.unwrap_or(32);
+                    if prefix > 32 {
+                        return Err(line.to_string());
+                    }
+
+                    let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                    let start = u32::from(addr) & mask;
+                    v4.push((start, start | !mask));
+                }
+                IpAddr::V6(addr) => {
+                    let prefix = prefix.unwrap_or(128);
+                    if prefix > 128 {
+                        return Err(line.to_string());
+                    }
+
+                    let mask =
+                        u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                    let start = u128::from(addr) & mask;
+                    v6.push((start, start | !mask));
+                }
+            }
+        }
+
+        Ok(IpRanges {
+            v4: merge_ranges(v4),
+            v6: merge_ranges(v6),
+        })
+    }
+
+    /// Check whether an address is in any of the ranges. IPv4 addresses mapped
+    /// to IPv6 are checked against the IPv4 ranges.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6_addr) => v6_addr
+                .to_ipv4_mapped()
+                .map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+
+        match ip {
+            IpAddr::V4(v4_addr) => ranges_contain(&self.v4, v4_addr.into()),
+            IpAddr::V6(v6_addr) => ranges_contain(&self.v6, v6_addr.into()),
+        }
+    }
+}
+
+/// Sort ranges and merge the ones that overlap.
+fn merge_ranges<T>(mut ranges: Vec<(T, T)>) -> Vec<(T, T)>
+where
+    T: Ord + Copy,
+{
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(T, T)> = Vec::with_capacity(ranges.len());
+
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+}
+
+/// Check whether sorted, non-overlapping ranges contain an address.
+fn ranges_contain<T>(ranges: &[(T, T)], addr: T) -> bool
+where
+    T: Ord + Copy,
+{
+    let after = ranges.partition_point(|&(start, _)| start <= addr);
+
+    after > 0 && addr <= ranges[after - 1].1
+}
+
 /// A banner to be displayed at the top of the page.
 #[derive(Debug, Clone, Deserialize)]
 #[serde(transparent)]
 pub struct Banner {
     pub name: String,
+    /// The extension whose banner directory the banner is in, if it isn't a
+    /// global banner.
+    #[serde(skip)]
+    pub extension: Option<String>,
 }
 
 impl Banner {
     /// The URI of the banner.
     pub fn uri(&self) -> String {
-        uri!(crate::routes::banner: PathBuf::from(&self.name)).to_string()
+        let path = PathBuf::from(&self.name);
+
+        match &self.extension {
+            Some(extension) => uri!(
+                crate::routes::extension_banner: extension.as_str(),
+                path
+            )
+            .to_string(),
+            None => uri!(crate::routes::banner: path).to_string(),
+        }
     }
 }
 
+/// Read the banners in `dir`, which belong to `extension` if it's set.
+fn read_banners(dir: &Path, extension: Option<&str>) -> Result<Vec<Banner>> {
+    let iter = read_dir(dir).map_err(|cause| Error::IoErrorMsg {
+        cause,
+        msg: format!("Couldn't open banners dir at {}", dir.display()),
+    })?;
+
+    let mut banners = Vec::new();
+
+    for entry in iter {
+        banners.push(Banner {
+            name: entry?.file_name().into_string().unwrap(),
+            extension: extension.map(ToString::to_string),
+        });
+    }
+
+    Ok(banners)
+}
+
 /// A custom page.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Page {
     pub name: String,
     pub path: PathBuf,
+    /// The title of the page.
+    pub title: String,
+    /// Where the page goes in the page footer.
+    pub order: i32,
+}
+
+/// Metadata for a custom page, given in a YAML front-matter block at the
+/// start of the page.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct PageMeta {
+    /// The title of the page. Defaults to the page's file name.
+    pub title: Option<String>,
+    /// Where the page goes in the page footer. Pages are sorted by their
+    /// order, then by their title.
+    pub order: i32,
+    /// Whether to leave the page out of the page footer.
+    pub hidden: bool,
+}
+
+/// Check that a `content_similarity` threshold is a similarity from 0 to 1.
+fn check_content_similarity(threshold: Option<f64>) -> Result<()> {
+    match threshold {
+        Some(threshold) if !(0.0..=1.0).contains(&threshold) => {
+            Err(Error::InvalidConfigValue {
+                option: "content_similarity".into(),
+                reason: "it must be a number from 0 to 1".into(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Load the pages to list in the page footer from the pages directory.
+///
+/// Pages whose front-matter can't be read are left out with a warning, so one
+/// bad page doesn't break every other page on the site.
+fn load_pages(path: &Path) -> Result<Vec<Page>> {
+    let iter = read_dir(path).map_err(|cause| Error::IoErrorMsg {
+        cause,
+        msg: format!("Couldn't open pages dir at {}", path.display()),
+    })?;
+
+    let mut pages = Vec::new();
+
+    for entry in iter {
+        let entry = entry?;
+
+        let name = match entry.path().file_stem().and_then(OsStr::to_str) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+
+        if name.to_lowercase() == "home" {
+            continue;
+        }
+
+        let meta = read_to_string(entry.path())
+            .map_err(Error::from)
+            .and_then(|contents| {
+                parse_front_matter(&contents).map(|(meta, _)| meta)
+            });
+
+        let meta = match meta {
+            Ok(meta) => meta,
+            Err(err) => {
+                warn!("Skipping page at {}: {}", entry.path().display(), err);
+                continue;
+            }
+        };
+
+        if meta.hidden {
+            continue;
+        }
+
+        pages.push(Page {
+            title: meta.title.unwrap_or_else(|| name.clone()),
+            order: meta.order,
+            name,
+            path: entry.path(),
+        })
+    }
+
+    pages.sort_by(|a, b| {
+        a.order.cmp(&b.order).then_with(|| a.title.cmp(&b.title))
+    });
+
+    Ok(pages)
+}
+
+/// Split the front-matter off of a custom page.
+///
+/// The front-matter is a YAML block at the start of the page, between two
+/// lines containing only `---`. Pages without front-matter have the default
+/// metadata.
+pub fn parse_front_matter(contents: &str) -> Result<(PageMeta, &str)> {
+    let rest = match contents
+        .strip_prefix("---\n")
+        .or_else(|| contents.strip_prefix("---\r\n"))
+    {
+        Some(rest) => rest,
+        None => return Ok((PageMeta::default(), contents)),
+    };
+
+    let mut offset = 0;
+
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            let yaml = &rest[..offset];
+
+            let meta = if yaml.trim().is_empty() {
+                PageMeta::default()
+            } else {
+                serde_yaml::from_str(yaml)?
+            };
+
+            return Ok((meta, &rest[offset + line.len()..]));
+        }
+
+        offset += line.len();
+    }
+
+    // There's no end to the front-matter, so there isn't any front-matter.
+    Ok((PageMeta::default(), contents))
 }
 
 /// Parse a list of MIME types.
@@ -678,6 +2395,21 @@ where
         .collect::<std::result::Result<_, String>>()
 }
 
+/// The most files that can be uploaded with one post.
+pub const MAX_FILES_PER_POST: u64 = 1;
+
+/// Room left in the default request size limit for the text fields of a post
+/// and the multipart headers.
+const REQUEST_SIZE_SLACK: u64 = 2u64.pow(20); // 1 MiB
+
+/// The request size limit used when none is configured: enough for every file
+/// of a post at the file size limit, plus the rest of the form.
+pub fn default_request_size_limit(file_size_limit: u64) -> u64 {
+    file_size_limit
+        .saturating_mul(MAX_FILES_PER_POST)
+        .saturating_add(REQUEST_SIZE_SLACK)
+}
+
 /// Parse a file size limit.
 ///
 /// The limit is number of bytes and an optional suffix K, M, or G for KiB, MiB,
@@ -757,26 +2489,38 @@ pub struct Conf<'a> {
     pub site_name: &'a str,
     /// The path to the favicon.
     pub favicon_path: &'a Path,
+    /// The URL the site is served at, without a trailing slash.
+    pub base_url: Option<&'a str>,
     /// Address to bind to.
     pub address: &'a str,
     /// Port to bind to.
     pub port: u16,
     /// File to log to.
     pub log_file: Option<&'a Path>,
+    /// How to format log messages.
+    pub log_format: LogFormat,
     /// URL to connect to the database.
     pub database_uri: &'a str,
     /// Where the site resources (styles, templates, ...) are.
     pub resource_dir: &'a Path,
     /// Where the user-uploaded files are.
     pub upload_dir: &'a Path,
+    /// Where the extension's page banners are, if it has its own.
+    pub banner_dir: Option<&'a Path>,
     /// Where the staff-added pages are.
     pub pages_dir: Option<&'a Path>,
+    /// The pages in the page footer, loaded from `pages_dir`.
+    pub pages: &'a [Page],
     /// The path to a list of user names.
     pub names_path: Option<&'a Path>,
     /// The path to a notice file to be displayed at the top of each board.
     pub notice_path: Option<&'a Path>,
     /// Whether to allow user file uploads.
     pub allow_uploads: bool,
+    /// Whether to mark every uploaded file as a spoiler.
+    pub force_spoiler: bool,
+    /// Whether users may upload files that aren't marked as a spoiler.
+    pub spoiler_optional: bool,
     /// Allowed file types for file uploads.
     pub allow_file_types: &'a [Mime],
     /// The file size limit for uploaded files.
@@ -797,6 +2541,8 @@ pub struct Conf<'a> {
     pub dns_block_list: &'a [String],
     /// The extension that is loaded, if any.
     pub extension_name: Option<&'a str>,
+    /// The board to redirect to from the home page, if any.
+    pub home_redirect: Option<&'a str>,
     /// How many threads should be displayed per page.
     pub threads_per_page: u32,
     /// How many preview posts should be displayed per thread.
@@ -807,26 +2553,224 @@ pub struct Conf<'a> {
     pub num_recent_posts: u32,
     /// The maximum number of threads per board.
     pub thread_limit: u32,
+    /// The maximum number of replies a thread can have before it is locked.
+    pub reply_cap: Option<u32>,
+    /// Whether to warn users before creating a thread with a similar subject
+    /// to an existing thread.
+    pub dedup_threads: bool,
+    /// Whether to allow users to create cyclical threads.
+    pub allow_cyclical: bool,
+    /// The Content-Security-Policy header to send with pages.
+    pub content_security_policy: &'a str,
+    /// Whether to require the first post in a thread to have a file.
+    pub op_requires_file: bool,
+    /// The hours during which users can post.
+    pub posting_hours: Option<&'a PostingHours>,
+    /// Have the front-end web server serve files for us.
+    pub accel_redirect: Option<&'a AccelRedirect>,
+    /// How long browsers should cache uploaded files.
+    pub upload_cache_age: &'a Duration,
+    /// How long browsers should cache site resources, like styles and scripts.
+    pub resource_cache_age: &'a Duration,
+    /// Where to move the files of posts deleted by staff members, instead of
+    /// leaving them in the upload directory.
+    pub quarantine_dir: Option<&'a Path>,
+    /// How many bits of an IPv6 address identify a user. Addresses in the same
+    /// network prefix are treated as the same user.
+    pub ipv6_prefix: u8,
+    /// How similar a post must be to a recent post to be rate limited,
+    /// from 0 to 1.
+    pub content_similarity: Option<f64>,
+    /// How staff roles are shown on posts.
+    pub role_flair: &'a RoleFlair,
+    /// How many files to show at a time in the gallery.
+    pub gallery_page_size: u32,
+    /// The most boards that can be created.
+    pub max_boards: Option<u32>,
+    /// The directory to store uploads in while they are being received. If not
+    /// set, the system temporary directory is used.
+    pub upload_temp_dir: Option<&'a Path>,
+    /// How long to keep reports after a staff member closes them. Reports that
+    /// haven't been closed are always kept.
+    pub report_retention: Option<&'a Duration>,
+    /// How long to keep entries in the staff log.
+    pub log_retention: Option<&'a Duration>,
+    /// The least time that must pass between loading a posting form and
+    /// submitting it.
+    pub min_form_time: Option<&'a Duration>,
+    /// The secret used to sign the tokens in posting forms and of previewed
+    /// posts.
+    pub form_secret: &'a str,
+    /// The name of a hidden field in posting forms that must be left empty.
+    pub honeypot_field: Option<&'a str>,
+    /// The least number of seconds between new threads on a board, across all
+    /// users.
+    pub min_seconds_between_threads: Option<u32>,
+    /// The HTML tags allowed in posts on boards that use sanitized HTML.
+    pub sanitizer_allowed_tags: &'a [String],
+    /// The most lines a post can have.
+    pub max_lines: Option<u32>,
+    /// The most quote lines in a row a post can have.
+    pub max_quote_lines: Option<u32>,
+    /// Which view of a board is shown at the board's bare URI, by board name.
+    pub default_views: &'a HashMap<String, DefaultView>,
+    /// HTML tags that are allowed in the notice and custom pages, mapped to the
+    /// attributes allowed on each. If this is empty, all HTML in them is
+    /// escaped.
+    pub page_allowed_html: &'a HashMap<String, Vec<String>>,
+    /// Whether to show post and thread IDs prefixed with their board name, like
+    /// `/tech/#123`.
+    pub board_id_prefixes: bool,
+    /// The most lines a post can have before it's collapsed.
+    pub post_collapse_lines: Option<u32>,
+    /// The most characters a post can have before it's collapsed.
+    pub post_collapse_chars: Option<u32>,
+    /// The host names that links can be embedded from, on boards that allow
+    /// embeds.
+    pub embed_hosts: &'a [String],
+    /// How long after making a post users can delete it with its password.
+    pub self_delete_window: Option<&'a Duration>,
+    /// Whether posts from users who have no approved posts are held until staff
+    /// approve them.
+    pub hold_first_posts: bool,
+    /// The most threads a user can have open on a board, by board name. Boards
+    /// that aren't listed have no limit.
+    pub max_open_threads_per_user: &'a HashMap<String, u32>,
+    /// Whether post references show the start of the referenced post when
+    /// hovered over.
+    pub ref_snippets: bool,
+    /// How lines of text in posts are separated.
+    pub line_break_mode: LineBreakMode,
+    /// Whether uploaded files are saved under a sanitized version of their
+    /// original name, instead of the time they were uploaded.
+    pub preserve_filename: bool,
+    /// The path to the icon shown for non-media uploads.
+    pub file_icon_path: &'a Path,
+    /// How many replies should be displayed per page of a thread.
+    pub posts_per_thread_page: u32,
+    /// How many of the newest replies the "last posts" link on threads shows.
+    pub last_posts_count: u32,
+    /// Filter rules to apply to posts shown in board previews and the catalog.
+    pub preview_filter_rules: &'a [FilterRule],
+    /// How threads are ordered on each board, by board name.
+    pub sort_modes: &'a HashMap<String, SortMode>,
+    /// Whether logged in staff members skip the IP block lists and the user
+    /// rate limits when posting.
+    pub exempt_staff: bool,
+    /// Allow threads to be created without a subject.
+    pub subject_optional: bool,
+    /// The most characters of the first line of the first post used as the
+    /// subject of a thread made without one.
+    pub subject_fallback_chars: u32,
+    /// The IP ranges loaded from `blocked_ranges_path`.
+    pub blocked_ranges: &'a IpRanges,
+    /// The boards where posts must be previewed before they can be submitted.
+    pub require_preview: &'a [String],
+    /// The `rel` attribute of links in posts. Links have no `rel` attribute if
+    /// this is empty.
+    pub link_rel: &'a str,
+    /// The `target` attribute of links in posts. Links have no `target`
+    /// attribute if this is empty.
+    pub link_target: &'a str,
+    /// The maximum number of uploads that can be sent to clients at once.
+    pub max_downloads: Option<u32>,
+    /// The maximum number of uploads that can be sent to a single user at once.
+    pub max_downloads_per_user: Option<u32>,
+    /// Whether crawlers are asked not to index pages on NSFW boards.
+    pub nsfw_noindex: bool,
+    /// Whether thumbnails on NSFW boards are blurred until they're hovered
+    /// over.
+    pub nsfw_blur_thumbnails: bool,
+    /// How long after a thread is made that another thread with the same
+    /// subject can't be made on the same board.
+    pub duplicate_subject_cooldown: Option<&'a Duration>,
+    /// The size limit for the whole body of a post request.
+    pub request_size_limit: u64,
+    /// Whether to leave the preview image out of the link preview tags of
+    /// threads on NSFW boards.
+    pub nsfw_hide_share_image: bool,
+    /// The secret used to hash users' IP addresses.
+    pub hash_secret: &'a str,
+    /// The largest width and height of the thumbnails shown in catalogs. If
+    /// not set, catalogs show the regular thumbnails.
+    pub catalog_thumb_size: Option<u32>,
+    /// The most pinned threads shown at the top of a board. Other pinned
+    /// threads are sorted in with the rest of the threads.
+    pub max_pinned_on_page: Option<u32>,
+    /// If set, posts with links from users with fewer than this many posts
+    /// are held or rejected, see `first_link_action`.
+    pub first_link_min_posts: Option<u32>,
+    /// What happens to posts caught by `first_link_min_posts`.
+    pub first_link_action: FirstLinkAction,
+    /// Whether thumbnails on board pages and catalogs are marked to be loaded
+    /// lazily, once they're scrolled into view.
+    pub lazy_thumbnails: bool,
+    /// If set, catalogs are laid out in this many columns, instead of as many
+    /// as fit on the screen.
+    pub catalog_columns: Option<u32>,
+    /// The maximum number of thumbnails that can be generated at once.
+    pub max_concurrent_thumbnails: Option<u32>,
+    /// The image format that new thumbnails are saved in.
+    pub thumbnail_format: ThumbnailFormat,
+    /// Whether to rotate and flip images according to their EXIF orientation
+    /// when making thumbnails.
+    pub auto_orient_images: bool,
+    /// Links in posts that are longer than this are shown shortened, down to
+    /// their host and the start of their path.
+    pub link_display_chars: Option<u32>,
+    /// Whether users who post with JSON responses are given a token which can
+    /// delete the post in place of a delete password.
+    pub delete_tokens: bool,
+    /// HTML added to the end of the `<head>` of every page. This is trusted and
+    /// shown as it is, so it must only ever come from the site's admins.
+    pub extra_head_html: Option<&'a str>,
+    /// HTML added to the end of the `<body>` of every page. This is trusted and
+    /// shown as it is, so it must only ever come from the site's admins.
+    pub extra_footer_html: Option<&'a str>,
+    /// Whether `extra_head_html` and `extra_footer_html` are added to pages.
+    pub allow_extra_html: bool,
+    /// Whether requests for pages at paths that aren't canonical, like
+    /// `/Tech/123/`, are redirected to their canonical path.
+    pub canonical_redirects: bool,
+    /// Whether uploaded videos of the types in `transcode_types` are converted
+    /// with ffmpeg to a format that browsers can play.
+    pub transcode_uploads: bool,
+    /// The formats that uploaded videos are converted to, by their content
+    /// type. See `transcode_uploads`.
+    pub transcode_types: &'a HashMap<String, TranscodeFormat>,
+    /// Whether the original files of converted uploads are kept in the upload
+    /// directory.
+    pub keep_transcoded_originals: bool,
 }
 
 impl<'a> Conf<'a> {
+    /// The format that an upload of the given type should be converted to, if
+    /// any. See `transcode_uploads`.
+    pub fn transcode_format(
+        &self,
+        content_type: &Mime,
+    ) -> Option<TranscodeFormat> {
+        if !self.transcode_uploads {
+            return None;
+        }
+
+        self.transcode_types.get(content_type.essence_str()).copied()
+    }
+
     /// Get all of the page banners.
+    ///
+    /// If the extension has its own banner directory with any banners in it,
+    /// those are used instead of the global banners.
     pub fn banners(&self) -> Result<Vec<Banner>> {
-        let path = self.resource_dir.join("banners");
-        let iter = read_dir(&path).map_err(|cause| Error::IoErrorMsg {
-            cause,
-            msg: format!("Couldn't open banners dir at {}", path.display()),
-        })?;
+        if let Some(dir) = self.banner_dir {
+            let banners = read_banners(dir, self.extension_name)?;
 
-        let mut banners = Vec::new();
-
-        for entry in iter {
-            banners.push(Banner {
-                name: entry?.file_name().into_string().unwrap(),
-            });
+            if !banners.is_empty() {
+                return Ok(banners);
+            }
         }
 
-        Ok(banners)
+        read_banners(&self.resource_dir.join("banners"), None)
     }
 
     /// Choose a page banner at random.
@@ -841,43 +2785,6 @@ impl<'a> Conf<'a> {
         }
     }
 
-    /// Get all of the added pages.
-    pub fn pages(&self) -> Result<Vec<Page>> {
-        if let Some(path) = &self.pages_dir {
-            let iter = read_dir(path).map_err(|cause| Error::IoErrorMsg {
-                cause,
-                msg: format!("Couldn't open pages dir at {}", path.display()),
-            })?;
-
-            let mut pages = Vec::new();
-
-            for entry in iter {
-                let entry = entry?;
-
-                let name = entry
-                    .path()
-                    .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string();
-
-                if name.to_lowercase() == "home" {
-                    continue;
-                }
-
-                pages.push(Page {
-                    name,
-                    path: entry.path(),
-                })
-            }
-
-            Ok(pages)
-        } else {
-            Ok(Vec::new())
-        }
-    }
-
     /// Get all of the default names for anonymous posts.
     pub fn names(&self) -> Result<Vec<String>> {
         if let Some(path) = &self.names_path {
@@ -894,6 +2801,46 @@ impl<'a> Conf<'a> {
         }
     }
 
+    /// The most threads a user can have open on a board, if there's a limit.
+    pub fn open_thread_limit<S>(&self, board_name: S) -> Option<u32>
+    where
+        S: AsRef<str>,
+    {
+        self.max_open_threads_per_user
+            .get(board_name.as_ref())
+            .copied()
+    }
+
+    /// Whether posts on a board must be previewed before they're submitted.
+    pub fn preview_required<S>(&self, board_name: S) -> bool
+    where
+        S: AsRef<str>,
+    {
+        self.require_preview.iter().any(|name| name == board_name.as_ref())
+    }
+
+    /// Which view of a board is shown at the board's bare URI.
+    pub fn default_view<S>(&self, board_name: S) -> DefaultView
+    where
+        S: AsRef<str>,
+    {
+        self.default_views
+            .get(board_name.as_ref())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// How threads are ordered on a board.
+    pub fn sort_mode<S>(&self, board_name: S) -> SortMode
+    where
+        S: AsRef<str>,
+    {
+        self.sort_modes
+            .get(board_name.as_ref())
+            .copied()
+            .unwrap_or_default()
+    }
+
     /// Choose a name at random.
     pub fn choose_name(&self) -> Result<String> {
         let mut rng = thread_rng();
@@ -918,16 +2865,29 @@ impl<'a> Conf<'a> {
                     ),
                 });
 
-            let mut notice_html = String::new();
-            push_html(&mut notice_html, Parser::new(&contents?));
-
-            Ok(Some(notice_html))
+            Ok(Some(render_admin_markdown(contents?, self.page_allowed_html)))
         } else {
             Ok(None)
         }
     }
 }
 
+/// What secret options are replaced with in `Conf::redacted`.
+pub const REDACTED: &str = "[redacted]";
+
+impl<'a> Conf<'a> {
+    /// A copy of this configuration with secrets like the database URI
+    /// replaced, so that it can be shown to staff.
+    pub fn redacted(&self) -> Conf<'a> {
+        Conf {
+            database_uri: REDACTED,
+            form_secret: REDACTED,
+            hash_secret: REDACTED,
+            ..self.clone()
+        }
+    }
+}
+
 impl<'a, 'r> FromRequest<'a, 'r> for Conf<'r> {
     type Error = Error;
 
@@ -955,3 +2915,397 @@ impl<'a, 'r> FromRequest<'a, 'r> for Conf<'r> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, write};
+
+    use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+
+    use tempfile::tempdir;
+
+    use super::{
+        load_pages, parse_front_matter, parse_time_range, parse_utc_offset,
+        Config, DefaultView, GlobalConfig, IpRanges, PageMeta, PostingHours,
+        TimeRange, REDACTED,
+    };
+    use crate::Error;
+
+    fn time(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    fn utc(hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 4, 20, hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn time_range() {
+        let range = parse_time_range("08:00-17:30").unwrap();
+        assert_eq!(range.start, time(8, 0));
+        assert_eq!(range.end, time(17, 30));
+        assert!(range.contains(time(8, 0)));
+        assert!(range.contains(time(12, 0)));
+        assert!(!range.contains(time(17, 30)));
+        assert!(!range.contains(time(7, 59)));
+
+        assert!(parse_time_range("08:00").is_err());
+        assert!(parse_time_range("08:00-25:00").is_err());
+    }
+
+    #[test]
+    fn ip_ranges() {
+        let ranges = IpRanges::parse(
+            "# datacenters\n\
+             192.0.2.0/24\n\
+             198.51.100.7\n\
+             \n\
+             2001:db8::/32\n\
+             2001:db8:1::/48\n",
+        )
+        .unwrap();
+
+        let contains = |ip: &str| ranges.contains(ip.parse().unwrap());
+
+        assert!(contains("192.0.2.0"));
+        assert!(contains("192.0.2.255"));
+        assert!(!contains("192.0.3.0"));
+        assert!(!contains("192.0.1.255"));
+        assert!(contains("198.51.100.7"));
+        assert!(!contains("198.51.100.8"));
+        assert!(contains("::ffff:192.0.2.10"));
+
+        assert!(contains("2001:db8::1"));
+        assert!(contains("2001:db8:ffff:ffff:ffff:ffff:ffff:ffff"));
+        assert!(!contains("2001:db9::"));
+        assert!(!contains("2001:db7:ffff::"));
+
+        let everything = IpRanges::parse("0.0.0.0/0\n::/0").unwrap();
+        assert!(everything.contains("255.255.255.255".parse().unwrap()));
+        assert!(everything.contains("::1".parse().unwrap()));
+
+        assert_eq!(IpRanges::parse("192.0.2.0/33"), Err("192.0.2.0/33".into()));
+        assert_eq!(IpRanges::parse("not an ip"), Err("not an ip".into()));
+    }
+
+    #[test]
+    fn time_range_wraps() {
+        let range = parse_time_range("22:00-02:00").unwrap();
+        assert!(range.contains(time(23, 0)));
+        assert!(range.contains(time(1, 0)));
+        assert!(!range.contains(time(12, 0)));
+    }
+
+    #[test]
+    fn utc_offset() {
+        assert_eq!(parse_utc_offset("+02:00").unwrap().local_minus_utc(), 7200);
+        assert_eq!(
+            parse_utc_offset("-05:30").unwrap().local_minus_utc(),
+            -19800
+        );
+        assert!(parse_utc_offset("02:00").is_err());
+    }
+
+    #[test]
+    fn posting_hours() {
+        let hours = PostingHours {
+            utc_offset: parse_utc_offset("+02:00").unwrap(),
+            open: vec![TimeRange {
+                start: time(8, 0),
+                end: time(17, 0),
+            }],
+        };
+
+        // 10:00 local time.
+        assert!(hours.is_open(utc(8, 0)));
+        // 18:00 local time.
+        assert!(!hours.is_open(utc(16, 0)));
+
+        let next = hours.next_open(utc(16, 0)).unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2020, 4, 21, 6, 0, 0).unwrap());
+
+        let next = hours.next_open(utc(4, 0)).unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2020, 4, 20, 6, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn front_matter() {
+        let page = "---\ntitle: Rules\norder: 2\nhidden: true\n---\n# Rules\n";
+        let (meta, contents) = parse_front_matter(page).unwrap();
+
+        assert_eq!(meta.title.as_deref(), Some("Rules"));
+        assert_eq!(meta.order, 2);
+        assert!(meta.hidden);
+        assert_eq!(contents, "# Rules\n");
+    }
+
+    #[test]
+    fn front_matter_partial() {
+        let page = "---\ntitle: FAQ\n---\nQuestions";
+        let (meta, contents) = parse_front_matter(page).unwrap();
+
+        assert_eq!(meta.title.as_deref(), Some("FAQ"));
+        assert_eq!(meta.order, 0);
+        assert!(!meta.hidden);
+        assert_eq!(contents, "Questions");
+    }
+
+    #[test]
+    fn no_front_matter() {
+        let page = "# Rules\n---\nNo spam.\n";
+        assert_eq!(
+            parse_front_matter(page).unwrap(),
+            (PageMeta::default(), page)
+        );
+
+        let page = "---\nnot front-matter\n";
+        assert_eq!(
+            parse_front_matter(page).unwrap(),
+            (PageMeta::default(), page)
+        );
+    }
+
+    #[test]
+    fn default_view() {
+        let mut config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        config
+            .global_config
+            .default_views
+            .insert("b".to_string(), DefaultView::Catalog);
+        let conf = config.global();
+
+        assert_eq!(conf.default_view("b"), DefaultView::Catalog);
+        assert_eq!(conf.default_view("g"), DefaultView::Board);
+    }
+
+    #[test]
+    fn open_thread_limit() {
+        let mut config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        config
+            .global_config
+            .max_open_threads_per_user
+            .insert("b".to_string(), 3);
+        let conf = config.global();
+
+        assert_eq!(conf.open_thread_limit("b"), Some(3));
+        assert_eq!(conf.open_thread_limit("g"), None);
+    }
+
+    #[test]
+    fn redacted() {
+        let mut config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        config.global_config.form_secret = Some("a-form-secret".into());
+        let conf = config.global().redacted();
+
+        assert_eq!(conf.database_uri, REDACTED);
+        assert_eq!(conf.form_secret, REDACTED);
+        assert_eq!(conf.hash_secret, REDACTED);
+        assert_eq!(conf.site_name, config.global_config.site_name);
+
+        let debug = format!("{:?}", conf);
+        assert!(!debug.contains(&config.global_config.database_uri));
+        assert!(!debug.contains("a-form-secret"));
+    }
+
+    #[test]
+    fn extension_overrides_rate_limit_same_content() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.yaml");
+
+        write(
+            &config_path,
+            format!(
+                "resource_dir: {0}\n\
+                 upload_dir: {0}\n\
+                 rate_limit_same_user: 10s\n\
+                 rate_limit_same_content: 2m\n",
+                dir.path().display()
+            ),
+        )
+        .unwrap();
+
+        write(
+            dir.path().join("ext.yaml"),
+            "rate_limit_same_content: 5m\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&config_path, dir.path()).unwrap();
+        let conf = config.extension("ext").unwrap();
+
+        assert_eq!(*conf.rate_limit_same_content, Duration::minutes(5));
+        assert_eq!(*conf.rate_limit_same_user, Duration::seconds(10));
+    }
+
+    #[test]
+    fn content_similarity_range() {
+        let mut conf = GlobalConfig::default();
+
+        conf.content_similarity = Some(0.8);
+        conf.check_values().unwrap();
+
+        for threshold in [-0.1, 1.5, f64::NAN] {
+            conf.content_similarity = Some(threshold);
+            assert!(matches!(
+                conf.check_values(),
+                Err(Error::InvalidConfigValue { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn pages_skip_bad_front_matter() {
+        let dir = tempdir().unwrap();
+
+        write(dir.path().join("rules.md"), "---\norder: 2\n---\nBe nice.")
+            .unwrap();
+        write(dir.path().join("faq.md"), "---\ntitle: FAQ\n---\n").unwrap();
+        write(dir.path().join("bad.md"), "---\norder: [\n---\n").unwrap();
+        write(dir.path().join("secret.md"), "---\nhidden: true\n---\n")
+            .unwrap();
+        write(dir.path().join("home.md"), "Welcome!").unwrap();
+
+        let pages = load_pages(dir.path()).unwrap();
+        let titles: Vec<_> = pages.iter().map(|page| &page.title).collect();
+
+        assert_eq!(titles, ["FAQ", "rules"]);
+    }
+
+    #[test]
+    fn missing_templates() {
+        let mut config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        config.global_config.resource_dir = "res".into();
+
+        config.check_templates().unwrap();
+
+        let dir = tempdir().unwrap();
+        create_dir(dir.path().join("templates")).unwrap();
+        config.global_config.resource_dir = dir.path().to_path_buf();
+
+        assert!(matches!(
+            config.check_templates(),
+            Err(Error::ConfigPathNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn extension_extra_html() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.yaml");
+
+        write(
+            &config_path,
+            format!(
+                "resource_dir: {0}\n\
+                 upload_dir: {0}\n\
+                 extra_head_html: '<meta name=\"a\">'\n",
+                dir.path().display()
+            ),
+        )
+        .unwrap();
+
+        write(
+            dir.path().join("ext.yaml"),
+            "extra_footer_html: '<p>b</p>'\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&config_path, dir.path()).unwrap();
+        let conf = config.extension("ext").unwrap();
+
+        assert_eq!(conf.extra_head_html, Some("<meta name=\"a\">"));
+        assert_eq!(conf.extra_footer_html, Some("<p>b</p>"));
+        assert_eq!(config.global().extra_footer_html, None);
+
+        write(dir.path().join("ext.yaml"), "extra_head_html: [a, b]\n")
+            .unwrap();
+        assert!(Config::load(&config_path, dir.path()).is_err());
+    }
+
+    #[test]
+    fn extension_banners() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.yaml");
+
+        create_dir(dir.path().join("banners")).unwrap();
+        write(dir.path().join("banners/global.png"), "").unwrap();
+
+        write(
+            &config_path,
+            format!(
+                "resource_dir: {0}\n\
+                 upload_dir: {0}\n",
+                dir.path().display()
+            ),
+        )
+        .unwrap();
+
+        let ext_banners = dir.path().join("ext-banners");
+        create_dir(&ext_banners).unwrap();
+
+        write(
+            dir.path().join("ext.yaml"),
+            format!("banner_dir: {}\n", ext_banners.display()),
+        )
+        .unwrap();
+
+        // An empty banner directory falls back to the global banners.
+        let config = Config::load(&config_path, dir.path()).unwrap();
+        let banners = config.extension("ext").unwrap().banners().unwrap();
+        assert_eq!(banners.len(), 1);
+        assert_eq!(banners[0].uri(), "/file/banner/global.png");
+
+        write(ext_banners.join("ext.png"), "").unwrap();
+
+        let banners = config.extension("ext").unwrap().banners().unwrap();
+        assert_eq!(banners.len(), 1);
+        assert_eq!(banners[0].uri(), "/file/extension-banner/ext/ext.png");
+
+        let banners = config.global().banners().unwrap();
+        assert_eq!(banners[0].name, "global.png");
+
+        // The banner directory has to exist.
+        write(
+            dir.path().join("ext.yaml"),
+            format!("banner_dir: {}\n", dir.path().join("missing").display()),
+        )
+        .unwrap();
+        assert!(Config::load(&config_path, dir.path()).is_err());
+    }
+
+    #[test]
+    fn invalid_values() {
+        let mut conf = GlobalConfig::default();
+        assert!(conf.check_values().is_ok());
+
+        conf.max_concurrent_thumbnails = Some(0);
+        assert!(matches!(
+            conf.check_values(),
+            Err(Error::InvalidConfigValue { .. })
+        ));
+
+        conf.max_concurrent_thumbnails = Some(1);
+        assert!(conf.check_values().is_ok());
+
+        conf.base_url = Some("example.com".into());
+        assert!(matches!(
+            conf.check_values(),
+            Err(Error::InvalidConfigValue { .. })
+        ));
+
+        conf.base_url = Some("https://example.com/".into());
+        assert!(conf.check_values().is_ok());
+    }
+}
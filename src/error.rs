@@ -13,8 +13,10 @@ use rocket::{uri, Request};
 
 use derive_more::{Display, From};
 
-use crate::models::{staff::Role, PostId, ThreadId};
+use crate::models::staff::{ApiScope, Role};
+use crate::models::{PostId, ThreadId, UserId};
 use crate::views::error::*;
+use crate::views::staff::ApiResponse;
 use crate::views::Context;
 
 /// Our error type.
@@ -32,8 +34,12 @@ pub enum Error {
     },
     #[display(fmt = "File uploads are not allowed.")]
     FileUploadNotAllowed,
+    #[display(fmt = "Uploaded files must be marked as a spoiler.")]
+    SpoilerRequired,
     #[display(fmt = "Banned user {} attempted to access page", user_hash)]
     UserIsBanned { user_hash: String },
+    #[display(fmt = "Honeypot field '{}' was filled in", field)]
+    HoneypotFilled { field: String },
     #[display(fmt = "Rate limit exceeded for IP.")]
     UserRateLimitExceeded,
     #[display(fmt = "Rate limit exceeded for post content.")]
@@ -43,14 +49,40 @@ pub enum Error {
         ip_addr
     )]
     UserNotFound { ip_addr: IpAddr },
+    #[display(fmt = "No user with a hash starting with '{}'", hash)]
+    UserHashNotFound { hash: String },
+    #[display(fmt = "No user with ID {}", user_id)]
+    UserIdNotFound { user_id: UserId },
+    #[display(
+        fmt = "{} users have a hash starting with '{}'; use a longer prefix",
+        count,
+        hash
+    )]
+    UserHashAmbiguous { hash: String, count: usize },
+    #[display(
+        fmt = "Invalid board name '{}'. Board names must be 1 to 16 lowercase \
+               letters or digits, and can't be a reserved name.",
+        board_name
+    )]
+    InvalidBoardName { board_name: String },
+    #[display(fmt = "Can't create more than {} boards", max_boards)]
+    BoardLimitReached { max_boards: u32 },
     #[display(fmt = "Board '{}' not found", board_name)]
     BoardNotFound { board_name: String },
+    #[display(fmt = "Board '{}' already exists", board_name)]
+    BoardAlreadyExists { board_name: String },
+    #[display(fmt = "Invalid board export: {}", reason)]
+    InvalidExport { reason: String },
     #[display(fmt = "Thread #{} not found", thread_id)]
     ThreadNotFound { thread_id: ThreadId },
     #[display(fmt = "Post #{} not found", post_id)]
     PostNotFound { post_id: PostId },
+    #[display(fmt = "Post #{} isn't waiting for approval", post_id)]
+    PostNotPending { post_id: PostId },
     #[display(fmt = "Custom page {} not found", name)]
     CustomPageNotFound { name: String },
+    #[display(fmt = "Page {} is out of range", page)]
+    PageOutOfRange { page: u32 },
     #[display(fmt = "Missing parameter '{}' for new thread", param)]
     MissingThreadParam { param: String },
     #[display(fmt = "Missing parameter '{}' for new post", param)]
@@ -67,7 +99,7 @@ pub enum Error {
     )]
     UploadBadContentType { content_type: Mime },
     #[display(
-        fmt = "Uploaded file exceeded the maximum file size of {}.",
+        fmt = "Upload exceeded the maximum size of {}.",
         "human_size(*size_limit)"
     )]
     UploadTooBig { size_limit: u64 },
@@ -75,6 +107,10 @@ pub enum Error {
     DeleteInvalidPassword,
     #[display(fmt = "Deleting files only is not a valid option for threads")]
     CannotDeleteThreadFilesOnly,
+    #[display(fmt = "System posts cannot be deleted")]
+    CannotDeleteSystemPost,
+    #[display(fmt = "This post is too old to be deleted")]
+    DeleteWindowExpired,
     #[display(fmt = "No staff member with username '{}'", staff_name)]
     StaffInvalidUsername { staff_name: String },
     #[display(fmt = "Invalid password for username '{}'", staff_name)]
@@ -89,8 +125,69 @@ pub enum Error {
         fmt = "Report length was more than the maximum of 250 characters"
     )]
     ReportTooLong,
+    #[display(
+        fmt = "The form was submitted too quickly. Please wait a moment and \
+               try again."
+    )]
+    PostedTooFast,
+    #[display(
+        fmt = "Posts on this board must be previewed before they're \
+               submitted. Please preview your post and try again."
+    )]
+    PreviewRequired,
+    #[display(fmt = "Posts can't have more than {} lines", max_lines)]
+    TooManyLines { max_lines: u32 },
+    #[display(
+        fmt = "Posts can't have more than {} quote lines in a row",
+        max_quote_lines
+    )]
+    TooManyQuoteLines { max_quote_lines: u32 },
     #[display(fmt = "Cannot add a post to a locked thread")]
     ThreadLocked,
+    #[display(
+        fmt = "Too many threads have been made on this board recently. Try \
+               again in {} seconds.",
+        wait_seconds
+    )]
+    ThreadsThrottled { wait_seconds: i64 },
+    #[display(
+        fmt = "You can't have more than {} open threads on this board.",
+        max_open_threads
+    )]
+    TooManyOpenThreads { max_open_threads: u32 },
+    #[display(
+        fmt = "Links can't be posted until you've made {} posts. Try posting \
+               without links for now.",
+        min_posts
+    )]
+    FirstLinkRejected { min_posts: u32 },
+    #[display(
+        fmt = "Too many files are being downloaded right now. Please try \
+               again later."
+    )]
+    TooManyDownloads,
+    #[display(
+        fmt = "The server is too busy to take uploads right now. Please try \
+               again in a moment."
+    )]
+    ServerBusy,
+    #[display(fmt = "Posting is closed until {}.", reopens_at)]
+    PostingClosed { reopens_at: String },
+    #[display(
+        fmt = "A thread with a similar subject exists at {}",
+        thread_uri
+    )]
+    SimilarThreadExists { thread_uri: String },
+    #[display(fmt = "A thread with the same subject was made recently")]
+    DuplicateThread { existing_uri: Option<String> },
+    #[display(fmt = "Missing API key")]
+    MissingApiKey,
+    #[display(fmt = "Invalid API key")]
+    InvalidApiKey,
+    #[display(fmt = "API key is not allowed to {}", scope)]
+    ApiKeyNotInScope { scope: ApiScope },
+    #[display(fmt = "Unknown API key scope: {}", scope)]
+    UnknownApiScope { scope: String },
     #[display(fmt = "Tried to access a staff page without authentication")]
     NotAuthenticated,
     #[display(fmt = "Banner directory is empty")]
@@ -103,6 +200,27 @@ pub enum Error {
         path
     )]
     ConfigPathNotFound { description: String, path: String },
+    #[display(fmt = "IPv6 prefix length {} is more than 128 bits", prefix)]
+    InvalidIpv6Prefix { prefix: u8 },
+    #[display(fmt = "Invalid value for {}: {}", option, reason)]
+    InvalidConfigValue { option: String, reason: String },
+    #[display(fmt = "Invalid IP range '{}' in {}", range, path)]
+    InvalidIpRange { range: String, path: String },
+    #[display(
+        fmt = "Extension '{}' redirects to board '{}', which doesn't exist",
+        extension,
+        board_name
+    )]
+    HomeRedirectBoardNotFound {
+        extension: String,
+        board_name: String,
+    },
+    #[display(fmt = "Extension '{}' does not exist", name)]
+    ExtensionNotFound { name: String },
+    #[display(fmt = "Catalog thumbnails are off, set catalog_thumb_size first")]
+    CatalogThumbsDisabled,
+    #[display(fmt = "Unknown markup mode: {}", mode)]
+    UnknownMarkupMode { mode: String },
     #[display(fmt = "Unknown role: {}", role)]
     UnknownRole { role: String },
     #[display(
@@ -144,6 +262,8 @@ pub enum Error {
     #[display(fmt = "Database connection pool error: {}", _0)]
     #[from]
     R2d2Error(r2d2::Error),
+    #[display(fmt = "The database connection pool was closed")]
+    ConnectionPoolClosed,
     #[display(fmt = "Database error: {}", _0)]
     #[from]
     DatabaseError(diesel::result::Error),
@@ -213,21 +333,69 @@ impl Error {
     ) -> Self {
         Error::DatabaseMigrationError(cause)
     }
+
+    /// The HTTP status to send along with this error from an API route.
+    pub fn api_status(&self) -> Status {
+        match self {
+            Error::MissingApiKey | Error::InvalidApiKey => Status::Unauthorized,
+
+            Error::ApiKeyNotInScope { .. } | Error::UnauthorizedRole { .. } => {
+                Status::Forbidden
+            }
+
+            Error::PostNotFound { .. }
+            | Error::ThreadNotFound { .. }
+            | Error::BoardNotFound { .. }
+            | Error::UserIdNotFound { .. }
+            | Error::UserHashNotFound { .. }
+            | Error::PageOutOfRange { .. }
+            | Error::DatabaseError(diesel::result::Error::NotFound) => {
+                Status::NotFound
+            }
+
+            Error::CannotDeleteSystemPost
+            | Error::CannotDeleteThreadFilesOnly
+            | Error::PostNotPending { .. }
+            | Error::InvalidBoardName { .. }
+            | Error::UserHashAmbiguous { .. } => Status::BadRequest,
+
+            Error::ServerBusy | Error::ConnectionPoolClosed => {
+                Status::ServiceUnavailable
+            }
+
+            _ => Status::InternalServerError,
+        }
+    }
 }
 
 impl<'r> Responder<'r> for Error {
     fn respond_to(self, req: &Request) -> rocket::response::Result<'r> {
         match self {
-            Error::CannotDeleteThreadFilesOnly
+            Error::BoardLimitReached { .. }
+            | Error::CannotDeleteSystemPost
+            | Error::CannotDeleteThreadFilesOnly
             | Error::DeleteInvalidPassword
+            | Error::DeleteWindowExpired
+            | Error::PostNotPending { .. }
             | Error::FileUploadNotAllowed
+            | Error::FirstLinkRejected { .. }
             | Error::ImageError(..)
+            | Error::InvalidBoardName { .. }
             | Error::MissingPostParam { .. }
             | Error::MissingThreadParam { .. }
+            | Error::PostedTooFast
+            | Error::PreviewRequired
+            | Error::PostingClosed { .. }
             | Error::ReportTooLong
+            | Error::SpoilerRequired
             | Error::StaffInvalidPassword { .. }
             | Error::StaffInvalidUsername { .. }
             | Error::ThreadLocked
+            | Error::ThreadsThrottled { .. }
+            | Error::TooManyLines { .. }
+            | Error::TooManyOpenThreads { .. }
+            | Error::TooManyQuoteLines { .. }
+            | Error::UnknownMarkupMode { .. }
             | Error::UploadTooBig { .. } => {
                 warn!("{}", &self);
 
@@ -262,6 +430,23 @@ impl<'r> Responder<'r> for Error {
                 Ok(res)
             }
 
+            Error::HoneypotFilled { .. } => {
+                // Don't tell bots which field gave them away.
+
+                warn!("{}", &self);
+
+                let mut context = req.guard::<Context>().unwrap();
+                let page = SpamDetectedPage::new(
+                    "Your post was detected as spam.".to_string(),
+                    &mut context,
+                );
+
+                let mut res = page.respond_to(req)?;
+                res.set_status(Status::Forbidden);
+
+                Ok(res)
+            }
+
             Error::UserRateLimitExceeded | Error::ContentRateLimitExceeded => {
                 // In the same way as when the IP is blocked; we don't want to
                 // show the exact error message to the client when the rate
@@ -281,10 +466,57 @@ impl<'r> Responder<'r> for Error {
                 Ok(res)
             }
 
+            Error::TooManyDownloads => {
+                warn!("{}", &self);
+
+                let mut context = req.guard::<Context>().unwrap();
+                let page = BadRequestPage::new(self.to_string(), &mut context);
+
+                let mut res = page.respond_to(req)?;
+                res.set_status(Status::TooManyRequests);
+
+                Ok(res)
+            }
+
+            Error::ServerBusy => {
+                warn!("{}", &self);
+
+                let mut context = req.guard::<Context>().unwrap();
+                let page = BadRequestPage::new(self.to_string(), &mut context);
+
+                let mut res = page.respond_to(req)?;
+                res.set_status(Status::ServiceUnavailable);
+
+                Ok(res)
+            }
+
+            Error::SimilarThreadExists { thread_uri } => {
+                let mut context = req.guard::<Context>().unwrap();
+                let page = SimilarThreadPage::new(thread_uri, &mut context);
+
+                let mut res = page.respond_to(req)?;
+                res.set_status(Status::Conflict);
+
+                Ok(res)
+            }
+
+            Error::DuplicateThread { existing_uri } => {
+                let mut context = req.guard::<Context>().unwrap();
+                let page = DuplicateThreadPage::new(existing_uri, &mut context);
+
+                let mut res = page.respond_to(req)?;
+                res.set_status(Status::Conflict);
+
+                Ok(res)
+            }
+
             Error::PostNotFound { .. }
             | Error::BoardNotFound { .. }
             | Error::ThreadNotFound { .. }
-            | Error::CustomPageNotFound { .. } => {
+            | Error::CustomPageNotFound { .. }
+            | Error::PageOutOfRange { .. }
+            | Error::ExtensionNotFound { .. }
+            | Error::UserIdNotFound { .. } => {
                 warn!("{}", &self);
 
                 let mut context = req.guard::<Context>().unwrap();
@@ -328,5 +560,33 @@ impl<'r> Responder<'r> for Error {
 
 impl std::error::Error for Error {}
 
+/// An error from an API route, which is sent to the client as JSON instead of
+/// as an HTML page.
+#[derive(Debug)]
+pub struct ApiError(pub Error);
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> ApiError {
+        ApiError(error)
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, req: &Request) -> rocket::response::Result<'r> {
+        let status = self.0.api_status();
+
+        if status == Status::InternalServerError {
+            error!("{}", self.0);
+        } else {
+            warn!("{}", self.0);
+        }
+
+        let mut res = ApiResponse::error(self.0.to_string()).respond_to(req)?;
+        res.set_status(status);
+
+        Ok(res)
+    }
+}
+
 /// Our result type.
 pub type Result<T> = std::result::Result<T, Error>;
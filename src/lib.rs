@@ -26,15 +26,19 @@ extern crate diesel_migrations;
 
 use std::fmt::Write;
 use std::string::ToString;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use rocket::config::{Config as RocketConfig, Environment, LoggingLevel};
 use rocket::fairing::{Fairing, Info, Kind};
-use rocket::http::StatusClass;
-use rocket::{Request, Response, Rocket};
+use rocket::http::{Method, Status, StatusClass};
+use rocket::{Data, Request, Response, Rocket};
 
 use rocket_contrib::templates::Template;
 
-use log::{info, warn};
+use log::{info, log, warn, Level};
 
 pub mod config;
 pub mod error;
@@ -43,15 +47,20 @@ pub mod parse;
 pub mod routes;
 pub mod views;
 
-use config::Config;
-pub use error::{Error, Result};
-use models::{ConnectionPool, User};
+use config::{Config, LogFormat};
+pub use error::{ApiError, Error, Result};
+use models::{ConnectionPool, PooledConnection, SingleConnection, User};
+use routes::new::{Previews, Thumbnails};
+use routes::{canonical_path, Downloads};
 
 /// Auto-generated by diesel.
 pub mod schema;
 
 /// A rocket fairing for logging about requests.
-pub struct LogFairing;
+pub struct LogFairing {
+    /// How log messages are formatted.
+    pub format: LogFormat,
+}
 
 impl Fairing for LogFairing {
     fn info(&self) -> Info {
@@ -68,8 +77,6 @@ impl Fairing for LogFairing {
     }
 
     fn on_response(&self, request: &Request, response: &mut Response) {
-        let mut msg = String::new();
-
         let ip = request
             .client_ip()
             .map(|ip| ip.to_string())
@@ -78,32 +85,216 @@ impl Fairing for LogFairing {
         let user_hash = request
             .guard::<User>()
             .succeeded()
-            .map(|user| user.hash.split('$').last().unwrap().to_string())
+            .map(|user| user.short_hash().to_string())
             .unwrap_or(String::from("Unknown"));
 
+        let method = request.method();
+        let uri = request.uri().to_string();
+        let status = response.status();
+        let content_type = response.content_type().map(|ty| ty.to_string());
+        let referer = request.headers().get_one("Referer");
+        let user_agent = request.headers().get_one("User-Agent");
+
+        let level = match status.class() {
+            StatusClass::ClientError | StatusClass::ServerError => Level::Warn,
+            _ => Level::Info,
+        };
+
+        if self.format == LogFormat::Json {
+            log!(
+                level,
+                ip = ip.as_str(),
+                user = user_hash.as_str(),
+                method = method.as_str(),
+                uri = uri.as_str(),
+                status = status.code,
+                content_type = content_type.as_deref(),
+                referer = referer,
+                user_agent = user_agent;
+                "{} {} {}", method, uri, status
+            );
+            return;
+        }
+
+        let mut msg = String::new();
+
         write!(msg, "[{}] [{}]", ip, user_hash).unwrap();
-        write!(msg, " {}", request.method()).unwrap();
-        write!(msg, " {}", request.uri().to_string()).unwrap();
-        write!(msg, " {}", response.status()).unwrap();
+        write!(msg, " {}", method).unwrap();
+        write!(msg, " {}", uri).unwrap();
+        write!(msg, " {}", status).unwrap();
 
-        if let Some(content_type) = response.content_type() {
+        if let Some(content_type) = content_type {
             write!(msg, " ({})", content_type).unwrap();
         }
 
-        if let Some(referer) = request.headers().get_one("Referer") {
+        if let Some(referer) = referer {
             write!(msg, " Referer \"{}\"", referer).unwrap();
         }
 
-        if let Some(user_agent) = request.headers().get_one("User-Agent") {
+        if let Some(user_agent) = user_agent {
             write!(msg, " User-Agent \"{}\"", user_agent).unwrap();
         }
 
-        if let StatusClass::ClientError | StatusClass::ServerError =
-            response.status().class()
+        log!(level, "{}", msg);
+    }
+}
+
+/// A rocket fairing for adding security headers to pages.
+pub struct SecurityHeadersFairing {
+    /// The value of the Content-Security-Policy header.
+    pub content_security_policy: String,
+}
+
+impl Fairing for SecurityHeadersFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security Headers Fairing",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, _request: &Request, response: &mut Response) {
+        let is_html = response
+            .content_type()
+            .map_or(false, |content_type| content_type.is_html());
+
+        if !is_html {
+            return;
+        }
+
+        response.set_raw_header(
+            "Content-Security-Policy",
+            self.content_security_policy.clone(),
+        );
+        response.set_raw_header("X-Content-Type-Options", "nosniff");
+        response.set_raw_header("Referrer-Policy", "same-origin");
+        response.set_raw_header("X-Frame-Options", "DENY");
+    }
+}
+
+/// A handle for shutting the server down cleanly.
+///
+/// Clones of the handle share the same state. It's kept in rocket's managed
+/// state, along with the connection pool that it closes.
+#[derive(Clone)]
+pub struct Shutdown {
+    /// The number of requests being handled.
+    in_flight: Arc<AtomicUsize>,
+    /// Whether the server is shutting down.
+    stopping: Arc<AtomicBool>,
+    pool: ConnectionPool,
+}
+
+impl Shutdown {
+    /// Create a new handle for shutting down the server that uses `pool`.
+    fn new(pool: ConnectionPool) -> Shutdown {
+        Shutdown {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            stopping: Arc::new(AtomicBool::new(false)),
+            pool,
+        }
+    }
+
+    /// Shut down: wait up to `timeout` for the requests that are being handled
+    /// to finish, and then close the database connection pool.
+    ///
+    /// Rocket can't stop accepting connections, so the process should exit
+    /// right after this returns.
+    pub fn run(&self, timeout: Duration) {
+        self.stopping.store(true, Ordering::SeqCst);
+
+        let started = Instant::now();
+        let mut in_flight = self.in_flight.load(Ordering::SeqCst);
+
+        if in_flight > 0 {
+            info!("Waiting for {} requests to finish", in_flight);
+        }
+
+        while in_flight > 0 && started.elapsed() < timeout {
+            thread::sleep(Duration::from_millis(50));
+            in_flight = self.in_flight.load(Ordering::SeqCst);
+        }
+
+        if in_flight > 0 {
+            warn!(
+                "Gave up waiting for {} requests after {} seconds",
+                in_flight,
+                timeout.as_secs()
+            );
+        }
+
+        self.pool.close();
+        info!("Closed database connections");
+    }
+}
+
+/// A rocket fairing that counts the requests being handled, so that shutting
+/// down can wait for them. See `Shutdown`.
+pub struct ShutdownFairing(Shutdown);
+
+impl Fairing for ShutdownFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Shutdown Fairing",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, _request: &mut Request, _data: &Data) {
+        self.0.in_flight.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_response(&self, _request: &Request, response: &mut Response) {
+        // Don't keep connections open for more requests while shutting down.
+        if self.0.stopping.load(Ordering::SeqCst) {
+            response.set_raw_header("Connection", "close");
+        }
+
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A fairing that redirects requests for pages at paths that aren't canonical,
+/// like `/Tech/123/`, to their canonical path. See `routes::canonical_path`.
+struct CanonicalPathFairing;
+
+impl Fairing for CanonicalPathFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Canonical Path Fairing",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if request.method() != Method::Get && request.method() != Method::Head
         {
-            warn!("{}", msg);
-        } else {
-            info!("{}", msg);
+            return;
+        }
+
+        let uri = request.uri();
+
+        // Only paths with capital letters need the database, so only connect
+        // for those.
+        let mut db = None;
+        let board_exists = |name: &str| {
+            db.get_or_insert_with(|| {
+                request.guard::<PooledConnection>().succeeded()
+            })
+            .as_mut()
+            .map_or(false, |db| db.board(name).is_ok())
+        };
+
+        if let Some(path) = canonical_path(uri.path(), board_exists) {
+            let location = match uri.query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path,
+            };
+
+            *response = Response::build()
+                .status(Status::MovedPermanently)
+                .raw_header("Location", location)
+                .finalize();
         }
     }
 }
@@ -114,11 +305,35 @@ pub mod sql_types {
 }
 
 /// Create a new rocket instance for our application.
-pub fn new_instance(config: Config) -> Result<Rocket> {
+pub fn new_instance(mut config: Config) -> Result<Rocket> {
+    let database_uri = config.global_config.database_uri.clone();
+
+    // Migrations are run when the connection pool is created, so create it
+    // before using the database for anything else.
+    let pool = ConnectionPool::new(&database_uri)?;
+
+    let mut db = SingleConnection::establish(&database_uri)?;
+    config.load_secrets(&mut db)?;
+    config.check_home_redirects(&mut db)?;
+
+    if config.global_config.check_templates {
+        config.check_templates()?;
+    }
+
     let conf = config.global();
 
     let template_dir = conf.resource_dir.join("templates");
 
+    let security_headers = SecurityHeadersFairing {
+        content_security_policy: conf.content_security_policy.to_string(),
+    };
+
+    let log_fairing = LogFairing {
+        format: conf.log_format,
+    };
+
+    let shutdown = Shutdown::new(pool.clone());
+
     let rocket_conf = RocketConfig::build(Environment::Development)
         .address(conf.address)
         .port(conf.port)
@@ -127,10 +342,27 @@ pub fn new_instance(config: Config) -> Result<Rocket> {
         .finalize()
         .unwrap();
 
-    Ok(rocket::custom(rocket_conf)
+    let canonical_redirects = conf.canonical_redirects;
+
+    let mut rocket = rocket::custom(rocket_conf)
         .mount("/", crate::routes::routes())
-        .manage(ConnectionPool::new(conf.database_uri)?)
+        .register(rocket::catchers![crate::routes::staff::unauthorized])
+        .manage(pool)
         .manage(config)
-        .attach(Template::fairing())
-        .attach(LogFairing))
+        .manage(Downloads::default())
+        .manage(Previews::default())
+        .manage(Thumbnails::default())
+        .manage(shutdown.clone())
+        .attach(Template::fairing());
+
+    // Redirects are made before the other fairings see the response, so that
+    // they're logged as redirects.
+    if canonical_redirects {
+        rocket = rocket.attach(CanonicalPathFairing);
+    }
+
+    Ok(rocket
+        .attach(security_headers)
+        .attach(log_fairing)
+        .attach(ShutdownFairing(shutdown)))
 }
@@ -3,35 +3,113 @@
 use std::convert::TryInto;
 use std::fmt::Debug;
 
-use diesel::dsl::count;
+use chrono::{DateTime, Utc};
+
+use diesel::dsl::{count, max};
+use diesel::pg::Pg;
 use diesel::sql_types::{Integer, Text};
-use diesel::{delete, insert_into, prelude::*, sql_query, update};
+use diesel::{delete, insert_into, prelude::*, update};
 
 use rocket::uri;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use crate::config::SortMode;
 use crate::models::{Connection, *};
-use crate::schema::board;
+use crate::parse::MarkupMode;
+use crate::schema::{board, thread};
 use crate::{Error, Result};
 
 /// A collection of post threads about a similar topic.
-#[derive(Debug, Queryable, Serialize, Insertable)]
+#[derive(Debug, Queryable, Serialize, Deserialize, Insertable)]
 #[diesel(table_name = board)]
 pub struct Board {
     /// The unique name of the board.
     pub name: String,
     /// The description of the board.
     pub description: String,
+    /// How post bodies on the board are turned into HTML.
+    pub markup_mode: String,
+    /// Whether links to known video sites are shown as embedded videos.
+    pub allow_embeds: bool,
+    /// Whether new threads can be made on the board. Replies can still be made
+    /// to existing threads either way.
+    pub allow_new_threads: bool,
+    /// Whether the board is shown in the board navigation. Unlisted boards can
+    /// still be reached by their URI.
+    #[serde(default = "listed_default")]
+    pub listed: bool,
+    /// Whether the board is not safe for work.
+    #[serde(default)]
+    pub nsfw: bool,
+}
+
+/// Boards exported before they could be unlisted were all listed.
+fn listed_default() -> bool {
+    true
 }
 
 impl Board {
+    /// How post bodies on the board are turned into HTML.
+    pub fn markup_mode(&self) -> Result<MarkupMode> {
+        self.markup_mode.parse()
+    }
+
     /// The URI for the board.
+    ///
+    /// This leaves out the page number, so that it leads to the board's default
+    /// view.
     pub fn uri(&self) -> String {
-        uri!(crate::routes::board: &self.name, 1).to_string()
+        uri!(crate::routes::board: &self.name, _).to_string()
     }
 }
 
+/// The longest a board name can be.
+const MAX_BOARD_NAME_LEN: usize = 16;
+
+/// Names that can't be used for boards, because they would collide with other
+/// routes.
+///
+/// Any new top-level route should have its first path segment added here,
+/// along with names that are likely to be used for routes in the future.
+pub const RESERVED_BOARD_NAMES: &[&str] = &[
+    "api",
+    "file",
+    "form-help",
+    "gallery",
+    "healthz",
+    "metrics",
+    "options",
+    "page",
+    "staff",
+    "stats",
+];
+
+/// Check that a board name can be used in URIs and doesn't collide with other
+/// routes.
+///
+/// Board names must be 1 to 16 lowercase letters or digits.
+pub fn validate_board_name<S>(name: S) -> Result<()>
+where
+    S: AsRef<str>,
+{
+    let name = name.as_ref();
+
+    let is_valid = !name.is_empty()
+        && name.len() <= MAX_BOARD_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+
+    if !is_valid || RESERVED_BOARD_NAMES.contains(&name) {
+        return Err(Error::InvalidBoardName {
+            board_name: name.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
 /// A page location for a paginated resource, for example a page of threads.
 pub struct Page {
     /// The page number.
@@ -40,12 +118,74 @@ pub struct Page {
     pub width: u32,
 }
 
+/// A query for the threads on a board that aren't waiting for approval, in the
+/// order they're shown in.
+///
+/// Pinned threads come first, and then threads are ordered by `sort_mode`. If
+/// `shown_pinned` is given, only those pinned threads come first, and the
+/// rest are ordered with the unpinned threads.
+fn board_threads_query(
+    board_name: &str,
+    sort_mode: SortMode,
+    shown_pinned: Option<Vec<ThreadId>>,
+) -> thread::BoxedQuery<'_, Pg> {
+    let query = thread::table
+        .filter(thread::board.eq(board_name))
+        .filter(thread::pending.eq(false))
+        .into_boxed();
+
+    let query = match shown_pinned {
+        Some(ids) => query.order_by(thread::id.eq_any(ids).desc()),
+        None => query.order_by(thread::pinned.desc()),
+    };
+
+    match sort_mode {
+        SortMode::Bump => query.then_order_by(thread::bump_date.desc()),
+        SortMode::Created => query.then_order_by(thread::time_stamp.desc()),
+    }
+}
+
+/// A query for the IDs of the first `limit` pinned threads on a board, in the
+/// order they're shown in.
+fn pinned_threads_query(
+    board_name: &str,
+    sort_mode: SortMode,
+    limit: u32,
+) -> thread::BoxedQuery<'_, Pg, Integer> {
+    board_threads_query(board_name, sort_mode, None)
+        .filter(thread::pinned.eq(true))
+        .select(thread::id)
+        .limit(limit.into())
+}
+
+/// A query for the boards that are shown in the board navigation.
+fn listed_boards_query() -> board::BoxedQuery<'static, Pg> {
+    board::table.filter(board::listed.eq(true)).into_boxed()
+}
+
+/// A query for the names of the boards that are shown in the board
+/// navigation. Posts and files on other boards are left out of the site-wide
+/// recent posts and the gallery.
+pub(crate) fn listed_board_names() -> board::BoxedQuery<'static, Pg, Text> {
+    listed_boards_query().select(board::name)
+}
+
+/// A query for a board by name, whether or not it's listed.
+fn board_query(board_name: &str) -> board::BoxedQuery<'_, Pg> {
+    board::table.filter(board::name.eq(board_name)).into_boxed()
+}
+
 impl Page {
     /// The offset in items to the start of the page.
     ///
-    /// The offset to page 1 is 0.
-    pub fn offset(&self) -> u32 {
-        (self.num - 1) * self.width
+    /// The offset to page 1 is 0. There's no page 0, and pages too far in to
+    /// have an offset are out of range too.
+    pub fn offset(&self) -> Result<u32> {
+        if self.num == 0 {
+            return Err(Error::PageOutOfRange { page: 0 });
+        }
+
+        page_offset(self.num, self.width)
     }
 }
 
@@ -75,27 +215,42 @@ where
         Ok(board.load(&mut self.inner)?)
     }
 
-    /// Get a board.
+    /// Get the boards that are shown in the board navigation.
+    pub fn listed_boards(&mut self) -> Result<Vec<Board>> {
+        Ok(listed_boards_query().load(&mut self.inner)?)
+    }
+
+    /// Get a board. Unlisted boards are included.
     pub fn board<S>(&mut self, board_name: S) -> Result<Board>
     where
         S: Into<String>,
     {
-        use crate::schema::board::columns::name;
-        use crate::schema::board::dsl::board;
-
         let board_name = board_name.into();
 
-        Ok(board
-            .filter(name.eq(&board_name))
+        Ok(board_query(&board_name)
             .limit(1)
             .first(&mut self.inner)
             .map_err(conv_board_error(board_name))?)
     }
 
-    /// Insert a new board.
-    pub fn insert_board(&mut self, new_board: Board) -> Result<()> {
+    /// Insert a new board, if there are less than `max_boards` boards.
+    pub fn insert_board(
+        &mut self,
+        new_board: Board,
+        max_boards: Option<u32>,
+    ) -> Result<()> {
         use crate::schema::board::dsl::board;
 
+        validate_board_name(&new_board.name)?;
+
+        if let Some(max_boards) = max_boards {
+            let num_boards = self.all_boards()?.len();
+
+            if num_boards >= max_boards.try_into().unwrap() {
+                return Err(Error::BoardLimitReached { max_boards });
+            }
+        }
+
         insert_into(board)
             .values(&new_board)
             .execute(&mut self.inner)?;
@@ -103,17 +258,27 @@ where
         Ok(())
     }
 
-    /// Update a board.
+    /// Update a board. The markup mode, whether embeds are allowed, whether new
+    /// threads are allowed, and whether the board is listed are left as is if
+    /// `new_markup_mode`, `new_allow_embeds`, `new_allow_new_threads`, or
+    /// `new_listed` are `None`.
     pub fn update_board<S1, S2>(
         &mut self,
         board_name: S1,
         new_description: S2,
+        new_markup_mode: Option<MarkupMode>,
+        new_allow_embeds: Option<bool>,
+        new_allow_new_threads: Option<bool>,
+        new_listed: Option<bool>,
     ) -> Result<()>
     where
         S1: Into<String>,
         S2: AsRef<str>,
     {
-        use crate::schema::board::columns::{description, name};
+        use crate::schema::board::columns::{
+            allow_embeds, allow_new_threads, description, listed, markup_mode,
+            name,
+        };
         use crate::schema::board::dsl::board;
 
         let board_name = board_name.into();
@@ -121,6 +286,49 @@ where
         update(board.filter(name.eq(&board_name)))
             .set(description.eq(new_description.as_ref()))
             .execute(&mut self.inner)
+            .map_err(conv_board_error(board_name.clone()))?;
+
+        if let Some(new_markup_mode) = new_markup_mode {
+            update(board.filter(name.eq(&board_name)))
+                .set(markup_mode.eq(new_markup_mode.to_string()))
+                .execute(&mut self.inner)
+                .map_err(conv_board_error(board_name.clone()))?;
+        }
+
+        if let Some(new_allow_embeds) = new_allow_embeds {
+            update(board.filter(name.eq(&board_name)))
+                .set(allow_embeds.eq(new_allow_embeds))
+                .execute(&mut self.inner)
+                .map_err(conv_board_error(board_name.clone()))?;
+        }
+
+        if let Some(new_allow_new_threads) = new_allow_new_threads {
+            update(board.filter(name.eq(&board_name)))
+                .set(allow_new_threads.eq(new_allow_new_threads))
+                .execute(&mut self.inner)
+                .map_err(conv_board_error(board_name.clone()))?;
+        }
+
+        if let Some(new_listed) = new_listed {
+            update(board.filter(name.eq(&board_name)))
+                .set(listed.eq(new_listed))
+                .execute(&mut self.inner)
+                .map_err(conv_board_error(board_name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Mark a board as safe or not safe for work.
+    pub fn set_board_nsfw<S>(&mut self, board_name: S, nsfw: bool) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let board_name = board_name.into();
+
+        update(board::table.filter(board::name.eq(&board_name)))
+            .set(board::nsfw.eq(nsfw))
+            .execute(&mut self.inner)
             .map_err(conv_board_error(board_name))?;
 
         Ok(())
@@ -151,7 +359,8 @@ where
     /// Trim a board; delete any threads past the thread limit.
     ///
     /// This function deletes recursively, it will also delete any posts, files,
-    /// and reports associated with old threads.
+    /// and reports associated with old threads. Threads waiting for approval
+    /// don't count toward the limit and are never trimmed.
     pub fn trim_board<S>(
         &mut self,
         board_name: S,
@@ -163,57 +372,31 @@ where
         let board_name = board_name.into();
 
         self.inner.transaction::<_, Error, _>(|conn| {
-            let query = "DELETE FROM report R \
-                               USING post P, thread T \
-                               WHERE R.post = P.id \
-                                 AND P.thread = ANY ( \
-                                     SELECT id FROM thread \
-                                      WHERE board = $1 \
-                                   ORDER BY bump_date DESC \
-                                     OFFSET $2);";
-            sql_query(query)
-                .bind::<Text, _>(&board_name)
-                .bind::<Integer, i32>(max_threads.try_into().unwrap())
-                .execute(conn)
-                .map_err(conv_board_error(&board_name))?;
-
-            let query = "DELETE FROM file F \
-                               USING post P, thread T \
-                               WHERE F.post = P.id \
-                                 AND P.thread = ANY( \
-                                     SELECT id FROM thread \
-                                      WHERE board = $1 \
-                                   ORDER BY bump_date DESC \
-                                     OFFSET $2);";
-            sql_query(query)
-                .bind::<Text, _>(&board_name)
-                .bind::<Integer, i32>(max_threads.try_into().unwrap())
-                .execute(conn)
-                .map_err(conv_board_error(&board_name))?;
-
-            let query = "DELETE FROM post \
-                               WHERE thread = ANY( \
-                                     SELECT id FROM thread \
-                                      WHERE board = $1 \
-                                   ORDER BY bump_date DESC \
-                                     OFFSET $2)";
-            sql_query(query)
-                .bind::<Text, _>(&board_name)
-                .bind::<Integer, i32>(max_threads.try_into().unwrap())
-                .execute(conn)
-                .map_err(conv_board_error(&board_name))?;
-
-            let query = "DELETE FROM thread \
-                               WHERE id = ANY( \
-                                     SELECT id FROM thread \
-                                      WHERE board = $1 \
-                                   ORDER BY bump_date DESC \
-                                     OFFSET $2)";
-            sql_query(query)
-                .bind::<Text, _>(&board_name)
-                .bind::<Integer, i32>(max_threads.try_into().unwrap())
-                .execute(conn)
+            use crate::schema::{file, post};
+
+            let trimmed_threads: Vec<ThreadId> = thread::table
+                .filter(thread::board.eq(&board_name))
+                .filter(thread::pending.eq(false))
+                .order_by(thread::bump_date.desc())
+                .offset(max_threads.into())
+                .select(thread::id)
+                .load(conn)
                 .map_err(conv_board_error(&board_name))?;
+            let post_ids: Vec<PostId> = post::table
+                .filter(post::thread.eq_any(&trimmed_threads))
+                .select(post::id)
+                .load(conn)?;
+            delete_reports_for_posts(conn, &post_ids)?;
+
+            // Delete the threads selected above rather than selecting them
+            // again, so that a thread bumped in the meantime can't leave its
+            // posts behind.
+            delete(file::table.filter(file::post.eq_any(&post_ids)))
+                .execute(conn)?;
+            delete(post::table.filter(post::id.eq_any(&post_ids)))
+                .execute(conn)?;
+            delete(thread::table.filter(thread::id.eq_any(&trimmed_threads)))
+                .execute(conn)?;
 
             Ok(())
         })?;
@@ -223,30 +406,39 @@ where
 
     /// Get a single page of threads on a board.
     ///
-    /// The order is the bump order of the thread, i.e. sort by the timestamp of
-    /// the most recent post made to the thread which isn't a "no bump" post.
+    /// The order depends on `sort_mode`: either the bump order of the thread,
+    /// i.e. sort by the timestamp of the most recent post made to the thread
+    /// which isn't a "no bump" post, or the order the threads were created in.
     ///
-    /// Pinned threads are always displayed first and the order of pinned
-    /// threads is their bump order as well.
+    /// Pinned threads are displayed first and the order of pinned threads is
+    /// given by `sort_mode` as well. If `max_pinned` is given, only that many
+    /// pinned threads are displayed first, so that they can't push every other
+    /// thread off of the first page. The rest are sorted in with the unpinned
+    /// threads.
     pub fn thread_page<S>(
         &mut self,
         board_name: S,
         page: Page,
+        sort_mode: SortMode,
+        max_pinned: Option<u32>,
     ) -> Result<Vec<Thread>>
     where
         S: Into<String>,
     {
-        use crate::schema::thread::columns::{board, bump_date, pinned};
-        use crate::schema::thread::dsl::thread;
-
         let board_name = board_name.into();
 
-        thread
-            .filter(board.eq(&board_name))
-            .order_by(pinned.desc())
-            .then_order_by(bump_date.desc())
+        let shown_pinned = match max_pinned {
+            Some(limit) => Some(
+                pinned_threads_query(&board_name, sort_mode, limit)
+                    .load(&mut self.inner)
+                    .map_err(conv_board_error(board_name.clone()))?,
+            ),
+            None => None,
+        };
+
+        board_threads_query(&board_name, sort_mode, shown_pinned)
             .limit(page.width as i64)
-            .offset(page.offset() as i64)
+            .offset(i64::from(page.offset()?))
             .load(&mut self.inner)
             .map_err(conv_board_error(board_name))
     }
@@ -260,13 +452,14 @@ where
     where
         S: Into<String>,
     {
-        use crate::schema::thread::columns::{board, id};
+        use crate::schema::thread::columns::{board, id, pending};
         use crate::schema::thread::dsl::thread;
 
         let board_name = board_name.into();
 
         let thread_count: i64 = thread
             .filter(board.eq(&board_name))
+            .filter(pending.eq(false))
             .select(count(id))
             .first(&mut self.inner)
             .map_err(conv_board_error(board_name))?;
@@ -274,10 +467,80 @@ where
         Ok((thread_count as f64 / page_width as f64).ceil() as u32)
     }
 
+    /// When the newest thread on a board was created, if it has any threads.
+    pub fn newest_thread_time<S>(
+        &mut self,
+        board_name: S,
+    ) -> Result<Option<DateTime<Utc>>>
+    where
+        S: Into<String>,
+    {
+        use crate::schema::thread::columns::{board, time_stamp};
+        use crate::schema::thread::dsl::thread;
+
+        let board_name = board_name.into();
+
+        thread
+            .filter(board.eq(&board_name))
+            .select(max(time_stamp))
+            .first(&mut self.inner)
+            .map_err(conv_board_error(board_name))
+    }
+
+    /// The number of threads on the given board whose first post was made by
+    /// the given user.
+    pub fn user_thread_count_on_board<S>(
+        &mut self,
+        uid: UserId,
+        board_name: S,
+    ) -> Result<u32>
+    where
+        S: Into<String>,
+    {
+        use crate::schema::post::columns as post_columns;
+        use crate::schema::thread::columns as thread_columns;
+        use crate::schema::thread::dsl::thread;
+
+        let board_name = board_name.into();
+
+        let (inner_post, outer_post) = alias!(
+            crate::schema::post as inner_post,
+            crate::schema::post as outer_post
+        );
+
+        // The ID of the first (original) post of each thread.
+        let first_post_id = {
+            inner_post
+                .select(inner_post.field(post_columns::id))
+                .filter(
+                    inner_post
+                        .field(post_columns::thread)
+                        .eq(thread_columns::id),
+                )
+                .order_by(inner_post.field(post_columns::id).asc())
+                .limit(1)
+        };
+
+        let thread_count: i64 = outer_post
+            .inner_join(thread)
+            .select(count(outer_post.field(post_columns::id)))
+            .filter(thread_columns::board.eq(&board_name))
+            .filter(outer_post.field(post_columns::user_id).eq(uid))
+            .filter(outer_post.field(post_columns::id).eq_any(first_post_id))
+            .first(&mut self.inner)
+            .map_err(conv_board_error(board_name))?;
+
+        Ok(thread_count.try_into().unwrap())
+    }
+
     /// All of the first posts of threads on the given board.
     ///
     /// The order here is the same as `thread_page`.
-    pub fn first_posts<S>(&mut self, board_name: S) -> Result<Vec<Post>>
+    pub fn first_posts<S>(
+        &mut self,
+        board_name: S,
+        sort_mode: SortMode,
+    ) -> Result<Vec<Post>>
     where
         S: Into<String>,
     {
@@ -303,7 +566,9 @@ where
             inner_post
                 .select(inner_post.field(post_columns::id))
                 .filter(
-                    inner_post.field(post_columns::id).eq(thread_columns::id),
+                    inner_post
+                        .field(post_columns::thread)
+                        .eq(thread_columns::id),
                 )
                 .order_by(inner_post.field(post_columns::id).asc())
                 .limit(1)
@@ -312,7 +577,7 @@ where
         // Here, we join the two tables, post (aliased to outer_post), and
         // thread. This allows us to use the above SQL statement to filter out
         // only the first posts.
-        outer_post
+        let query = outer_post
             .inner_join(thread)
             .select(outer_post.fields((
                 post_columns::id,
@@ -326,12 +591,367 @@ where
                 post_columns::board,
                 post_columns::user_id,
                 post_columns::no_bump,
+                post_columns::system,
+                post_columns::author_ident_class,
+                post_columns::truncated_html,
+                post_columns::embeds,
+                post_columns::pending,
+                post_columns::delete_token_hash,
             )))
             .filter(outer_post.field(post_columns::board).eq(&board_name))
             .filter(outer_post.field(post_columns::id).eq_any(first_post_id))
-            .order_by(thread_columns::pinned.desc())
-            .then_order_by(thread_columns::bump_date.desc())
-            .load(&mut self.inner)
-            .map_err(conv_board_error(board_name))
+            .filter(thread_columns::pending.eq(false))
+            .order_by(thread_columns::pinned.desc());
+
+        let posts = match sort_mode {
+            SortMode::Bump => query
+                .then_order_by(thread_columns::bump_date.desc())
+                .load(&mut self.inner),
+            SortMode::Created => query
+                .then_order_by(thread_columns::time_stamp.desc())
+                .load(&mut self.inner),
+        };
+
+        posts.map_err(conv_board_error(board_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::debug_query;
+    use diesel::pg::Pg;
+
+    use super::{
+        board_threads_query, pinned_threads_query, validate_board_name, Board,
+        Page,
+    };
+    use crate::config::SortMode;
+    use crate::Error;
+
+    #[test]
+    fn thread_sort_modes() {
+        let bump_sql = debug_query::<Pg, _>(&board_threads_query(
+            "b",
+            SortMode::Bump,
+            None,
+        ))
+        .to_string();
+        let created_sql = debug_query::<Pg, _>(&board_threads_query(
+            "b",
+            SortMode::Created,
+            None,
+        ))
+        .to_string();
+
+        assert_ne!(bump_sql, created_sql);
+
+        assert!(bump_sql.contains(
+            r#"ORDER BY "thread"."pinned" DESC, "thread"."bump_date" DESC"#
+        ));
+        assert!(created_sql.contains(
+            r#"ORDER BY "thread"."pinned" DESC, "thread"."time_stamp" DESC"#
+        ));
+    }
+
+    #[test]
+    fn bumped_thread_order() {
+        use chrono::{Duration, Utc};
+        use diesel::prelude::*;
+
+        use crate::models::{testing, Thread};
+        use crate::schema::thread;
+
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "order");
+        let old = testing::thread(&mut db, "order", false);
+        let new = testing::thread(&mut db, "order", false);
+
+        // The old thread was made first, but was bumped most recently.
+        let now = Utc::now();
+        for (id, created, bumped) in [(old, 3, 0), (new, 2, 1)] {
+            diesel::update(thread::table.find(id))
+                .set((
+                    thread::time_stamp.eq(now - Duration::hours(created)),
+                    thread::bump_date.eq(now - Duration::hours(bumped)),
+                ))
+                .execute(&mut db.inner)
+                .unwrap();
+        }
+
+        let mut ids = |sort_mode| {
+            let page = Page { num: 1, width: 10 };
+            let threads = db.thread_page("order", page, sort_mode, None);
+            let threads: Vec<Thread> = threads.unwrap();
+            threads.into_iter().map(|thread| thread.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(SortMode::Bump), [old, new]);
+        assert_eq!(ids(SortMode::Created), [new, old]);
+    }
+
+    #[test]
+    fn pinned_thread_cap() {
+        let pinned_sql =
+            debug_query::<Pg, _>(&pinned_threads_query("b", SortMode::Bump, 2))
+                .to_string();
+
+        assert!(pinned_sql.starts_with(r#"SELECT "thread"."id" FROM"#));
+        assert!(pinned_sql.contains(r#""thread"."pinned" = $3"#));
+        assert!(pinned_sql.contains("LIMIT $4"));
+        assert!(pinned_sql.ends_with(r#"-- binds: ["b", false, true, 2]"#));
+
+        // Only the shown pinned threads are sorted first, so the rest of the
+        // pinned threads don't push other threads off of the first page.
+        let capped_sql = debug_query::<Pg, _>(&board_threads_query(
+            "b",
+            SortMode::Bump,
+            Some(vec![4, 7]),
+        ))
+        .to_string();
+
+        assert!(
+            capped_sql.contains(r#"ORDER BY "thread"."id" = ANY($3) DESC"#)
+        );
+        assert!(capped_sql.contains(r#"DESC, "thread"."bump_date" DESC"#));
+        assert!(!capped_sql.contains(r#""thread"."pinned" DESC"#));
+        assert!(capped_sql.ends_with(r#"-- binds: ["b", false, [4, 7]]"#));
+    }
+
+    #[test]
+    fn pinned_threads_over_cap() {
+        use chrono::{Duration, Utc};
+        use diesel::prelude::*;
+
+        use crate::models::{testing, PooledConnection, Thread};
+        use crate::schema::thread;
+
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        // Threads made in the same transaction are all bumped at the same
+        // time, so give each one its own bump time.
+        let mut hours_ago = 0;
+        let mut new_thread = |db: &mut PooledConnection, pinned| {
+            let id = testing::thread(db, "sticky", false);
+
+            if pinned {
+                db.pin_thread(id).unwrap();
+            }
+
+            hours_ago += 1;
+            let bump_date = Utc::now() - Duration::hours(hours_ago);
+            diesel::update(thread::table.find(id))
+                .set(thread::bump_date.eq(bump_date))
+                .execute(&mut db.inner)
+                .unwrap();
+
+            id
+        };
+
+        testing::board(&mut db, "sticky");
+        let regular: Vec<_> =
+            (0..3).map(|_| new_thread(&mut db, false)).collect();
+        let pinned: Vec<_> =
+            (0..4).map(|_| new_thread(&mut db, true)).collect();
+
+        let page = || Page { num: 1, width: 4 };
+        let ids = |threads: Vec<Thread>| {
+            threads.into_iter().map(|thread| thread.id).collect::<Vec<_>>()
+        };
+
+        // Without a cap, the pinned threads fill the first page.
+        let threads = db.thread_page("sticky", page(), SortMode::Bump, None);
+        assert_eq!(ids(threads.unwrap()), pinned);
+
+        // With a cap, the newest pinned threads come first, then the other
+        // threads in bump order.
+        let threads = db.thread_page("sticky", page(), SortMode::Bump, Some(2));
+        assert_eq!(
+            ids(threads.unwrap()),
+            [pinned[0], pinned[1], regular[0], regular[1]]
+        );
+
+        // The pinned threads left out at the top are sorted in after the
+        // regular threads, which were bumped more recently.
+        let page = Page { num: 2, width: 4 };
+        let threads = db.thread_page("sticky", page, SortMode::Bump, Some(2));
+        assert_eq!(ids(threads.unwrap()), [regular[2], pinned[2], pinned[3]]);
+    }
+
+    #[test]
+    fn held_threads_arent_trimmed() {
+        use chrono::{Duration, Utc};
+        use diesel::prelude::*;
+
+        use crate::models::testing;
+        use crate::schema::thread;
+
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "full");
+        let shown: Vec<_> = (0..2)
+            .map(|_| testing::thread(&mut db, "full", false))
+            .collect();
+
+        // The held thread is the newest, so it would push out a shown thread
+        // if it counted toward the limit.
+        diesel::update(thread::table.filter(thread::id.eq_any(&shown)))
+            .set(thread::bump_date.eq(Utc::now() - Duration::hours(1)))
+            .execute(&mut db.inner)
+            .unwrap();
+        let held = testing::thread(&mut db, "full", true);
+
+        db.trim_board("full", 2).unwrap();
+
+        for id in shown.iter().chain([&held]) {
+            assert!(db.thread(*id).is_ok(), "thread {} was trimmed", id);
+        }
+    }
+
+    #[test]
+    fn unlisted_boards() {
+        use crate::models::{testing, NewFile};
+
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "shown");
+        db.insert_board(
+            Board {
+                name: "hidden".into(),
+                description: String::new(),
+                markup_mode: "longboard".into(),
+                allow_embeds: false,
+                allow_new_threads: true,
+                listed: false,
+                nsfw: false,
+            },
+            None,
+        )
+        .unwrap();
+
+        let user = testing::user(&mut db);
+        let mut post_ids = Vec::new();
+
+        for board_name in &["shown", "hidden"] {
+            let thread_id = testing::thread(&mut db, board_name, false);
+            let post_id =
+                testing::post(&mut db, board_name, thread_id, &user, false);
+
+            db.insert_file(NewFile {
+                save_name: format!("{}.png", board_name),
+                thumb_name: format!("{}-thumb.png", board_name),
+                catalog_thumb_name: None,
+                orig_name: None,
+                content_type: "image/png".into(),
+                is_spoiler: false,
+                post: post_id,
+            })
+            .unwrap();
+
+            post_ids.push(post_id);
+        }
+
+        let (shown_post, hidden_post) = (post_ids[0], post_ids[1]);
+
+        // The navigation.
+        let listed: Vec<String> = db
+            .listed_boards()
+            .unwrap()
+            .into_iter()
+            .map(|board| board.name)
+            .collect();
+        assert!(listed.contains(&"shown".to_string()));
+        assert!(!listed.contains(&"hidden".to_string()));
+
+        // The home page.
+        let recent: Vec<_> =
+            db.recent_posts(100).unwrap().iter().map(|p| p.id).collect();
+        assert!(recent.contains(&shown_post));
+        assert!(!recent.contains(&hidden_post));
+
+        let recent: Vec<_> =
+            db.recent_files(100).unwrap().iter().map(|f| f.post_id).collect();
+        assert!(recent.contains(&shown_post));
+        assert!(!recent.contains(&hidden_post));
+
+        // The gallery.
+        let gallery: Vec<_> = db
+            .recent_files_before(None, 100)
+            .unwrap()
+            .iter()
+            .map(|f| f.post_id)
+            .collect();
+        assert!(gallery.contains(&shown_post));
+        assert!(!gallery.contains(&hidden_post));
+
+        // The board's own pages still work.
+        assert!(!db.board("hidden").unwrap().listed);
+        let page = Page { num: 1, width: 10 };
+        let threads = db
+            .thread_page("hidden", page, SortMode::Bump, None)
+            .unwrap();
+        assert_eq!(threads.len(), 1);
+        assert!(db.post(hidden_post).is_ok());
+    }
+
+    #[test]
+    fn page_offsets() {
+        assert_eq!(Page { num: 1, width: 10 }.offset().unwrap(), 0);
+        assert_eq!(Page { num: 3, width: 10 }.offset().unwrap(), 20);
+
+        for num in [0, u32::MAX] {
+            assert!(matches!(
+                Page { num, width: 10 }.offset(),
+                Err(Error::PageOutOfRange { page }) if page == num
+            ));
+        }
+    }
+
+    #[test]
+    fn valid_board_names() {
+        for name in &["b", "g", "tech", "v4", "abcdefghijklmnop"] {
+            assert!(validate_board_name(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn invalid_board_names() {
+        for name in &["", "Tech", "my board", "a/b", "a-b", "abcdefghijklmnopq"]
+        {
+            assert!(
+                matches!(
+                    validate_board_name(name),
+                    Err(Error::InvalidBoardName { .. })
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_board_names() {
+        for name in &["file", "staff", "page", "options", "gallery"] {
+            assert!(
+                matches!(
+                    validate_board_name(name),
+                    Err(Error::InvalidBoardName { .. })
+                ),
+                "{}",
+                name
+            );
+        }
     }
 }
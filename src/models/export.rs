@@ -0,0 +1,436 @@
+//! Types related to exporting and importing whole boards.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use diesel::dsl::exists;
+use diesel::{insert_into, prelude::*, select};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Connection, *};
+use crate::parse::remap_post_refs;
+use crate::{Error, Result};
+
+/// A board with all of its threads, posts, files, and reports, along with the
+/// users that made them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BoardExport {
+    /// The board.
+    pub board: Board,
+    /// The threads on the board, oldest first.
+    pub threads: Vec<ThreadExport>,
+    /// The users that made posts or reports on the board.
+    pub users: Vec<User>,
+}
+
+/// An exported thread.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThreadExport {
+    /// The thread.
+    pub thread: Thread,
+    /// The posts in the thread, oldest first.
+    pub posts: Vec<PostExport>,
+}
+
+/// An exported post.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostExport {
+    /// The post.
+    pub post: Post,
+    /// The files attached to the post.
+    pub files: Vec<File>,
+    /// The reports made about the post.
+    pub reports: Vec<Report>,
+}
+
+impl BoardExport {
+    /// All of the files in the export.
+    pub fn files(&self) -> impl Iterator<Item = &File> {
+        self.threads
+            .iter()
+            .flat_map(|thread| thread.posts.iter())
+            .flat_map(|post| post.files.iter())
+    }
+
+    /// The number of posts in the export.
+    pub fn post_count(&self) -> usize {
+        self.threads.iter().map(|thread| thread.posts.len()).sum()
+    }
+}
+
+/// The error for a post in an export that refers to a thread that isn't in it.
+fn missing_thread(post_id: PostId, thread_id: ThreadId) -> Error {
+    Error::InvalidExport {
+        reason: format!(
+            "post #{} is in missing thread #{}",
+            post_id, thread_id
+        ),
+    }
+}
+
+/// The error for a post in an export that refers to a user that isn't in it.
+fn missing_user(post_id: PostId, user_id: UserId) -> Error {
+    Error::InvalidExport {
+        reason: format!("post #{} refers to missing user {}", post_id, user_id),
+    }
+}
+
+impl<C, M> Connection<C, M>
+where
+    C: InnerConnection<M> + diesel::connection::LoadConnection,
+    M: diesel::connection::TransactionManager<C>,
+{
+    /// Export a board with everything on it.
+    pub fn export_board<S>(&mut self, board_name: S) -> Result<BoardExport>
+    where
+        S: Into<String>,
+    {
+        use crate::schema::anon_user::dsl::{anon_user, id as user_id};
+        use crate::schema::file::dsl::file;
+        use crate::schema::post::dsl::{
+            board as post_board, id as post_id, post,
+        };
+        use crate::schema::report::dsl::{id as report_id, report};
+        use crate::schema::thread::dsl::{board as thread_board, id, thread};
+
+        let board_name = board_name.into();
+        let board = self.board(&board_name)?;
+
+        self.inner.transaction::<_, Error, _>(|conn| {
+            let threads: Vec<Thread> = thread
+                .filter(thread_board.eq(&board_name))
+                .order(id.asc())
+                .load(conn)?;
+
+            let posts: Vec<Post> = post
+                .filter(post_board.eq(&board_name))
+                .order(post_id.asc())
+                .load(conn)?;
+
+            let files: Vec<DbFile> = file
+                .inner_join(post)
+                .filter(post_board.eq(&board_name))
+                .select(crate::schema::file::all_columns)
+                .load(conn)?;
+
+            let reports: Vec<Report> = report
+                .inner_join(post)
+                .filter(post_board.eq(&board_name))
+                .order(report_id.asc())
+                .select(crate::schema::report::all_columns)
+                .load(conn)?;
+
+            let mut user_ids: Vec<UserId> = posts
+                .iter()
+                .map(|p| p.user_id)
+                .chain(reports.iter().map(|r| r.user_id))
+                .collect();
+            user_ids.sort_unstable();
+            user_ids.dedup();
+
+            let users: Vec<User> = anon_user
+                .filter(user_id.eq_any(user_ids))
+                .order(user_id.asc())
+                .load(conn)?;
+
+            let mut post_exports: HashMap<ThreadId, Vec<PostExport>> =
+                HashMap::new();
+            let mut files_by_post: HashMap<PostId, Vec<File>> = HashMap::new();
+            let mut reports_by_post: HashMap<PostId, Vec<Report>> =
+                HashMap::new();
+
+            for f in files {
+                files_by_post
+                    .entry(f.post_id)
+                    .or_default()
+                    .push(File::from(f));
+            }
+
+            for r in reports {
+                reports_by_post.entry(r.post_id).or_default().push(r);
+            }
+
+            for p in posts {
+                post_exports
+                    .entry(p.thread_id)
+                    .or_default()
+                    .push(PostExport {
+                        files: files_by_post.remove(&p.id).unwrap_or_default(),
+                        reports: reports_by_post
+                            .remove(&p.id)
+                            .unwrap_or_default(),
+                        post: p,
+                    });
+            }
+
+            let threads = threads
+                .into_iter()
+                .map(|t| ThreadExport {
+                    posts: post_exports.remove(&t.id).unwrap_or_default(),
+                    thread: t,
+                })
+                .collect();
+
+            Ok(BoardExport {
+                board,
+                threads,
+                users,
+            })
+        })
+    }
+
+    /// Import an exported board. The board must not already exist.
+    ///
+    /// Threads, posts, and reports are given new IDs, but are inserted in the
+    /// same order as they were exported, and references between posts on the
+    /// board are updated to point to the new IDs. Users are matched up with
+    /// existing users by their hash.
+    pub fn import_board(&mut self, export: &BoardExport) -> Result<()> {
+        use crate::schema::anon_user::dsl::{anon_user, hash, id as user_id};
+        use crate::schema::board::dsl::board;
+        use crate::schema::file::dsl::file;
+        use crate::schema::post::dsl::{id as post_id, post};
+        use crate::schema::report::dsl::report;
+        use crate::schema::thread::dsl::{id as thread_id, thread};
+
+        let board_name = &export.board.name;
+
+        validate_board_name(board_name)?;
+
+        self.inner.transaction::<_, Error, _>(|conn| {
+            let board_exists: bool = select(exists(
+                board.filter(crate::schema::board::name.eq(board_name)),
+            ))
+            .get_result(conn)?;
+
+            if board_exists {
+                return Err(Error::BoardAlreadyExists {
+                    board_name: board_name.clone(),
+                });
+            }
+
+            insert_into(board).values(&export.board).execute(conn)?;
+
+            let mut user_ids = HashMap::new();
+
+            for user in &export.users {
+                let existing = anon_user
+                    .filter(hash.eq(&user.hash))
+                    .select(user_id)
+                    .first(conn)
+                    .optional()?;
+
+                let new_id = match existing {
+                    Some(existing) => existing,
+                    None => insert_into(anon_user)
+                        .values(&NewUser {
+                            hash: user.hash.clone(),
+                            ban_expires: user.ban_expires,
+                            note: user.note.clone(),
+                            ip: user.ip.clone(),
+                        })
+                        .returning(user_id)
+                        .get_result(conn)?,
+                };
+
+                user_ids.insert(user.id, new_id);
+            }
+
+            let mut thread_ids = HashMap::new();
+
+            for t in &export.threads {
+                let t = &t.thread;
+
+                let new_id: ThreadId = insert_into(thread)
+                    .values((
+                        &NewThread {
+                            subject: t.subject.clone(),
+                            board: board_name.clone(),
+                            locked: t.locked,
+                            pinned: t.pinned,
+                            cyclical: t.cyclical,
+                            pending: t.pending,
+                        },
+                        crate::schema::thread::time_stamp.eq(t.time_stamp),
+                        crate::schema::thread::bump_date.eq(t.bump_date),
+                        crate::schema::thread::post_count.eq(t.post_count),
+                        crate::schema::thread::file_count.eq(t.file_count),
+                    ))
+                    .returning(thread_id)
+                    .get_result(conn)?;
+
+                thread_ids.insert(t.id, new_id);
+            }
+
+            // Posts are inserted in the order they were originally made, so
+            // that a post is always inserted after the posts it references.
+            let mut posts: Vec<&PostExport> =
+                export.threads.iter().flat_map(|t| t.posts.iter()).collect();
+            posts.sort_by_key(|p| p.post.id);
+
+            let mut post_ids = HashMap::new();
+
+            for p in posts {
+                let old = &p.post;
+
+                let new_thread_id = *thread_ids
+                    .get(&old.thread_id)
+                    .ok_or_else(|| missing_thread(old.id, old.thread_id))?;
+                let new_user_id = *user_ids
+                    .get(&old.user_id)
+                    .ok_or_else(|| missing_user(old.id, old.user_id))?;
+
+                let remap = |html: &str| {
+                    remap_post_refs(html, board_name, &thread_ids, &post_ids)
+                };
+
+                let new_id: PostId = insert_into(post)
+                    .values((
+                        &NewPost {
+                            body: remap(&old.body),
+                            author_name: old.author_name.clone(),
+                            author_contact: old.author_contact.clone(),
+                            author_ident: old.author_ident.clone(),
+                            delete_hash: old.delete_hash.clone(),
+                            thread: new_thread_id,
+                            board: board_name.clone(),
+                            user_id: new_user_id,
+                            no_bump: old.no_bump,
+                            system: old.system,
+                            author_ident_class: old.author_ident_class.clone(),
+                            truncated_html: old
+                                .truncated_html
+                                .as_deref()
+                                .map(remap),
+                            embeds: old.embeds.clone(),
+                            pending: old.pending,
+                            delete_token_hash: old.delete_token_hash.clone(),
+                        },
+                        crate::schema::post::time_stamp.eq(old.time_stamp),
+                    ))
+                    .returning(post_id)
+                    .get_result(conn)?;
+
+                post_ids.insert(old.id, new_id);
+
+                for f in &p.files {
+                    insert_into(file)
+                        .values(&NewFile {
+                            save_name: f.save_name.clone(),
+                            thumb_name: f.thumb_name.clone(),
+                            catalog_thumb_name: f.catalog_thumb_name.clone(),
+                            orig_name: f.orig_name.clone(),
+                            content_type: f.content_type.to_string(),
+                            is_spoiler: f.is_spoiler,
+                            post: new_id,
+                        })
+                        .execute(conn)?;
+                }
+
+                for r in &p.reports {
+                    let reporter_id = *user_ids
+                        .get(&r.user_id)
+                        .ok_or_else(|| missing_user(old.id, r.user_id))?;
+
+                    insert_into(report)
+                        .values((
+                            &NewReport {
+                                reason: r.reason.clone(),
+                                post: new_id,
+                                user_id: reporter_id,
+                            },
+                            crate::schema::report::time_stamp.eq(r.time_stamp),
+                            crate::schema::report::closed_at.eq(r.closed_at),
+                        ))
+                        .execute(conn)?;
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::testing;
+
+    #[test]
+    fn round_trip() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "roundtrip");
+        let user = testing::user(&mut db);
+        let old_thread = testing::thread(&mut db, "roundtrip", false);
+        let old_first =
+            testing::post(&mut db, "roundtrip", old_thread, &user, false);
+        let old_reply = db
+            .insert_post(NewPost {
+                body: format!(
+                    "<a class=\"post-ref\" href=\"/roundtrip/{}#{}\">{}</a>",
+                    old_thread, old_first, old_first
+                ),
+                ..testing::new_post("roundtrip", old_thread, &user)
+            })
+            .unwrap();
+        db.insert_report(NewReport {
+            reason: "spam".into(),
+            post: old_reply,
+            user_id: user.id,
+        })
+        .unwrap();
+
+        let export = db.export_board("roundtrip").unwrap();
+        db.delete_board("roundtrip").unwrap();
+        db.import_board(&export).unwrap();
+
+        let import = db.export_board("roundtrip").unwrap();
+        assert_eq!(import.users.len(), 1);
+        assert_eq!(import.users[0].hash, user.hash);
+        assert_eq!(import.threads.len(), 1);
+
+        let thread = &import.threads[0];
+        assert_ne!(thread.thread.id, old_thread);
+        assert_eq!(thread.thread.subject, export.threads[0].thread.subject);
+        assert_eq!(thread.posts.len(), 2);
+
+        let (first, reply) = (&thread.posts[0], &thread.posts[1]);
+        assert_eq!(
+            reply.post.body,
+            format!(
+                "<a class=\"post-ref\" href=\"/roundtrip/{}#{}\">{}</a>",
+                thread.thread.id, first.post.id, first.post.id
+            )
+        );
+        assert_eq!(reply.reports.len(), 1);
+        assert_eq!(reply.reports[0].reason, "spam");
+    }
+
+    #[test]
+    fn import_with_missing_user() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "badexport");
+        let user = testing::user(&mut db);
+        let thread_id = testing::thread(&mut db, "badexport", false);
+        testing::post(&mut db, "badexport", thread_id, &user, false);
+
+        let mut export = db.export_board("badexport").unwrap();
+        db.delete_board("badexport").unwrap();
+        export.users.clear();
+
+        assert!(matches!(
+            db.import_board(&export),
+            Err(Error::InvalidExport { .. })
+        ));
+        assert!(db.board("badexport").is_err());
+    }
+}
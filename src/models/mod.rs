@@ -10,6 +10,7 @@
 
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
 
 use diesel::dsl::exists;
 use diesel::r2d2;
@@ -33,6 +34,8 @@ pub mod post;
 pub use post::*;
 pub mod staff;
 pub use staff::*;
+pub mod export;
+pub use export::*;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
@@ -46,8 +49,25 @@ fn run_migrations(
     Ok(())
 }
 
+/// The offset in items to the start of page `page`, where pages start at 1 and
+/// have `per_page` items each.
+///
+/// Pages too far in to have an offset are out of range.
+pub(crate) fn page_offset(page: u32, per_page: u32) -> Result<u32> {
+    page.saturating_sub(1)
+        .checked_mul(per_page)
+        .ok_or(Error::PageOutOfRange { page })
+}
+
+/// The manager for the connections in a `ConnectionPool`.
+type PoolManager = r2d2::ConnectionManager<PgConnection>;
+
 /// A PostgreSQL connection pool.
-pub struct ConnectionPool(r2d2::Pool<r2d2::ConnectionManager<PgConnection>>);
+///
+/// Clones of the pool share the same connections. The pool can be closed when
+/// shutting down, after which no more connections can be taken from it.
+#[derive(Clone)]
+pub struct ConnectionPool(Arc<RwLock<Option<r2d2::Pool<PoolManager>>>>);
 
 impl ConnectionPool {
     /// Create a new connection pool.
@@ -60,7 +80,26 @@ impl ConnectionPool {
 
         run_migrations(&mut pool.get()?)?;
 
-        Ok(ConnectionPool(pool))
+        Ok(ConnectionPool(Arc::new(RwLock::new(Some(pool)))))
+    }
+
+    /// Take a connection from the pool.
+    ///
+    /// The lock is released before waiting for a connection, so that closing
+    /// the pool isn't held up by requests waiting on a busy pool.
+    fn get(&self) -> Result<r2d2::PooledConnection<PoolManager>> {
+        let pool = self.0.read().unwrap().clone();
+
+        match pool {
+            Some(pool) => Ok(pool.get()?),
+            None => Err(Error::ConnectionPoolClosed),
+        }
+    }
+
+    /// Close the pool. Idle connections are closed right away, and connections
+    /// that are in use are closed when they're returned.
+    pub fn close(&self) {
+        self.0.write().unwrap().take();
     }
 }
 
@@ -78,7 +117,7 @@ impl<'a, 'r> FromRequest<'a, 'r> for PooledConnection {
     type Error = Error;
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
-        let ConnectionPool(pool) = request
+        let pool = request
             .guard::<State<ConnectionPool>>()
             .expect("expected database connection pool to be initialized")
             .inner();
@@ -88,10 +127,10 @@ impl<'a, 'r> FromRequest<'a, 'r> for PooledConnection {
                 inner: conn,
                 manager: PhantomData,
             }),
-            Err(err) => Outcome::Failure((
-                Status::InternalServerError,
-                Error::from(err),
-            )),
+            Err(err @ Error::ConnectionPoolClosed) => {
+                Outcome::Failure((Status::ServiceUnavailable, err))
+            }
+            Err(err) => Outcome::Failure((Status::InternalServerError, err)),
         }
     }
 }
@@ -147,3 +186,130 @@ where
         write!(fmt, "<database connection>")
     }
 }
+
+/// Helpers for tests that need a database.
+///
+/// These tests only run when `LONGBOARD_TEST_DATABASE` is set to the URI of a
+/// database they can use, and pass without doing anything otherwise. Each test
+/// runs in a transaction that's never committed, so nothing is left behind.
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::marker::PhantomData;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Once;
+
+    use diesel::r2d2;
+    use diesel::Connection as _;
+
+    use super::{run_migrations, PoolManager, PooledConnection};
+    use crate::models::*;
+
+    /// Migrations are only run by the first test to connect.
+    static MIGRATIONS_RUN: Once = Once::new();
+
+    /// Used to give each test user a different hash.
+    static NEXT_USER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Connect to the test database, if there is one.
+    pub fn connect() -> Option<PooledConnection> {
+        let uri = std::env::var("LONGBOARD_TEST_DATABASE").ok()?;
+
+        let pool = r2d2::Pool::builder()
+            .max_size(1)
+            .build(PoolManager::new(uri))
+            .expect("couldn't connect to the test database");
+        let mut inner = pool.get().unwrap();
+
+        MIGRATIONS_RUN.call_once(|| run_migrations(&mut inner).unwrap());
+        inner.begin_test_transaction().unwrap();
+
+        Some(PooledConnection {
+            inner,
+            manager: PhantomData,
+        })
+    }
+
+    /// Insert a listed board.
+    pub fn board(db: &mut PooledConnection, name: &str) {
+        let board = Board {
+            name: name.into(),
+            description: String::new(),
+            markup_mode: "longboard".into(),
+            allow_embeds: false,
+            allow_new_threads: true,
+            listed: true,
+            nsfw: false,
+        };
+
+        db.insert_board(board, None).unwrap();
+    }
+
+    /// Insert a new user.
+    pub fn user(db: &mut PooledConnection) -> User {
+        let n = NEXT_USER.fetch_add(1, Ordering::SeqCst);
+
+        db.insert_user(&NewUser {
+            hash: format!("test-user-{}-{}", std::process::id(), n),
+            ban_expires: None,
+            note: None,
+            ip: "127.0.0.1".into(),
+        })
+        .unwrap()
+    }
+
+    /// Insert a thread on a board.
+    pub fn thread(
+        db: &mut PooledConnection,
+        board: &str,
+        pending: bool,
+    ) -> ThreadId {
+        db.insert_thread(NewThread {
+            subject: "Test".into(),
+            board: board.into(),
+            locked: false,
+            pinned: false,
+            cyclical: false,
+            pending,
+        })
+        .unwrap()
+    }
+
+    /// A post by `user` in a thread.
+    pub fn new_post(board: &str, thread: ThreadId, user: &User) -> NewPost {
+        NewPost {
+            body: "Test".into(),
+            author_name: "Anonymous".into(),
+            author_contact: None,
+            author_ident: None,
+            delete_hash: None,
+            thread,
+            board: board.into(),
+            user_id: user.id,
+            no_bump: false,
+            system: false,
+            author_ident_class: None,
+            truncated_html: None,
+            embeds: None,
+            pending: false,
+            delete_token_hash: None,
+        }
+    }
+
+    /// Insert a post by `user` in a thread. If `pending` is set, it's held
+    /// until it's approved.
+    pub fn post(
+        db: &mut PooledConnection,
+        board: &str,
+        thread: ThreadId,
+        user: &User,
+        pending: bool,
+    ) -> PostId {
+        let new_post = new_post(board, thread, user);
+
+        if pending {
+            db.insert_pending_post(new_post).unwrap()
+        } else {
+            db.insert_post(new_post).unwrap()
+        }
+    }
+}
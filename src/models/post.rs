@@ -1,28 +1,54 @@
 //! Types related to posts.
 
+use std::collections::HashMap;
 use std::fmt::Debug;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use chrono::offset::Utc;
 use chrono::DateTime;
 
-use diesel::{delete, insert_into, prelude::*};
+use diesel::{delete, insert_into, prelude::*, update};
 
 use mime::Mime;
 
 use rocket::uri;
 
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::models::board::listed_board_names;
 use crate::models::{Connection, *};
-use crate::schema::{file, post};
+use crate::parse::{html_snippet, rendered_post_refs, Embed};
+use crate::schema::{deleted_file, file, post};
 use crate::{Error, Result};
 
 /// A post ID.
 pub type PostId = i32;
 
+/// The author name of system posts.
+pub const SYSTEM_AUTHOR_NAME: &str = "System";
+
+/// The IP address recorded for the user that makes system posts. This can
+/// never match the IP address of a real user.
+const SYSTEM_USER_IP: &str = "system";
+
+/// The number of characters of a post shown in the snippet of a reference to
+/// it.
+const REF_SNIPPET_CHARS: usize = 80;
+
+/// The post that a post reference points to. See `Connection::ref_targets`.
+#[derive(Debug)]
+pub struct RefTarget {
+    /// The URI of the post.
+    pub uri: String,
+    /// The board that the post was posted on.
+    pub board_name: String,
+    /// The start of the first line of the post, as plain text. This is empty
+    /// if the post is waiting for approval.
+    pub snippet: String,
+}
+
 /// A user-made post.
-#[derive(Debug, Queryable, Serialize)]
+#[derive(Debug, Queryable, Serialize, Deserialize)]
 pub struct Post {
     /// The ID of the post.
     pub id: PostId,
@@ -46,14 +72,61 @@ pub struct Post {
     pub user_id: UserId,
     /// Whether a post should not bump it's thread.
     pub no_bump: bool,
+    /// Whether the post is a message from the site itself.
+    pub system: bool,
+    /// The CSS class for the author's identity, if the author is a staff
+    /// member.
+    pub author_ident_class: Option<String>,
+    /// The beginning of the post's contents, if the post is long enough to be
+    /// collapsed.
+    pub truncated_html: Option<String>,
+    /// The videos linked in the post which can be embedded, as JSON.
+    pub embeds: Option<String>,
+    /// Whether the post is waiting for staff to approve it.
+    pub pending: bool,
+    /// The argon2 hash of the token the user was given for deletion when they
+    /// made the post, if any.
+    #[serde(default)]
+    pub delete_token_hash: Option<String>,
+}
+
+/// How a post or thread ID is shown to users.
+///
+/// If `prefix_board` is set, the ID is prefixed with the board name, like
+/// `/tech/#123`, and otherwise it's shown like `#123`. This only changes how
+/// the ID looks; anchors and URIs are always just the ID.
+pub fn display_id(board_name: &str, id: i32, prefix_board: bool) -> String {
+    if prefix_board {
+        format!("/{}/#{}", board_name, id)
+    } else {
+        format!("#{}", id)
+    }
 }
 
 impl Post {
     /// The URI of the post.
     pub fn uri(&self) -> String {
         let uri =
-            uri!(crate::routes::thread: &self.board_name, &self.thread_id);
-        format!("{}#{}", uri, self.id)
+            uri!(crate::routes::thread: &self.board_name, &self.thread_id, _);
+        format!("{}#{}", uri, self.anchor())
+    }
+
+    /// The anchor of the post on its thread's page.
+    pub fn anchor(&self) -> String {
+        self.id.to_string()
+    }
+
+    /// The posts that this post references, in the order they're referenced.
+    pub fn replies_to(&self) -> Vec<PostId> {
+        rendered_post_refs(&self.body)
+    }
+
+    /// The videos linked in the post which can be embedded.
+    pub fn embeds(&self) -> Vec<Embed> {
+        self.embeds
+            .as_deref()
+            .and_then(|embeds| serde_json::from_str(embeds).ok())
+            .unwrap_or_default()
     }
 }
 
@@ -70,6 +143,12 @@ pub struct NewPost {
     pub board: String,
     pub user_id: UserId,
     pub no_bump: bool,
+    pub system: bool,
+    pub author_ident_class: Option<String>,
+    pub truncated_html: Option<String>,
+    pub embeds: Option<String>,
+    pub pending: bool,
+    pub delete_token_hash: Option<String>,
 }
 
 /// A helper for serializing MIME types.
@@ -83,8 +162,18 @@ where
     content_type.to_string().serialize(se)
 }
 
+/// A helper for deserializing MIME types.
+fn de_content_type<'de, D>(de: D) -> std::result::Result<Mime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(de)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
 /// A user-uploaded file.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct File {
     /// The name the file is saved at.
     pub save_name: String,
@@ -93,12 +182,18 @@ pub struct File {
     /// The original name of the file, if any.
     pub orig_name: Option<String>,
     /// The content-type of the file.
-    #[serde(serialize_with = "se_content_type")]
+    #[serde(
+        serialize_with = "se_content_type",
+        deserialize_with = "de_content_type"
+    )]
     pub content_type: Mime,
     /// The post that the file belongs to.
     pub post_id: PostId,
     /// Whether or not the file should be hidden by default.
     pub is_spoiler: bool,
+    /// The name of the smaller thumbnail shown in catalogs, if one was made.
+    #[serde(default)]
+    pub catalog_thumb_name: Option<String>,
 }
 
 /// A new file to be inserted in the database.
@@ -107,12 +202,50 @@ pub struct File {
 pub struct NewFile {
     pub save_name: String,
     pub thumb_name: String,
+    pub catalog_thumb_name: Option<String>,
     pub orig_name: Option<String>,
     pub content_type: String,
     pub is_spoiler: bool,
     pub post: PostId,
 }
 
+/// A record of a file that was kept after its post was deleted by a staff
+/// member.
+#[derive(Debug, Insertable)]
+#[diesel(table_name = deleted_file)]
+pub struct NewDeletedFile {
+    pub save_name: String,
+    pub thumb_name: String,
+    pub orig_name: Option<String>,
+    pub content_type: String,
+    pub post: PostId,
+    pub is_spoiler: bool,
+    /// Where the file was moved to.
+    pub quarantine_path: String,
+    /// The staff member who deleted the post.
+    pub deleted_by: String,
+}
+
+impl NewDeletedFile {
+    /// Create a record of a file that was moved to `quarantine_path`.
+    pub fn new<P, S>(file: File, quarantine_path: P, deleted_by: S) -> Self
+    where
+        P: AsRef<Path>,
+        S: Into<String>,
+    {
+        NewDeletedFile {
+            save_name: file.save_name,
+            thumb_name: file.thumb_name,
+            orig_name: file.orig_name,
+            content_type: file.content_type.to_string(),
+            post: file.post_id,
+            is_spoiler: file.is_spoiler,
+            quarantine_path: quarantine_path.as_ref().display().to_string(),
+            deleted_by: deleted_by.into(),
+        }
+    }
+}
+
 impl From<DbFile> for File {
     fn from(file: DbFile) -> File {
         File {
@@ -122,13 +255,14 @@ impl From<DbFile> for File {
             content_type: file.content_type.parse().unwrap(),
             post_id: file.post_id,
             is_spoiler: file.is_spoiler,
+            catalog_thumb_name: file.catalog_thumb_name,
         }
     }
 }
 
 /// The database representation of a file.
 #[derive(Debug, Queryable, Serialize)]
-struct DbFile {
+pub(crate) struct DbFile {
     /// The name the file is saved at.
     pub save_name: String,
     /// The name of the thumbnail of the file, if any.
@@ -141,6 +275,8 @@ struct DbFile {
     pub post_id: PostId,
     /// Whether or not the file should be hidden by default.
     pub is_spoiler: bool,
+    /// The name of the smaller thumbnail shown in catalogs, if one was made.
+    pub catalog_thumb_name: Option<String>,
 }
 
 impl From<File> for DbFile {
@@ -152,6 +288,7 @@ impl From<File> for DbFile {
             content_type: file.content_type.to_string(),
             post_id: file.post_id,
             is_spoiler: file.is_spoiler,
+            catalog_thumb_name: file.catalog_thumb_name,
         }
     }
 }
@@ -166,6 +303,37 @@ impl File {
     pub fn thumb_uri(&self) -> String {
         uri!(crate::routes::upload: PathBuf::from(&self.thumb_name)).to_string()
     }
+
+    /// The URI of the file's catalog thumbnail, or of its regular thumbnail if
+    /// it doesn't have one.
+    pub fn catalog_thumb_uri(&self) -> String {
+        match self.catalog_thumb_name {
+            Some(ref name) => {
+                uri!(crate::routes::upload: PathBuf::from(name)).to_string()
+            }
+            None => self.thumb_uri(),
+        }
+    }
+
+    /// Whether the file is an image or a video, and so has a real thumbnail.
+    pub fn is_media(&self) -> bool {
+        let type_ = self.content_type.type_();
+        type_ == mime::IMAGE || type_ == mime::VIDEO
+    }
+
+    /// A short label for the kind of file, such as "PDF" or "ZIP".
+    ///
+    /// This is taken from the extension of the original file name if there is
+    /// one, and from the content-type otherwise.
+    pub fn type_label(&self) -> String {
+        self.orig_name
+            .as_deref()
+            .and_then(|name| Path::new(name).extension())
+            .and_then(|ext| ext.to_str())
+            .filter(|ext| !ext.is_empty() && ext.len() <= 8)
+            .unwrap_or_else(|| self.content_type.subtype().as_str())
+            .to_uppercase()
+    }
 }
 
 /// Convenience function to convert from diesel's error type into our error
@@ -197,70 +365,321 @@ where
 
     /// Insert a new post into the database.
     pub fn insert_post(&mut self, new_post: NewPost) -> Result<PostId> {
-        use crate::schema::post::columns::id;
-        use crate::schema::post::dsl::post;
+        use crate::schema::post::columns::id as post_id;
+        use crate::schema::post::dsl::post as table_post;
+        use crate::schema::thread::columns::{id as thread_id, post_count};
+        use crate::schema::thread::dsl::thread as table_thread;
+
+        if self.is_locked(new_post.thread)? {
+            return Err(Error::ThreadLocked);
+        }
+
+        self.inner.transaction::<_, Error, _>(|conn| {
+            let new_post_id = insert_into(table_post)
+                .values(&new_post)
+                .returning(post_id)
+                .get_result(conn)?;
+
+            update(table_thread.filter(thread_id.eq(new_post.thread)))
+                .set(post_count.eq(post_count + 1))
+                .execute(conn)?;
+
+            Ok(new_post_id)
+        })
+    }
+
+    /// Insert a new post that's held until staff approve it.
+    ///
+    /// Unlike `insert_post`, the post isn't counted in its thread until it's
+    /// approved. See `approve_post`.
+    pub fn insert_pending_post(&mut self, new_post: NewPost) -> Result<PostId> {
+        use crate::schema::post::columns::id as post_id;
+        use crate::schema::post::dsl::post as table_post;
 
         if self.is_locked(new_post.thread)? {
             return Err(Error::ThreadLocked);
         }
 
-        Ok(insert_into(post)
-            .values(&new_post)
-            .returning(id)
+        Ok(insert_into(table_post)
+            .values(&NewPost {
+                pending: true,
+                ..new_post
+            })
+            .returning(post_id)
             .get_result(&mut self.inner)?)
     }
 
+    /// Approve a post that was held for approval, showing it on its thread. If
+    /// the post started its thread, the thread is shown as well.
+    ///
+    /// The thread is bumped, unless the post was a "no bump" post.
+    pub fn approve_post(&mut self, pid: PostId) -> Result<()> {
+        use crate::schema::post::columns::{id as post_id, pending};
+        use crate::schema::post::dsl::post as table_post;
+        use crate::schema::thread::columns::{
+            bump_date, id as thread_id, pending as thread_pending, post_count,
+        };
+        use crate::schema::thread::dsl::thread as table_thread;
+
+        use diesel::dsl::now;
+
+        self.inner.transaction::<_, Error, _>(|conn| {
+            let held: Post = table_post
+                .filter(post_id.eq(pid))
+                .limit(1)
+                .first(conn)
+                .map_err(conv_post_error(pid))?;
+
+            if !held.pending {
+                return Err(Error::PostNotPending { post_id: pid });
+            }
+
+            update(table_post.filter(post_id.eq(pid)))
+                .set(pending.eq(false))
+                .execute(conn)?;
+
+            update(table_thread.filter(thread_id.eq(held.thread_id)))
+                .set((post_count.eq(post_count + 1), thread_pending.eq(false)))
+                .execute(conn)?;
+
+            if !held.no_bump {
+                update(table_thread.filter(thread_id.eq(held.thread_id)))
+                    .set(bump_date.eq(now))
+                    .execute(conn)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Get all of the posts that are waiting for approval, oldest first.
+    pub fn pending_posts(&mut self) -> Result<Vec<Post>> {
+        use crate::schema::post::columns::{id, pending};
+        use crate::schema::post::dsl::post;
+
+        Ok(post
+            .filter(pending.eq(true))
+            .order(id.asc())
+            .load(&mut self.inner)?)
+    }
+
+    /// Whether a user has any posts that aren't waiting for approval.
+    pub fn user_has_approved_posts(&mut self, uid: UserId) -> Result<bool> {
+        use crate::schema::post::columns::{pending, user_id};
+        use crate::schema::post::dsl::post;
+
+        use diesel::dsl::exists;
+
+        Ok(diesel::select(exists(
+            post.filter(user_id.eq(uid)).filter(pending.eq(false)),
+        ))
+        .get_result(&mut self.inner)?)
+    }
+
+    /// Insert a message from the site itself into a thread.
+    ///
+    /// System posts ignore rate limits and whether the thread is locked, and
+    /// never bump the thread. The body is inserted as HTML, so it must be
+    /// escaped by the caller.
+    pub fn insert_system_post<S>(
+        &mut self,
+        tid: ThreadId,
+        body: S,
+    ) -> Result<PostId>
+    where
+        S: Into<String>,
+    {
+        use crate::schema::anon_user::columns::{id as user_id, ip};
+        use crate::schema::anon_user::dsl::anon_user;
+        use crate::schema::post::columns::id as post_id;
+        use crate::schema::post::dsl::post as table_post;
+        use crate::schema::thread::columns::{
+            board, id as thread_id, post_count,
+        };
+        use crate::schema::thread::dsl::thread as table_thread;
+
+        let body = body.into();
+
+        self.inner.transaction::<_, Error, _>(|conn| {
+            let board_name: String = table_thread
+                .filter(thread_id.eq(tid))
+                .select(board)
+                .limit(1)
+                .first(conn)
+                .map_err(crate::models::thread::conv_thread_error(tid))?;
+
+            let system_user: Option<UserId> = anon_user
+                .filter(ip.eq(SYSTEM_USER_IP))
+                .select(user_id)
+                .limit(1)
+                .first(conn)
+                .optional()?;
+
+            let system_user = match system_user {
+                Some(id) => id,
+                None => insert_into(anon_user)
+                    .values(&NewUser {
+                        hash: SYSTEM_USER_IP.to_string(),
+                        ban_expires: None,
+                        note: None,
+                        ip: SYSTEM_USER_IP.to_string(),
+                    })
+                    .returning(user_id)
+                    .get_result(conn)?,
+            };
+
+            let new_post_id = insert_into(table_post)
+                .values(&NewPost {
+                    body,
+                    author_name: SYSTEM_AUTHOR_NAME.to_string(),
+                    author_contact: None,
+                    author_ident: None,
+                    delete_hash: None,
+                    thread: tid,
+                    board: board_name,
+                    user_id: system_user,
+                    no_bump: true,
+                    system: true,
+                    author_ident_class: None,
+                    truncated_html: None,
+                    embeds: None,
+                    pending: false,
+                    delete_token_hash: None,
+                })
+                .returning(post_id)
+                .get_result(conn)?;
+
+            update(table_thread.filter(thread_id.eq(tid)))
+                .set(post_count.eq(post_count + 1))
+                .execute(conn)?;
+
+            Ok(new_post_id)
+        })
+    }
+
     /// Delete a post.
     pub fn delete_post(&mut self, pid: PostId) -> Result<()> {
         self.inner.transaction::<_, Error, _>(|conn| {
             use crate::schema::file::columns::post as file_post;
             use crate::schema::file::dsl::file as table_file;
             use crate::schema::post::columns::id as post_id;
+            use crate::schema::post::columns::pending;
+            use crate::schema::post::columns::thread as post_thread;
             use crate::schema::post::dsl::post as table_post;
-            use crate::schema::report::columns::post as report_post;
-            use crate::schema::report::dsl::report as table_report;
+            use crate::schema::thread::columns::{
+                file_count, id as thread_id, post_count,
+            };
+            use crate::schema::thread::dsl::thread as table_thread;
+
+            let (parent_id, was_pending): (ThreadId, bool) = table_post
+                .filter(post_id.eq(pid))
+                .select((post_thread, pending))
+                .limit(1)
+                .first(conn)
+                .map_err(conv_post_error(pid))?;
 
-            delete(table_report.filter(report_post.eq(pid))).execute(conn)?;
+            // Posts waiting for approval aren't counted in their thread.
+            let counted = if was_pending { 0 } else { 1 };
 
-            delete(table_file.filter(file_post.eq(pid))).execute(conn)?;
+            delete_reports_for_posts(conn, &[pid])?;
+
+            let num_files: usize =
+                delete(table_file.filter(file_post.eq(pid))).execute(conn)?;
 
             delete(table_post.filter(post_id.eq(pid))).execute(conn)?;
 
+            update(table_thread.filter(thread_id.eq(parent_id)))
+                .set((
+                    post_count.eq(post_count - counted),
+                    file_count.eq(file_count - num_files as i32),
+                ))
+                .execute(conn)?;
+
             Ok(())
         })?;
 
         Ok(())
     }
 
-    /// Get the URI for a post.
-    pub fn post_uri(&mut self, post_id: PostId) -> Result<String> {
-        let thread_uri = self.inner.transaction::<_, Error, _>(|conn| {
-            let thread_id: ThreadId = {
-                use crate::schema::post::columns::{id, thread};
-                use crate::schema::post::dsl::post;
-
-                post.filter(id.eq(post_id))
-                    .select(thread)
-                    .limit(1)
-                    .first(conn)
-                    .map_err(conv_post_error(post_id))?
-            };
-
-            let board_name: String = {
-                use crate::schema::thread::columns::{board, id};
-                use crate::schema::thread::dsl::thread;
+    /// Look up the posts that references in a post body point to, by ID.
+    ///
+    /// Posts that don't exist are left out.
+    pub fn ref_targets(
+        &mut self,
+        post_ids: &[PostId],
+    ) -> Result<HashMap<PostId, RefTarget>> {
+        use crate::schema::post::columns as post_columns;
+        use crate::schema::post::dsl::post;
+        use crate::schema::thread::columns as thread_columns;
+        use crate::schema::thread::dsl::thread;
 
-                thread
-                    .filter(id.eq(thread_id))
-                    .select(board)
-                    .limit(1)
-                    .first(conn)?
-            };
+        let rows: Vec<(PostId, ThreadId, String, String, bool)> = post
+            .inner_join(thread)
+            .filter(post_columns::id.eq_any(post_ids))
+            .select((
+                post_columns::id,
+                post_columns::thread,
+                thread_columns::board,
+                post_columns::body,
+                post_columns::pending,
+            ))
+            .load(&mut self.inner)?;
 
-            Ok(uri!(crate::routes::thread: board_name, thread_id))
-        })?;
+        Ok(rows
+            .into_iter()
+            .map(|(post_id, thread_id, board_name, body, pending)| {
+                let thread_uri =
+                    uri!(crate::routes::thread: &board_name, thread_id, _);
+
+                let snippet = if pending {
+                    String::new()
+                } else {
+                    html_snippet(&body, REF_SNIPPET_CHARS)
+                };
+
+                let target = RefTarget {
+                    uri: format!("{}#{}", thread_uri, post_id),
+                    board_name,
+                    snippet,
+                };
+
+                (post_id, target)
+            })
+            .collect())
+    }
 
-        Ok(format!("{}#{}", thread_uri.to_string(), post_id))
+    /// Get the URI for a post, and the board it was posted on.
+    pub fn post_uri(&mut self, post_id: PostId) -> Result<(String, String)> {
+        let (thread_id, board_name) =
+            self.inner.transaction::<_, Error, _>(|conn| {
+                let thread_id: ThreadId = {
+                    use crate::schema::post::columns::{id, thread};
+                    use crate::schema::post::dsl::post;
+
+                    post.filter(id.eq(post_id))
+                        .select(thread)
+                        .limit(1)
+                        .first(conn)
+                        .map_err(conv_post_error(post_id))?
+                };
+
+                let board_name: String = {
+                    use crate::schema::thread::columns::{board, id};
+                    use crate::schema::thread::dsl::thread;
+
+                    thread
+                        .filter(id.eq(thread_id))
+                        .select(board)
+                        .limit(1)
+                        .first(conn)?
+                };
+
+                Ok((thread_id, board_name))
+            })?;
+
+        let thread_uri = uri!(crate::routes::thread: &board_name, thread_id, _);
+
+        Ok((format!("{}#{}", thread_uri, post_id), board_name))
     }
 
     /// Get the thread that a post belongs to.
@@ -322,22 +741,156 @@ where
         Ok(files.into_iter().map(File::from).collect())
     }
 
+    /// Get a file by the name it's saved at.
+    pub fn file_by_save_name<S>(&mut self, name: S) -> Result<File>
+    where
+        S: AsRef<str>,
+    {
+        use crate::schema::file::dsl::file;
+
+        let db_file: DbFile =
+            file.find(name.as_ref()).first(&mut self.inner)?;
+
+        Ok(File::from(db_file))
+    }
+
     /// Insert a new file into the database.
     pub fn insert_file(&mut self, new_file: NewFile) -> Result<()> {
         use crate::schema::file::dsl::file;
+        use crate::schema::post::columns::id as post_id;
+        use crate::schema::post::columns::thread as post_thread;
+        use crate::schema::post::dsl::post as table_post;
+        use crate::schema::thread::columns::{file_count, id as thread_id};
+        use crate::schema::thread::dsl::thread as table_thread;
+
+        self.inner.transaction::<_, Error, _>(|conn| {
+            let parent_id: ThreadId = table_post
+                .filter(post_id.eq(new_file.post))
+                .select(post_thread)
+                .limit(1)
+                .first(conn)
+                .map_err(conv_post_error(new_file.post))?;
+
+            insert_into(file).values(&new_file).execute(conn)?;
+
+            update(table_thread.filter(thread_id.eq(parent_id)))
+                .set(file_count.eq(file_count + 1))
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+
+    /// Get all of the files that don't have a catalog thumbnail.
+    pub fn files_without_catalog_thumb(&mut self) -> Result<Vec<File>> {
+        use crate::schema::file::columns::catalog_thumb_name;
+        use crate::schema::file::dsl::file;
+
+        let files: Vec<DbFile> = file
+            .filter(catalog_thumb_name.is_null())
+            .load(&mut self.inner)?;
 
-        insert_into(file)
-            .values(&new_file)
+        Ok(files.into_iter().map(File::from).collect())
+    }
+
+    /// Set the name of a file's catalog thumbnail.
+    pub fn set_catalog_thumb_name<S1, S2>(
+        &mut self,
+        file_name: S1,
+        thumb_name: S2,
+    ) -> Result<()>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        use crate::schema::file::columns::{catalog_thumb_name, save_name};
+        use crate::schema::file::dsl::file;
+
+        update(file.filter(save_name.eq(file_name.as_ref())))
+            .set(catalog_thumb_name.eq(thumb_name.as_ref()))
+            .execute(&mut self.inner)?;
+
+        Ok(())
+    }
+
+    /// Get every file with a generated thumbnail, as opposed to files that are
+    /// their own thumbnail.
+    pub fn files_with_thumbnails(&mut self) -> Result<Vec<File>> {
+        use crate::schema::file::columns::{save_name, thumb_name};
+        use crate::schema::file::dsl::file;
+
+        let files: Vec<DbFile> = file
+            .filter(thumb_name.ne(save_name))
+            .load(&mut self.inner)?;
+
+        Ok(files.into_iter().map(File::from).collect())
+    }
+
+    /// Set the names of a file's thumbnail and catalog thumbnail.
+    pub fn set_thumb_names<S1, S2>(
+        &mut self,
+        file_name: S1,
+        new_thumb_name: S2,
+        new_catalog_thumb_name: Option<&str>,
+    ) -> Result<()>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        use crate::schema::file::columns::{
+            catalog_thumb_name, save_name, thumb_name,
+        };
+        use crate::schema::file::dsl::file;
+
+        update(file.filter(save_name.eq(file_name.as_ref())))
+            .set((
+                thumb_name.eq(new_thumb_name.as_ref()),
+                catalog_thumb_name.eq(new_catalog_thumb_name),
+            ))
             .execute(&mut self.inner)?;
 
         Ok(())
     }
 
     /// Delete all the files that belong to a post.
-    pub fn delete_files_of_post(&mut self, post_id: PostId) -> Result<()> {
+    pub fn delete_files_of_post(&mut self, pid: PostId) -> Result<()> {
         use crate::schema::file::columns::post;
         use crate::schema::file::dsl::file;
-        delete(file.filter(post.eq(post_id))).execute(&mut self.inner)?;
+        use crate::schema::post::columns::id as post_id;
+        use crate::schema::post::columns::thread as post_thread;
+        use crate::schema::post::dsl::post as table_post;
+        use crate::schema::thread::columns::{file_count, id as thread_id};
+        use crate::schema::thread::dsl::thread as table_thread;
+
+        self.inner.transaction::<_, Error, _>(|conn| {
+            let parent_id: ThreadId = table_post
+                .filter(post_id.eq(pid))
+                .select(post_thread)
+                .limit(1)
+                .first(conn)
+                .map_err(conv_post_error(pid))?;
+
+            let num_files: usize =
+                delete(file.filter(post.eq(pid))).execute(conn)?;
+
+            update(table_thread.filter(thread_id.eq(parent_id)))
+                .set(file_count.eq(file_count - num_files as i32))
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+
+    /// Record files that were kept after their posts were deleted.
+    pub fn insert_deleted_files(
+        &mut self,
+        new_files: &[NewDeletedFile],
+    ) -> Result<()> {
+        use crate::schema::deleted_file::dsl::deleted_file;
+
+        insert_into(deleted_file)
+            .values(new_files)
+            .execute(&mut self.inner)?;
 
         Ok(())
     }
@@ -349,26 +902,32 @@ where
         Ok(post.count().first(&mut self.inner)?)
     }
 
-    /// Get up to `limit` recent posts.
+    /// Get up to `limit` recent posts, leaving out posts that are waiting for
+    /// approval and posts on unlisted boards.
     pub fn recent_posts(&mut self, limit: u32) -> Result<Vec<Post>> {
-        use crate::schema::post::columns::time_stamp;
+        use crate::schema::post::columns::{board, pending, time_stamp};
         use crate::schema::post::dsl::post;
 
         Ok(post
+            .filter(pending.eq(false))
+            .filter(board.eq_any(listed_board_names()))
             .order(time_stamp.desc())
             .limit(limit.into())
             .load(&mut self.inner)?)
     }
 
-    /// Get up to `limit` recently uploaded files.
+    /// Get up to `limit` recently uploaded files, leaving out files of posts
+    /// that are waiting for approval and files on unlisted boards.
     pub fn recent_files(&mut self, limit: u32) -> Result<Vec<File>> {
         use crate::schema::file::columns::*;
         use crate::schema::file::dsl::file;
-        use crate::schema::post::columns::time_stamp;
+        use crate::schema::post::columns::{board, pending, time_stamp};
         use crate::schema::post::dsl::post as post_table;
 
         let files: Vec<DbFile> = file
             .inner_join(post_table)
+            .filter(pending.eq(false))
+            .filter(board.eq_any(listed_board_names()))
             .order(time_stamp.desc())
             .limit(limit.into())
             .select((
@@ -378,9 +937,38 @@ where
                 content_type,
                 post,
                 is_spoiler,
+                catalog_thumb_name,
             ))
             .load(&mut self.inner)?;
 
         Ok(files.into_iter().map(File::from).collect())
     }
+
+    /// Get up to `limit` recently uploaded files from posts older than the
+    /// post `before`, or the most recent files if `before` is `None`.
+    ///
+    /// Files are sorted by their post, newest first. Files of posts that are
+    /// waiting for approval and files on unlisted boards are left out.
+    pub fn recent_files_before(
+        &mut self,
+        before: Option<PostId>,
+        limit: u32,
+    ) -> Result<Vec<File>> {
+        use crate::schema::file::columns::*;
+        use crate::schema::file::dsl::file;
+        use crate::schema::post::columns::{board, pending};
+        use crate::schema::post::dsl::post as post_table;
+
+        let files: Vec<DbFile> = file
+            .inner_join(post_table)
+            .filter(pending.eq(false))
+            .filter(board.eq_any(listed_board_names()))
+            .filter(post.lt(before.unwrap_or(PostId::MAX)))
+            .order(post.desc())
+            .limit(limit.into())
+            .select(crate::schema::file::all_columns)
+            .load(&mut self.inner)?;
+
+        Ok(files.into_iter().map(File::from).collect())
+    }
 }
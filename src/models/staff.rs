@@ -1,7 +1,7 @@
 //! Types for staff roles and moderation actions.
 
 use std::convert::TryInto;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 use std::str::FromStr;
 
 use argon2::hash_encoded;
@@ -10,13 +10,21 @@ use chrono::{DateTime, Duration, Utc};
 
 use derive_more::Display;
 
+use diesel::pg::Pg;
 use diesel::prelude::*;
-use diesel::{delete, insert_into, update, Insertable, Queryable};
+use diesel::sql_types::{
+    BigInt, Bool, Double, Integer, Nullable, Text, Timestamptz,
+};
+use diesel::{delete, insert_into, sql_query, update, Insertable, Queryable};
 
-use serde::Serialize;
+use log::info;
+
+use serde::{Deserialize, Serialize};
 
 use crate::models::{Connection, *};
-use crate::schema::{anon_user, report, session, staff, staff_action};
+use crate::schema::{
+    anon_user, api_key, post, report, session, staff, staff_action,
+};
 use crate::{Error, Result};
 
 /// A session for a staff member.
@@ -124,11 +132,112 @@ pub mod sql_types {
     }
 }
 
+/// An ID for an API key.
+pub type ApiKeyId = i32;
+
+/// A key that lets a program perform staff actions on behalf of a staff
+/// member.
+#[derive(Debug)]
+pub struct ApiKey {
+    pub id: ApiKeyId,
+    /// The staff member who owns the key.
+    pub staff: Staff,
+    /// Which actions the key may be used for.
+    pub scope: ApiScope,
+    /// When the key was created.
+    pub created: DateTime<Utc>,
+}
+
+impl ApiKey {
+    /// Whether or not the key may be used for an action.
+    pub fn allows(&self, scope: ApiScope) -> bool {
+        self.scope == ApiScope::All || self.scope == scope
+    }
+
+    /// Check that the key may be used for an action.
+    pub fn authorize(&self, scope: ApiScope) -> Result<()> {
+        if self.allows(scope) {
+            Ok(())
+        } else {
+            Err(Error::ApiKeyNotInScope { scope })
+        }
+    }
+
+    /// Hash an API key.
+    ///
+    /// Keys are long random strings, so they are hashed with a fixed salt so
+    /// that they can be looked up by their hash.
+    pub fn hash_key<S>(key: S) -> String
+    where
+        S: AsRef<str>,
+    {
+        let salt = b"longboard-api-key";
+        let conf = argon2::Config::default();
+
+        hash_encoded(key.as_ref().as_bytes(), salt, &conf)
+            .expect("could not hash API key with Argon2")
+    }
+}
+
+/// Insertable database type for API keys.
+#[derive(Insertable)]
+#[diesel(table_name = api_key)]
+struct NewApiKey {
+    key_hash: String,
+    staff_name: String,
+    scope: String,
+}
+
+/// The actions that an API key may be used for.
+#[derive(Copy, Clone, Debug, Display, PartialEq, Eq)]
+pub enum ApiScope {
+    #[display(fmt = "delete")]
+    Delete,
+    #[display(fmt = "ban")]
+    Ban,
+    #[display(fmt = "all")]
+    All,
+}
+
+impl FromStr for ApiScope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "delete" => Ok(ApiScope::Delete),
+            "ban" => Ok(ApiScope::Ban),
+            "all" => Ok(ApiScope::All),
+            _ => Err(Error::UnknownApiScope {
+                scope: s.to_string(),
+            }),
+        }
+    }
+}
+
 /// An ID for an anonymous site user.
 pub type UserId = i32;
 
+/// The number of users on each page of user search results.
+pub const USERS_PER_PAGE: u32 = 50;
+
+/// Escape the characters in `s` that have a special meaning in a `LIKE`
+/// pattern.
+fn escape_like_pattern(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
 /// An anonymous site user.
-#[derive(Debug, Queryable, Serialize)]
+#[derive(Debug, Queryable, Serialize, Deserialize)]
 pub struct User {
     /// The user's ID in the database.
     pub id: UserId,
@@ -149,6 +258,18 @@ impl User {
             .map(|time| time > Utc::now())
             .unwrap_or(false)
     }
+
+    /// Whether the user was banned and the ban has run out, as of `now`.
+    pub fn ban_has_expired(&self, now: &DateTime<Utc>) -> bool {
+        self.ban_expires
+            .map(|time| time <= *now)
+            .unwrap_or(false)
+    }
+
+    /// The part of the user's hash that is shown to staff members.
+    pub fn short_hash(&self) -> &str {
+        self.hash.split('$').last().unwrap()
+    }
 }
 
 /// A new anonymous site user to insert into the database.
@@ -163,21 +284,31 @@ pub struct NewUser {
 
 impl NewUser {
     /// Create a `NewUser` from an user's IP address.
-    pub fn from_ip(ip: IpAddr) -> NewUser {
+    ///
+    /// IPv6 addresses are reduced to their first `ipv6_prefix` bits.
+    pub fn from_ip(ip: IpAddr, ipv6_prefix: u8, secret: &str) -> NewUser {
         NewUser {
-            hash: NewUser::hash_ip(ip),
+            hash: NewUser::hash_ip(ip, ipv6_prefix, secret),
             ban_expires: None,
             note: None,
-            ip: ip.to_string(),
+            ip: normalize_ip(ip, ipv6_prefix).to_string(),
         }
     }
 
     /// Hash a user's IP address.
-    pub fn hash_ip(ip: IpAddr) -> String {
+    ///
+    /// IPv6 addresses are reduced to their first `ipv6_prefix` bits, so that
+    /// all addresses in a network hash to the same value. The hash is keyed
+    /// with the instance's `secret`, so the same address hashes differently
+    /// on different sites.
+    pub fn hash_ip(ip: IpAddr, ipv6_prefix: u8, secret: &str) -> String {
         let salt = b"longboard-user";
-        let conf = argon2::Config::default();
+        let conf = argon2::Config {
+            secret: secret.as_bytes(),
+            ..argon2::Config::default()
+        };
 
-        let octets = match ip {
+        let octets = match normalize_ip(ip, ipv6_prefix) {
             IpAddr::V4(v4_addr) => v4_addr.octets().to_vec(),
             IpAddr::V6(v6_addr) => v6_addr.octets().to_vec(),
         };
@@ -187,6 +318,20 @@ impl NewUser {
     }
 }
 
+/// Reduce an IPv6 address to the network address of its first `ipv6_prefix`
+/// bits. IPv4 addresses are returned unchanged.
+pub fn normalize_ip(ip: IpAddr, ipv6_prefix: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6_addr) => {
+            let prefix = u32::from(ipv6_prefix.min(128));
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+
+            IpAddr::V6(Ipv6Addr::from(u128::from(v6_addr) & mask))
+        }
+    }
+}
+
 /// An ID for a staff member action.
 type StaffActionId = i32;
 
@@ -205,6 +350,75 @@ pub struct StaffAction {
     pub time_stamp: DateTime<Utc>,
 }
 
+/// How many posts are shown on each page of a user's posts.
+pub const USER_POSTS_PER_PAGE: u32 = 50;
+
+/// A query for a page of the posts a user has made on every board, newest
+/// first. Pages start at 1.
+fn user_posts_query(
+    user_id: UserId,
+    page: u32,
+) -> Result<post::BoxedQuery<'static, Pg>> {
+    let offset = page_offset(page, USER_POSTS_PER_PAGE)?;
+
+    Ok(post::table
+        .filter(post::user_id.eq(user_id))
+        .order_by((post::time_stamp.desc(), post::id.desc()))
+        .limit(i64::from(USER_POSTS_PER_PAGE))
+        .offset(i64::from(offset))
+        .into_boxed())
+}
+
+/// A query for the reports on any of `post_ids`.
+fn post_reports_query(post_ids: &[PostId]) -> report::BoxedQuery<'_, Pg> {
+    report::table
+        .filter(report::post.eq_any(post_ids))
+        .into_boxed()
+}
+
+/// Delete the reports on `post_ids`, for when the posts are deleted. Returns
+/// the number of reports deleted.
+///
+/// Reports reference their post with a foreign key, so they have to be deleted
+/// before the post can be. This takes the inner connection so that it can be
+/// called in the transaction that deletes the posts.
+pub(crate) fn delete_reports_for_posts<C>(
+    conn: &mut C,
+    post_ids: &[PostId],
+) -> Result<u32>
+where
+    C: diesel::Connection<Backend = Pg> + diesel::connection::LoadConnection,
+{
+    let report_ids: Vec<ReportId> = post_reports_query(post_ids)
+        .select(report::id)
+        .load(conn)?;
+
+    if report_ids.is_empty() {
+        return Ok(0);
+    }
+
+    delete(report::table.filter(report::id.eq_any(&report_ids)))
+        .execute(conn)?;
+
+    info!(
+        "Deleted {} reports on deleted posts: {:?}",
+        report_ids.len(),
+        report_ids
+    );
+
+    Ok(report_ids.len().try_into().unwrap())
+}
+
+/// How many rows to delete at once when pruning old reports and staff actions.
+const PRUNE_BATCH_SIZE: i64 = 1000;
+
+/// The name that actions done with `longctl` are recorded under.
+pub const CLI_STAFF_NAME: &str = "longctl";
+
+/// How many of the most recent posts are compared against a new post when
+/// checking for similar content.
+const SIMILARITY_WINDOW: i64 = 500;
+
 /// Insertable database type for staff member actions.
 #[derive(Insertable)]
 #[diesel(table_name = staff_action)]
@@ -218,7 +432,7 @@ pub struct NewStaffAction {
 pub type ReportId = i32;
 
 /// A report that a user made about a post which breaks the rules.
-#[derive(Debug, Queryable, Serialize)]
+#[derive(Debug, Queryable, Serialize, Deserialize)]
 pub struct Report {
     /// The report ID.
     pub id: ReportId,
@@ -230,6 +444,9 @@ pub struct Report {
     pub post_id: PostId,
     /// The user that made the report.
     pub user_id: UserId,
+    /// When a staff member closed the report, if one has.
+    #[serde(default)]
+    pub closed_at: Option<DateTime<Utc>>,
 }
 
 /// A new report to be inserted in the database.
@@ -271,6 +488,47 @@ where
         Ok(())
     }
 
+    /// Change a staff member's password hash.
+    ///
+    /// The staff member is signed out of all of their sessions, since the old
+    /// password may have been used to sign in to them. Their API keys are
+    /// revoked too, unless `keep_api_keys` is set. Unlike removing and adding
+    /// the staff member again, this keeps their staff log entries.
+    pub fn update_staff_password<S1, S2>(
+        &mut self,
+        name: S1,
+        password_hash: S2,
+        keep_api_keys: bool,
+    ) -> Result<()>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let name = name.as_ref();
+
+        self.inner.transaction::<_, Error, _>(|conn| {
+            let updated = update(staff::table.filter(staff::name.eq(name)))
+                .set(staff::password_hash.eq(password_hash.as_ref()))
+                .execute(conn)?;
+
+            if updated == 0 {
+                return Err(Error::StaffInvalidUsername {
+                    staff_name: name.to_string(),
+                });
+            }
+
+            delete(session::table.filter(session::staff_name.eq(name)))
+                .execute(conn)?;
+
+            if !keep_api_keys {
+                delete(api_key::table.filter(api_key::staff_name.eq(name)))
+                    .execute(conn)?;
+            }
+
+            Ok(())
+        })
+    }
+
     /// Delete a staff member.
     pub fn delete_staff<S>(&mut self, name: S) -> Result<()>
     where
@@ -279,6 +537,9 @@ where
         use crate::schema::session::columns::staff_name;
         use crate::schema::session::dsl::session;
 
+        use crate::schema::api_key::columns::staff_name as key_staff_name;
+        use crate::schema::api_key::dsl::api_key;
+
         use crate::schema::staff_action::columns::done_by;
         use crate::schema::staff_action::dsl::staff_action;
 
@@ -289,6 +550,9 @@ where
 
         delete(session.filter(staff_name.eq(name))).execute(&mut self.inner)?;
 
+        delete(api_key.filter(key_staff_name.eq(name)))
+            .execute(&mut self.inner)?;
+
         delete(staff_action.filter(done_by.eq(name)))
             .execute(&mut self.inner)?;
 
@@ -361,7 +625,74 @@ where
         Ok(())
     }
 
+    /// Get an API key.
+    pub fn api_key<S>(&mut self, key: S) -> Result<ApiKey>
+    where
+        S: AsRef<str>,
+    {
+        use crate::schema::api_key::columns::{
+            created, id, key_hash, scope, staff_name,
+        };
+        use crate::schema::api_key::dsl::api_key;
+
+        let (key_id, key_staff_name, key_scope, key_created): (
+            ApiKeyId,
+            String,
+            String,
+            DateTime<Utc>,
+        ) = api_key
+            .filter(key_hash.eq(ApiKey::hash_key(key)))
+            .select((id, staff_name, scope, created))
+            .limit(1)
+            .first(&mut self.inner)?;
+
+        Ok(ApiKey {
+            id: key_id,
+            staff: self.staff(key_staff_name)?,
+            scope: key_scope.parse()?,
+            created: key_created,
+        })
+    }
+
+    /// Insert a new API key for a staff member.
+    pub fn insert_api_key<S, K>(
+        &mut self,
+        staff_name: S,
+        scope: ApiScope,
+        key: K,
+    ) -> Result<ApiKeyId>
+    where
+        S: Into<String>,
+        K: AsRef<str>,
+    {
+        use crate::schema::api_key::columns::id;
+        use crate::schema::api_key::dsl::api_key;
+
+        let new_key = NewApiKey {
+            key_hash: ApiKey::hash_key(key),
+            staff_name: staff_name.into(),
+            scope: scope.to_string(),
+        };
+
+        Ok(insert_into(api_key)
+            .values(new_key)
+            .returning(id)
+            .get_result(&mut self.inner)?)
+    }
+
+    /// Delete an API key.
+    pub fn delete_api_key(&mut self, key_id: ApiKeyId) -> Result<()> {
+        use crate::schema::api_key::columns::id;
+        use crate::schema::api_key::dsl::api_key;
+
+        delete(api_key.filter(id.eq(key_id))).execute(&mut self.inner)?;
+
+        Ok(())
+    }
+
     /// Get a user by their IP.
+    ///
+    /// IPv6 addresses should be normalized with `normalize_ip` first.
     pub fn user(&mut self, user_ip: IpAddr) -> Result<User> {
         use crate::schema::anon_user::columns::ip;
         use crate::schema::anon_user::dsl::anon_user;
@@ -372,6 +703,31 @@ where
             .first(&mut self.inner)?)
     }
 
+    /// Get a user by the start of their short hash, as shown to staff members.
+    pub fn user_by_hash<S>(&mut self, hash_prefix: S) -> Result<User>
+    where
+        S: AsRef<str>,
+    {
+        let hash_prefix = hash_prefix.as_ref();
+
+        let mut users: Vec<User> = self
+            .all_users()?
+            .into_iter()
+            .filter(|user| user.short_hash().starts_with(hash_prefix))
+            .collect();
+
+        match users.len() {
+            0 => Err(Error::UserHashNotFound {
+                hash: hash_prefix.to_string(),
+            }),
+            1 => Ok(users.remove(0)),
+            count => Err(Error::UserHashAmbiguous {
+                hash: hash_prefix.to_string(),
+                count,
+            }),
+        }
+    }
+
     /// Get all users.
     pub fn all_users(&mut self) -> Result<Vec<User>> {
         use crate::schema::anon_user::dsl::anon_user;
@@ -385,7 +741,6 @@ where
         use crate::schema::anon_user::columns as user_columns;
         use crate::schema::anon_user::dsl::anon_user;
         use crate::schema::post::dsl::post;
-        use diesel::sql_types::BigInt;
 
         let data: Vec<(
             i32,
@@ -419,6 +774,104 @@ where
             .collect())
     }
 
+    /// Search for users whose short hash starts with `query` or whose note
+    /// contains it, along with their total number of posts made, sorted by the
+    /// post count.
+    ///
+    /// An empty query matches every user. If `only_banned` is set, only users
+    /// who are banned are found. Pages start at 1 and have `USERS_PER_PAGE`
+    /// users each.
+    pub fn search_users<S>(
+        &mut self,
+        query: S,
+        only_banned: bool,
+        page: u32,
+    ) -> Result<Vec<(User, u32)>>
+    where
+        S: AsRef<str>,
+    {
+        #[derive(QueryableByName)]
+        struct UserRow {
+            #[diesel(sql_type = Integer)]
+            id: UserId,
+            #[diesel(sql_type = Text)]
+            hash: String,
+            #[diesel(sql_type = Nullable<Timestamptz>)]
+            ban_expires: Option<DateTime<Utc>>,
+            #[diesel(sql_type = Nullable<Text>)]
+            note: Option<String>,
+            #[diesel(sql_type = Text)]
+            ip: String,
+            #[diesel(sql_type = BigInt)]
+            post_count: i64,
+        }
+
+        // The short hash is the part of the hash after the last '$', see
+        // `User::short_hash`.
+        let sql = "SELECT U.id, U.hash, U.ban_expires, U.note, U.ip, \
+                          COUNT(P.id) AS post_count \
+                     FROM anon_user U \
+                     LEFT OUTER JOIN post P ON P.user_id = U.id \
+                    WHERE ($1 = '' \
+                           OR substring(U.hash FROM '[^$]*$') LIKE $2 \
+                           OR U.note ILIKE $3) \
+                      AND (NOT $4 OR U.ban_expires > now()) \
+                 GROUP BY U.id \
+                 ORDER BY post_count DESC, U.id \
+                    LIMIT $5 OFFSET $6";
+
+        let query = query.as_ref().trim();
+        let escaped = escape_like_pattern(query);
+        let offset = page_offset(page, USERS_PER_PAGE)?;
+
+        let rows: Vec<UserRow> = sql_query(sql)
+            .bind::<Text, _>(query)
+            .bind::<Text, _>(format!("{}%", escaped))
+            .bind::<Text, _>(format!("%{}%", escaped))
+            .bind::<Bool, _>(only_banned)
+            .bind::<BigInt, _>(i64::from(USERS_PER_PAGE))
+            .bind::<BigInt, _>(i64::from(offset))
+            .load(&mut self.inner)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let post_count = u32::try_from(row.post_count)
+                    .expect("post count will not fit into a u32");
+
+                let user = User {
+                    id: row.id,
+                    hash: row.hash,
+                    ban_expires: row.ban_expires,
+                    note: row.note,
+                    ip: row.ip,
+                };
+
+                (user, post_count)
+            })
+            .collect())
+    }
+
+    /// Get a user by their ID.
+    pub fn user_by_id(&mut self, user_id: UserId) -> Result<User> {
+        anon_user::table
+            .find(user_id)
+            .first(&mut self.inner)
+            .optional()?
+            .ok_or(Error::UserIdNotFound { user_id })
+    }
+
+    /// Get a page of the posts a user has made on every board, newest first.
+    ///
+    /// Pages start at 1 and have `USER_POSTS_PER_PAGE` posts each.
+    pub fn posts_by_user(
+        &mut self,
+        user_id: UserId,
+        page: u32,
+    ) -> Result<Vec<Post>> {
+        Ok(user_posts_query(user_id, page)?.load(&mut self.inner)?)
+    }
+
     /// Get the total number of posts a user has made.
     pub fn user_post_count(&mut self, user_id: UserId) -> Result<u32> {
         use crate::schema::post::columns::user_id as column_user_id;
@@ -432,6 +885,105 @@ where
         Ok(count.try_into().unwrap())
     }
 
+    /// Get the number of posts a user has made that aren't waiting for
+    /// approval.
+    pub fn approved_post_count(&mut self, user_id: UserId) -> Result<u32> {
+        use crate::schema::post::columns::{pending, user_id as column_user_id};
+        use crate::schema::post::dsl::post;
+
+        let count: i64 = post
+            .filter(column_user_id.eq(user_id))
+            .filter(pending.eq(false))
+            .count()
+            .first(&mut self.inner)?;
+
+        Ok(count.try_into().unwrap())
+    }
+
+    /// Replace a user's IP address and hash.
+    pub fn set_user_ip<S>(
+        &mut self,
+        user_id: UserId,
+        new_ip: IpAddr,
+        new_hash: S,
+    ) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        use crate::schema::anon_user::columns::{hash, id, ip};
+        use crate::schema::anon_user::dsl::anon_user;
+
+        update(anon_user.filter(id.eq(user_id)))
+            .set((ip.eq(new_ip.to_string()), hash.eq(new_hash.as_ref())))
+            .execute(&mut self.inner)?;
+
+        Ok(())
+    }
+
+    /// Merge one user into another, moving their posts and reports over and
+    /// keeping the later of their bans. The merged user is deleted.
+    pub fn merge_user(&mut self, from: UserId, into: UserId) -> Result<()> {
+        self.inner.transaction::<_, Error, _>(|conn| {
+            let from_user: User = anon_user::table.find(from).first(conn)?;
+            let into_user: User = anon_user::table.find(into).first(conn)?;
+
+            update(post::table.filter(post::columns::user_id.eq(from)))
+                .set(post::columns::user_id.eq(into))
+                .execute(conn)?;
+            update(report::table.filter(report::columns::user_id.eq(from)))
+                .set(report::columns::user_id.eq(into))
+                .execute(conn)?;
+
+            let ban_expires = into_user.ban_expires.max(from_user.ban_expires);
+            let note = match (into_user.note, from_user.note) {
+                (Some(into_note), Some(from_note)) => {
+                    Some(format!("{}\n{}", into_note, from_note))
+                }
+                (into_note, from_note) => into_note.or(from_note),
+            };
+
+            update(anon_user::table.find(into))
+                .set((
+                    anon_user::columns::ban_expires.eq(ban_expires),
+                    anon_user::columns::note.eq(note),
+                ))
+                .execute(conn)?;
+            delete(anon_user::table.find(from)).execute(conn)?;
+
+            Ok(())
+        })
+    }
+
+    /// Get the instance secret with the given name, storing `new_value` as the
+    /// secret first if there isn't one yet.
+    pub fn instance_secret<S1, S2>(
+        &mut self,
+        name: S1,
+        new_value: S2,
+    ) -> Result<String>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        use crate::schema::instance_secret::columns;
+        use crate::schema::instance_secret::dsl::instance_secret;
+
+        let name = name.as_ref();
+
+        insert_into(instance_secret)
+            .values((
+                columns::name.eq(name),
+                columns::value.eq(new_value.as_ref()),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&mut self.inner)?;
+
+        Ok(instance_secret
+            .filter(columns::name.eq(name))
+            .select(columns::value)
+            .first(&mut self.inner)?)
+    }
+
     /// Insert a user.
     pub fn insert_user(&mut self, new_user: &NewUser) -> Result<User> {
         use crate::schema::anon_user::dsl::anon_user;
@@ -471,6 +1023,33 @@ where
         Ok(())
     }
 
+    /// Get the users whose bans have run out, but haven't been cleared.
+    pub fn expired_bans(&mut self) -> Result<Vec<User>> {
+        use crate::schema::anon_user::columns::{ban_expires, id};
+        use crate::schema::anon_user::dsl::anon_user;
+
+        Ok(anon_user
+            .filter(ban_expires.le(Utc::now()))
+            .order_by(id)
+            .load(&mut self.inner)?)
+    }
+
+    /// Clear the bans that have run out. Returns the number of users whose
+    /// bans were cleared.
+    ///
+    /// This doesn't change who is banned, it only tidies up the list of users.
+    /// The staff log still records the bans.
+    pub fn clear_expired_bans(&mut self) -> Result<u32> {
+        use crate::schema::anon_user::columns::ban_expires;
+        use crate::schema::anon_user::dsl::anon_user;
+
+        let count = update(anon_user.filter(ban_expires.le(Utc::now())))
+            .set(ban_expires.eq::<Option<DateTime<Utc>>>(None))
+            .execute(&mut self.inner)?;
+
+        Ok(count.try_into().unwrap())
+    }
+
     /// Update the moderation notes for a user.
     pub fn set_user_note<S>(
         &mut self,
@@ -505,11 +1084,29 @@ where
     /// Delete all of the posts a user has made. Returns the amount of rows
     /// deleted.
     pub fn delete_posts_for_user(&mut self, id: UserId) -> Result<u32> {
-        use crate::schema::post::columns::user_id;
+        use crate::schema::post::columns::{id as post_id, user_id};
         use crate::schema::post::dsl::post;
 
-        let count: usize =
-            delete(post.filter(user_id.eq(id))).execute(&mut self.inner)?;
+        let count = self.inner.transaction::<_, Error, _>(|conn| {
+            let post_ids: Vec<PostId> = post
+                .filter(user_id.eq(id))
+                .select(post_id)
+                .load(conn)?;
+            delete_reports_for_posts(conn, &post_ids)?;
+
+            let query = "UPDATE thread T \
+                         SET post_count = T.post_count - P.num_posts \
+                         FROM (SELECT thread, COUNT(*) AS num_posts \
+                               FROM post WHERE user_id = $1 \
+                               GROUP BY thread) P \
+                         WHERE T.id = P.thread";
+            sql_query(query).bind::<Integer, _>(id).execute(conn)?;
+
+            let count: usize =
+                delete(post.filter(user_id.eq(id))).execute(conn)?;
+
+            Ok(count)
+        })?;
 
         Ok(count.try_into().unwrap())
     }
@@ -549,6 +1146,37 @@ where
         Ok(())
     }
 
+    /// Delete staff actions done before `cutoff`. Returns the number of
+    /// actions deleted.
+    pub fn prune_actions_before(
+        &mut self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<u32> {
+        use crate::schema::staff_action::columns::{id, time_stamp};
+        use crate::schema::staff_action::dsl::staff_action;
+
+        let mut count = 0;
+
+        // Delete in batches, so that we don't hold a lock on the whole table.
+        loop {
+            let batch = staff_action
+                .select(id)
+                .filter(time_stamp.lt(cutoff))
+                .limit(PRUNE_BATCH_SIZE);
+
+            let deleted = delete(staff_action.filter(id.eq_any(batch)))
+                .execute(&mut self.inner)?;
+
+            if deleted == 0 {
+                break;
+            }
+
+            count += deleted;
+        }
+
+        Ok(count.try_into().unwrap())
+    }
+
     /// Get a report.
     pub fn report(&mut self, report_id: ReportId) -> Result<Report> {
         use crate::schema::report::columns::id;
@@ -560,11 +1188,12 @@ where
             .first(&mut self.inner)?)
     }
 
-    /// Get all post reports.
+    /// Get all post reports that haven't been closed.
     pub fn all_reports(&mut self) -> Result<Vec<Report>> {
+        use crate::schema::report::columns::closed_at;
         use crate::schema::report::dsl::report;
 
-        Ok(report.load(&mut self.inner)?)
+        Ok(report.filter(closed_at.is_null()).load(&mut self.inner)?)
     }
 
     /// Insert a new post report.
@@ -578,16 +1207,54 @@ where
         Ok(())
     }
 
-    /// Delete a report.
-    pub fn delete_report(&mut self, report_id: ReportId) -> Result<()> {
-        use crate::schema::report::columns::id;
+    /// Close a report, so that it's no longer shown to staff. The report is
+    /// kept until it's pruned. See `prune_reports_before`.
+    pub fn close_report(&mut self, report_id: ReportId) -> Result<()> {
+        use crate::schema::report::columns::{closed_at, id};
         use crate::schema::report::dsl::report;
 
-        delete(report.filter(id.eq(report_id))).execute(&mut self.inner)?;
+        use diesel::dsl::now;
+
+        update(report.filter(id.eq(report_id)).filter(closed_at.is_null()))
+            .set(closed_at.eq(now))
+            .execute(&mut self.inner)?;
 
         Ok(())
     }
 
+    /// Delete reports that were closed before `cutoff`. Returns the number of
+    /// reports deleted.
+    ///
+    /// Reports that haven't been closed are never deleted, no matter how old
+    /// they are.
+    pub fn prune_reports_before(
+        &mut self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<u32> {
+        use crate::schema::report::columns::{closed_at, id};
+        use crate::schema::report::dsl::report;
+
+        let mut count = 0;
+
+        loop {
+            let batch = report
+                .select(id)
+                .filter(closed_at.lt(cutoff))
+                .limit(PRUNE_BATCH_SIZE);
+
+            let deleted = delete(report.filter(id.eq_any(batch)))
+                .execute(&mut self.inner)?;
+
+            if deleted == 0 {
+                break;
+            }
+
+            count += deleted;
+        }
+
+        Ok(count.try_into().unwrap())
+    }
+
     /// Check if the user has made any posts recently.
     pub fn user_rate_limit_exceeded(
         &mut self,
@@ -604,7 +1271,8 @@ where
         Ok(select(exists(query)).get_result(&mut self.inner)?)
     }
 
-    /// Check if an identical post has been made based on the given content recently.
+    /// Check if an identical post has been made based on the given content
+    /// recently.
     pub fn content_rate_limit_exceeded<S>(
         &mut self,
         post_body: S,
@@ -622,4 +1290,408 @@ where
 
         Ok(select(exists(query)).get_result(&mut self.inner)?)
     }
+
+    /// Check if a post with content similar to the given content has been
+    /// made recently.
+    ///
+    /// Posts are compared by trigram similarity, and are similar if their
+    /// similarity is at least `threshold`. Only the `SIMILARITY_WINDOW` most
+    /// recent posts are compared, so a flood of posts can't make every new
+    /// post slow to check.
+    pub fn similar_recent_content_exists<S>(
+        &mut self,
+        post_body: S,
+        within: Duration,
+        threshold: f64,
+    ) -> Result<bool>
+    where
+        S: AsRef<str>,
+    {
+        #[derive(QueryableByName)]
+        struct FoundRow {
+            #[diesel(sql_type = Bool)]
+            found: bool,
+        }
+
+        let query = "SELECT EXISTS ( \
+                         SELECT 1 FROM ( \
+                             SELECT body FROM post \
+                              WHERE time_stamp > $2 \
+                              ORDER BY id DESC \
+                              LIMIT $4) AS recent \
+                          WHERE similarity(body, $1) >= $3) AS found";
+
+        let row: FoundRow = sql_query(query)
+            .bind::<Text, _>(post_body.as_ref())
+            .bind::<Timestamptz, _>(Utc::now() - within)
+            .bind::<Double, _>(threshold)
+            .bind::<BigInt, _>(SIMILARITY_WINDOW)
+            .get_result(&mut self.inner)?;
+
+        Ok(row.found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(scope: ApiScope) -> ApiKey {
+        ApiKey {
+            id: 1,
+            staff: Staff {
+                name: "bot".into(),
+                password_hash: String::new(),
+                role: Role::Janitor,
+            },
+            scope,
+            created: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn expired_bans() {
+        let now = Utc::now();
+        let mut user = User {
+            id: 1,
+            hash: String::from("$argon2i$v=19$m=4096,t=3,p=1$salt$hash"),
+            ban_expires: None,
+            note: None,
+            ip: String::from("127.0.0.1"),
+        };
+        assert!(!user.ban_has_expired(&now));
+
+        user.ban_expires = Some(now - Duration::hours(1));
+        assert!(user.ban_has_expired(&now));
+
+        user.ban_expires = Some(now + Duration::hours(1));
+        assert!(!user.ban_has_expired(&now));
+    }
+
+    #[test]
+    fn like_patterns() {
+        assert_eq!(escape_like_pattern("abc"), "abc");
+        assert_eq!(escape_like_pattern("100%"), "100\\%");
+        assert_eq!(escape_like_pattern("a_b\\c"), "a\\_b\\\\c");
+    }
+
+    #[test]
+    fn api_key_scope() {
+        assert!(key(ApiScope::Delete).allows(ApiScope::Delete));
+        assert!(!key(ApiScope::Delete).allows(ApiScope::Ban));
+        assert!(!key(ApiScope::Ban).allows(ApiScope::Delete));
+        assert!(key(ApiScope::All).allows(ApiScope::Delete));
+        assert!(key(ApiScope::All).allows(ApiScope::Ban));
+    }
+
+    #[test]
+    fn parse_api_scope() {
+        for scope in &[ApiScope::Delete, ApiScope::Ban, ApiScope::All] {
+            let parsed: ApiScope = scope.to_string().parse().unwrap();
+            assert_eq!(parsed, *scope);
+        }
+
+        assert!("everything".parse::<ApiScope>().is_err());
+    }
+
+    #[test]
+    fn normalize_ipv6() {
+        let ip: IpAddr = "2001:db8:1:2:3:4:5:6".parse().unwrap();
+        let network: IpAddr = "2001:db8:1:2::".parse().unwrap();
+        let unspecified: IpAddr = "::".parse().unwrap();
+
+        assert_eq!(normalize_ip(ip, 64), network);
+        assert_eq!(normalize_ip(ip, 128), ip);
+        assert_eq!(normalize_ip(ip, 0), unspecified);
+    }
+
+    #[test]
+    fn normalize_ipv4() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        assert_eq!(normalize_ip(ip, 64), ip);
+    }
+
+    #[test]
+    fn hash_ipv6_same_prefix() {
+        let first: IpAddr = "2001:db8:1:2::1".parse().unwrap();
+        let second: IpAddr =
+            "2001:db8:1:2:ffff:ffff:ffff:ffff".parse().unwrap();
+        let other: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+
+        let hash = |ip, prefix| NewUser::hash_ip(ip, prefix, "secret");
+
+        assert_eq!(hash(first, 64), hash(second, 64));
+        assert_ne!(hash(first, 64), hash(other, 64));
+        assert_ne!(hash(first, 128), hash(second, 128));
+    }
+
+    #[test]
+    fn hash_ip_uses_secret() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        assert_eq!(
+            NewUser::hash_ip(ip, 64, "one"),
+            NewUser::hash_ip(ip, 64, "one")
+        );
+        assert_ne!(
+            NewUser::hash_ip(ip, 64, "one"),
+            NewUser::hash_ip(ip, 64, "two")
+        );
+    }
+
+    #[test]
+    fn user_posts_pages() {
+        let first_page = user_posts_query(7, 1).unwrap();
+        let first_page = diesel::debug_query::<Pg, _>(&first_page).to_string();
+        let third_page = user_posts_query(7, 3).unwrap();
+        let third_page = diesel::debug_query::<Pg, _>(&third_page).to_string();
+
+        assert!(first_page.contains(r#"WHERE "post"."user_id" = $1"#));
+        assert!(first_page.contains(
+            r#"ORDER BY "post"."time_stamp" DESC, "post"."id" DESC"#
+        ));
+        assert!(first_page.contains("binds: [7, 50, 0]"));
+        assert!(third_page.contains("binds: [7, 50, 100]"));
+
+        assert!(matches!(
+            user_posts_query(7, u32::MAX),
+            Err(Error::PageOutOfRange { page: u32::MAX })
+        ));
+    }
+
+    #[test]
+    fn page_offsets() {
+        assert_eq!(page_offset(0, USERS_PER_PAGE).unwrap(), 0);
+        assert_eq!(page_offset(1, USERS_PER_PAGE).unwrap(), 0);
+        assert_eq!(page_offset(2, USERS_PER_PAGE).unwrap(), USERS_PER_PAGE);
+        assert!(matches!(
+            page_offset(u32::MAX / 2, USERS_PER_PAGE),
+            Err(Error::PageOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn removed_posts_leave_no_reports() {
+        use crate::models::testing;
+
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "orphans");
+        let user = testing::user(&mut db);
+        let spammer = testing::user(&mut db);
+        let thread_id = testing::thread(&mut db, "orphans", false);
+
+        let report = |db: &mut PooledConnection, post_id: PostId| {
+            db.insert_report(NewReport {
+                reason: "spam".into(),
+                post: post_id,
+                user_id: user.id,
+            })
+            .unwrap();
+
+            db.all_reports()
+                .unwrap()
+                .iter()
+                .find(|r| r.post_id == post_id)
+                .unwrap()
+                .id
+        };
+
+        let post_id =
+            testing::post(&mut db, "orphans", thread_id, &user, false);
+        let deleted_post = report(&mut db, post_id);
+        db.delete_post(post_id).unwrap();
+
+        let post_id =
+            testing::post(&mut db, "orphans", thread_id, &spammer, false);
+        let users_post = report(&mut db, post_id);
+        db.delete_posts_for_user(spammer.id).unwrap();
+
+        let deleted_thread = testing::thread(&mut db, "orphans", false);
+        let post_id =
+            testing::post(&mut db, "orphans", deleted_thread, &user, false);
+        let thread_post = report(&mut db, post_id);
+        db.delete_thread(deleted_thread).unwrap();
+
+        let post_id =
+            testing::post(&mut db, "orphans", thread_id, &user, false);
+        let trimmed_post = report(&mut db, post_id);
+        db.trim_board("orphans", 0).unwrap();
+        assert!(db.thread(thread_id).is_err());
+
+        for report_id in [deleted_post, users_post, thread_post, trimmed_post] {
+            assert!(db.report(report_id).is_err());
+        }
+    }
+
+    #[test]
+    fn open_reports_survive_pruning() {
+        use crate::models::testing;
+
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "prune");
+        let user = testing::user(&mut db);
+        let thread_id = testing::thread(&mut db, "prune", false);
+        let post_id = testing::post(&mut db, "prune", thread_id, &user, false);
+
+        for reason in &["open", "closed"] {
+            db.insert_report(NewReport {
+                reason: reason.to_string(),
+                post: post_id,
+                user_id: user.id,
+            })
+            .unwrap();
+        }
+
+        let reports = db.all_reports().unwrap();
+        let report_id = |reason: &str| {
+            reports
+                .iter()
+                .find(|r| r.post_id == post_id && r.reason == reason)
+                .unwrap()
+                .id
+        };
+        let (open_id, closed_id) = (report_id("open"), report_id("closed"));
+
+        db.close_report(closed_id).unwrap();
+
+        let open: Vec<ReportId> =
+            db.all_reports().unwrap().iter().map(|r| r.id).collect();
+        assert!(open.contains(&open_id));
+        assert!(!open.contains(&closed_id));
+
+        // Prune everything, even reports made just now.
+        let cutoff = Utc::now() + Duration::days(1);
+        assert_eq!(db.prune_reports_before(cutoff).unwrap(), 1);
+
+        assert!(db.report(open_id).is_ok());
+        assert!(db.report(closed_id).is_err());
+    }
+
+    #[test]
+    fn password_change_signs_out() {
+        use crate::models::testing;
+
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        let staff = Staff {
+            name: "passwd".into(),
+            password_hash: "old".into(),
+            role: Role::Janitor,
+        };
+        db.insert_staff(&staff).unwrap();
+
+        let sign_in = |db: &mut PooledConnection, id: &str| {
+            db.insert_session(Session {
+                id: id.into(),
+                expires: Utc::now() + Duration::days(1),
+                staff: staff.clone(),
+            })
+            .unwrap();
+            db.insert_api_key("passwd", ApiScope::Delete, id).unwrap();
+        };
+
+        sign_in(&mut db, "first");
+        db.update_staff_password("passwd", "new", false).unwrap();
+
+        assert_eq!(db.staff("passwd").unwrap().password_hash, "new");
+        assert!(db.session("first").is_err());
+        assert!(db.api_key("first").is_err());
+
+        sign_in(&mut db, "second");
+        db.update_staff_password("passwd", "newer", true).unwrap();
+
+        assert!(db.session("second").is_err());
+        assert!(db.api_key("second").is_ok());
+
+        assert!(matches!(
+            db.update_staff_password("nobody", "new", false),
+            Err(Error::StaffInvalidUsername { .. })
+        ));
+    }
+
+    #[test]
+    fn merged_users_keep_bans() {
+        use crate::models::testing;
+
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "merge");
+        let kept = testing::user(&mut db);
+        let merged = testing::user(&mut db);
+        let thread_id = testing::thread(&mut db, "merge", false);
+        let post_id =
+            testing::post(&mut db, "merge", thread_id, &merged, false);
+
+        db.ban_user(merged.id, Duration::days(1)).unwrap();
+        db.set_user_note(merged.id, "spammer").unwrap();
+
+        let network: IpAddr = "2001:db8::".parse().unwrap();
+        db.set_user_ip(kept.id, network, "rehashed").unwrap();
+        db.merge_user(merged.id, kept.id).unwrap();
+
+        assert!(db.user_by_id(merged.id).is_err());
+        assert_eq!(db.post(post_id).unwrap().user_id, kept.id);
+
+        let user = db.user(network).unwrap();
+        assert_eq!(user.id, kept.id);
+        assert_eq!(user.hash, "rehashed");
+        assert!(user.is_banned());
+        assert_eq!(user.note.as_deref(), Some("spammer"));
+    }
+
+    #[test]
+    fn similar_content() {
+        use crate::models::testing;
+
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "similar");
+        let user = testing::user(&mut db);
+        let thread_id = testing::thread(&mut db, "similar", false);
+
+        let mut new_post = testing::new_post("similar", thread_id, &user);
+        new_post.body = "<p>Buy cheap watches at example.com today</p>".into();
+        db.insert_post(new_post).unwrap();
+
+        let within = Duration::minutes(1);
+        let similar = "<p>Buy cheap watches at example.com now</p>";
+        let different = "<p>Does anyone know a good Rust book?</p>";
+
+        assert!(db
+            .similar_recent_content_exists(similar, within, 0.8)
+            .unwrap());
+        assert!(!db
+            .similar_recent_content_exists(different, within, 0.8)
+            .unwrap());
+        assert!(!db
+            .similar_recent_content_exists(similar, within, 1.0)
+            .unwrap());
+
+        // Posts older than the most recent few aren't compared.
+        for _ in 0..SIMILARITY_WINDOW {
+            testing::post(&mut db, "similar", thread_id, &user, false);
+        }
+
+        assert!(!db
+            .similar_recent_content_exists(similar, within, 0.8)
+            .unwrap());
+    }
 }
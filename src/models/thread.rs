@@ -1,17 +1,18 @@
 //! Types related to threads of posts.
 
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Debug;
 
 use chrono::offset::Utc;
-use chrono::DateTime;
+use chrono::{DateTime, Duration};
 
-use diesel::sql_types::Integer;
+use diesel::sql_types::{Array, Double, Integer, Text};
 use diesel::{delete, insert_into, prelude::*, sql_query, update};
 
 use rocket::uri;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::models::{Connection, *};
 use crate::schema::thread;
@@ -21,7 +22,7 @@ use crate::{Error, Result};
 pub type ThreadId = i32;
 
 /// A series of posts about a specific subject.
-#[derive(Debug, Queryable, Serialize)]
+#[derive(Debug, Queryable, Serialize, Deserialize)]
 pub struct Thread {
     /// The ID of the thread.
     pub id: ThreadId,
@@ -37,11 +38,20 @@ pub struct Thread {
     pub locked: bool,
     /// When the thread was last bumped.
     pub bump_date: DateTime<Utc>,
+    /// The number of posts in the thread.
+    pub post_count: i32,
+    /// The number of files in the thread.
+    pub file_count: i32,
+    /// Whether old posts are pruned instead of the thread being locked when it
+    /// reaches the reply cap.
+    pub cyclical: bool,
+    /// Whether the thread is waiting for staff to approve its first post.
+    pub pending: bool,
 }
 
 impl Thread {
     pub fn uri(&self) -> String {
-        uri!(crate::routes::thread: &self.board_name, self.id).to_string()
+        uri!(crate::routes::thread: &self.board_name, self.id, _).to_string()
     }
 }
 
@@ -53,11 +63,85 @@ pub struct NewThread {
     pub board: String,
     pub locked: bool,
     pub pinned: bool,
+    pub cyclical: bool,
+    pub pending: bool,
+}
+
+/// How similar two subjects need to be for `find_similar_thread` to consider
+/// them the same, from 0 to 1.
+const SUBJECT_SIMILARITY: f64 = 0.6;
+
+/// How many threads `fix_bump_dates` repairs at once.
+const BUMP_DATE_BATCH_SIZE: usize = 1000;
+
+/// Normalize a thread subject for comparison, by lowercasing it and removing
+/// punctuation and extra whitespace.
+pub fn normalize_subject<S>(subject: S) -> String
+where
+    S: AsRef<str>,
+{
+    subject
+        .as_ref()
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Get the posts that should be deleted from a thread with the posts
+/// `post_ids` (oldest first) so that at most `keep` replies are left.
+///
+/// The first post is never included.
+fn posts_to_trim(post_ids: &[PostId], keep: u32) -> &[PostId] {
+    let keep: usize = keep.try_into().unwrap();
+
+    match post_ids.split_first() {
+        Some((_first_post, replies)) => {
+            &replies[..replies.len().saturating_sub(keep)]
+        }
+        None => &[],
+    }
+}
+
+/// Group rows of posts joined with their files into each post and all of its
+/// files.
+///
+/// The rows must be ordered by post, and posts without any files should appear
+/// once with no file.
+fn group_files<P, F>(
+    rows: Vec<(P, Option<F>)>,
+    post_id: impl Fn(&P) -> PostId,
+) -> Vec<(P, Vec<F>)> {
+    let mut grouped: Vec<(P, Vec<F>)> = Vec::new();
+
+    for (post, file) in rows {
+        let same_post = grouped
+            .last()
+            .map_or(false, |(last, _)| post_id(last) == post_id(&post));
+
+        if !same_post {
+            grouped.push((post, Vec::new()));
+        }
+
+        if let Some(file) = file {
+            grouped.last_mut().unwrap().1.push(file);
+        }
+    }
+
+    grouped
+}
+
+/// The result of a raw query for a thread ID.
+#[derive(Debug, QueryableByName)]
+struct ThreadIdRow {
+    #[diesel(sql_type = Integer)]
+    id: ThreadId,
 }
 
 /// Convenience function to convert from diesel's error type into our error
 /// type, when we're querying for a thread.
-fn conv_thread_error(
+pub(crate) fn conv_thread_error(
     thread_id: ThreadId,
 ) -> impl FnOnce(diesel::result::Error) -> Error {
     move |e: diesel::result::Error| match e {
@@ -66,6 +150,52 @@ fn conv_thread_error(
     }
 }
 
+/// A query for a thread with the given ID on the given board.
+///
+/// Threads that are waiting for approval aren't found.
+fn thread_on_board_query(
+    board_name: &str,
+    thread_id: ThreadId,
+) -> thread::BoxedQuery<'_, diesel::pg::Pg> {
+    thread::table
+        .filter(thread::board.eq(board_name))
+        .filter(thread::id.eq(thread_id))
+        .filter(diesel::dsl::not(thread::pending))
+        .into_boxed()
+}
+
+/// A query for the threads made on a board since `cutoff`, newest first.
+fn threads_since_query(
+    board_name: &str,
+    cutoff: DateTime<Utc>,
+) -> thread::BoxedQuery<'_, diesel::pg::Pg> {
+    thread::table
+        .filter(thread::board.eq(board_name))
+        .filter(thread::time_stamp.gt(cutoff))
+        .filter(thread::pending.eq(false))
+        .order(thread::time_stamp.desc())
+        .into_boxed()
+}
+
+/// Find the thread in `threads` with the same subject as `subject`, once both
+/// are normalized with `normalize_subject`.
+///
+/// Subjects that are only punctuation don't match anything.
+fn thread_with_subject(threads: Vec<Thread>, subject: &str) -> Option<Thread> {
+    threads
+        .into_iter()
+        .find(|thread| same_subject(&thread.subject, subject))
+}
+
+/// Whether two subjects are the same once they're normalized with
+/// `normalize_subject`. Subjects that are only punctuation are never the same
+/// as anything.
+fn same_subject(a: &str, b: &str) -> bool {
+    let a = normalize_subject(a);
+
+    !a.is_empty() && a == normalize_subject(b)
+}
+
 impl<C, M> Connection<C, M>
 where
     C: InnerConnection<M> + diesel::connection::LoadConnection,
@@ -83,6 +213,110 @@ where
             .map_err(conv_thread_error(thread_id))
     }
 
+    /// Get a thread, checking that it's on the given board.
+    ///
+    /// Returns `ThreadNotFound` if the thread is on a different board.
+    pub fn thread_on_board<S>(
+        &mut self,
+        board_name: S,
+        thread_id: ThreadId,
+    ) -> Result<Thread>
+    where
+        S: AsRef<str>,
+    {
+        thread_on_board_query(board_name.as_ref(), thread_id)
+            .limit(1)
+            .first(&mut self.inner)
+            .map_err(conv_thread_error(thread_id))
+    }
+
+    /// Find a thread on a board with a subject similar to `subject`, if there
+    /// is one.
+    pub fn find_similar_thread<S1, S2>(
+        &mut self,
+        board_name: S1,
+        subject: S2,
+    ) -> Result<Option<Thread>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let query = "SELECT id FROM ( \
+                         SELECT id, bump_date, \
+                             similarity(trim(regexp_replace(lower(subject), \
+                                 '[^[:alnum:]]+', ' ', 'g')), $2) AS score \
+                           FROM thread \
+                          WHERE board = $1) T \
+                      WHERE score >= $3 \
+                   ORDER BY score DESC, bump_date DESC \
+                      LIMIT 1";
+
+        let row: Option<ThreadIdRow> = sql_query(query)
+            .bind::<Text, _>(board_name.as_ref())
+            .bind::<Text, _>(normalize_subject(subject))
+            .bind::<Double, _>(SUBJECT_SIMILARITY)
+            .get_result(&mut self.inner)
+            .optional()?;
+
+        row.map(|row| self.thread(row.id)).transpose()
+    }
+
+    /// Find a thread on a board made in the last `within` with the same
+    /// subject as `subject`, ignoring case and punctuation, if there is one.
+    pub fn recent_thread_with_subject<S1, S2>(
+        &mut self,
+        board_name: S1,
+        subject: S2,
+        within: Duration,
+    ) -> Result<Option<Thread>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let cutoff = Utc::now() - within;
+
+        let threads = threads_since_query(board_name.as_ref(), cutoff)
+            .load(&mut self.inner)?;
+
+        Ok(thread_with_subject(threads, subject.as_ref()))
+    }
+
+    /// Whether a thread on a board made in the last `within` with the same
+    /// subject as `subject` has been deleted.
+    ///
+    /// Deleted threads made before `within` can't match anything anymore, so
+    /// they're forgotten here.
+    pub fn recently_deleted_subject<S1, S2>(
+        &mut self,
+        board_name: S1,
+        subject: S2,
+        within: Duration,
+    ) -> Result<bool>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        use crate::schema::deleted_thread;
+
+        let cutoff = Utc::now() - within;
+
+        delete(
+            deleted_thread::table
+                .filter(deleted_thread::board.eq(board_name.as_ref()))
+                .filter(deleted_thread::time_stamp.le(cutoff)),
+        )
+        .execute(&mut self.inner)?;
+
+        let subjects: Vec<String> = deleted_thread::table
+            .filter(deleted_thread::board.eq(board_name.as_ref()))
+            .select(deleted_thread::subject)
+            .load(&mut self.inner)?;
+
+        Ok(subjects
+            .iter()
+            .any(|deleted| same_subject(deleted, subject.as_ref())))
+    }
+
     /// Insert a new thread into the database.
     pub fn insert_thread(&mut self, new_thread: NewThread) -> Result<ThreadId> {
         use crate::schema::thread::columns::id;
@@ -114,16 +348,37 @@ where
     /// This function will recursively delete all reports, posts, and files
     /// associated with the thread as well.
     pub fn delete_thread(&mut self, tid: ThreadId) -> Result<()> {
+        use crate::schema::post::columns::id as post_id;
         use crate::schema::post::columns::thread as post_thread;
         use crate::schema::post::dsl::post as table_post;
         use crate::schema::thread::columns::id as thread_id;
         use crate::schema::thread::dsl::thread as table_thread;
 
         self.inner.transaction::<_, Error, _>(|conn| {
-            let query = "DELETE FROM report R \
-                               USING post P \
-                               WHERE R.post = P.id AND P.thread = $1";
-            sql_query(query).bind::<Integer, _>(tid).execute(conn)?;
+            use crate::schema::deleted_thread;
+
+            let post_ids: Vec<PostId> = table_post
+                .filter(post_thread.eq(tid))
+                .select(post_id)
+                .load(conn)?;
+            delete_reports_for_posts(conn, &post_ids)?;
+
+            // Remember the subject, so that the thread can't be made again
+            // right away. See `duplicate_subject_cooldown`.
+            let deleted: Option<(String, String, DateTime<Utc>)> = table_thread
+                .filter(thread_id.eq(tid))
+                .select((thread::board, thread::subject, thread::time_stamp))
+                .first(conn)
+                .optional()?;
+            if let Some((board, subject, time_stamp)) = deleted {
+                insert_into(deleted_thread::table)
+                    .values((
+                        deleted_thread::board.eq(board),
+                        deleted_thread::subject.eq(subject),
+                        deleted_thread::time_stamp.eq(time_stamp),
+                    ))
+                    .execute(conn)?;
+            }
 
             let query = "DELETE FROM file F \
                                USING post P \
@@ -140,7 +395,8 @@ where
         Ok(())
     }
 
-    /// Get all of the posts in a thread.
+    /// Get all of the posts in a thread, including posts that are waiting for
+    /// approval.
     pub fn posts_in_thread(
         &mut self,
         thread_id: ThreadId,
@@ -154,14 +410,210 @@ where
             .map_err(conv_thread_error(thread_id))
     }
 
-    /// Get the number of posts in a thread.
-    pub fn thread_post_count(&mut self, thread_id: ThreadId) -> Result<u32> {
-        use crate::schema::post::columns::thread;
+    /// Get a thread along with all of its posts and their files.
+    ///
+    /// The posts are ordered by ID, and posts that are waiting for approval are
+    /// left out. This takes two queries, one for the thread and one for the
+    /// posts joined with their files.
+    pub fn thread_with_posts(
+        &mut self,
+        thread_id: ThreadId,
+    ) -> Result<(Thread, Vec<(Post, Vec<File>)>)> {
+        use crate::schema::{file, post};
+
+        let thread = self.thread(thread_id)?;
+
+        let rows: Vec<(Post, Option<DbFile>)> = post::table
+            .left_join(file::table)
+            .filter(post::thread.eq(thread_id))
+            .filter(post::pending.eq(false))
+            .order((post::id.asc(), file::save_name.asc()))
+            .select((post::all_columns, file::all_columns.nullable()))
+            .load(&mut self.inner)
+            .map_err(conv_thread_error(thread_id))?;
+
+        let posts = group_files(rows, |post| post.id)
+            .into_iter()
+            .map(|(post, files)| {
+                (post, files.into_iter().map(File::from).collect())
+            })
+            .collect();
+
+        Ok((thread, posts))
+    }
+
+    /// Get the original post of a thread along with one page of its replies,
+    /// and their files.
+    ///
+    /// Like `thread_with_posts`, posts are ordered by ID and posts that are
+    /// waiting for approval are left out. The original post isn't counted as
+    /// part of any page, so every page starts with it.
+    pub fn posts_in_thread_page(
+        &mut self,
+        thread_id: ThreadId,
+        page: Page,
+    ) -> Result<Vec<(Post, Vec<File>)>> {
+        use crate::schema::post;
+
+        let op_id = match self.original_post_id(thread_id)? {
+            Some(op_id) => op_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let reply_ids: Vec<PostId> = post::table
+            .filter(post::thread.eq(thread_id))
+            .filter(post::pending.eq(false))
+            .filter(post::id.gt(op_id))
+            .order(post::id.asc())
+            .select(post::id)
+            .offset(i64::from(page.offset()?))
+            .limit(page.width as i64)
+            .load(&mut self.inner)
+            .map_err(conv_thread_error(thread_id))?;
+
+        let post_ids = std::iter::once(op_id).chain(reply_ids).collect();
+        self.posts_with_files(thread_id, post_ids)
+    }
+
+    /// Get the original post of a thread along with its `limit` newest
+    /// replies, and their files.
+    ///
+    /// Like `posts_in_thread_page`, posts are ordered by ID and posts that are
+    /// waiting for approval are left out.
+    pub fn last_posts_in_thread(
+        &mut self,
+        thread_id: ThreadId,
+        limit: u32,
+    ) -> Result<Vec<(Post, Vec<File>)>> {
+        use crate::schema::post;
+
+        let op_id = match self.original_post_id(thread_id)? {
+            Some(op_id) => op_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let reply_ids: Vec<PostId> = post::table
+            .filter(post::thread.eq(thread_id))
+            .filter(post::pending.eq(false))
+            .filter(post::id.gt(op_id))
+            .order(post::id.desc())
+            .select(post::id)
+            .limit(limit as i64)
+            .load(&mut self.inner)
+            .map_err(conv_thread_error(thread_id))?;
+
+        let post_ids = std::iter::once(op_id).chain(reply_ids).collect();
+        self.posts_with_files(thread_id, post_ids)
+    }
+
+    /// Get the ID of the original post of a thread, unless it's waiting for
+    /// approval.
+    fn original_post_id(
+        &mut self,
+        thread_id: ThreadId,
+    ) -> Result<Option<PostId>> {
+        use crate::schema::post;
+
+        post::table
+            .filter(post::thread.eq(thread_id))
+            .filter(post::pending.eq(false))
+            .order(post::id.asc())
+            .select(post::id)
+            .first(&mut self.inner)
+            .optional()
+            .map_err(conv_thread_error(thread_id))
+    }
+
+    /// Get some posts in a thread along with their files, ordered by ID.
+    fn posts_with_files(
+        &mut self,
+        thread_id: ThreadId,
+        post_ids: Vec<PostId>,
+    ) -> Result<Vec<(Post, Vec<File>)>> {
+        use crate::schema::{file, post};
+
+        let rows: Vec<(Post, Option<DbFile>)> = post::table
+            .left_join(file::table)
+            .filter(post::id.eq_any(post_ids))
+            .order((post::id.asc(), file::save_name.asc()))
+            .select((post::all_columns, file::all_columns.nullable()))
+            .load(&mut self.inner)
+            .map_err(conv_thread_error(thread_id))?;
+
+        Ok(group_files(rows, |post| post.id)
+            .into_iter()
+            .map(|(post, files)| {
+                (post, files.into_iter().map(File::from).collect())
+            })
+            .collect())
+    }
+
+    /// How many pages of replies a thread has, given the number of replies on
+    /// each page.
+    ///
+    /// A thread always has at least one page, even if nobody has replied to
+    /// it.
+    pub fn thread_reply_page_count(
+        &mut self,
+        thread_id: ThreadId,
+        page_width: u32,
+    ) -> Result<u32> {
+        use diesel::dsl::count;
+
+        use crate::schema::post::columns::{id, pending, thread};
+        use crate::schema::post::dsl::post;
+
+        let post_count: i64 = post
+            .filter(thread.eq(thread_id))
+            .filter(pending.eq(false))
+            .select(count(id))
+            .first(&mut self.inner)
+            .map_err(conv_thread_error(thread_id))?;
+
+        let reply_count = (post_count - 1).max(0) as f64;
+
+        Ok(((reply_count / page_width as f64).ceil() as u32).max(1))
+    }
+
+    /// The page of replies a post shows up on, given the number of replies on
+    /// each page.
+    ///
+    /// The original post shows up on every page, so it's on page 1.
+    pub fn thread_page_of_post(
+        &mut self,
+        thread_id: ThreadId,
+        post_id: PostId,
+        page_width: u32,
+    ) -> Result<u32> {
+        use diesel::dsl::count;
+
+        use crate::schema::post::columns::{id, pending, thread};
         use crate::schema::post::dsl::post;
 
-        let count: i64 = post
+        let earlier_count: i64 = post
             .filter(thread.eq(thread_id))
-            .count()
+            .filter(pending.eq(false))
+            .filter(id.lt(post_id))
+            .select(count(id))
+            .first(&mut self.inner)
+            .map_err(conv_thread_error(thread_id))?;
+
+        // The original post is the first post, so it's the only one that has
+        // no posts before it.
+        let reply_index = (earlier_count - 1).max(0) as u32;
+
+        Ok(reply_index / page_width + 1)
+    }
+
+    /// Get the number of posts in a thread.
+    pub fn thread_post_count(&mut self, thread_id: ThreadId) -> Result<u32> {
+        use crate::schema::thread::columns::{id, post_count};
+        use crate::schema::thread::dsl::thread;
+
+        let count: i32 = thread
+            .filter(id.eq(thread_id))
+            .select(post_count)
+            .limit(1)
             .first(&mut self.inner)
             .map_err(conv_thread_error(thread_id))?;
 
@@ -170,28 +622,170 @@ where
 
     /// Get the number of files in a thread.
     pub fn thread_file_count(&mut self, thread_id: ThreadId) -> Result<u32> {
-        use crate::schema::file::dsl::file;
-        use crate::schema::post::dsl::post;
-        use crate::schema::thread::columns::id;
+        use crate::schema::thread::columns::{file_count, id};
         use crate::schema::thread::dsl::thread;
 
-        let count: i64 = thread
-            .inner_join(post.inner_join(file))
+        let count: i32 = thread
             .filter(id.eq(thread_id))
-            .count()
+            .select(file_count)
+            .limit(1)
             .first(&mut self.inner)
             .map_err(conv_thread_error(thread_id))?;
 
+        Ok(count.try_into().expect("couldn't convert i32 to u32"))
+    }
+
+    /// Get the number of different users that posted in each of the threads
+    /// `thread_ids`.
+    ///
+    /// System posts and posts waiting for approval aren't counted. Threads
+    /// without any counted posts are left out of the map.
+    pub fn unique_poster_counts(
+        &mut self,
+        thread_ids: &[ThreadId],
+    ) -> Result<HashMap<ThreadId, u32>> {
+        use crate::schema::post::columns::{pending, system, thread, user_id};
+        use crate::schema::post::dsl::post;
+
+        use diesel::dsl::count_distinct;
+
+        let counts: Vec<(ThreadId, i64)> = post
+            .filter(thread.eq_any(thread_ids))
+            .filter(system.eq(false))
+            .filter(pending.eq(false))
+            .group_by(thread)
+            .select((thread, count_distinct(user_id)))
+            .load(&mut self.inner)?;
+
+        Ok(counts
+            .into_iter()
+            .map(|(thread_id, count)| (thread_id, count.try_into().unwrap()))
+            .collect())
+    }
+
+    /// Get the number of different users that posted in a thread. See
+    /// `unique_poster_counts`.
+    pub fn thread_unique_poster_count(
+        &mut self,
+        thread_id: ThreadId,
+    ) -> Result<u32> {
+        // Check that the thread exists, so that we can give a better error.
+        self.thread(thread_id)?;
+
+        let counts = self.unique_poster_counts(&[thread_id])?;
+        Ok(counts.get(&thread_id).copied().unwrap_or(0))
+    }
+
+    /// Recompute the cached post and file counts of every thread. Returns the
+    /// number of threads whose counts were wrong.
+    pub fn recount_threads(&mut self) -> Result<u32> {
+        let query = "UPDATE thread T \
+                     SET post_count = C.post_count, file_count = C.file_count \
+                     FROM ( \
+                         SELECT T.id, \
+                             (SELECT COUNT(*) FROM post P \
+                                 WHERE P.thread = T.id \
+                                     AND NOT P.pending) AS post_count, \
+                             (SELECT COUNT(*) FROM file F \
+                                 INNER JOIN post P ON F.post = P.id \
+                                 WHERE P.thread = T.id) AS file_count \
+                         FROM thread T) C \
+                     WHERE T.id = C.id \
+                         AND (T.post_count <> C.post_count \
+                             OR T.file_count <> C.file_count)";
+
+        let count: usize = sql_query(query).execute(&mut self.inner)?;
+
+        Ok(count.try_into().unwrap())
+    }
+
+    /// Set the bump dates of the threads `thread_ids` to the time of their
+    /// newest post that bumped the thread, or the time the thread was made if
+    /// no posts bumped it. Returns the number of threads that were changed.
+    ///
+    /// Bump dates are set slightly after their post is made, so bump dates
+    /// within a second of the right time are left alone.
+    fn recompute_bump_dates(&mut self, thread_ids: &[ThreadId]) -> Result<u32> {
+        let query = "UPDATE thread T \
+                     SET bump_date = C.bump_date \
+                     FROM ( \
+                         SELECT T.id, COALESCE( \
+                             (SELECT MAX(P.time_stamp) FROM post P \
+                                 WHERE P.thread = T.id AND NOT P.no_bump \
+                                     AND NOT P.pending), \
+                             T.time_stamp) AS bump_date \
+                         FROM thread T \
+                         WHERE T.id = ANY($1)) C \
+                     WHERE T.id = C.id \
+                         AND ABS(EXTRACT(EPOCH FROM \
+                             T.bump_date - C.bump_date)) >= 1";
+
+        let count: usize = sql_query(query)
+            .bind::<Array<Integer>, _>(thread_ids)
+            .execute(&mut self.inner)?;
+
         Ok(count.try_into().unwrap())
     }
 
-    /// Get the first post and up to `limit` recent posts from a thread.
+    /// Set a thread's bump date to the time of its newest post that bumped the
+    /// thread. Returns whether the bump date was wrong.
+    pub fn recompute_bump_date(&mut self, thread_id: ThreadId) -> Result<bool> {
+        // Check that the thread exists, so that we can give a better error.
+        self.thread(thread_id)?;
+
+        Ok(self.recompute_bump_dates(&[thread_id])? > 0)
+    }
+
+    /// Check and repair the bump dates of every thread, in batches. Returns the
+    /// number of threads whose bump dates were wrong.
+    pub fn fix_bump_dates(&mut self) -> Result<u32> {
+        use crate::schema::thread::columns::id;
+        use crate::schema::thread::dsl::thread;
+
+        let thread_ids: Vec<ThreadId> =
+            thread.select(id).order(id.asc()).load(&mut self.inner)?;
+
+        let mut count = 0;
+
+        for batch in thread_ids.chunks(BUMP_DATE_BATCH_SIZE) {
+            count += self.recompute_bump_dates(batch)?;
+        }
+
+        Ok(count)
+    }
+
+    /// Delete the oldest posts in a thread so that at most `keep` replies are
+    /// left. The first post in the thread is never deleted.
+    pub fn trim_thread(
+        &mut self,
+        thread_id: ThreadId,
+        keep: u32,
+    ) -> Result<()> {
+        use crate::schema::post::columns::{id, thread};
+        use crate::schema::post::dsl::post;
+
+        let post_ids: Vec<PostId> = post
+            .filter(thread.eq(thread_id))
+            .select(id)
+            .order(id.asc())
+            .load(&mut self.inner)
+            .map_err(conv_thread_error(thread_id))?;
+
+        for &post_id in posts_to_trim(&post_ids, keep) {
+            self.delete_post(post_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the first post and up to `limit` recent posts from a thread, leaving
+    /// out posts that are waiting for approval.
     pub fn preview_thread(
         &mut self,
         thread_id: ThreadId,
         limit: u32,
     ) -> Result<Vec<Post>> {
-        use crate::schema::post::columns::{id, thread};
+        use crate::schema::post::columns::{id, pending, thread};
         use crate::schema::post::dsl::post;
 
         let mut posts: Vec<Post> = Vec::new();
@@ -207,6 +801,7 @@ where
             posts = post
                 .filter(id.ne(first_post.id))
                 .filter(thread.eq(thread_id))
+                .filter(pending.eq(false))
                 .order(id.desc())
                 .limit(limit.into())
                 .load(conn)
@@ -282,3 +877,231 @@ where
             .first(&mut self.inner)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use diesel::debug_query;
+    use diesel::pg::Pg;
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{
+        group_files, normalize_subject, posts_to_trim, thread_on_board_query,
+        thread_with_subject, threads_since_query, Thread,
+    };
+    use crate::models::{testing, Page, PooledConnection, Post, PostId};
+
+    /// The IDs of posts loaded along with their files.
+    fn post_ids<F>(posts: Vec<(Post, F)>) -> Vec<PostId> {
+        posts.into_iter().map(|(post, _)| post.id).collect()
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_subject("Hello, World!"), "hello world");
+        assert_eq!(normalize_subject("  rust   general  "), "rust general");
+        assert_eq!(normalize_subject("/g/ - Technology"), "g technology");
+        assert_eq!(normalize_subject("!!!"), "");
+    }
+
+    #[test]
+    fn trim() {
+        assert_eq!(posts_to_trim(&[1, 2, 3, 4, 5], 2), &[2, 3]);
+        assert_eq!(posts_to_trim(&[1, 2, 3], 2), &[] as &[i32]);
+        assert_eq!(posts_to_trim(&[1, 2, 3], 0), &[2, 3]);
+        assert_eq!(posts_to_trim(&[1], 0), &[] as &[i32]);
+        assert_eq!(posts_to_trim(&[], 0), &[] as &[i32]);
+    }
+
+    #[test]
+    fn thread_on_board_filters_board() {
+        let query = thread_on_board_query("b", 1);
+        let sql = debug_query::<Pg, _>(&query).to_string();
+
+        assert!(sql.contains(r#""thread"."board" = $1"#));
+        assert!(sql.contains(r#""thread"."id" = $2"#));
+        assert!(sql.contains("NOT"));
+        assert!(sql.contains(r#""thread"."pending""#));
+        assert!(sql.ends_with(r#"-- binds: ["b", 1]"#));
+    }
+
+    #[test]
+    fn threads_since_filters_board_and_time() {
+        let cutoff = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+        let query = threads_since_query("b", cutoff);
+        let sql = debug_query::<Pg, _>(&query).to_string();
+
+        assert!(sql.contains(r#""thread"."board" = $1"#));
+        assert!(sql.contains(r#""thread"."time_stamp" > $2"#));
+        assert!(sql.contains(r#""thread"."pending" = $3"#));
+    }
+
+    #[test]
+    fn duplicate_subjects() {
+        let time_stamp = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+        let thread = |id, subject: &str| Thread {
+            id,
+            time_stamp,
+            subject: subject.to_string(),
+            board_name: "b".to_string(),
+            pinned: false,
+            locked: false,
+            bump_date: time_stamp,
+            post_count: 1,
+            file_count: 0,
+            cyclical: false,
+            pending: false,
+        };
+        let threads = || vec![thread(1, "rust general"), thread(2, "")];
+
+        let found = thread_with_subject(threads(), "Rust General!");
+        assert_eq!(found.map(|thread| thread.id), Some(1));
+        assert!(thread_with_subject(threads(), "go general").is_none());
+        assert!(thread_with_subject(threads(), "!!!").is_none());
+    }
+
+    #[test]
+    fn deleted_and_held_subjects() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+        let cooldown = Duration::hours(1);
+
+        testing::board(&mut db, "subjects");
+        let thread_id = testing::thread(&mut db, "subjects", false);
+
+        let recent = db
+            .recent_thread_with_subject("subjects", "test!", cooldown)
+            .unwrap();
+        assert_eq!(recent.map(|thread| thread.id), Some(thread_id));
+        assert!(!db
+            .recently_deleted_subject("subjects", "test!", cooldown)
+            .unwrap());
+
+        db.delete_thread(thread_id).unwrap();
+
+        assert!(db
+            .recent_thread_with_subject("subjects", "test", cooldown)
+            .unwrap()
+            .is_none());
+        assert!(db
+            .recently_deleted_subject("subjects", "test", cooldown)
+            .unwrap());
+        assert!(!db
+            .recently_deleted_subject("subjects", "other", cooldown)
+            .unwrap());
+        assert!(!db
+            .recently_deleted_subject("other", "test", cooldown)
+            .unwrap());
+
+        // Threads waiting for approval can't be linked to.
+        testing::thread(&mut db, "subjects", true);
+        assert!(db
+            .recent_thread_with_subject("subjects", "test", cooldown)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn thread_reply_pages() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "pages");
+        let thread_id = testing::thread(&mut db, "pages", false);
+        let user = testing::user(&mut db);
+
+        let op = testing::post(&mut db, "pages", thread_id, &user, false);
+        assert_eq!(db.thread_reply_page_count(thread_id, 2).unwrap(), 1);
+
+        let replies: Vec<PostId> = (0..5)
+            .map(|_| testing::post(&mut db, "pages", thread_id, &user, false))
+            .collect();
+
+        // Posts waiting for approval aren't on any page.
+        testing::post(&mut db, "pages", thread_id, &user, true);
+
+        assert_eq!(db.thread_reply_page_count(thread_id, 2).unwrap(), 3);
+        assert_eq!(db.thread_reply_page_count(thread_id, 5).unwrap(), 1);
+
+        let page_of = |db: &mut PooledConnection, post_id| {
+            db.thread_page_of_post(thread_id, post_id, 2).unwrap()
+        };
+        assert_eq!(page_of(&mut db, op), 1);
+        assert_eq!(page_of(&mut db, replies[0]), 1);
+        assert_eq!(page_of(&mut db, replies[1]), 1);
+        assert_eq!(page_of(&mut db, replies[2]), 2);
+        assert_eq!(page_of(&mut db, replies[4]), 3);
+
+        let page = Page { num: 2, width: 2 };
+        assert_eq!(
+            post_ids(db.posts_in_thread_page(thread_id, page).unwrap()),
+            [op, replies[2], replies[3]]
+        );
+
+        assert_eq!(
+            post_ids(db.last_posts_in_thread(thread_id, 2).unwrap()),
+            [op, replies[3], replies[4]]
+        );
+    }
+
+    #[test]
+    fn thread_slice_boundaries() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "slices");
+        let thread_id = testing::thread(&mut db, "slices", false);
+        let user = testing::user(&mut db);
+
+        let op = testing::post(&mut db, "slices", thread_id, &user, false);
+        let replies: Vec<PostId> = (0..4)
+            .map(|_| testing::post(&mut db, "slices", thread_id, &user, false))
+            .collect();
+
+        let mut page = |num| {
+            let page = Page { num, width: 3 };
+            post_ids(db.posts_in_thread_page(thread_id, page).unwrap())
+        };
+        assert_eq!(page(1), [op, replies[0], replies[1], replies[2]]);
+        assert_eq!(page(2), [op, replies[3]]);
+        assert_eq!(page(3), [op]);
+
+        let mut last = |count| {
+            post_ids(db.last_posts_in_thread(thread_id, count).unwrap())
+        };
+        let whole_thread: Vec<PostId> =
+            std::iter::once(op).chain(replies.iter().copied()).collect();
+        assert_eq!(last(1), [op, replies[3]]);
+        assert_eq!(last(4), whole_thread);
+        assert_eq!(last(5), whole_thread);
+    }
+
+    #[test]
+    fn group_post_files() {
+        let rows = vec![
+            (1, Some("a")),
+            (1, Some("b")),
+            (2, None),
+            (3, Some("c")),
+            (4, None),
+        ];
+
+        assert_eq!(
+            group_files(rows, |post| *post),
+            vec![
+                (1, vec!["a", "b"]),
+                (2, vec![]),
+                (3, vec!["c"]),
+                (4, vec![]),
+            ]
+        );
+
+        assert!(group_files::<i32, &str>(vec![], |post| *post).is_empty());
+    }
+}
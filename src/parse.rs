@@ -1,15 +1,26 @@
 //! Parsing for uploaded posts.
 
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use ammonia::Builder;
+
 use combine::parser::char::*;
 use combine::parser::combinator::*;
 use combine::parser::repeat::*;
 use combine::stream::position;
 use combine::*;
 
+use derive_more::Display;
+
 use horrorshow::html;
 use horrorshow::prelude::*;
 
-use regex::Regex;
+use pulldown_cmark::{html::push_html, Event, Parser};
+
+use regex::{Captures, Regex};
+
+use serde::{Deserialize, Serialize};
 
 use crate::config::FilterRule;
 use crate::models::*;
@@ -99,6 +110,8 @@ where
         .map(|s: String| LineItem::PostRef {
             id: s.parse().unwrap(),
             uri: None,
+            label: None,
+            snippet: None,
         })
 }
 
@@ -297,6 +310,523 @@ where
     .map(PostBody)
 }
 
+/// How the bodies of posts on a board are turned into HTML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum MarkupMode {
+    /// Posts are written in our own markup, which is parsed by `PostBody`.
+    #[display(fmt = "longboard")]
+    Longboard,
+    /// Posts are written in HTML, which is cleaned by `sanitize_html`.
+    #[display(fmt = "sanitized-html")]
+    SanitizedHtml,
+}
+
+impl FromStr for MarkupMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "longboard" => Ok(MarkupMode::Longboard),
+            "sanitized-html" => Ok(MarkupMode::SanitizedHtml),
+            _ => Err(Error::UnknownMarkupMode {
+                mode: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// How lines of text in a post body are separated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineBreakMode {
+    /// Each line of text is its own paragraph.
+    #[default]
+    Paragraph,
+    /// Lines of text in a row are one paragraph, separated by `<br>` tags.
+    BreakTags,
+}
+
+/// Apply filter rules to the content of a post.
+fn apply_filter_rules(mut content: String, rules: &[FilterRule]) -> String {
+    for rule in rules {
+        content = rule
+            .pattern
+            .replace_all(&content, rule.replace_with.as_str())
+            .into_owned();
+    }
+
+    content
+}
+
+/// Clean a post body written in HTML.
+///
+/// Only the tags in `allowed_tags` are kept, along with the attributes that
+/// `ammonia` considers safe for them. Scripts, styles, and event handlers are
+/// always removed, whatever tags are allowed. Filter rules are applied before
+/// the HTML is cleaned, so they can't be used to add unsafe HTML.
+pub fn sanitize_html<S>(
+    content: S,
+    rules: &[FilterRule],
+    allowed_tags: &[String],
+) -> String
+where
+    S: Into<String>,
+{
+    let content = apply_filter_rules(content.into(), rules);
+
+    // The contents of scripts and styles are removed along with the tags, and
+    // ammonia doesn't allow a tag to be both allowed and removed.
+    let tags = allowed_tags
+        .iter()
+        .map(String::as_str)
+        .filter(|tag| !matches!(*tag, "script" | "style"))
+        .collect();
+
+    Builder::default()
+        .tags(tags)
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .clean(&content)
+        .to_string()
+}
+
+/// The number of links in a post body that was cleaned with `sanitize_html`.
+pub fn sanitized_link_count(html: &str) -> usize {
+    // Attribute values are always quoted in cleaned HTML, and quotes in text
+    // are escaped, so this can't match outside of a tag.
+    html.matches(" href=\"").count()
+}
+
+/// Split a post body's HTML into its non-empty lines, as plain text.
+fn html_text_lines(html: &str) -> Vec<String> {
+    let line_break =
+        Regex::new(r"(?i)</p>|<br\s*/?>|</h\d>|</pre>|\n").unwrap();
+    let tag = Regex::new(r"<[^>]*>").unwrap();
+
+    line_break
+        .split(html)
+        .map(|line| {
+            tag.replace_all(line, "")
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&#39;", "'")
+                .replace("&#x27;", "'")
+                .replace("&amp;", "&")
+        })
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Cut `text` down to at most `max_chars` characters, ending it with an
+/// ellipsis if anything was cut.
+fn truncate_text(text: String, max_chars: usize) -> String {
+    if text.chars().count() > max_chars {
+        let mut truncated: String =
+            text.chars().take(max_chars.saturating_sub(1)).collect();
+        truncated.push('…');
+        truncated
+    } else {
+        text
+    }
+}
+
+/// Get the start of the first line of a post body's HTML as plain text, with at
+/// most `max_chars` characters.
+pub fn html_snippet<S>(html: S, max_chars: usize) -> String
+where
+    S: AsRef<str>,
+{
+    let line = html_text_lines(html.as_ref())
+        .into_iter()
+        .next()
+        .unwrap_or_default();
+
+    truncate_text(line, max_chars)
+}
+
+/// Get the whole of a post body's HTML as plain text on one line, with at most
+/// `max_chars` characters.
+pub fn html_excerpt<S>(html: S, max_chars: usize) -> String
+where
+    S: AsRef<str>,
+{
+    let text = html_text_lines(html.as_ref())
+        .iter()
+        .flat_map(|line| line.split_whitespace())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    truncate_text(text, max_chars)
+}
+
+/// Apply filter rules to the text of a rendered post body, leaving its tags
+/// alone.
+///
+/// The rules see the text as it is in the HTML, so special characters show up
+/// as entities like `&amp;`. What the rules replace matches with is escaped, so
+/// it can't add HTML to the body.
+pub fn filter_html_text<S>(html: S, rules: &[FilterRule]) -> String
+where
+    S: AsRef<str>,
+{
+    let html = html.as_ref();
+
+    if rules.is_empty() {
+        return html.to_string();
+    }
+
+    let rules: Vec<FilterRule> = rules
+        .iter()
+        .map(|rule| FilterRule {
+            pattern: rule.pattern.clone(),
+            replace_with: rule
+                .replace_with
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;"),
+        })
+        .collect();
+
+    let tag = Regex::new(r"<[^>]*>").unwrap();
+
+    let mut filtered = String::with_capacity(html.len());
+    let mut text_start = 0;
+
+    for tag_match in tag.find_iter(html) {
+        let text = &html[text_start..tag_match.start()];
+        filtered.push_str(&apply_filter_rules(text.to_string(), &rules));
+        filtered.push_str(tag_match.as_str());
+        text_start = tag_match.end();
+    }
+
+    let text = &html[text_start..];
+    filtered.push_str(&apply_filter_rules(text.to_string(), &rules));
+
+    filtered
+}
+
+/// Find the posts referenced in a rendered post body, in the order they're
+/// referenced.
+///
+/// Only references to posts that existed when the body was rendered are found,
+/// since those are the ones that link to their post. See `LineItem::PostRef`.
+pub fn rendered_post_refs<S>(html: S) -> Vec<PostId>
+where
+    S: AsRef<str>,
+{
+    // References are shown as the post ID, possibly prefixed with the board,
+    // like "/tech/#123".
+    let post_ref = Regex::new(concat!(
+        r#"<a class="post-ref" href="[^"]*"(?: title="[^"]*")?>"#,
+        r#"(?:[^<#]*#)?(\d+)</a>"#,
+    ))
+    .unwrap();
+
+    let mut ids = Vec::new();
+
+    for captures in post_ref.captures_iter(html.as_ref()) {
+        if let Ok(id) = captures[1].parse() {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+
+    ids
+}
+
+/// Rewrite the references in a rendered post body to posts on `board_name`,
+/// using maps from old thread and post IDs to new ones. This is used when a
+/// board is imported, since its threads and posts get new IDs.
+///
+/// References to other boards, or to posts that aren't in the maps, are left
+/// alone.
+pub fn remap_post_refs<S>(
+    html: S,
+    board_name: &str,
+    thread_ids: &HashMap<ThreadId, ThreadId>,
+    post_ids: &HashMap<PostId, PostId>,
+) -> String
+where
+    S: AsRef<str>,
+{
+    let post_ref = Regex::new(concat!(
+        r#"<a class="post-ref" href="/([^/"]+)/(\d+)#([^"]*?)(\d+)""#,
+        r#"((?: title="[^"]*")?)>([^<#]*#)?(\d+)</a>"#,
+    ))
+    .unwrap();
+
+    let remapped = post_ref.replace_all(html.as_ref(), |c: &Captures| {
+        let old_thread: Option<ThreadId> = c[2].parse().ok();
+        let old_post: Option<PostId> = c[4].parse().ok();
+
+        let new_ids = old_thread
+            .and_then(|id| thread_ids.get(&id))
+            .zip(old_post.and_then(|id| post_ids.get(&id)));
+
+        match new_ids {
+            Some((new_thread, new_post)) if &c[1] == board_name => format!(
+                r#"<a class="post-ref" href="/{}/{}#{}{}"{}>{}{}</a>"#,
+                &c[1],
+                new_thread,
+                &c[3],
+                new_post,
+                &c[5],
+                c.get(6).map_or("", |m| m.as_str()),
+                new_post,
+            ),
+            _ => c[0].to_string(),
+        }
+    });
+
+    remapped.into_owned()
+}
+
+/// Render markdown written by the site's admin, such as the notice or a custom
+/// page, into HTML.
+///
+/// If `allowed_html` is empty, any raw HTML in the markdown is escaped.
+/// Otherwise, raw HTML is passed through and the result is sanitized, allowing
+/// the tags in `allowed_html` with their listed attributes along with
+/// ammonia's default tags. This must never be used for user posts.
+pub fn render_admin_markdown<S>(
+    content: S,
+    allowed_html: &HashMap<String, Vec<String>>,
+) -> String
+where
+    S: AsRef<str>,
+{
+    let parser = Parser::new(content.as_ref());
+    let mut html = String::new();
+
+    if allowed_html.is_empty() {
+        push_html(
+            &mut html,
+            parser.map(|event| match event {
+                Event::Html(raw) => Event::Text(raw),
+                event => event,
+            }),
+        );
+
+        return html;
+    }
+
+    push_html(&mut html, parser);
+
+    let mut builder = Builder::default();
+
+    builder.add_tags(allowed_html.keys().map(String::as_str));
+
+    for (tag, attributes) in allowed_html {
+        builder.add_tag_attributes(
+            tag.as_str(),
+            attributes.iter().map(String::as_str),
+        );
+    }
+
+    builder.clean(&html).to_string()
+}
+
+/// Limits on the number of lines in a post body.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LineLimits {
+    /// The most lines a post can have, not counting empty lines.
+    pub max_lines: Option<u32>,
+    /// The most quote lines in a row a post can have.
+    pub max_quote_lines: Option<u32>,
+}
+
+/// How long a post can be before it's collapsed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CollapseLimits {
+    /// The most lines a post can have before it's collapsed.
+    pub lines: Option<u32>,
+    /// The most characters of text a post can have before it's collapsed.
+    pub chars: Option<u32>,
+}
+
+/// Options for rendering a post body into HTML.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOptions<'a> {
+    /// The `rel` attribute of links. Links have no `rel` if this is empty.
+    pub link_rel: &'a str,
+    /// The `target` attribute of links. Links have no `target` if this is
+    /// empty.
+    pub link_target: &'a str,
+    /// If set, links longer than this are shown shortened. See
+    /// `shorten_link`.
+    pub link_display_chars: Option<u32>,
+}
+
+impl Default for RenderOptions<'_> {
+    fn default() -> Self {
+        RenderOptions {
+            link_rel: "nofollow noopener",
+            link_target: "_blank",
+            link_display_chars: None,
+        }
+    }
+}
+
+/// Shorten a link that's longer than `max_chars` for display. The scheme is
+/// left out and the path is cut off with "…", but the host is always kept in
+/// full. Returns `None` if the link doesn't need shortening.
+fn shorten_link(link: &str, max_chars: usize) -> Option<String> {
+    if link.chars().count() <= max_chars {
+        return None;
+    }
+
+    let without_scheme = match link.find("://") {
+        Some(i) => &link[i + 3..],
+        None => link,
+    };
+
+    if without_scheme.chars().count() <= max_chars {
+        return Some(without_scheme.to_string());
+    }
+
+    let host = without_scheme.split('/').next().unwrap_or_default();
+    let keep = max_chars.saturating_sub(1).max(host.chars().count());
+
+    let mut shortened: String = without_scheme.chars().take(keep).collect();
+    shortened.push('…');
+
+    Some(shortened)
+}
+
+/// An item of a post body along with the options to render it with.
+struct WithOptions<'a, T>(&'a T, RenderOptions<'a>);
+
+impl<'a, T> RenderMut for WithOptions<'a, T>
+where
+    WithOptions<'a, T>: Render,
+{
+    fn render_mut(&mut self, tmpl: &mut TemplateBuffer) {
+        self.render(tmpl)
+    }
+}
+
+impl<'a, T> RenderOnce for WithOptions<'a, T>
+where
+    WithOptions<'a, T>: Render,
+{
+    fn render_once(self, tmpl: &mut TemplateBuffer) {
+        self.render(tmpl)
+    }
+}
+
+/// A site that videos can be embedded from.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbedProvider {
+    #[display(fmt = "YouTube")]
+    YouTube,
+    #[display(fmt = "Vimeo")]
+    Vimeo,
+    #[display(fmt = "PeerTube")]
+    PeerTube,
+}
+
+/// A linked video which can be embedded in a post.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Embed {
+    /// The site the video is on.
+    pub provider: EmbedProvider,
+    /// The host name the video was linked from.
+    pub host: String,
+    /// The ID of the video on the site.
+    pub id: String,
+}
+
+impl Embed {
+    /// Recognize a link to a video on one of `hosts`.
+    ///
+    /// Links to hosts that aren't in `hosts` are never recognized, so that
+    /// posts can't embed arbitrary pages. Hosts other than YouTube and Vimeo
+    /// are assumed to be PeerTube instances.
+    pub fn from_link(link: &str, hosts: &[String]) -> Option<Embed> {
+        let captures =
+            Regex::new(r"^https?://([^/?#:]+)(?::\d+)?([^?#]*)(?:\?([^#]*))?")
+                .unwrap()
+                .captures(link)?;
+
+        let host = captures.get(1)?.as_str().to_lowercase();
+        let path = captures.get(2).map_or("", |m| m.as_str());
+        let query = captures.get(3).map_or("", |m| m.as_str());
+
+        if !hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(&host)) {
+            return None;
+        }
+
+        let (provider, id) = match host.as_str() {
+            "youtube.com" | "www.youtube.com" | "m.youtube.com" => {
+                if path != "/watch" {
+                    return None;
+                }
+
+                let id = query
+                    .split('&')
+                    .find_map(|param| param.strip_prefix("v="))?;
+
+                (EmbedProvider::YouTube, id)
+            }
+            "youtu.be" => {
+                (EmbedProvider::YouTube, path.trim_start_matches('/'))
+            }
+            "vimeo.com" | "www.vimeo.com" => {
+                let id = path.trim_start_matches('/');
+
+                if !id.chars().all(|c| c.is_ascii_digit()) {
+                    return None;
+                }
+
+                (EmbedProvider::Vimeo, id)
+            }
+            _ => {
+                let id = path
+                    .strip_prefix("/w/")
+                    .or_else(|| path.strip_prefix("/videos/watch/"))?;
+
+                (EmbedProvider::PeerTube, id)
+            }
+        };
+
+        let valid_id = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if !valid_id {
+            return None;
+        }
+
+        Some(Embed {
+            provider,
+            id: id.to_string(),
+            host,
+        })
+    }
+
+    /// The URI of the player to show in the embed's frame.
+    pub fn embed_uri(&self) -> String {
+        match self.provider {
+            EmbedProvider::YouTube => {
+                format!("https://www.youtube-nocookie.com/embed/{}", self.id)
+            }
+            EmbedProvider::Vimeo => {
+                format!("https://player.vimeo.com/video/{}", self.id)
+            }
+            EmbedProvider::PeerTube => {
+                format!("https://{}/videos/embed/{}", self.host, self.id)
+            }
+        }
+    }
+}
+
 /// A parsed post body which can be rendered into HTML.
 pub struct PostBody(Vec<BlockItem>);
 
@@ -306,14 +836,19 @@ impl PostBody {
     where
         S: Into<String>,
     {
-        let mut content: String = content.into();
+        PostBody::parse_limited(content, rules, LineLimits::default())
+    }
 
-        for rule in rules {
-            content = rule
-                .pattern
-                .replace_all(&content, rule.replace_with.as_str())
-                .into_owned();
-        }
+    /// Parse the post body, checking that it's within the given line limits.
+    pub fn parse_limited<S>(
+        content: S,
+        rules: &[FilterRule],
+        limits: LineLimits,
+    ) -> Result<PostBody>
+    where
+        S: Into<String>,
+    {
+        let mut content = apply_filter_rules(content.into(), rules);
 
         // Here we do some preprocessing, so the parser can assume that no lines
         // are empty and all lines end with a newline.
@@ -340,30 +875,165 @@ impl PostBody {
             content.push('\n');
         }
 
+        if let Some(max_lines) = limits.max_lines {
+            let lines = content.matches('\n').count();
+
+            if lines > max_lines as usize {
+                return Err(Error::TooManyLines { max_lines });
+            }
+        }
+
         println!();
 
         let (output, _input) = post_body_parser()
             .easy_parse(position::Stream::new(content.as_str()))
             .map_err(|err| Error::ParseError(err.to_string()))?;
 
+        if let Some(max_quote_lines) = limits.max_quote_lines {
+            if output.longest_quote_run() > max_quote_lines as usize {
+                return Err(Error::TooManyQuoteLines { max_quote_lines });
+            }
+        }
+
         Ok(output)
     }
 
+    /// Join lines of text in a row into one block if `mode` is
+    /// `LineBreakMode::BreakTags`, so they're rendered with `<br>` tags between
+    /// them instead of as separate paragraphs.
+    pub fn apply_line_break_mode(&mut self, mode: LineBreakMode) {
+        if mode != LineBreakMode::BreakTags {
+            return;
+        }
+
+        let mut joined: Vec<BlockItem> = Vec::with_capacity(self.0.len());
+
+        for block_item in self.0.drain(..) {
+            match (joined.last_mut(), block_item) {
+                (Some(BlockItem::Text(items)), BlockItem::Text(next)) => {
+                    items.push(LineItem::Break);
+                    items.extend(next);
+                }
+                (_, block_item) => joined.push(block_item),
+            }
+        }
+
+        self.0 = joined;
+    }
+
+    /// The most quote lines in a row in the post body.
+    fn longest_quote_run(&self) -> usize {
+        let mut longest = 0;
+        let mut current = 0;
+
+        for block_item in &self.0 {
+            if let BlockItem::Quote(_) = block_item {
+                current += 1;
+                longest = longest.max(current);
+            } else {
+                current = 0;
+            }
+        }
+
+        longest
+    }
+
     /// Resolve post references. This adds an URI to the post reference if the
     /// post in question exists.
-    pub fn resolve_refs<C, M>(&mut self, db: &mut Connection<C, M>)
-    where
+    ///
+    /// If `prefix_board` is set, the references are shown like `/tech/#123`.
+    /// If `snippets` is set, the start of the referenced post is shown when
+    /// hovering over the reference.
+    pub fn resolve_refs<C, M>(
+        &mut self,
+        db: &mut Connection<C, M>,
+        prefix_board: bool,
+        snippets: bool,
+    ) where
         C: InnerConnection<M> + diesel::connection::LoadConnection,
         M: diesel::connection::TransactionManager<C>,
     {
-        for block_item in self.0.iter_mut() {
+        let ref_ids = self.ref_ids();
+
+        if ref_ids.is_empty() {
+            return;
+        }
+
+        // References to posts that can't be looked up are left unresolved.
+        let targets = db.ref_targets(&ref_ids).unwrap_or_default();
+
+        self.resolve_refs_with(&targets, prefix_board, snippets);
+    }
+
+    /// The IDs of the posts referenced in the post body. Each ID is only
+    /// listed once, in the order it first appears.
+    pub fn ref_ids(&self) -> Vec<PostId> {
+        let mut ref_ids = Vec::new();
+
+        for block_item in &self.0 {
+            match block_item {
+                BlockItem::Header(items)
+                | BlockItem::Quote(items)
+                | BlockItem::Text(items) => {
+                    for item in items {
+                        if let LineItem::PostRef { id, .. } = item {
+                            if !ref_ids.contains(id) {
+                                ref_ids.push(*id);
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        ref_ids
+    }
+
+    /// Resolve post references using targets that have already been looked
+    /// up, e.g. with `Connection::ref_targets` for the IDs of several posts at
+    /// once. See `PostBody::resolve_refs`.
+    pub fn resolve_refs_with(
+        &mut self,
+        targets: &HashMap<PostId, RefTarget>,
+        prefix_board: bool,
+        snippets: bool,
+    ) {
+        for items in self.line_items_mut() {
+            for item in items.iter_mut() {
+                item.resolve_ref(targets, prefix_board, snippets);
+            }
+        }
+    }
+
+    /// The line items of each header, quote, and text block.
+    fn line_items_mut(&mut self) -> impl Iterator<Item = &mut Vec<LineItem>> {
+        self.0.iter_mut().filter_map(|block_item| match block_item {
+            BlockItem::Header(items)
+            | BlockItem::Quote(items)
+            | BlockItem::Text(items) => Some(items),
+            _ => None,
+        })
+    }
+
+    /// Find the links in the post body to videos which can be embedded. See
+    /// `Embed::from_link`.
+    pub fn embeds(&self, hosts: &[String]) -> Vec<Embed> {
+        let mut embeds = Vec::new();
+
+        for block_item in &self.0 {
             match block_item {
                 BlockItem::Header(items)
                 | BlockItem::Quote(items)
                 | BlockItem::Text(items) => {
-                    for line_item in items.iter_mut() {
-                        if let LineItem::PostRef { id, uri } = line_item {
-                            *uri = db.post_uri(*id).ok();
+                    for item in items {
+                        if let LineItem::Link(link) = item {
+                            match Embed::from_link(link, hosts) {
+                                Some(embed) if !embeds.contains(&embed) => {
+                                    embeds.push(embed)
+                                }
+                                _ => (),
+                            }
                         }
                     }
                 }
@@ -371,10 +1041,118 @@ impl PostBody {
                 _ => (),
             }
         }
+
+        embeds
+    }
+
+    /// The number of links to other sites in the post body. References to
+    /// posts and boards aren't counted.
+    pub fn link_count(&self) -> usize {
+        self.0
+            .iter()
+            .filter_map(|block_item| match block_item {
+                BlockItem::Header(items)
+                | BlockItem::Quote(items)
+                | BlockItem::Text(items) => Some(items),
+                _ => None,
+            })
+            .flatten()
+            .filter(|item| matches!(item, LineItem::Link(_)))
+            .count()
+    }
+
+    /// Render the post body.
+    pub fn into_html(self, options: RenderOptions) -> String {
+        format!(
+            "{}",
+            html! {
+                @ for item in &self.0 {
+                    : WithOptions(item, options)
+                }
+            }
+        )
     }
 
-    pub fn into_html(self) -> String {
-        format!("{}", html! { : &self })
+    /// Render the beginning of the post body, if it's longer than `limits`.
+    ///
+    /// The body is cut off between blocks, or between lines of text joined by
+    /// `LineBreakMode::BreakTags`, so that the HTML stays valid. At least one
+    /// line is always kept. Returns `None` if the body is within the limits, or
+    /// if it can't be shortened.
+    pub fn render_truncated(
+        &self,
+        limits: CollapseLimits,
+        options: RenderOptions,
+    ) -> Option<String> {
+        let fits = |lines: usize, chars: usize| {
+            limits.lines.map_or(true, |max| lines <= max as usize)
+                && limits.chars.map_or(true, |max| chars <= max as usize)
+        };
+
+        let mut lines = 0;
+        let mut chars = 0;
+        let mut kept = 0;
+        // The lines at the start of the first text block that doesn't fit.
+        let mut partial: Option<&[LineItem]> = None;
+
+        for block_item in &self.0 {
+            let block_lines = lines + block_item.line_count();
+            let block_chars = chars + block_item.char_count();
+
+            if !fits(block_lines, block_chars) {
+                if let BlockItem::Text(items) = block_item {
+                    let mut cut = None;
+                    let (mut lines, mut chars) = (lines + 1, chars);
+
+                    for (i, item) in items.iter().enumerate() {
+                        if let LineItem::Break = item {
+                            cut = Some(i);
+                            lines += 1;
+                        }
+
+                        chars += item.char_count();
+
+                        if !fits(lines, chars) {
+                            break;
+                        }
+                    }
+
+                    partial = cut.map(|cut| &items[..cut]);
+                }
+
+                break;
+            }
+
+            lines = block_lines;
+            chars = block_chars;
+            kept += 1;
+        }
+
+        if partial.is_none() {
+            kept = kept.max(1);
+        }
+
+        if kept >= self.0.len() {
+            return None;
+        }
+
+        let blocks = &self.0[..kept];
+
+        Some(format!(
+            "{}",
+            html! {
+                @ for item in blocks {
+                    : WithOptions(item, options)
+                }
+                @ if let Some(items) = partial {
+                    p {
+                        @ for item in items {
+                            : WithOptions(item, options)
+                        }
+                    }
+                }
+            }
+        ))
     }
 }
 
@@ -414,12 +1192,20 @@ enum BlockItem {
 
 impl Render for BlockItem {
     fn render(&self, tmpl: &mut TemplateBuffer) {
-        match self {
+        WithOptions(self, RenderOptions::default()).render(tmpl)
+    }
+}
+
+impl Render for WithOptions<'_, BlockItem> {
+    fn render(&self, tmpl: &mut TemplateBuffer) {
+        let WithOptions(block_item, options) = *self;
+
+        match block_item {
             BlockItem::Header(items) => {
                 tmpl << html! {
                     h3 {
                         @ for item in items {
-                            : item
+                            : WithOptions(item, options)
                         }
                     }
                 }
@@ -429,7 +1215,7 @@ impl Render for BlockItem {
                     blockquote {
                         p {
                             @ for item in items {
-                                : item
+                                : WithOptions(item, options)
                            }
                         }
                     }
@@ -452,7 +1238,7 @@ impl Render for BlockItem {
                 tmpl << html! {
                     p {
                         @ for item in items {
-                            : item
+                            : WithOptions(item, options)
                         }
                     }
                 }
@@ -461,6 +1247,34 @@ impl Render for BlockItem {
     }
 }
 
+impl BlockItem {
+    /// The number of lines in the block.
+    fn line_count(&self) -> usize {
+        match self {
+            BlockItem::Code { contents, .. } => contents.lines().count().max(1),
+            BlockItem::Text(items) => {
+                1 + items
+                    .iter()
+                    .filter(|item| matches!(item, LineItem::Break))
+                    .count()
+            }
+            _ => 1,
+        }
+    }
+
+    /// The number of characters of text in the block.
+    fn char_count(&self) -> usize {
+        match self {
+            BlockItem::Header(items)
+            | BlockItem::Quote(items)
+            | BlockItem::Text(items) => {
+                items.iter().map(LineItem::char_count).sum()
+            }
+            BlockItem::Code { contents, .. } => contents.chars().count(),
+        }
+    }
+}
+
 impl RenderMut for BlockItem {
     fn render_mut(&mut self, tmpl: &mut TemplateBuffer) {
         self.render(tmpl)
@@ -482,15 +1296,81 @@ enum LineItem {
     Strong(String),
     Emphasis(String),
     Spoiler(String),
-    PostRef { id: PostId, uri: Option<String> },
+    PostRef {
+        id: PostId,
+        uri: Option<String>,
+        /// How the reference is shown, if not as the plain post ID.
+        label: Option<String>,
+        /// The start of the referenced post, shown when hovering over the
+        /// reference.
+        snippet: Option<String>,
+    },
     Link(String),
     Code(String),
     Text(String),
+    /// A line break between lines of text, see `LineBreakMode::BreakTags`.
+    Break,
+}
+
+impl LineItem {
+    /// The number of characters of text in the item.
+    fn char_count(&self) -> usize {
+        match self {
+            LineItem::Strong(s)
+            | LineItem::Emphasis(s)
+            | LineItem::Spoiler(s)
+            | LineItem::Link(s)
+            | LineItem::Code(s)
+            | LineItem::Text(s) => s.chars().count(),
+            LineItem::PostRef { id, label, .. } => match label {
+                Some(label) => label.chars().count(),
+                None => id.to_string().len(),
+            },
+            LineItem::Break => 0,
+        }
+    }
+
+    /// Resolve this item if it's a post reference. See
+    /// `PostBody::resolve_refs`.
+    fn resolve_ref(
+        &mut self,
+        targets: &HashMap<PostId, RefTarget>,
+        prefix_board: bool,
+        snippets: bool,
+    ) {
+        if let LineItem::PostRef {
+            id,
+            uri,
+            label,
+            snippet,
+        } = self
+        {
+            if let Some(target) = targets.get(id) {
+                *uri = Some(target.uri.clone());
+
+                if prefix_board {
+                    *label = Some(display_id(&target.board_name, *id, true));
+                }
+
+                if snippets && !target.snippet.is_empty() {
+                    *snippet = Some(target.snippet.clone());
+                }
+            }
+        }
+    }
 }
 
 impl Render for LineItem {
     fn render(&self, tmpl: &mut TemplateBuffer) {
-        match self {
+        WithOptions(self, RenderOptions::default()).render(tmpl)
+    }
+}
+
+impl Render for WithOptions<'_, LineItem> {
+    fn render(&self, tmpl: &mut TemplateBuffer) {
+        let WithOptions(line_item, options) = *self;
+
+        match line_item {
             LineItem::Strong(s) => tmpl << html! { strong { : s } },
             LineItem::Emphasis(s) => tmpl << html! { em { : s } },
             LineItem::Spoiler(s) => {
@@ -500,26 +1380,47 @@ impl Render for LineItem {
                     }
                 }
             }
-            LineItem::PostRef { id, uri } => {
-                if let Some(uri) = uri {
+            LineItem::PostRef {
+                id,
+                uri,
+                label,
+                snippet,
+            } => {
+                let label = label.clone().unwrap_or_else(|| id.to_string());
+
+                if let (Some(uri), Some(snippet)) = (uri, snippet) {
+                    tmpl << html! {
+                        a(class = "post-ref", href = (uri), title = (snippet)) {
+                            : label
+                        }
+                    }
+                } else if let Some(uri) = uri {
                     tmpl << html! {
                         a(class = "post-ref", href = (uri)) {
-                            : id
+                            : label
                         }
                     }
                 } else {
-                    tmpl << html! { a(class = "post-ref") { : id } }
+                    tmpl << html! { a(class = "post-ref") { : label } }
                 }
             }
             LineItem::Link(s) => {
+                let rel = Some(options.link_rel).filter(|rel| !rel.is_empty());
+                let target = Some(options.link_target)
+                    .filter(|target| !target.is_empty());
+                let shown = options
+                    .link_display_chars
+                    .and_then(|max| shorten_link(s, max as usize));
+
                 tmpl << html! {
-                    a(href = s, rel = "nofollow noopener", target = "_blank") {
-                        : s
+                    a(href = s, rel ?= rel, target ?= target) {
+                        : shown.as_deref().unwrap_or(s)
                     }
                 }
             }
             LineItem::Code(s) => tmpl << html! { code { : s } },
             LineItem::Text(s) => tmpl << html! { : s },
+            LineItem::Break => tmpl << html! { br; },
         }
     }
 }
@@ -538,7 +1439,22 @@ impl RenderOnce for LineItem {
 
 #[cfg(test)]
 mod tests {
-    use super::PostBody;
+    use std::collections::HashMap;
+
+    use horrorshow::html;
+
+    use regex::Regex;
+
+    use super::{
+        filter_html_text, html_excerpt, html_snippet, remap_post_refs,
+        render_admin_markdown, rendered_post_refs, sanitize_html,
+        sanitized_link_count, CollapseLimits, Embed, EmbedProvider,
+        LineBreakMode, LineItem, LineLimits, MarkupMode, PostBody,
+        RenderOptions,
+    };
+    use crate::config::FilterRule;
+    use crate::models::RefTarget;
+    use crate::Error;
     use crate::Result;
 
     fn test_parse<S1, S2>(input: S1, expected_output: S2) -> Result<()>
@@ -548,7 +1464,10 @@ mod tests {
     {
         let body = PostBody::parse(input.into(), &[])?;
 
-        assert_eq!(body.into_html(), expected_output.as_ref());
+        assert_eq!(
+            body.into_html(RenderOptions::default()),
+            expected_output.as_ref()
+        );
 
         Ok(())
     }
@@ -588,6 +1507,111 @@ mod tests {
         test_parse("What do you think of https://lainchan.org? I think it's pretty cool.", "<p>What do you think of <a href=\"https://lainchan.org\" rel=\"nofollow noopener\" target=\"_blank\">https://lainchan.org</a>? I think it's pretty cool.</p>")
     }
 
+    #[test]
+    fn link_options() -> Result<()> {
+        let body = PostBody::parse("https://lainchan.org", &[])?;
+        let options = RenderOptions {
+            link_rel: "noreferrer",
+            link_target: "_self",
+            link_display_chars: None,
+        };
+        assert_eq!(
+            body.into_html(options),
+            "<p><a href=\"https://lainchan.org\" rel=\"noreferrer\" \
+             target=\"_self\">https://lainchan.org</a></p>"
+        );
+
+        let body = PostBody::parse("https://lainchan.org", &[])?;
+        let options = RenderOptions {
+            link_rel: "",
+            link_target: "",
+            link_display_chars: None,
+        };
+        assert_eq!(
+            body.into_html(options),
+            "<p><a href=\"https://lainchan.org\">https://lainchan.org</a></p>"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn shortened_links() -> Result<()> {
+        let options = RenderOptions {
+            link_display_chars: Some(30),
+            ..RenderOptions::default()
+        };
+
+        let long = "https://example.com/a/very/long/path/to/a/page.html";
+        let body = PostBody::parse(long, &[])?;
+        assert_eq!(
+            body.into_html(options),
+            format!(
+                "<p><a href=\"{}\" rel=\"nofollow noopener\" \
+                 target=\"_blank\">example.com/a/very/long/path/…</a></p>",
+                long
+            )
+        );
+
+        let short = "https://example.com/page.html";
+        let body = PostBody::parse(short, &[])?;
+        assert_eq!(
+            body.into_html(options),
+            format!(
+                "<p><a href=\"{0}\" rel=\"nofollow noopener\" \
+                 target=\"_blank\">{0}</a></p>",
+                short
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn link_counts() -> Result<()> {
+        let body = PostBody::parse(
+            "https://example.com and >>42\n> http://example.org",
+            &[],
+        )?;
+        assert_eq!(body.link_count(), 2);
+
+        let body = PostBody::parse("no links >>>/g/", &[])?;
+        assert_eq!(body.link_count(), 0);
+
+        let allowed = vec!["a".to_string()];
+        let html = sanitize_html(
+            r#"<a href="https://example.com">a</a> href="x" <a>b</a>"#,
+            &[],
+            &allowed,
+        );
+        assert_eq!(sanitized_link_count(&html), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_refs() -> Result<()> {
+        let mut body = PostBody::parse(">>5 and >>7\n>>5\n> >>5", &[])?;
+        assert_eq!(body.ref_ids(), vec![5, 7]);
+
+        let mut targets = HashMap::new();
+        targets.insert(
+            5,
+            RefTarget {
+                uri: String::from("/tech/1#5"),
+                board_name: String::from("tech"),
+                snippet: String::new(),
+            },
+        );
+        body.resolve_refs_with(&targets, false, false);
+
+        let html = body.into_html(RenderOptions::default());
+        assert_eq!(html.matches("href=\"/tech/1#5\"").count(), 3);
+        assert!(!html.contains("#7\""));
+
+        Ok(())
+    }
+
     #[test]
     fn header() -> Result<()> {
         test_parse(
@@ -793,4 +1817,444 @@ I'll take a look at OP's project and give it a whirl when I get home from work.
 
         Ok(())
     }
+
+    #[test]
+    fn sanitize_removes_disallowed_tags() {
+        let allowed = vec!["b".to_string(), "script".to_string()];
+
+        assert_eq!(
+            sanitize_html(
+                "<b>bold</b><script>alert(1)</script><i>italic</i>",
+                &[],
+                &allowed
+            ),
+            "<b>bold</b>italic"
+        );
+    }
+
+    #[test]
+    fn sanitize_links() {
+        let allowed = vec!["a".to_string()];
+
+        assert_eq!(
+            sanitize_html(
+                r#"<a href="https://example.com" onclick="evil()">link</a>"#,
+                &[],
+                &allowed
+            ),
+            r#"<a href="https://example.com" rel="noopener noreferrer nofollow">link</a>"#
+        );
+        assert_eq!(
+            sanitize_html(
+                r#"<a href="javascript:evil()">link</a>"#,
+                &[],
+                &allowed
+            ),
+            r#"<a rel="noopener noreferrer nofollow">link</a>"#
+        );
+    }
+
+    #[test]
+    fn parse_markup_mode() {
+        assert_eq!("longboard".parse().ok(), Some(MarkupMode::Longboard));
+        assert_eq!(
+            "sanitized-html".parse().ok(),
+            Some(MarkupMode::SanitizedHtml)
+        );
+        assert!("markdown".parse::<MarkupMode>().is_err());
+    }
+
+    #[test]
+    fn max_lines() -> Result<()> {
+        let limits = LineLimits {
+            max_lines: Some(3),
+            ..LineLimits::default()
+        };
+
+        PostBody::parse_limited("one\ntwo\nthree", &[], limits)?;
+
+        // Empty lines are removed before counting.
+        PostBody::parse_limited("one\n\n\ntwo\r\n\r\nthree\n", &[], limits)?;
+
+        assert!(matches!(
+            PostBody::parse_limited("one\ntwo\nthree\nfour", &[], limits),
+            Err(Error::TooManyLines { max_lines: 3 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_quote_lines() -> Result<()> {
+        let limits = LineLimits {
+            max_quote_lines: Some(2),
+            ..LineLimits::default()
+        };
+
+        let input = ">one\n>two\ntext\n>three\n>four";
+        PostBody::parse_limited(input, &[], limits)?;
+        PostBody::parse_limited(">>1\n>>2\n>>3", &[], limits)?;
+
+        assert!(matches!(
+            PostBody::parse_limited(">one\n>two\n>three", &[], limits),
+            Err(Error::TooManyQuoteLines { max_quote_lines: 2 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn admin_markdown_escapes_html_by_default() {
+        let html = render_admin_markdown(
+            "*hi* <iframe src=\"https://example.com\"></iframe>",
+            &HashMap::new(),
+        );
+
+        assert!(html.contains("<em>hi</em>"));
+        assert!(html.contains("&lt;iframe"));
+        assert!(!html.contains("<iframe"));
+    }
+
+    #[test]
+    fn admin_markdown_allows_listed_html() {
+        let mut allowed = HashMap::new();
+        allowed.insert(String::from("iframe"), vec![String::from("src")]);
+
+        let html = render_admin_markdown(
+            "*hi*\n\n<iframe src=\"https://example.com\" \
+             onload=\"alert(1)\"></iframe><script>alert(1)</script>",
+            &allowed,
+        );
+
+        assert!(html.contains("<em>hi</em>"));
+        assert!(html.contains("<iframe src=\"https://example.com\"></iframe>"));
+        assert!(!html.contains("onload"));
+        assert!(!html.contains("script"));
+    }
+
+    #[test]
+    fn post_ref_labels() {
+        let item = LineItem::PostRef {
+            id: 123,
+            uri: Some(String::from("/tech/45#123")),
+            label: None,
+            snippet: None,
+        };
+        assert_eq!(
+            format!("{}", html! { : &item }),
+            "<a class=\"post-ref\" href=\"/tech/45#123\">123</a>"
+        );
+
+        let item = LineItem::PostRef {
+            id: 123,
+            uri: Some(String::from("/tech/45#123")),
+            label: Some(String::from("/tech/#123")),
+            snippet: None,
+        };
+        assert_eq!(
+            format!("{}", html! { : &item }),
+            "<a class=\"post-ref\" href=\"/tech/45#123\">/tech/#123</a>"
+        );
+
+        let item = LineItem::PostRef {
+            id: 123,
+            uri: Some(String::from("/tech/45#123")),
+            label: None,
+            snippet: Some(String::from("first line")),
+        };
+        assert_eq!(
+            format!("{}", html! { : &item }),
+            "<a class=\"post-ref\" href=\"/tech/45#123\" \
+             title=\"first line\">123</a>"
+        );
+    }
+
+    #[test]
+    fn line_break_modes() -> Result<()> {
+        let input = "one\ntwo\n\n>quote\nthree\nfour";
+
+        let mut body = PostBody::parse(input, &[])?;
+        body.apply_line_break_mode(LineBreakMode::Paragraph);
+        assert_eq!(
+            body.into_html(RenderOptions::default()),
+            "<p>one</p><p>two</p><blockquote><p>quote</p></blockquote>\
+             <p>three</p><p>four</p>"
+        );
+
+        let mut body = PostBody::parse(input, &[])?;
+        body.apply_line_break_mode(LineBreakMode::BreakTags);
+        assert_eq!(
+            body.into_html(RenderOptions::default()),
+            "<p>one<br>two</p><blockquote><p>quote</p></blockquote>\
+             <p>three<br>four</p>"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn find_rendered_post_refs() {
+        let items = [
+            (123, None, None),
+            (45, Some("/tech/#45"), Some("quoted")),
+            (123, None, None),
+        ];
+
+        let html: String = items
+            .iter()
+            .map(|(id, label, snippet)| {
+                let item = LineItem::PostRef {
+                    id: *id,
+                    uri: Some(format!("/tech/1#{}", id)),
+                    label: label.map(String::from),
+                    snippet: snippet.map(String::from),
+                };
+                format!("{}", html! { : &item })
+            })
+            .collect();
+
+        assert_eq!(rendered_post_refs(&html), vec![123, 45]);
+
+        // References to posts that didn't exist don't link anywhere.
+        let item = LineItem::PostRef {
+            id: 67,
+            uri: None,
+            label: None,
+            snippet: None,
+        };
+        let html = format!("{}", html! { : &item });
+        assert!(rendered_post_refs(html).is_empty());
+    }
+
+    #[test]
+    fn remap_rendered_post_refs() {
+        let thread_ids = maplit::hashmap! { 45 => 4 };
+        let post_ids = maplit::hashmap! { 123 => 12, 124 => 13 };
+
+        let html = "<p><a class=\"post-ref\" href=\"/tech/45#123\" \
+                    title=\"quoted\">123</a> \
+                    <a class=\"post-ref\" href=\"/tech/45#tech-124\">\
+                    /tech/#124</a> \
+                    <a class=\"post-ref\" href=\"/meta/45#123\">\
+                    /meta/#123</a> \
+                    <a class=\"post-ref\" href=\"/tech/46#125\">125</a></p>";
+
+        assert_eq!(
+            remap_post_refs(html, "tech", &thread_ids, &post_ids),
+            "<p><a class=\"post-ref\" href=\"/tech/4#12\" \
+             title=\"quoted\">12</a> \
+             <a class=\"post-ref\" href=\"/tech/4#tech-13\">/tech/#13</a> \
+             <a class=\"post-ref\" href=\"/meta/45#123\">/meta/#123</a> \
+             <a class=\"post-ref\" href=\"/tech/46#125\">125</a></p>"
+        );
+    }
+
+    #[test]
+    fn snippets() {
+        assert_eq!(
+            html_snippet("<p>one &amp; <em>two</em></p><p>three</p>", 80),
+            "one & two"
+        );
+        assert_eq!(html_snippet("<p>one<br>two</p>", 80), "one");
+        assert_eq!(html_snippet("<p></p><p>two</p>", 80), "two");
+        assert_eq!(html_snippet("<p>abcdef</p>", 4), "abc…");
+        assert_eq!(html_snippet("", 80), "");
+    }
+
+    #[test]
+    fn excerpts() {
+        assert_eq!(
+            html_excerpt("<p>one &amp; <em>two</em></p><p>three</p>", 80),
+            "one & two three"
+        );
+        assert_eq!(
+            html_excerpt("<p>one<br>two   three</p>", 80),
+            "one two three"
+        );
+        assert_eq!(html_excerpt("<p>abc</p><p>def</p>", 6), "abc d…");
+        assert_eq!(html_excerpt("<p></p>", 80), "");
+    }
+
+    #[test]
+    fn truncate_by_lines() -> Result<()> {
+        let body = PostBody::parse("one\ntwo\nthree\nfour", &[])?;
+        let limits = CollapseLimits {
+            lines: Some(2),
+            chars: None,
+        };
+
+        assert_eq!(
+            body.render_truncated(limits, RenderOptions::default())
+                .as_deref(),
+            Some("<p>one</p><p>two</p>")
+        );
+
+        let body = PostBody::parse("one\ntwo", &[])?;
+        assert_eq!(
+            body.render_truncated(limits, RenderOptions::default()),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn truncate_break_tags() -> Result<()> {
+        let limits = CollapseLimits {
+            lines: Some(3),
+            chars: None,
+        };
+
+        let input = (1..=20)
+            .map(|n| format!("line {}", n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut body = PostBody::parse(&input, &[])?;
+        body.apply_line_break_mode(LineBreakMode::BreakTags);
+        assert_eq!(
+            body.render_truncated(limits, RenderOptions::default())
+                .as_deref(),
+            Some("<p>line 1<br>line 2<br>line 3</p>")
+        );
+
+        let mut body = PostBody::parse("one\n\n>quote\ntwo\nthree", &[])?;
+        body.apply_line_break_mode(LineBreakMode::BreakTags);
+        assert_eq!(
+            body.render_truncated(limits, RenderOptions::default())
+                .as_deref(),
+            Some("<p>one</p><blockquote><p>quote</p></blockquote><p>two</p>")
+        );
+
+        let limits = CollapseLimits {
+            lines: None,
+            chars: Some(10),
+        };
+        let mut body = PostBody::parse("short\nsomething longer", &[])?;
+        body.apply_line_break_mode(LineBreakMode::BreakTags);
+        assert_eq!(
+            body.render_truncated(limits, RenderOptions::default())
+                .as_deref(),
+            Some("<p>short</p>")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn truncate_by_chars() -> Result<()> {
+        let limits = CollapseLimits {
+            lines: None,
+            chars: Some(10),
+        };
+
+        let body = PostBody::parse("**short**\nsomething longer", &[])?;
+        assert_eq!(
+            body.render_truncated(limits, RenderOptions::default())
+                .as_deref(),
+            Some("<p><strong>short</strong></p>")
+        );
+
+        // The first block is always kept, so a single long block can't be
+        // truncated.
+        let body = PostBody::parse("a single line that's too long", &[])?;
+        assert_eq!(
+            body.render_truncated(limits, RenderOptions::default()),
+            None
+        );
+
+        let body = PostBody::parse("short", &[])?;
+        assert_eq!(
+            body.render_truncated(
+                CollapseLimits::default(),
+                RenderOptions::default()
+            ),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn recognize_embeds() {
+        let hosts = vec![
+            "www.youtube.com".to_string(),
+            "youtu.be".to_string(),
+            "vimeo.com".to_string(),
+            "tube.example.org".to_string(),
+        ];
+
+        let embed = |link: &str| Embed::from_link(link, &hosts);
+
+        let youtube = embed("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=4")
+            .expect("should recognize a YouTube link");
+        assert_eq!(youtube.provider, EmbedProvider::YouTube);
+        assert_eq!(youtube.id, "dQw4w9WgXcQ");
+        assert_eq!(
+            youtube.embed_uri(),
+            "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ"
+        );
+
+        assert_eq!(embed("https://youtu.be/dQw4w9WgXcQ"), Some(youtube));
+
+        let vimeo = embed("https://vimeo.com/76979871").unwrap();
+        assert_eq!(
+            vimeo.embed_uri(),
+            "https://player.vimeo.com/video/76979871"
+        );
+
+        let peertube = embed("https://tube.example.org/w/kkGMgK9ZtnKfYAgn")
+            .expect("should recognize a PeerTube link");
+        assert_eq!(peertube.provider, EmbedProvider::PeerTube);
+        assert_eq!(
+            peertube.embed_uri(),
+            "https://tube.example.org/videos/embed/kkGMgK9ZtnKfYAgn"
+        );
+
+        // Hosts that aren't allowed are never embedded.
+        assert_eq!(embed("https://youtube.com/watch?v=dQw4w9WgXcQ"), None);
+        assert_eq!(embed("https://other.example.org/w/abc"), None);
+        assert_eq!(embed("https://user@tube.example.org/w/abc"), None);
+
+        // Neither are links that aren't to a video.
+        assert_eq!(embed("https://www.youtube.com/feed/trending"), None);
+        assert_eq!(embed("https://vimeo.com/channels/staffpicks"), None);
+        assert_eq!(embed("https://tube.example.org/w/\"onload"), None);
+    }
+
+    #[test]
+    fn find_embeds() -> Result<()> {
+        let hosts = vec!["youtu.be".to_string()];
+
+        let body = PostBody::parse(
+            "https://youtu.be/dQw4w9WgXcQ\n\
+             > https://youtu.be/dQw4w9WgXcQ\n\
+             https://example.org/video",
+            &[],
+        )?;
+
+        let embeds = body.embeds(&hosts);
+        assert_eq!(embeds.len(), 1);
+        assert_eq!(embeds[0].id, "dQw4w9WgXcQ");
+
+        assert!(body.embeds(&[]).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_html_text_only() {
+        let rules = vec![FilterRule {
+            pattern: Regex::new(r"(?i)\bheck\b|\bclass\b").unwrap(),
+            replace_with: String::from("<b>***</b>"),
+        }];
+
+        let html = r#"<p class="quote">What the heck, class</p>"#;
+
+        assert_eq!(
+            filter_html_text(html, &rules),
+            r#"<p class="quote">What the &lt;b&gt;***&lt;/b&gt;, &lt;b&gt;***&lt;/b&gt;</p>"#
+        );
+
+        assert_eq!(filter_html_text(html, &[]), html);
+    }
 }
@@ -1,27 +1,36 @@
 //! Rocket HTTP routes.
 
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs::read_to_string;
-use std::net::ToSocketAddrs;
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
 use std::string::ToString;
+use std::sync::{Arc, Mutex};
 
 use argon2::verify_encoded;
 
-use pulldown_cmark::{html, Parser};
+use chrono::{DateTime, Duration, Utc};
 
-use rocket::http::Status;
-use rocket::request::{Form, FromForm, FromRequest, Outcome, Request};
-use rocket::response::NamedFile;
-use rocket::{get, post, routes, uri, Route};
+use rocket::http::{ContentType, Cookie, Cookies, Header, RawStr, Status};
+use rocket::request::{Form, FromForm, FromParam, FromRequest, Outcome, Request};
+use rocket::response::{self, NamedFile, Redirect, Responder, Response};
+use rocket::{get, post, routes, uri, Route, State};
 
 use rocket_contrib::templates::Template;
 
 use serde_json::value::{to_value, Value as JsonValue};
 
+use crate::config::{
+    parse_front_matter, AccelRedirectHeader, Conf, Config, DefaultView,
+    PageMeta,
+};
 use crate::models::*;
+use crate::parse::render_admin_markdown;
 use crate::views::*;
-use crate::{config::Conf, Error, Result};
+use crate::{Error, Result};
 
 pub mod new;
 pub mod options;
@@ -30,6 +39,8 @@ pub mod staff;
 pub use options::UserOptions;
 
 /// Request guard to check if a user's IP is blocked.
+///
+/// If `exempt_staff` is set, logged in staff members are never blocked.
 pub struct NotBlocked;
 
 impl<'a, 'r> FromRequest<'a, 'r> for NotBlocked {
@@ -40,6 +51,27 @@ impl<'a, 'r> FromRequest<'a, 'r> for NotBlocked {
             .guard::<Conf>()
             .expect("couldn't load configuration");
 
+        let ClientIp(ip) = request.guard::<ClientIp>().unwrap();
+
+        // Only look up the session if it matters. The session guard fails for
+        // expired or invalid sessions, so those don't count as staff.
+        let is_staff =
+            conf.exempt_staff && request.guard::<Session>().is_success();
+
+        match check_ip(&conf, ip, is_staff) {
+            Ok(()) => Outcome::Success(NotBlocked),
+            Err(e) => Outcome::Failure((Status::Forbidden, e)),
+        }
+    }
+}
+
+/// Request guard for the client's IP address.
+pub struct ClientIp(pub IpAddr);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
         // If we are using a local request (i.e., if we're running a test) then
         // we might not have an IP address. In production, all requests should
         // have an IP address.
@@ -49,58 +81,69 @@ impl<'a, 'r> FromRequest<'a, 'r> for NotBlocked {
             request.client_ip().expect("expected client to have ip")
         };
 
-        if ip.is_loopback() {
-            return Outcome::Success(NotBlocked);
-        }
+        Outcome::Success(ClientIp(ip))
+    }
+}
 
-        if conf.allow_list.contains(&ip) {
-            return Outcome::Success(NotBlocked);
-        }
+/// Whether `ip` is in `list`. IPv6 addresses are compared by their network
+/// prefix, see `ipv6_prefix`.
+pub(crate) fn in_ip_list(conf: &Conf, list: &[IpAddr], ip: IpAddr) -> bool {
+    let network = normalize_ip(ip, conf.ipv6_prefix);
 
-        if conf.block_list.contains(&ip) {
-            return Outcome::Failure((
-                Status::Forbidden,
-                Error::IpIsBlocked { ip },
-            ));
-        }
+    list.iter()
+        .any(|entry| normalize_ip(*entry, conf.ipv6_prefix) == network)
+}
 
-        for dnsbl in conf.dns_block_list.iter() {
-            let host = format!("{}.{}:42069", ip, dnsbl);
-
-            if let Ok(mut addrs) = host.to_socket_addrs() {
-                return Outcome::Failure((
-                    Status::Forbidden,
-                    Error::IpIsBlockedDnsbl {
-                        dnsbl: dnsbl.to_string(),
-                        result: addrs.next().unwrap().ip(),
-                        ip,
-                    },
-                ));
-            }
-        }
+/// Check whether `ip` is on the block list, in one of the blocked ranges, or on
+/// a DNS block list.
+///
+/// Loopback addresses and addresses on the allow list are never blocked, and
+/// neither are staff members when `exempt_staff` is set.
+fn check_ip(conf: &Conf, ip: IpAddr, is_staff: bool) -> Result<()> {
+    if ip.is_loopback() || (conf.exempt_staff && is_staff) {
+        return Ok(());
+    }
+
+    if in_ip_list(conf, conf.allow_list, ip) {
+        return Ok(());
+    }
 
-        Outcome::Success(NotBlocked)
+    if in_ip_list(conf, conf.block_list, ip)
+        || conf.blocked_ranges.contains(ip)
+    {
+        return Err(Error::IpIsBlocked { ip });
     }
+
+    for dnsbl in conf.dns_block_list.iter() {
+        let host = format!("{}.{}:42069", ip, dnsbl);
+
+        if let Ok(mut addrs) = host.to_socket_addrs() {
+            return Err(Error::IpIsBlockedDnsbl {
+                dnsbl: dnsbl.to_string(),
+                result: addrs.next().unwrap().ip(),
+                ip,
+            });
+        }
+    }
+
+    Ok(())
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for User {
     type Error = Error;
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let conf = request
+            .guard::<Conf>()
+            .expect("couldn't load configuration");
+
         let mut db = request
             .guard::<PooledConnection>()
             .expect("expected database to be initialized");
 
-        // If we are using a local request (i.e., if we're running a test) then
-        // we might not have an IP address. In production, all requests should
-        // have an IP address.
-        let ip = if cfg!(debug_assertions) {
-            request.client_ip().unwrap_or("127.0.0.1".parse().unwrap())
-        } else {
-            request.client_ip().expect("expected client to have ip")
-        };
+        let ClientIp(ip) = request.guard::<ClientIp>().unwrap();
 
-        match db.user(ip) {
+        match db.user(normalize_ip(ip, conf.ipv6_prefix)) {
             Ok(user) => {
                 if user.is_banned() {
                     Outcome::Failure((
@@ -114,7 +157,8 @@ impl<'a, 'r> FromRequest<'a, 'r> for User {
                 }
             }
             Err(Error::DatabaseError(diesel::result::Error::NotFound)) => {
-                let new_user = NewUser::from_ip(ip);
+                let new_user =
+                    NewUser::from_ip(ip, conf.ipv6_prefix, conf.hash_secret);
 
                 let user = db
                     .insert_user(&new_user)
@@ -133,21 +177,29 @@ pub fn routes() -> Vec<Route> {
         crate::routes::home,
         crate::routes::static_file,
         crate::routes::favicon,
+        crate::routes::file_icon,
         crate::routes::banner,
+        crate::routes::extension_banner,
         crate::routes::style,
         crate::routes::upload,
         crate::routes::custom_page,
+        crate::routes::gallery,
         crate::routes::form_help,
         crate::routes::board,
         crate::routes::board_catalog,
         crate::routes::thread,
+        crate::routes::thread_post,
+        crate::routes::thread_last,
         crate::routes::post_preview,
+        crate::routes::post_preview_json,
         crate::routes::new::new_thread,
         crate::routes::new::new_post,
+        crate::routes::new::preview_body,
         crate::routes::report,
         crate::routes::new_report,
         crate::routes::delete,
         crate::routes::handle_delete,
+        crate::routes::quick_delete,
         crate::routes::options::options,
         crate::routes::options::update_options,
         crate::routes::staff::login,
@@ -155,9 +207,15 @@ pub fn routes() -> Vec<Route> {
         crate::routes::staff::logout,
         crate::routes::staff::overview,
         crate::routes::staff::history,
+        crate::routes::staff::users,
+        crate::routes::staff::user_posts,
+        crate::routes::staff::effective_config,
         crate::routes::staff::close_report,
+        crate::routes::staff::approve_post,
+        crate::routes::staff::reject_post,
         crate::routes::staff::create_board,
         crate::routes::staff::edit_board,
+        crate::routes::staff::set_board_nsfw,
         crate::routes::staff::delete_board,
         crate::routes::staff::ban_user,
         crate::routes::staff::unban_user,
@@ -169,13 +227,457 @@ pub fn routes() -> Vec<Route> {
         crate::routes::staff::unpin,
         crate::routes::staff::lock,
         crate::routes::staff::unlock,
+        crate::routes::staff::api_delete,
+        crate::routes::staff::api_ban_user,
     ]
 }
 
+/// The canonical form of a request path, if `path` isn't already canonical.
+///
+/// Canonical paths have no trailing slash. Board names are lowercase, but
+/// boards made before that was enforced can have capital letters, so the first
+/// segment is only lowercased if `board_exists` says that there's a board with
+/// the lowercase name and none with the name as given. The rest of the path is
+/// left alone, since file names can have capital letters.
+pub fn canonical_path<F>(path: &str, mut board_exists: F) -> Option<String>
+where
+    F: FnMut(&str) -> bool,
+{
+    let trimmed = path.trim_end_matches('/');
+
+    let canonical = match trimmed.strip_prefix('/') {
+        Some(rest) => {
+            let end = rest.find('/').unwrap_or(rest.len());
+            let (first, rest) = rest.split_at(end);
+            let lower = first.to_lowercase();
+
+            if lower != first && !board_exists(first) && board_exists(&lower)
+            {
+                format!("/{}{}", lower, rest)
+            } else {
+                format!("/{}{}", first, rest)
+            }
+        }
+        None => String::from("/"),
+    };
+
+    Some(canonical).filter(|canonical| canonical != path)
+}
+
+/// The canonical URI of a page at `path` with one optional query parameter.
+///
+/// Only the parameters that a route uses are put in its canonical URI, so that
+/// unrelated query strings don't make new canonical pages.
+fn canonical_uri_with<T>(path: String, name: &str, value: Option<T>) -> String
+where
+    T: Display,
+{
+    match value {
+        Some(value) => format!("{}?{}={}", path, name, value),
+        None => path,
+    }
+}
+
+/// The newest post a user has seen in each of the threads they visited most
+/// recently.
+///
+/// This is stored in a cookie as a list of `thread:post` pairs, most recently
+/// visited first.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LastSeen(Vec<(ThreadId, PostId)>);
+
+impl LastSeen {
+    /// The name of the cookie.
+    const COOKIE_NAME: &'static str = "last-seen";
+
+    /// How many threads to remember. Older threads are forgotten, to keep the
+    /// cookie small.
+    const MAX_THREADS: usize = 50;
+
+    /// Parse the value of the cookie. Invalid entries are ignored.
+    fn parse(value: &str) -> LastSeen {
+        LastSeen(
+            value
+                .split(',')
+                .filter_map(|entry| {
+                    let (thread_id, post_id) = entry.split_once(':')?;
+                    Some((thread_id.parse().ok()?, post_id.parse().ok()?))
+                })
+                .take(LastSeen::MAX_THREADS)
+                .collect(),
+        )
+    }
+
+    /// The newest post the user has seen in a thread.
+    pub fn get(&self, thread_id: ThreadId) -> Option<PostId> {
+        self.0
+            .iter()
+            .find(|(id, _)| *id == thread_id)
+            .map(|(_, post_id)| *post_id)
+    }
+
+    /// Record that the user has seen all the posts in a thread up to
+    /// `post_id`.
+    pub fn set(&mut self, thread_id: ThreadId, post_id: PostId) {
+        self.0.retain(|(id, _)| *id != thread_id);
+        self.0.insert(0, (thread_id, post_id));
+        self.0.truncate(LastSeen::MAX_THREADS);
+    }
+
+    /// Make a cookie to store the list in.
+    pub fn to_cookie(&self) -> Cookie<'static> {
+        let value = self
+            .0
+            .iter()
+            .map(|(thread_id, post_id)| format!("{}:{}", thread_id, post_id))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Cookie::build(LastSeen::COOKIE_NAME, value)
+            .path("/")
+            .permanent()
+            .finish()
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for LastSeen {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let last_seen = request
+            .cookies()
+            .get(LastSeen::COOKIE_NAME)
+            .map(|cookie| LastSeen::parse(cookie.value()))
+            .unwrap_or_default();
+
+        Outcome::Success(last_seen)
+    }
+}
+
+/// Where a served file is stored.
+#[derive(Debug, Clone, Copy)]
+enum FileRoot<'a> {
+    /// The site resource directory.
+    Resource,
+    /// The user upload directory.
+    Upload,
+    /// Some other directory, such as an extension's banner directory.
+    Directory(&'a Path),
+}
+
+/// A file to be served, with caching headers.
+pub struct FileResponse {
+    source: FileSource,
+    /// How long the file can be cached.
+    max_age: Duration,
+    /// Whether the file will never change.
+    immutable: bool,
+    /// The name the browser should give the file, if not the name in its URI.
+    filename: Option<String>,
+    /// The download held open while the file is being sent.
+    download: Option<DownloadSlot>,
+}
+
+/// Who serves a file.
+enum FileSource {
+    /// We serve the file ourselves.
+    File(NamedFile),
+    /// The front-end web server serves the file.
+    Redirect {
+        header: Header<'static>,
+        content_type: Option<ContentType>,
+    },
+}
+
+impl FileResponse {
+    /// Serve the file at `path` under `root`.
+    fn new<P>(root: FileRoot, path: P, conf: &Conf) -> Result<FileResponse>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        let full_path = match root {
+            FileRoot::Resource => conf.resource_dir.join(path),
+            FileRoot::Upload => conf.upload_dir.join(path),
+            FileRoot::Directory(dir) => dir.join(path),
+        };
+
+        // Uploads are never changed after they're saved, so they can be cached
+        // for as long as we like.
+        let (max_age, immutable) = match root {
+            FileRoot::Resource | FileRoot::Directory(_) => {
+                (*conf.resource_cache_age, false)
+            }
+            FileRoot::Upload => (*conf.upload_cache_age, true),
+        };
+
+        let source = FileSource::new(root, path, full_path, conf)?;
+
+        Ok(FileResponse {
+            source,
+            max_age,
+            immutable,
+            filename: None,
+            download: None,
+        })
+    }
+
+    /// Tell the browser to name the file `filename`, such as when it's saved.
+    fn with_filename<S>(mut self, filename: S) -> FileResponse
+    where
+        S: Into<String>,
+    {
+        self.filename = Some(filename.into());
+        self
+    }
+
+    /// Hold `download` until the file has been sent.
+    fn with_download(mut self, download: DownloadSlot) -> FileResponse {
+        self.download = Some(download);
+        self
+    }
+}
+
+/// The uploads being sent to clients, used to limit concurrent downloads.
+///
+/// Clients are grouped in the same way as user hashes, so all addresses in an
+/// IPv6 network share a limit.
+#[derive(Debug, Default)]
+pub struct Downloads {
+    counts: Arc<Mutex<DownloadCounts>>,
+}
+
+/// The number of downloads in progress.
+#[derive(Debug, Default)]
+struct DownloadCounts {
+    /// The number of downloads in progress overall.
+    total: u32,
+    /// The number of downloads in progress for each client.
+    by_client: HashMap<IpAddr, u32>,
+}
+
+impl Downloads {
+    /// Start a download for the client at `ip`.
+    ///
+    /// Fails if the client already has `max_downloads_per_user` downloads in
+    /// progress, or if `max_downloads` downloads are in progress overall.
+    fn start(&self, ip: IpAddr, conf: &Conf) -> Result<DownloadSlot> {
+        let client = normalize_ip(ip, conf.ipv6_prefix);
+
+        let mut counts = self.counts.lock().unwrap();
+        let client_count = counts.by_client.get(&client).copied().unwrap_or(0);
+
+        let over_total =
+            conf.max_downloads.map_or(false, |max| counts.total >= max);
+        let over_client = conf
+            .max_downloads_per_user
+            .map_or(false, |max| client_count >= max);
+
+        if over_total || over_client {
+            return Err(Error::TooManyDownloads);
+        }
+
+        counts.total += 1;
+        *counts.by_client.entry(client).or_default() += 1;
+
+        Ok(DownloadSlot {
+            counts: Arc::clone(&self.counts),
+            client,
+        })
+    }
+}
+
+/// A download in progress, which finishes when this is dropped.
+#[derive(Debug)]
+struct DownloadSlot {
+    counts: Arc<Mutex<DownloadCounts>>,
+    client: IpAddr,
+}
+
+impl Drop for DownloadSlot {
+    fn drop(&mut self) {
+        let mut counts = match self.counts.lock() {
+            Ok(counts) => counts,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        counts.total = counts.total.saturating_sub(1);
+
+        if let Entry::Occupied(mut entry) = counts.by_client.entry(self.client)
+        {
+            *entry.get_mut() -= 1;
+
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+/// A response body that holds a download until it's dropped, which is once the
+/// body has been sent or the client has gone away.
+struct DownloadBody<R> {
+    inner: R,
+    _download: DownloadSlot,
+}
+
+impl<R: Read> Read for DownloadBody<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// The value of a `Content-Disposition` header for showing a file inline with
+/// the given name.
+///
+/// Names that aren't plain ASCII are also given percent-encoded, as in RFC
+/// 6266, with a plain ASCII fallback for older browsers.
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+
+    if fallback == filename {
+        return format!("inline; filename=\"{}\"", fallback);
+    }
+
+    let mut encoded = String::new();
+
+    for byte in filename.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_'
+            | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    format!(
+        "inline; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+impl FileSource {
+    /// Serve the file at `full_path`, which is `path` under `root`.
+    fn new(
+        root: FileRoot,
+        path: &Path,
+        full_path: PathBuf,
+        conf: &Conf,
+    ) -> Result<FileSource> {
+        let accel = match conf.accel_redirect {
+            Some(accel) => accel,
+            None => return Ok(FileSource::File(NamedFile::open(full_path)?)),
+        };
+
+        if !full_path.is_file() {
+            return Err(Error::from_io_error(
+                io::Error::from(io::ErrorKind::NotFound),
+                format!("Couldn't find file {}", full_path.display()),
+            ));
+        }
+
+        let header = match accel.header {
+            AccelRedirectHeader::Nginx => {
+                let location = match root {
+                    FileRoot::Resource => &accel.resource_location,
+                    FileRoot::Upload => &accel.upload_location,
+                    // The front-end server only knows where the resource and
+                    // upload directories are.
+                    FileRoot::Directory(_) => {
+                        let file = NamedFile::open(full_path)?;
+                        return Ok(FileSource::File(file));
+                    }
+                };
+
+                let uri = format!(
+                    "{}/{}",
+                    location.trim_end_matches('/'),
+                    path.display()
+                );
+
+                Header::new("X-Accel-Redirect", uri)
+            }
+            AccelRedirectHeader::Apache => {
+                let full_path = full_path.canonicalize()?;
+                Header::new("X-Sendfile", full_path.display().to_string())
+            }
+        };
+
+        let content_type = path.extension().and_then(|ext| {
+            ContentType::from_extension(&ext.to_string_lossy())
+        });
+
+        Ok(FileSource::Redirect {
+            header,
+            content_type,
+        })
+    }
+}
+
+impl<'r> Responder<'r> for FileResponse {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        let mut res = match self.source {
+            FileSource::File(file) => file.respond_to(req)?,
+            FileSource::Redirect {
+                header,
+                content_type,
+            } => {
+                let mut res = Response::build();
+                res.header(header);
+
+                if let Some(content_type) = content_type {
+                    res.header(content_type);
+                }
+
+                res.finalize()
+            }
+        };
+
+        let mut cache_control =
+            format!("public, max-age={}", self.max_age.num_seconds());
+
+        if self.immutable {
+            cache_control.push_str(", immutable");
+        }
+
+        let expires = (Utc::now() + self.max_age)
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+
+        res.set_raw_header("Cache-Control", cache_control);
+        res.set_raw_header("Expires", expires);
+
+        if let Some(filename) = self.filename {
+            res.set_raw_header(
+                "Content-Disposition",
+                content_disposition(&filename),
+            );
+        }
+
+        if let Some(download) = self.download {
+            if let Some(body) = res.take_body() {
+                res.set_raw_body(body.map(|inner| DownloadBody {
+                    inner,
+                    _download: download,
+                }));
+            }
+        }
+
+        Ok(res)
+    }
+}
+
 /// Serve a static file.
 #[get("/file/<file..>", rank = 1)]
-pub fn static_file(file: PathBuf, conf: Conf) -> Result<NamedFile> {
-    Ok(NamedFile::open(conf.resource_dir.join(file))?)
+pub fn static_file(file: PathBuf, conf: Conf) -> Result<FileResponse> {
+    FileResponse::new(FileRoot::Resource, file, &conf)
 }
 
 /// Serve the favicon.
@@ -184,42 +686,100 @@ pub fn favicon(conf: Conf) -> Result<NamedFile> {
     Ok(NamedFile::open(conf.favicon_path)?)
 }
 
+/// Serve the icon shown in place of a thumbnail for non-media uploads.
+#[get("/file/file-icon.png", rank = 0)]
+pub fn file_icon(conf: Conf) -> Result<NamedFile> {
+    Ok(NamedFile::open(conf.file_icon_path)?)
+}
+
 /// Serve a stylesheet.
 #[get("/file/style/<file..>", rank = 0)]
-pub fn style(file: PathBuf, conf: Conf) -> Result<NamedFile> {
-    Ok(NamedFile::open(conf.resource_dir.join("style").join(file))?)
+pub fn style(file: PathBuf, conf: Conf) -> Result<FileResponse> {
+    FileResponse::new(FileRoot::Resource, Path::new("style").join(file), &conf)
 }
 
 /// Serve a script.
 #[get("/file/script/<file..>", rank = 0)]
-pub fn script(file: PathBuf, conf: Conf) -> Result<NamedFile> {
-    Ok(NamedFile::open(
-        conf.resource_dir.join("script").join(file),
-    )?)
+pub fn script(file: PathBuf, conf: Conf) -> Result<FileResponse> {
+    FileResponse::new(FileRoot::Resource, Path::new("script").join(file), &conf)
 }
 
 /// Serve a banner.
 #[get("/file/banner/<file..>", rank = 0)]
-pub fn banner(file: PathBuf, conf: Conf) -> Result<NamedFile> {
-    Ok(NamedFile::open(
-        conf.resource_dir.join("banners").join(file),
-    )?)
+pub fn banner(file: PathBuf, conf: Conf) -> Result<FileResponse> {
+    let path = Path::new("banners").join(file);
+    FileResponse::new(FileRoot::Resource, path, &conf)
+}
+
+/// Serve a banner from an extension's banner directory.
+///
+/// If the extension doesn't have its own banner directory, the banner is
+/// served from the global banners.
+#[get("/file/extension-banner/<extension_name>/<file..>", rank = 0)]
+pub fn extension_banner(
+    extension_name: String,
+    file: PathBuf,
+    config: State<Config>,
+) -> Result<FileResponse> {
+    let conf = config
+        .extension(&extension_name)
+        .ok_or(Error::ExtensionNotFound {
+            name: extension_name.clone(),
+        })?;
+
+    match conf.banner_dir {
+        Some(dir) => FileResponse::new(FileRoot::Directory(dir), file, &conf),
+        None => {
+            let path = Path::new("banners").join(file);
+            FileResponse::new(FileRoot::Resource, path, &conf)
+        }
+    }
 }
 
 /// Serve a user-uploaded file.
+///
+/// The file is named after the name it was uploaded with, if it has one.
+///
+/// Concurrent downloads are limited by `max_downloads` and
+/// `max_downloads_per_user`. Thumbnails aren't counted, since pages load many
+/// of them at once, and neither are files served by the front-end server.
 #[get("/file/upload/<file..>", rank = 0)]
-pub fn upload(file: PathBuf, conf: Conf) -> Result<NamedFile> {
-    let mut upload_path = conf.upload_dir.join(file);
+pub fn upload(
+    file: PathBuf,
+    conf: Conf,
+    mut db: PooledConnection,
+    downloads: State<Downloads>,
+    client_ip: ClientIp,
+) -> Result<FileResponse> {
+    if conf.upload_dir.join(&file).exists() {
+        let save_name = file.to_string_lossy().into_owned();
+
+        // Thumbnails aren't in the database, so they keep their own name.
+        let db_file = db.file_by_save_name(&save_name).ok();
+        let is_thumbnail = db_file.is_none();
+        let filename =
+            db_file.and_then(|file| file.orig_name).unwrap_or(save_name);
+
+        let download = if is_thumbnail || conf.accel_redirect.is_some() {
+            None
+        } else {
+            Some(downloads.start(client_ip.0, &conf)?)
+        };
 
-    if !upload_path.exists() {
-        upload_path = conf.resource_dir.join("deleted.png");
-    }
+        let res = FileResponse::new(FileRoot::Upload, file, &conf)?
+            .with_filename(filename);
 
-    Ok(NamedFile::open(upload_path)?)
+        Ok(match download {
+            Some(download) => res.with_download(download),
+            None => res,
+        })
+    } else {
+        FileResponse::new(FileRoot::Resource, "deleted.png", &conf)
+    }
 }
 
-/// Load a admin-created page.
-fn load_page<S>(page_name: S, conf: Conf) -> Result<String>
+/// Load a admin-created page, returning its metadata and its contents as HTML.
+fn load_page<S>(page_name: S, conf: Conf) -> Result<(PageMeta, String)>
 where
     S: AsRef<str>,
 {
@@ -233,22 +793,46 @@ where
                 name: page_name.clone(),
             }
         })?;
-        let parser = Parser::new(&page_contents);
+        let (meta, page_contents) = parse_front_matter(&page_contents)?;
 
-        let mut page_html = String::new();
-        html::push_html(&mut page_html, parser);
+        let page_html =
+            render_admin_markdown(page_contents, conf.page_allowed_html);
 
-        Ok(page_html)
+        Ok((meta, page_html))
     } else {
         Err(Error::CustomPageNotFound { name: page_name })
     }
 }
 
+/// The home page, or a redirect to a board for extensions that have one.
+#[derive(Responder)]
+pub enum HomeResponse {
+    Page(HomePage),
+    Redirect(Redirect),
+}
+
 /// Serve the home page.
 #[get("/", rank = 0)]
-pub fn home(conf: Conf, mut context: Context) -> Result<HomePage> {
-    let contents = load_page("home", conf).ok();
-    HomePage::new(contents, &mut context)
+pub fn home(conf: Conf, mut context: Context) -> Result<HomeResponse> {
+    if let Some(board_name) = conf.home_redirect {
+        let uri = uri!(board: board_name, 1);
+        return Ok(HomeResponse::Redirect(Redirect::to(uri)));
+    }
+
+    let contents = load_page("home", conf).ok().map(|(_meta, html)| html);
+    Ok(HomeResponse::Page(HomePage::new(contents, &mut context)?))
+}
+
+/// Serve the gallery of recently uploaded files.
+#[get("/gallery?<before>", rank = 1)]
+pub fn gallery(
+    before: Option<PostId>,
+    mut context: Context,
+) -> Result<GalleryPage> {
+    context.canonical_uri =
+        canonical_uri_with(context.canonical_uri, "before", before);
+
+    GalleryPage::new(before, &mut context)
 }
 
 /// Serve a admin-created page.
@@ -260,12 +844,13 @@ pub fn custom_page(
 ) -> Result<Template> {
     let mut data = HashMap::new();
 
-    let page_html = load_page(&page_name, conf)?;
+    let (meta, page_html) = load_page(&page_name, conf)?;
     data.insert("content".to_string(), JsonValue::String(page_html));
 
+    let title = meta.title.unwrap_or_else(|| page_name.clone());
     data.insert(
         "page_info".to_string(),
-        to_value(PageInfo::new(&page_name, &mut context))?,
+        to_value(PageInfo::new(&title, &mut context))?,
     );
     data.insert(
         "page_footer".to_string(),
@@ -289,6 +874,7 @@ pub fn form_help(mut context: Context, conf: Conf) -> Result<Template> {
         "page_footer".to_string(),
         to_value(PageFooter::new(&mut context)?)?,
     );
+    data.insert("allow_uploads".to_string(), to_value(conf.allow_uploads)?);
     data.insert(
         "file_size_limit".to_string(),
         to_value(conf.file_size_limit)?,
@@ -306,15 +892,37 @@ pub fn form_help(mut context: Context, conf: Conf) -> Result<Template> {
     Ok(Template::render("pages/form-help", data))
 }
 
+/// A board page, or a redirect to the board's catalog.
+#[derive(Responder)]
+pub enum BoardResponse {
+    Page(BoardPage),
+    Redirect(Redirect),
+}
+
 /// Serve a board.
+///
+/// If the board's default view is the catalog, the bare board URI redirects to
+/// the catalog. Pages of the board can still be viewed by giving a page
+/// number.
 #[get("/<board_name>?<page>", rank = 2)]
 pub fn board(
     board_name: String,
     page: Option<u32>,
     mut context: Context,
     _user: User,
-) -> Result<BoardPage> {
-    BoardPage::new(board_name, page.unwrap_or(1), &mut context)
+) -> Result<BoardResponse> {
+    if page.is_none()
+        && context.conf.default_view(&board_name) == DefaultView::Catalog
+    {
+        let uri = uri!(board_catalog: board_name);
+        return Ok(BoardResponse::Redirect(Redirect::to(uri)));
+    }
+
+    context.canonical_uri =
+        canonical_uri_with(context.canonical_uri, "page", page);
+
+    let page = BoardPage::new(board_name, page.unwrap_or(1), &mut context)?;
+    Ok(BoardResponse::Page(page))
 }
 
 /// Serve a board catalog.
@@ -327,39 +935,207 @@ pub fn board_catalog(
     BoardCatalogPage::new(board_name, &mut context)
 }
 
+/// Record the newest post on a thread page as seen.
+///
+/// Looking at an earlier page of a thread shouldn't forget about having seen
+/// the later ones, so nothing changes if a newer post was already seen.
+fn see_thread_page(
+    page: &ThreadPage,
+    thread_id: ThreadId,
+    last_seen: &mut LastSeen,
+    cookies: &mut Cookies,
+) {
+    if let Some(post_id) = page.newest_post_id() {
+        if last_seen.get(thread_id).map_or(true, |seen| post_id > seen) {
+            last_seen.set(thread_id, post_id);
+            cookies.add(last_seen.to_cookie());
+        }
+    }
+}
+
 /// Serve a thread.
-#[get("/<board_name>/<thread_id>", rank = 3)]
+///
+/// Only one page of replies is shown at a time, after the original post. Posts
+/// made since the user last viewed the thread are marked as new.
+#[get("/<board_name>/<thread_id>?<page>", rank = 3)]
 pub fn thread(
     board_name: String,
     thread_id: ThreadId,
+    page: Option<u32>,
     mut context: Context,
-    _user: User,
+    mut last_seen: LastSeen,
+    mut cookies: Cookies,
+    user: User,
 ) -> Result<ThreadPage> {
-    ThreadPage::new(board_name, thread_id, &mut context)
+    context.database.thread_on_board(&board_name, thread_id)?;
+
+    context.canonical_uri =
+        canonical_uri_with(context.canonical_uri, "page", page);
+
+    let slice = ThreadSlice::Page(page.unwrap_or(1).max(1));
+    let seen = last_seen.get(thread_id);
+
+    let page = ThreadPage::new(
+        board_name,
+        thread_id,
+        slice,
+        seen,
+        &user,
+        &mut context,
+    )?;
+
+    see_thread_page(&page, thread_id, &mut last_seen, &mut cookies);
+
+    Ok(page)
+}
+
+/// Serve the original post and the newest replies of a thread.
+///
+/// At most one page's worth of replies is shown. Posts made since the user
+/// last viewed the thread are marked as new.
+#[get("/<board_name>/<thread_id>/last/<count>")]
+pub fn thread_last(
+    board_name: String,
+    thread_id: ThreadId,
+    count: u32,
+    mut context: Context,
+    mut last_seen: LastSeen,
+    mut cookies: Cookies,
+    user: User,
+) -> Result<ThreadPage> {
+    context.database.thread_on_board(&board_name, thread_id)?;
+
+    let max_count = context.conf.posts_per_thread_page.max(1);
+    let slice = ThreadSlice::Last(count.clamp(1, max_count));
+    let seen = last_seen.get(thread_id);
+
+    let page = ThreadPage::new(
+        board_name,
+        thread_id,
+        slice,
+        seen,
+        &user,
+        &mut context,
+    )?;
+
+    see_thread_page(&page, thread_id, &mut last_seen, &mut cookies);
+
+    Ok(page)
+}
+
+/// Redirect to the page of a thread that a post is on.
+///
+/// Links to posts only have the post in their fragment, which never makes it to
+/// the server, so the thread page's javascript sends the reader here when the
+/// post it's looking for isn't on the page. Browsers keep the fragment across
+/// the redirect.
+#[get("/<board_name>/<thread_id>/post/<post_id>")]
+pub fn thread_post(
+    board_name: String,
+    thread_id: ThreadId,
+    post_id: PostId,
+    mut context: Context,
+    _user: User,
+) -> Result<Redirect> {
+    check_post_location(
+        &mut context.database,
+        &board_name,
+        thread_id,
+        post_id,
+    )?;
+
+    let page_width = context.conf.posts_per_thread_page.max(1);
+    let page_num = context
+        .database
+        .thread_page_of_post(thread_id, post_id, page_width)?;
+
+    Ok(Redirect::to(uri!(thread: board_name, thread_id, page_num)))
+}
+
+/// Check that a post is in the given thread, and that the thread is on the
+/// given board.
+fn check_post_location(
+    db: &mut PooledConnection,
+    board_name: &str,
+    thread_id: ThreadId,
+    post_id: PostId,
+) -> Result<()> {
+    db.thread_on_board(board_name, thread_id)?;
+
+    if db.post(post_id)?.thread_id != thread_id {
+        return Err(Error::PostNotFound { post_id });
+    }
+
+    Ok(())
 }
 
 /// Serve a post preview.
-#[get("/<_board_name>/<_thread_id>/preview/<post_id>", rank = 2)]
+#[get("/<board_name>/<thread_id>/preview/<post_id>", rank = 2)]
 pub fn post_preview(
-    _board_name: String,
-    _thread_id: ThreadId,
+    board_name: String,
+    thread_id: ThreadId,
     post_id: PostId,
     mut context: Context,
     _user: User,
 ) -> Result<PostPreview> {
+    // Posts in other threads can be previewed too, so only check the
+    // thread.
+    context.database.thread_on_board(&board_name, thread_id)?;
+
     PostPreview::new(post_id, &mut context)
 }
 
+/// A post ID followed by a `.json` extension, e.g. `123.json`.
+pub struct JsonPostId(pub PostId);
+
+impl<'a> FromParam<'a> for JsonPostId {
+    type Error = &'a RawStr;
+
+    fn from_param(
+        param: &'a RawStr,
+    ) -> std::result::Result<Self, Self::Error> {
+        param
+            .as_str()
+            .strip_suffix(".json")
+            .and_then(|id| id.parse().ok())
+            .map(JsonPostId)
+            .ok_or(param)
+    }
+}
+
+/// Serve a post preview as JSON.
+#[get("/<board_name>/<thread_id>/preview/<post_id>", rank = 3)]
+pub fn post_preview_json(
+    board_name: String,
+    thread_id: ThreadId,
+    post_id: JsonPostId,
+    mut context: Context,
+    _user: User,
+) -> Result<PostPreviewJson> {
+    // Posts in other threads can be previewed too, so only check the
+    // thread.
+    context.database.thread_on_board(&board_name, thread_id)?;
+
+    PostPreviewJson::new(post_id.0, &mut context)
+}
+
 /// Report a post.
-#[get("/<_board_name>/<_thread_id>/report/<post_id>")]
+#[get("/<board_name>/<thread_id>/report/<post_id>")]
 pub fn report(
-    _board_name: String,
-    _thread_id: ThreadId,
+    board_name: String,
+    thread_id: ThreadId,
     post_id: PostId,
     mut context: Context,
     _not_blocked: NotBlocked,
     _user: User,
 ) -> Result<ReportPage> {
+    check_post_location(
+        &mut context.database,
+        &board_name,
+        thread_id,
+        post_id,
+    )?;
+
     ReportPage::new(post_id, &mut context)
 }
 
@@ -370,16 +1146,23 @@ pub struct ReportData {
 }
 
 /// Create a new post report.
-#[post("/<_board_name>/<_thread_id>/report/<post_id>", data = "<report_data>")]
+#[post("/<board_name>/<thread_id>/report/<post_id>", data = "<report_data>")]
 pub fn new_report(
-    _board_name: String,
-    _thread_id: ThreadId,
+    board_name: String,
+    thread_id: ThreadId,
     post_id: PostId,
     report_data: Form<ReportData>,
     mut context: Context,
     user: User,
     _not_blocked: NotBlocked,
 ) -> Result<ActionSuccessPage> {
+    check_post_location(
+        &mut context.database,
+        &board_name,
+        thread_id,
+        post_id,
+    )?;
+
     let ReportData { reason } = report_data.into_inner();
 
     if reason.len() > 250 {
@@ -395,20 +1178,27 @@ pub fn new_report(
     })?;
 
     let msg = format!("Reported post {} successfully.", post_id);
-    let uri = uri!(thread: thread.board_name, thread.id).to_string();
+    let uri = uri!(thread: thread.board_name, thread.id, _).to_string();
     Ok(ActionSuccessPage::new(msg, uri, &mut context)?)
 }
 
 /// Serve a form for deleting a post.
-#[get("/<_board_name>/<_thread_id>/delete/<post_id>")]
+#[get("/<board_name>/<thread_id>/delete/<post_id>")]
 pub fn delete(
-    _board_name: String,
-    _thread_id: ThreadId,
+    board_name: String,
+    thread_id: ThreadId,
     post_id: PostId,
     mut context: Context,
     _not_blocked: NotBlocked,
     _user: User,
 ) -> Result<DeletePage> {
+    check_post_location(
+        &mut context.database,
+        &board_name,
+        thread_id,
+        post_id,
+    )?;
+
     if context.database.is_first_post(post_id)? {
         Ok(DeletePage::Thread(DeleteThreadPage::new(
             post_id,
@@ -429,48 +1219,416 @@ pub struct DeleteData {
     file_only: Option<String>,
 }
 
+/// What was deleted by `delete_with_password`.
+enum Deleted {
+    Thread,
+    Post,
+    Files,
+}
+
+/// Check that a post made at `time_stamp` can still be deleted by its author
+/// at `now`, given how long after posting users can delete their posts.
+fn check_delete_window(
+    time_stamp: &DateTime<Utc>,
+    window: Option<&Duration>,
+    now: &DateTime<Utc>,
+) -> Result<()> {
+    match window {
+        Some(window) if *now - *time_stamp > *window => {
+            Err(Error::DeleteWindowExpired)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that `password` is either the delete password of a post or the delete
+/// token that was given out when it was made.
+fn check_delete_password(post: &Post, password: &str) -> Result<()> {
+    for hash in post.delete_hash.iter().chain(&post.delete_token_hash) {
+        if verify_encoded(hash, password.as_bytes())? {
+            return Ok(());
+        }
+    }
+
+    Err(Error::DeleteInvalidPassword)
+}
+
+/// Delete a post (or its files) if `password` matches its delete password or
+/// its delete token.
+///
+/// If the post is the first post in a thread, the whole thread is deleted.
+fn delete_with_password(
+    db: &mut PooledConnection,
+    conf: &Conf,
+    post: &Post,
+    password: &str,
+    file_only: bool,
+) -> Result<Deleted> {
+    if post.system {
+        return Err(Error::CannotDeleteSystemPost);
+    }
+
+    let now = Utc::now();
+    check_delete_window(&post.time_stamp, conf.self_delete_window, &now)?;
+
+    check_delete_password(post, password)?;
+
+    if db.is_first_post(post.id)? {
+        if file_only {
+            return Err(Error::CannotDeleteThreadFilesOnly);
+        }
+
+        db.delete_thread(post.thread_id)?;
+        Ok(Deleted::Thread)
+    } else if file_only {
+        db.delete_files_of_post(post.id)?;
+        Ok(Deleted::Files)
+    } else {
+        db.delete_post(post.id)?;
+        Ok(Deleted::Post)
+    }
+}
+
 /// Delete a post.
-#[post("/<_board_name>/<_thread_id>/delete/<post_id>", data = "<delete_data>")]
+#[post("/<board_name>/<thread_id>/delete/<post_id>", data = "<delete_data>")]
 pub fn handle_delete(
-    _board_name: String,
-    _thread_id: ThreadId,
+    board_name: String,
+    thread_id: ThreadId,
     post_id: PostId,
     delete_data: Form<DeleteData>,
     mut context: Context,
     _not_blocked: NotBlocked,
     _user: User,
 ) -> Result<ActionSuccessPage> {
+    check_post_location(
+        &mut context.database,
+        &board_name,
+        thread_id,
+        post_id,
+    )?;
+
     let post = context.database.post(post_id)?;
 
-    let hash = post.delete_hash.ok_or(Error::DeleteInvalidPassword)?;
+    let deleted = delete_with_password(
+        &mut context.database,
+        &context.conf,
+        &post,
+        &delete_data.password,
+        delete_data.file_only.is_some(),
+    )?;
+
+    let (msg, redirect_uri) = match deleted {
+        Deleted::Thread => (
+            format!("Deleted thread {} successfully.", post.thread_id),
+            uri!(board: post.board_name, 1).to_string(),
+        ),
+        Deleted::Files => (
+            format!("Deleted files from post {} successfully.", post_id),
+            uri!(thread: post.board_name, post.id, _).to_string(),
+        ),
+        Deleted::Post => (
+            format!("Deleted post {} successfully.", post_id),
+            uri!(thread: post.board_name, post.id, _).to_string(),
+        ),
+    };
 
-    if !verify_encoded(&hash, delete_data.password.as_bytes())? {
-        return Err(Error::DeleteInvalidPassword);
+    Ok(ActionSuccessPage::new(msg, redirect_uri, &mut context)?)
+}
+
+/// Delete a post in a single request, without going through the delete page.
+///
+/// Redirects back to the thread, or to the board if the whole thread was
+/// deleted.
+#[post(
+    "/<board_name>/<thread_id>/quick-delete/<post_id>",
+    data = "<delete_data>"
+)]
+pub fn quick_delete(
+    board_name: String,
+    thread_id: ThreadId,
+    post_id: PostId,
+    delete_data: Form<DeleteData>,
+    mut db: PooledConnection,
+    conf: Conf,
+    _not_blocked: NotBlocked,
+    _user: User,
+) -> Result<Redirect> {
+    check_post_location(&mut db, &board_name, thread_id, post_id)?;
+
+    let post = db.post(post_id)?;
+
+    let deleted = delete_with_password(
+        &mut db,
+        &conf,
+        &post,
+        &delete_data.password,
+        delete_data.file_only.is_some(),
+    )?;
+
+    Ok(match deleted {
+        Deleted::Thread => Redirect::to(uri!(board: post.board_name, 1)),
+        Deleted::Post | Deleted::Files => {
+            Redirect::to(uri!(thread: post.board_name, post.thread_id, _))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::RawStr;
+    use rocket::request::FromParam;
+
+    use argon2::hash_encoded;
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{
+        canonical_path, canonical_uri_with, check_delete_password,
+        check_delete_window, check_ip, content_disposition, Downloads,
+        JsonPostId, LastSeen,
+    };
+    use crate::config::{Config, GlobalConfig, IpRanges};
+    use crate::models::Post;
+    use crate::Error;
+
+    fn parse(param: &str) -> Option<i32> {
+        JsonPostId::from_param(RawStr::from_str(param))
+            .ok()
+            .map(|id| id.0)
     }
 
-    let msg: String;
-    let redirect_uri: String;
+    #[test]
+    fn json_post_id() {
+        assert_eq!(parse("123.json"), Some(123));
+        assert_eq!(parse("123"), None);
+        assert_eq!(parse("123.html"), None);
+        assert_eq!(parse("abc.json"), None);
+        assert_eq!(parse(".json"), None);
+    }
 
-    if context.database.is_first_post(post_id)? {
-        if delete_data.file_only.is_some() {
-            return Err(Error::CannotDeleteThreadFilesOnly);
-        }
+    #[test]
+    fn last_seen_parse() {
+        assert_eq!(LastSeen::parse(""), LastSeen(vec![]));
+        assert_eq!(
+            LastSeen::parse("1:10,2:20,bad,3:x"),
+            LastSeen(vec![(1, 10), (2, 20)])
+        );
+    }
 
-        context.database.delete_thread(post.thread_id)?;
-        msg = format!("Deleted thread {} successfully.", post.thread_id);
+    #[test]
+    fn last_seen_set() {
+        let mut last_seen = LastSeen::parse("1:10,2:20");
 
-        redirect_uri = uri!(board: post.board_name, 1).to_string();
-    } else {
-        if delete_data.file_only.is_some() {
-            context.database.delete_files_of_post(post_id)?;
-            msg = format!("Deleted files from post {} successfully.", post_id)
-        } else {
-            context.database.delete_post(post_id)?;
-            msg = format!("Deleted post {} successfully.", post_id)
+        last_seen.set(2, 25);
+        assert_eq!(last_seen, LastSeen(vec![(2, 25), (1, 10)]));
+        assert_eq!(last_seen.get(2), Some(25));
+        assert_eq!(last_seen.get(3), None);
+
+        for thread_id in 100..200 {
+            last_seen.set(thread_id, 1);
         }
+        assert_eq!(last_seen.0.len(), LastSeen::MAX_THREADS);
+        assert_eq!(last_seen.get(1), None);
+        assert_eq!(last_seen.get(199), Some(1));
+    }
+
+    #[test]
+    fn last_seen_cookie() {
+        let mut last_seen = LastSeen::default();
+        last_seen.set(1, 10);
+        last_seen.set(2, 20);
 
-        redirect_uri = uri!(thread: post.board_name, post.id).to_string();
+        assert_eq!(last_seen.to_cookie().value(), "2:20,1:10");
     }
 
-    Ok(ActionSuccessPage::new(msg, redirect_uri, &mut context)?)
+    #[test]
+    fn delete_window() {
+        let posted = Utc.with_ymd_and_hms(2020, 4, 20, 16, 20, 0).unwrap();
+        let window = Duration::hours(1);
+
+        let inside = posted + Duration::minutes(59);
+        assert!(check_delete_window(&posted, Some(&window), &inside).is_ok());
+
+        let outside = posted + Duration::minutes(61);
+        assert!(check_delete_window(&posted, Some(&window), &outside).is_err());
+
+        assert!(check_delete_window(&posted, None, &outside).is_ok());
+    }
+
+    #[test]
+    fn canonical_paths() {
+        let boards = ["tech", "Music"];
+        let canonical =
+            |path: &str| canonical_path(path, |name| boards.contains(&name));
+
+        assert_eq!(canonical("/tech/123/"), Some("/tech/123".into()));
+        assert_eq!(canonical("/tech//"), Some("/tech".into()));
+        assert_eq!(canonical("/Tech/123"), Some("/tech/123".into()));
+        assert_eq!(canonical("/TECH/"), Some("/tech".into()));
+
+        // Boards that already have capital letters keep them.
+        assert_eq!(canonical("/Music/12"), None);
+        assert_eq!(canonical("/Music/"), Some("/Music".into()));
+        assert_eq!(canonical("/music"), None);
+
+        // Paths that aren't boards aren't changed either.
+        assert_eq!(canonical("/Gallery"), None);
+
+        // File names keep their case.
+        assert_eq!(canonical("/file/upload/Photo.PNG"), None);
+
+        assert_eq!(canonical("/tech/123"), None);
+        assert_eq!(canonical("/"), None);
+    }
+
+    #[test]
+    fn canonical_uris_with_params() {
+        let path = || String::from("/gallery");
+
+        assert_eq!(
+            canonical_uri_with(path(), "before", Some(42)),
+            "/gallery?before=42"
+        );
+        assert_eq!(
+            canonical_uri_with::<u32>(path(), "before", None),
+            "/gallery"
+        );
+    }
+
+    #[test]
+    fn delete_password_or_token() {
+        let hash = |pass: &str| {
+            let salt = b"longboard-test";
+            hash_encoded(pass.as_bytes(), salt, &argon2::Config::default())
+                .unwrap()
+        };
+
+        let mut post = Post {
+            id: 123,
+            time_stamp: Utc.with_ymd_and_hms(2020, 4, 20, 16, 20, 0).unwrap(),
+            body: String::new(),
+            author_name: String::from("Anonymous"),
+            author_contact: None,
+            author_ident: None,
+            thread_id: 45,
+            delete_hash: Some(hash("hunter2")),
+            board_name: String::from("tech"),
+            user_id: 1,
+            no_bump: false,
+            system: false,
+            author_ident_class: None,
+            truncated_html: None,
+            embeds: None,
+            pending: false,
+            delete_token_hash: Some(hash("token")),
+        };
+
+        assert!(check_delete_password(&post, "hunter2").is_ok());
+        assert!(check_delete_password(&post, "token").is_ok());
+        assert!(matches!(
+            check_delete_password(&post, "wrong"),
+            Err(Error::DeleteInvalidPassword)
+        ));
+
+        post.delete_hash = None;
+        assert!(check_delete_password(&post, "hunter2").is_err());
+        assert!(check_delete_password(&post, "token").is_ok());
+
+        post.delete_token_hash = None;
+        assert!(check_delete_password(&post, "token").is_err());
+    }
+
+    #[test]
+    fn staff_skip_block_list() {
+        let blocked = "203.0.113.7".parse().unwrap();
+
+        let mut config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        config.global_config.block_list = vec![blocked];
+
+        let conf = config.global();
+        assert!(matches!(
+            check_ip(&conf, blocked, true),
+            Err(Error::IpIsBlocked { .. })
+        ));
+
+        config.global_config.exempt_staff = true;
+
+        let conf = config.global();
+        assert!(check_ip(&conf, blocked, true).is_ok());
+        assert!(matches!(
+            check_ip(&conf, blocked, false),
+            Err(Error::IpIsBlocked { .. })
+        ));
+    }
+
+    #[test]
+    fn blocked_ranges() {
+        let mut config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        config.global_config.blocked_ranges =
+            IpRanges::parse("203.0.113.0/24\n2001:db8::/32").unwrap();
+        config.global_config.allow_list = vec!["203.0.113.1".parse().unwrap()];
+        let conf = config.global();
+
+        let check = |ip: &str| check_ip(&conf, ip.parse().unwrap(), false);
+
+        assert!(matches!(
+            check("203.0.113.7"),
+            Err(Error::IpIsBlocked { .. })
+        ));
+        assert!(matches!(
+            check("2001:db8::7"),
+            Err(Error::IpIsBlocked { .. })
+        ));
+        assert!(check("203.0.113.1").is_ok());
+        assert!(check("198.51.100.7").is_ok());
+    }
+
+    #[test]
+    fn download_limits() {
+        let mut config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        config.global_config.max_downloads = Some(3);
+        config.global_config.max_downloads_per_user = Some(2);
+        let conf = config.global();
+
+        let downloads = Downloads::default();
+        let start = |ip: &str| downloads.start(ip.parse().unwrap(), &conf);
+
+        let first = start("2001:db8::1").unwrap();
+        let _second = start("2001:db8::2").unwrap();
+
+        // These addresses are all in the same /64 network.
+        assert!(matches!(start("2001:db8::3"), Err(Error::TooManyDownloads)));
+
+        let _third = start("198.51.100.7").unwrap();
+        assert!(matches!(start("203.0.113.7"), Err(Error::TooManyDownloads)));
+
+        drop(first);
+        assert!(start("2001:db8::3").is_ok());
+    }
+
+    #[test]
+    fn content_dispositions() {
+        assert_eq!(
+            content_disposition("cat.jpg"),
+            "inline; filename=\"cat.jpg\""
+        );
+        assert_eq!(
+            content_disposition("say \"hi\".png"),
+            "inline; filename=\"say _hi_.png\"; \
+             filename*=UTF-8''say%20%22hi%22.png"
+        );
+        assert_eq!(
+            content_disposition("猫.png"),
+            "inline; filename=\"_.png\"; filename*=UTF-8''%E7%8C%AB.png"
+        );
+    }
 }
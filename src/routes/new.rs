@@ -1,17 +1,26 @@
 //! Routes for creating new threads and new posts.
 
+use std::collections::HashMap;
 use std::fmt::Display;
-use std::fs::File;
-use std::io::{self, BufReader};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::string::ToString;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration as StdDuration;
 
 use argon2::hash_encoded;
 
-use chrono::offset::Utc;
+use chrono::offset::{TimeZone, Utc};
+use chrono::{DateTime, Duration};
 
-use image::ImageFormat;
+use hmac::{Hmac, Mac, NewMac};
+
+use image::{DynamicImage, ImageError, ImageFormat};
+
+use log::warn;
 
 use mime::Mime;
 
@@ -24,16 +33,29 @@ use multipart::server::{Entries, Multipart};
 
 use rand::{thread_rng, Rng};
 
+use sha2::Sha256;
+
+use tempfile::Builder as TempDirBuilder;
+
 use rocket::http::hyper::header::Location;
 use rocket::http::{ContentType, Status};
+use rocket::request::{self, Form, FromForm, FromRequest};
+use rocket::response::status::{Accepted, Created};
 use rocket::response::Redirect;
 use rocket::{data, Outcome};
-use rocket::{post, uri, Data, Request, Responder};
+use rocket::{post, uri, Data, Request, Responder, State};
 
+use crate::config::{
+    Conf, FirstLinkAction, ThumbnailFormat, TranscodeFormat,
+};
 use crate::models::*;
-use crate::parse::PostBody;
-use crate::routes::NotBlocked;
-use crate::{config::Conf, Error, Result};
+use crate::parse::{
+    sanitize_html, sanitized_link_count, CollapseLimits, LineLimits,
+    MarkupMode, PostBody, RenderOptions,
+};
+use crate::routes::{in_ip_list, NotBlocked};
+use crate::views::{ActionSuccessPage, BodyPreview, Context, PostCreated};
+use crate::{Error, Result};
 
 /// This is a workaround for Rocket's URI type not supporting fragments (the
 /// portion after #).
@@ -62,6 +84,59 @@ impl FragmentRedirect {
     }
 }
 
+/// Where a user is sent after making a post.
+#[derive(Responder)]
+pub enum PostedResponse {
+    Redirect(Redirect),
+    Fragment(FragmentRedirect),
+    /// A page saying that the post is waiting for approval.
+    Held(ActionSuccessPage),
+    /// The new post, for clients that want JSON.
+    Created(Created<PostCreated>),
+    /// The new post, for clients that want JSON, when the post is waiting for
+    /// approval.
+    Accepted(Accepted<PostCreated>),
+}
+
+/// Request guard for whether the client wants a JSON response after posting,
+/// instead of a redirect.
+///
+/// This is the case when JSON is the client's preferred response format, or
+/// when the request was made with `XMLHttpRequest`.
+pub struct WantsJson(pub bool);
+
+impl<'a, 'r> FromRequest<'a, 'r> for WantsJson {
+    type Error = ();
+
+    fn from_request(
+        request: &'a Request<'r>,
+    ) -> request::Outcome<Self, Self::Error> {
+        let accepts_json = request
+            .accept()
+            .map_or(false, |accept| accept.preferred().media_type().is_json());
+
+        let is_xhr = request
+            .headers()
+            .get_one("X-Requested-With")
+            .map_or(false, |value| {
+                value.eq_ignore_ascii_case("XMLHttpRequest")
+            });
+
+        Outcome::Success(WantsJson(accepts_json || is_xhr))
+    }
+}
+
+/// The response for a post that's held for approval.
+fn held_response(
+    board_name: &str,
+    context: &mut Context,
+) -> Result<PostedResponse> {
+    let msg = "Your post will be shown once a staff member approves it.";
+    let uri = uri!(crate::routes::board: board_name, _).to_string();
+
+    Ok(PostedResponse::Held(ActionSuccessPage::new(msg, uri, context)?))
+}
+
 /// Data guard for multipart/form-data entries.
 #[derive(Debug)]
 pub struct MultipartEntries(Entries);
@@ -93,32 +168,104 @@ impl data::FromDataSimple for MultipartEntries {
                 }
             };
 
-        let entries = match Multipart::with_body(data.open(), boundary)
-            .save()
-            .size_limit(conf.file_size_limit)
-            .temp()
-        {
-            SaveResult::Full(entries) => entries,
-            SaveResult::Partial(_, PartialReason::SizeLimit) => {
-                return Outcome::Failure((
-                    Status::BadRequest,
-                    Error::UploadTooBig {
-                        size_limit: conf.file_size_limit,
-                    },
-                ))
-            }
-            _ => {
-                return Outcome::Failure((
-                    Status::BadRequest,
-                    Error::FormDataCouldntParse,
-                ))
+        let entries = match save_entries(
+            data.open(),
+            boundary,
+            conf.file_size_limit,
+            conf.request_size_limit,
+            conf.upload_temp_dir,
+        ) {
+            Ok(entries) => entries,
+            Err(e @ Error::IoErrorMsg { .. }) => {
+                return Outcome::Failure((Status::InternalServerError, e))
             }
+            Err(e) => return Outcome::Failure((Status::BadRequest, e)),
         };
 
         Outcome::Success(MultipartEntries(entries))
     }
 }
 
+/// A reader that fails once more than a limited number of bytes are read from
+/// it.
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        if read as u64 > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "request body is too large",
+            ));
+        }
+
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Save the entries of a multipart body. Files are stored in a new temporary
+/// directory under `temp_dir` (or the system temporary directory), which is
+/// removed again when the entries are dropped or the upload is rejected.
+///
+/// Each file may be at most `file_size_limit` bytes, and the whole body at
+/// most `request_size_limit` bytes.
+fn save_entries<R>(
+    body: R,
+    boundary: &str,
+    file_size_limit: u64,
+    request_size_limit: u64,
+    temp_dir: Option<&Path>,
+) -> Result<Entries>
+where
+    R: Read,
+{
+    let mut temp_builder = TempDirBuilder::new();
+    temp_builder.prefix("longboard-upload");
+
+    let temp_dir = match temp_dir {
+        Some(path) => temp_builder.tempdir_in(path),
+        None => temp_builder.tempdir(),
+    }
+    .map_err(|e| {
+        Error::from_io_error(e, "Couldn't create temporary upload directory")
+    })?;
+
+    let mut body = LimitedReader {
+        inner: body,
+        remaining: request_size_limit,
+        exceeded: false,
+    };
+
+    let result = Multipart::with_body(&mut body, boundary)
+        .save()
+        .size_limit(file_size_limit)
+        .with_temp_dir(temp_dir);
+
+    if body.exceeded {
+        return Err(Error::UploadTooBig {
+            size_limit: request_size_limit,
+        });
+    }
+
+    match result {
+        SaveResult::Full(entries) => Ok(entries),
+        SaveResult::Partial(_, PartialReason::SizeLimit) => {
+            Err(Error::UploadTooBig {
+                size_limit: file_size_limit,
+            })
+        }
+        _ => Err(Error::FormDataCouldntParse),
+    }
+}
+
 impl MultipartEntries {
     fn param<S>(&self, name: S) -> Option<&str>
     where
@@ -158,11 +305,82 @@ impl MultipartEntries {
     }
 }
 
+/// The most characters of an uploaded file's original name that are kept when
+/// it's saved.
+const MAX_SAVE_STEM_CHARS: usize = 64;
+
+/// Turn the original name of an uploaded file into a name it can be saved
+/// under, without its extension.
+///
+/// Only ASCII letters, digits, dashes and underscores are kept, and anything
+/// before the last path separator is dropped. Returns `None` if nothing is
+/// left of the name.
+fn sanitize_file_stem<S>(orig_name: S) -> Option<String>
+where
+    S: AsRef<str>,
+{
+    let base_name = orig_name.as_ref().rsplit(['/', '\\']).next()?;
+
+    // Only the last extension is removed, so "a.tar.gz" becomes "a-tar".
+    let stem = match base_name.rsplit_once('.') {
+        Some((stem, _ext)) if !stem.is_empty() => stem,
+        _ => base_name,
+    };
+
+    let mut sanitized = String::new();
+
+    for c in stem.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            sanitized.push(c);
+        } else if !sanitized.is_empty() && !sanitized.ends_with('-') {
+            sanitized.push('-');
+        }
+
+        if sanitized.len() >= MAX_SAVE_STEM_CHARS {
+            break;
+        }
+    }
+
+    let sanitized = sanitized.trim_end_matches('-');
+
+    if sanitized.is_empty() {
+        None
+    } else {
+        Some(sanitized.to_string())
+    }
+}
+
+/// How many random characters are added to file names taken from the original
+/// name.
+const SAVE_STEM_TAG_CHARS: usize = 8;
+
+/// The name to save an upload under, without its extension.
+///
+/// This is the current time, or `orig_name` if it's given and has anything left
+/// after being sanitized. Uploads are cached as immutable, so a random tag is
+/// added to original names, so that a name isn't reused once the file that had
+/// it is deleted.
+fn save_stem(orig_name: Option<&str>) -> String {
+    match orig_name.and_then(sanitize_file_stem) {
+        Some(stem) => {
+            let tag: String = thread_rng()
+                .sample_iter(rand::distributions::Alphanumeric)
+                .map(char::from)
+                .take(SAVE_STEM_TAG_CHARS)
+                .collect();
+
+            format!("{}-{}", stem, tag)
+        }
+        None => Utc::now().format("%s").to_string(),
+    }
+}
+
 /// Copy a file from the user's request into the uploads directory. Returns the
-/// path the file was saved under.
+/// path the file was saved under, see `save_stem`.
 fn save_file<P>(
     field: &SavedField,
     content_type: &Mime,
+    orig_name: Option<&str>,
     upload_dir: P,
 ) -> Result<PathBuf>
 where
@@ -178,44 +396,77 @@ where
         }
     };
 
-    let epoch = Utc::now().format("%s").to_string();
+    let stem = save_stem(orig_name);
+    let (new_path, mut new_file) =
+        create_upload_file(upload_dir.as_ref(), &stem, mime_ext)?;
+
+    let mut file_data = field.data.readable()?;
+
+    io::copy(&mut file_data, &mut new_file)?;
+
+    Ok(new_path)
+}
+
+/// Create a new, empty file in `upload_dir` named after `stem`, with a number
+/// added if the name is taken.
+///
+/// The file is created with `create_new`, so two uploads can't be given the
+/// same name, even at the same time.
+fn create_upload_file(
+    upload_dir: &Path,
+    stem: &str,
+    ext: &str,
+) -> Result<(PathBuf, File)> {
     let mut num = 0;
     let mut suffix = String::new();
 
     // Loop until we generate a filename that isn't already taken.
-    let mut new_path: PathBuf;
     loop {
-        let new_base_name = format!("{}{}", epoch, suffix);
+        let mut new_file_name = PathBuf::from(format!("{}{}", stem, suffix));
+        new_file_name.set_extension(ext);
 
-        let mut new_file_name = PathBuf::from(new_base_name);
-        new_file_name.set_extension(mime_ext);
+        let new_path = upload_dir.join(new_file_name);
 
-        new_path = upload_dir.as_ref().join(new_file_name);
+        // Names taken from the original file name could clash with the
+        // thumbnails of another file, in any format that thumbnails have been
+        // saved in, so check those too.
+        let thumb_taken = ThumbnailFormat::ALL.iter().any(|&format| {
+            thumb_path(&new_path, format).exists()
+                || catalog_thumb_path(&new_path, format).exists()
+        });
 
-        if !new_path.exists() {
-            break;
+        if !thumb_taken {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&new_path)
+            {
+                Ok(new_file) => return Ok((new_path, new_file)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => {
+                    return Err(Error::from_io_error(
+                        err,
+                        format!(
+                            "Couldn't create new upload file {}",
+                            new_path.display()
+                        ),
+                    ))
+                }
+            }
         }
 
         num += 1;
         suffix = format!("-{}", num);
     }
-
-    let mut new_file = File::create(&new_path).map_err(|err| {
-        Error::from_io_error(
-            err,
-            format!("Couldn't create new upload file {}", new_path.display()),
-        )
-    })?;
-
-    let mut file_data = field.data.readable()?;
-
-    io::copy(&mut file_data, &mut new_file)?;
-
-    Ok(new_path)
 }
 
-/// Create a thumbnail from a saved file.
-fn create_thumbnail<P>(save_path: P, content_type: &Mime) -> Result<PathBuf>
+/// The path that a thumbnail of a saved file is written to, with `suffix`
+/// added to the file's name.
+fn suffixed_thumb_path<P>(
+    save_path: P,
+    suffix: &str,
+    format: ThumbnailFormat,
+) -> PathBuf
 where
     P: AsRef<Path>,
 {
@@ -226,29 +477,173 @@ where
         .expect("bad thumb path")
         .to_str()
         .expect("bad thumb path");
-    let thumb_stem = format!("{}-thumb.png", save_path_stem);
-    let thumb_name = Path::new(&thumb_stem).with_extension("png");
-    let thumb_path =
-        save_path.parent().expect("bad thumb path").join(thumb_name);
+    let thumb_name =
+        format!("{}-{}.{}", save_path_stem, suffix, format.extension());
 
-    match content_type.type_() {
-        name if name == "image" => {
-            create_image_thumbnail(save_path, &thumb_path)?
+    save_path.parent().expect("bad thumb path").join(thumb_name)
+}
+
+/// The path that the thumbnail of a saved file is written to.
+fn thumb_path<P>(save_path: P, format: ThumbnailFormat) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    suffixed_thumb_path(save_path, "thumb", format)
+}
+
+/// The path that the catalog thumbnail of a saved file is written to.
+fn catalog_thumb_path<P>(save_path: P, format: ThumbnailFormat) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    suffixed_thumb_path(save_path, "catalog", format)
+}
+
+/// The largest width and height of a thumbnail.
+const THUMB_MAX_SIZE: u32 = 200;
+
+/// Create a thumbnail from a saved file, in `format`. Returns the path of the
+/// thumbnail. If `auto_orient` is set, images are turned upright according to
+/// their EXIF orientation first, see `apply_orientation`.
+///
+/// Images that already fit within the thumbnail size are their own thumbnail,
+/// so the path of the saved file is returned and no thumbnail is written. The
+/// same goes for files that aren't images or videos, which are shown with a
+/// generic file icon instead.
+pub fn create_thumbnail<P>(
+    save_path: P,
+    content_type: &Mime,
+    format: ThumbnailFormat,
+    auto_orient: bool,
+) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let save_path = save_path.as_ref();
+
+    if content_type.type_() == "image" {
+        let (width, height) = image::image_dimensions(save_path)?;
+
+        if width <= THUMB_MAX_SIZE && height <= THUMB_MAX_SIZE {
+            return Ok(save_path.to_path_buf());
         }
+    }
+
+    let thumb_path = thumb_path(save_path, format);
+
+    match content_type.type_() {
+        name if name == "image" => create_image_thumbnail(
+            save_path,
+            &thumb_path,
+            THUMB_MAX_SIZE,
+            auto_orient,
+        ),
         name if name == "video" => {
-            create_video_thumbnail(save_path, &thumb_path)?
+            create_video_thumbnail(save_path, &thumb_path)
         }
-        _ => {
-            return Err(Error::UploadBadContentType {
-                content_type: content_type.clone(),
-            })
+        // Other kinds of files are shown with a generic icon, so there's no
+        // thumbnail to make.
+        _ => Ok(save_path.to_path_buf()),
+    }
+}
+
+/// Create a catalog thumbnail, which fits within `max_size`, for a saved file
+/// whose regular thumbnail is at `thumb_path`.
+///
+/// Images are scaled down from the saved file, and videos from their regular
+/// thumbnail. As with regular thumbnails, if the source already fits then it's
+/// used as the catalog thumbnail, and files that aren't images or videos use
+/// their regular thumbnail.
+pub fn create_catalog_thumbnail<P1, P2>(
+    save_path: P1,
+    thumb_path: P2,
+    content_type: &Mime,
+    max_size: u32,
+    format: ThumbnailFormat,
+    auto_orient: bool,
+) -> Result<PathBuf>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let save_path = save_path.as_ref();
+    let thumb_path = thumb_path.as_ref();
+
+    let source_path = match content_type.type_() {
+        name if name == "image" => save_path,
+        name if name == "video" => thumb_path,
+        _ => return Ok(thumb_path.to_path_buf()),
+    };
+
+    let (width, height) = image::image_dimensions(source_path)?;
+
+    if width <= max_size && height <= max_size {
+        return Ok(source_path.to_path_buf());
+    }
+
+    // Video thumbnails were made from a frame, which has no orientation.
+    let auto_orient = auto_orient && source_path == save_path;
+
+    let catalog_path = catalog_thumb_path(save_path, format);
+    create_image_thumbnail(source_path, &catalog_path, max_size, auto_orient)
+}
+
+/// Save a thumbnail to `thumb_path`, in the format given by its extension.
+/// Returns the path that the thumbnail was saved to.
+///
+/// If the image library can't encode WebP, the thumbnail is saved as a PNG
+/// next to `thumb_path` instead.
+fn save_thumbnail(thumb: &DynamicImage, thumb_path: &Path) -> Result<PathBuf> {
+    let is_webp = thumb_path.extension().map_or(false, |ext| ext == "webp");
+
+    match thumb.save(thumb_path) {
+        Ok(()) => Ok(thumb_path.to_path_buf()),
+        Err(ImageError::Unsupported(err)) if is_webp => {
+            warn!("Couldn't save WebP thumbnail, saving PNG instead: {}", err);
+
+            let png_path = thumb_path.with_extension("png");
+            thumb.save(&png_path)?;
+
+            Ok(png_path)
         }
+        Err(err) => Err(Error::from(err)),
     }
+}
 
-    Ok(thumb_path)
+/// Read the EXIF orientation of an image, if it has one.
+fn exif_orientation(path: &Path) -> Option<u32> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
 }
 
-fn create_image_thumbnail<P1, P2>(source_path: P1, thumb_path: P2) -> Result<()>
+/// Rotate and flip an image so that it's upright, given its EXIF orientation.
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Scale an image down to fit within `max_size` and save it to `thumb_path`.
+/// Returns the path that the thumbnail was saved to, see `save_thumbnail`.
+fn create_image_thumbnail<P1, P2>(
+    source_path: P1,
+    thumb_path: P2,
+    max_size: u32,
+    auto_orient: bool,
+) -> Result<PathBuf>
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
@@ -267,16 +662,25 @@ where
 
     let format = ImageFormat::from_path(source_path)?;
 
-    let image = image::load(BufReader::new(source_file), format)?;
+    let mut image = image::load(BufReader::new(source_file), format)?;
 
-    let thumb = image.thumbnail(200, 200);
+    if auto_orient {
+        if let Some(orientation) = exif_orientation(source_path) {
+            image = apply_orientation(image, orientation);
+        }
+    }
 
-    thumb.save(&thumb_path)?;
+    let thumb = image.thumbnail(max_size, max_size);
 
-    Ok(())
+    save_thumbnail(&thumb, thumb_path)
 }
 
-fn create_video_thumbnail<P1, P2>(source_path: P1, thumb_path: P2) -> Result<()>
+/// Make a thumbnail of a video from a frame near its start. Returns the path
+/// that the thumbnail was saved to, see `save_thumbnail`.
+fn create_video_thumbnail<P1, P2>(
+    source_path: P1,
+    thumb_path: P2,
+) -> Result<PathBuf>
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
@@ -284,15 +688,20 @@ where
     let source_path = source_path.as_ref();
     let thumb_path = thumb_path.as_ref();
 
+    // The frame is always grabbed as a PNG, since ffmpeg may not be able to
+    // write the thumbnail format.
+    let frame_path = thumb_path.with_extension("png");
+
     // First, use ffmpeg to grab a still image from the start of the video.
     let output = Command::new("ffmpeg")
+        .arg("-y")
         .arg("-i")
         .arg(source_path)
         .arg("-ss")
         .arg("00:00:00.69")
         .arg("-vframes")
         .arg("1")
-        .arg(thumb_path)
+        .arg(&frame_path)
         .output()
         .map_err(|cause| Error::IoErrorMsg {
             cause,
@@ -310,291 +719,2452 @@ where
     }
 
     // Then, re-size that image into a thumbnail.
-    create_image_thumbnail(thumb_path, thumb_path)?;
+    let thumb_path =
+        create_image_thumbnail(&frame_path, thumb_path, THUMB_MAX_SIZE, false)?;
 
-    Ok(())
+    if thumb_path != frame_path {
+        std::fs::remove_file(&frame_path).map_err(|cause| Error::IoErrorMsg {
+            cause,
+            msg: format!(
+                "Couldn't remove video frame {}",
+                frame_path.display()
+            ),
+        })?;
+    }
+
+    Ok(thumb_path)
 }
 
-/// Handle a request to create a new thread.
-#[post("/<board_name>", data = "<entries>", rank = 1)]
-pub fn new_thread(
-    board_name: String,
-    entries: Result<MultipartEntries>,
-    conf: Conf,
-    mut db: PooledConnection,
-    user: User,
-    session: Option<Session>,
-    _not_blocked: NotBlocked,
-) -> Result<Redirect> {
-    let entries = entries?;
+/// Convert a video to `format` with ffmpeg, so that browsers can play it.
+/// Returns the path of the converted video, which is saved next to the
+/// original.
+pub fn transcode_video<P>(
+    source_path: P,
+    format: TranscodeFormat,
+) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let source_path = source_path.as_ref();
+
+    let stem = source_path
+        .file_stem()
+        .expect("bad filename for save path")
+        .to_string_lossy();
+    let upload_dir = source_path.parent().expect("bad save path");
+
+    // The name is taken before ffmpeg runs, so that the video can't overwrite
+    // another upload. ffmpeg then overwrites the empty file.
+    let (output_path, _) = create_upload_file(
+        upload_dir,
+        &format!("{}-web", stem),
+        format.extension(),
+    )?;
+
+    let output = Command::new("ffmpeg")
+        .arg("-y")
+        .arg("-i")
+        .arg(source_path)
+        .args(format.ffmpeg_args())
+        .arg(&output_path)
+        .output()
+        .map_err(|cause| Error::IoErrorMsg {
+            cause,
+            msg: "Error running ffmpeg".into(),
+        })?;
+
+    if !output.status.success() {
+        // Don't leave a partly written video behind.
+        let _ = std::fs::remove_file(&output_path);
+
+        return Err(Error::FfmpegError {
+            status: output.status,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+
+    Ok(output_path)
+}
 
-    if db.board(&board_name).is_err() {
-        return Err(Error::BoardNotFound { board_name });
+/// Decide whether an uploaded file should be marked as a spoiler.
+///
+/// `requested` is whether the user checked the spoiler option on the form.
+fn is_spoiler(conf: &Conf, requested: bool) -> Result<bool> {
+    if conf.force_spoiler {
+        return Ok(true);
     }
 
-    let new_thread_id =
-        db.create_thread(board_name.clone(), entries, conf, user, session)?;
+    if !requested && !conf.spoiler_optional {
+        return Err(Error::SpoilerRequired);
+    }
 
-    Ok(Redirect::to(uri!(
-        crate::routes::thread: board_name,
-        new_thread_id
-    )))
+    Ok(requested)
 }
 
-/// Handle a request to create a new post.
-#[post("/<board_name>/<thread_id>", data = "<entries>", rank = 1)]
-pub fn new_post(
-    board_name: String,
-    thread_id: ThreadId,
-    entries: Result<MultipartEntries>,
-    conf: Conf,
-    mut db: PooledConnection,
-    user: User,
-    session: Option<Session>,
-    _not_blocked: NotBlocked,
-) -> Result<FragmentRedirect> {
-    let entries = entries?;
+/// Check that a post's file can be uploaded, if it has one.
+fn check_upload(
+    conf: &Conf,
+    has_file: bool,
+    spoiler_requested: bool,
+) -> Result<()> {
+    if has_file {
+        if !conf.allow_uploads {
+            return Err(Error::FileUploadNotAllowed);
+        }
 
-    let new_post_id = db.create_post(
-        board_name.clone(),
-        thread_id,
-        entries,
-        conf,
-        user,
-        session,
-    )?;
+        is_spoiler(conf, spoiler_requested)?;
+    }
 
-    let uri = uri!(crate::routes::thread: board_name, thread_id);
-    Ok(FragmentRedirect::to(uri, new_post_id))
+    Ok(())
 }
 
-impl<C, M> Connection<C, M>
-where
-    C: InnerConnection<M> + diesel::connection::LoadConnection,
-    M: diesel::connection::TransactionManager<C>,
-{
-    /// Create a new thread.
-    ///
-    /// This function also creates a post, which will be the original post of
-    /// the new thread.
-    fn create_thread(
-        &mut self,
-        board_name: String,
-        entries: MultipartEntries,
-        conf: Conf,
-        user: User,
-        session: Option<Session>,
-    ) -> Result<ThreadId> {
-        let thread_limit = conf.thread_limit;
+/// Check whether a thread with `post_count` posts has reached the reply cap.
+///
+/// The original post doesn't count as a reply.
+fn reply_cap_reached(reply_cap: u32, post_count: u32) -> bool {
+    post_count.saturating_sub(1) >= reply_cap
+}
 
-        if entries.field("file").is_none() {
-            return Err(Error::MissingThreadParam {
-                param: "file".into(),
-            });
+/// The system post added to a thread when it's locked for reaching the reply
+/// cap.
+const REPLY_CAP_NOTICE: &str =
+    "<p>This thread has reached its reply limit and is now locked.</p>";
+
+/// Check that posting is open at the given time.
+fn check_posting_hours(conf: &Conf, now: DateTime<Utc>) -> Result<()> {
+    if let Some(hours) = conf.posting_hours {
+        if !hours.is_open(now) {
+            let reopens_at = match hours.next_open(now) {
+                Some(time) => time.format("%F %R (UTC%:z)").to_string(),
+                None => String::from("further notice"),
+            };
+
+            return Err(Error::PostingClosed { reopens_at });
         }
+    }
 
-        let subject = entries
-            .param("subject")
-            .ok_or(Error::MissingThreadParam {
-                param: "subject".into(),
-            })?
-            .to_string();
+    Ok(())
+}
+
+/// Check that a board is taking new threads.
+///
+/// This is only checked when making a thread, so replies to existing threads
+/// can still be made while new threads are closed.
+fn check_new_threads_allowed(board: &Board) -> Result<()> {
+    if !board.allow_new_threads {
+        return Err(Error::PostingClosed {
+            reopens_at: String::from("further notice"),
+        });
+    }
+
+    Ok(())
+}
+
+/// Create a token which the poster can use to delete their post in place of a
+/// delete password. Only its hash is stored, see `hash_delete_token`.
+fn new_delete_token() -> String {
+    thread_rng()
+        .sample_iter(rand::distributions::Alphanumeric)
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+/// Hash a delete token for storing with its post.
+fn hash_delete_token(token: &str) -> String {
+    let salt: [u8; 20] = thread_rng().gen();
+
+    hash_encoded(token.as_bytes(), &salt, &argon2::Config::default())
+        .expect("could not hash delete token with Argon2")
+}
+
+/// How many hours a posting form token stays valid for.
+const FORM_TOKEN_LIFETIME_HOURS: i64 = 24;
+
+/// Sign a message with HMAC-SHA256, returning the signature in hex.
+///
+/// Unlike a password hash, this is cheap enough to do for every page with a
+/// posting form.
+fn sign(secret: &str, message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Sign the time a posting form was loaded with the form secret.
+fn form_token_signature(secret: &str, time_stamp: i64) -> String {
+    sign(secret, &format!("longboard-form-token:{}", time_stamp))
+}
+
+/// Create a token for a posting form loaded at the given time.
+///
+/// The token is submitted with the form, so that we can check how long the
+/// user spent on the page.
+pub fn new_form_token(secret: &str, now: DateTime<Utc>) -> String {
+    let time_stamp = now.timestamp();
+
+    format!("{}.{}", time_stamp, form_token_signature(secret, time_stamp))
+}
+
+/// Check the token of a posting form submitted at the given time.
+///
+/// The form must have been loaded at least `min_form_time` ago, and the token
+/// must be signed with our secret.
+fn check_form_token(
+    conf: &Conf,
+    token: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let min_form_time = match conf.min_form_time {
+        Some(min_form_time) => min_form_time,
+        None => return Ok(()),
+    };
+
+    let (time_stamp, signature) = token
+        .and_then(|token| token.split_once('.'))
+        .ok_or(Error::PostedTooFast)?;
+
+    let time_stamp: i64 =
+        time_stamp.parse().map_err(|_| Error::PostedTooFast)?;
+
+    let expected = form_token_signature(conf.form_secret, time_stamp);
+
+    if !signatures_match(signature, &expected) {
+        return Err(Error::PostedTooFast);
+    }
+
+    let loaded_at = Utc
+        .timestamp_opt(time_stamp, 0)
+        .single()
+        .ok_or(Error::PostedTooFast)?;
+
+    let elapsed = now - loaded_at;
+    if elapsed < *min_form_time
+        || elapsed > Duration::hours(FORM_TOKEN_LIFETIME_HOURS)
+    {
+        return Err(Error::PostedTooFast);
+    }
+
+    Ok(())
+}
+
+/// Compare a signature against the expected one in constant time, so that it
+/// can't be guessed byte by byte.
+fn signatures_match(signature: &str, expected: &str) -> bool {
+    signature.len() == expected.len()
+        && signature
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Sign the body of a previewed post, along with the time it was previewed,
+/// with the form secret.
+///
+/// Line endings are normalized and surrounding whitespace is ignored, since
+/// browsers don't always submit a form's text exactly the way scripts see it.
+fn preview_token_signature(
+    secret: &str,
+    time_stamp: i64,
+    body: &str,
+) -> String {
+    let body = body.trim().replace("\r\n", "\n");
+
+    sign(
+        secret,
+        &format!("longboard-preview-token:{}:{}", time_stamp, body),
+    )
+}
+
+/// Create a token for a post body previewed at the given time.
+///
+/// The token is submitted with the post, so that we can check that the post
+/// was previewed, on boards where that's required.
+pub fn new_preview_token(
+    secret: &str,
+    body: &str,
+    now: DateTime<Utc>,
+) -> String {
+    let time_stamp = now.timestamp();
+
+    format!(
+        "{}.{}",
+        time_stamp,
+        preview_token_signature(secret, time_stamp, body)
+    )
+}
+
+/// When each user last previewed a post, used to rate limit previews.
+///
+/// Previews aren't stored in the database, so unlike posts they can't be rate
+/// limited by looking up the user's recent posts.
+#[derive(Debug, Default)]
+pub struct Previews {
+    last_previews: Mutex<HashMap<UserId, DateTime<Utc>>>,
+}
+
+impl Previews {
+    /// Record a preview made by a user at `now`.
+    ///
+    /// Fails if the user has already made a preview within `limit`.
+    fn start(
+        &self,
+        user_id: UserId,
+        limit: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut last_previews = self.last_previews.lock().unwrap();
+
+        // Forget previews that can't limit anyone anymore, so that this
+        // doesn't grow forever.
+        last_previews.retain(|_, previewed_at| *previewed_at + limit > now);
+
+        if last_previews.contains_key(&user_id) {
+            return Err(Error::UserRateLimitExceeded);
+        }
+
+        last_previews.insert(user_id, now);
+
+        Ok(())
+    }
+}
+
+/// Check the preview token submitted with a post on a board where posts must
+/// be previewed.
+///
+/// The token must be for the same body that's being submitted, and must be
+/// no older than a posting form token.
+fn check_preview_token(
+    conf: &Conf,
+    board_name: &str,
+    body: &str,
+    token: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    if !conf.preview_required(board_name) {
+        return Ok(());
+    }
+
+    let (time_stamp, signature) = token
+        .and_then(|token| token.split_once('.'))
+        .ok_or(Error::PreviewRequired)?;
+
+    let time_stamp: i64 =
+        time_stamp.parse().map_err(|_| Error::PreviewRequired)?;
+
+    let expected = preview_token_signature(conf.form_secret, time_stamp, body);
+
+    if !signatures_match(signature, &expected) {
+        return Err(Error::PreviewRequired);
+    }
+
+    let previewed_at = Utc
+        .timestamp_opt(time_stamp, 0)
+        .single()
+        .ok_or(Error::PreviewRequired)?;
+
+    if now - previewed_at > Duration::hours(FORM_TOKEN_LIFETIME_HOURS) {
+        return Err(Error::PreviewRequired);
+    }
+
+    Ok(())
+}
+
+/// Check that the honeypot field of a posting form was left empty.
+///
+/// `value` is the value submitted for the field, if any.
+fn check_honeypot(conf: &Conf, value: Option<&str>) -> Result<()> {
+    match (conf.honeypot_field, value) {
+        (Some(field), Some(_)) => Err(Error::HoneypotFilled {
+            field: field.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Check that enough time has passed since the newest thread on a board was
+/// made, if there is a minimum time between threads.
+fn check_thread_throttle(
+    conf: &Conf,
+    newest_thread: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    if let (Some(min_seconds), Some(newest_thread)) =
+        (conf.min_seconds_between_threads, newest_thread)
+    {
+        let next_allowed =
+            newest_thread + Duration::seconds(min_seconds.into());
+
+        if now < next_allowed {
+            return Err(Error::ThreadsThrottled {
+                wait_seconds: (next_allowed - now).num_seconds().max(1),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a post is from a logged in staff member who skips the user rate
+/// limits. See `exempt_staff`.
+fn is_exempt_staff(conf: &Conf, session: Option<&Session>) -> bool {
+    conf.exempt_staff && session.is_some()
+}
+
+/// Count the links to other sites in a new post's body, in the board's markup
+/// mode.
+fn body_link_count(conf: &Conf, board: &Board, body: &str) -> Result<usize> {
+    match board.markup_mode()? {
+        MarkupMode::Longboard => {
+            Ok(PostBody::parse(body, conf.filter_rules)?.link_count())
+        }
+        MarkupMode::SanitizedHtml => {
+            let html = sanitize_html(
+                body,
+                conf.filter_rules,
+                conf.sanitizer_allowed_tags,
+            );
+
+            Ok(sanitized_link_count(&html))
+        }
+    }
+}
+
+/// Make a subject for a thread created without one from the first non-empty
+/// line of its first post, with at most `max_chars` characters.
+fn fallback_subject(body: &str, max_chars: usize) -> String {
+    let line = body
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+
+    if line.chars().count() > max_chars {
+        let mut subject: String =
+            line.chars().take(max_chars.saturating_sub(1)).collect();
+        subject.push('…');
+        subject
+    } else {
+        line.to_string()
+    }
+}
+
+/// Check that the first post of a new thread has a file, if it needs one.
+///
+/// Threads never need a file when uploads are disabled.
+fn check_op_file(conf: &Conf, has_file: bool) -> Result<()> {
+    if conf.allow_uploads && conf.op_requires_file && !has_file {
+        return Err(Error::MissingThreadParam {
+            param: "file".into(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Get the identity and its CSS class for a staff member posting as `ident`,
+/// which is either a role or the staff member's name and role.
+///
+/// Returns `None` if the staff member is posting anonymously.
+fn staff_ident(
+    conf: &Conf,
+    staff: &Staff,
+    ident: &str,
+) -> Result<Option<(String, String)>> {
+    if ident == "Anonymous" {
+        return Ok(None);
+    }
+
+    let named_role = format!("{} ({})", staff.name, staff.role);
+
+    if ident == named_role {
+        let flair = conf.role_flair.get(staff.role);
+        let ident = format!("{} ({})", staff.name, flair.text);
+
+        return Ok(Some((ident, flair.class.clone())));
+    }
+
+    let role: Role = ident.parse()?;
+
+    if staff.is_authorized(role) {
+        let flair = conf.role_flair.get(role);
+
+        Ok(Some((flair.text.clone(), flair.class.clone())))
+    } else {
+        Err(Error::UnauthorizedRole {
+            staff_name: staff.name.clone(),
+            role,
+        })
+    }
+}
+
+/// How long a new post waits for a thumbnail to be free before giving up. See
+/// `Thumbnails`.
+const THUMBNAIL_QUEUE_TIMEOUT: StdDuration = StdDuration::from_secs(5);
+
+/// The thumbnails being generated, used to limit how many are generated at
+/// once. See `max_concurrent_thumbnails`.
+#[derive(Debug, Default)]
+pub struct Thumbnails {
+    counts: Arc<(Mutex<ThumbnailCounts>, Condvar)>,
+}
+
+/// The number of thumbnails being generated, and waiting to be generated.
+#[derive(Debug, Default)]
+struct ThumbnailCounts {
+    running: u32,
+    waiting: u32,
+}
+
+impl Thumbnails {
+    /// Start generating a thumbnail, if fewer than `max` are being generated.
+    ///
+    /// Otherwise, this waits up to `timeout` for one to finish. Only `max`
+    /// uploads can wait at once, and the rest fail right away.
+    fn start(
+        &self,
+        max: Option<u32>,
+        timeout: StdDuration,
+    ) -> Result<ThumbnailSlot> {
+        let max = match max {
+            Some(max) => max,
+            None => return Ok(ThumbnailSlot { counts: None }),
+        };
+
+        let (lock, finished) = &*self.counts;
+        let mut counts = lock.lock().unwrap();
+
+        if counts.running >= max {
+            if counts.waiting >= max {
+                return Err(Error::ServerBusy);
+            }
+
+            counts.waiting += 1;
+            let (waited, _) = finished
+                .wait_timeout_while(counts, timeout, |counts| {
+                    counts.running >= max
+                })
+                .unwrap();
+            counts = waited;
+            counts.waiting -= 1;
+
+            if counts.running >= max {
+                return Err(Error::ServerBusy);
+            }
+        }
+
+        counts.running += 1;
+
+        Ok(ThumbnailSlot {
+            counts: Some(Arc::clone(&self.counts)),
+        })
+    }
+
+    /// Wait for a thumbnail to be free if a new post has a file. See `start`.
+    ///
+    /// This is done before anything is inserted, so that a busy server doesn't
+    /// leave a thread without its first post, or a post without its file.
+    fn start_for_post(
+        &self,
+        conf: &Conf,
+        entries: &MultipartEntries,
+    ) -> Result<Option<ThumbnailSlot>> {
+        entries
+            .field("file")
+            .map(|_| {
+                self.start(
+                    conf.max_concurrent_thumbnails,
+                    THUMBNAIL_QUEUE_TIMEOUT,
+                )
+            })
+            .transpose()
+    }
+}
+
+/// A thumbnail being generated, which finishes when this is dropped.
+#[derive(Debug)]
+struct ThumbnailSlot {
+    counts: Option<Arc<(Mutex<ThumbnailCounts>, Condvar)>>,
+}
+
+impl Drop for ThumbnailSlot {
+    fn drop(&mut self) {
+        if let Some(ref counts) = self.counts {
+            let (lock, finished) = &**counts;
+
+            let mut counts = match lock.lock() {
+                Ok(counts) => counts,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+
+            counts.running = counts.running.saturating_sub(1);
+            finished.notify_one();
+        }
+    }
+}
+
+/// Handle a request to create a new thread.
+///
+/// The client is redirected to the new thread, or sent the new thread as JSON
+/// if it wants JSON (see `WantsJson`).
+#[post("/<board_name>", data = "<entries>", rank = 1)]
+pub fn new_thread(
+    board_name: String,
+    entries: Result<MultipartEntries>,
+    mut context: Context,
+    user: User,
+    session: Option<Session>,
+    wants_json: WantsJson,
+    thumbnails: State<Thumbnails>,
+    _not_blocked: NotBlocked,
+) -> Result<PostedResponse> {
+    let entries = entries?;
+    let db = &mut context.database;
+
+    let board = match db.board(&board_name) {
+        Ok(board) => board,
+        Err(_) => return Err(Error::BoardNotFound { board_name }),
+    };
+
+    let held = db.should_hold(
+        &context.conf,
+        &user,
+        session.as_ref(),
+        &board,
+        entries.param("body").unwrap_or_default(),
+    )?;
+
+    // Only clients that read the response can be given a token.
+    let delete_token =
+        (wants_json.0 && context.conf.delete_tokens).then(new_delete_token);
+
+    let _thumbnail = thumbnails.start_for_post(&context.conf, &entries)?;
+
+    let (new_thread_id, new_post_id) = db.create_thread(
+        board_name.clone(),
+        entries,
+        context.conf.clone(),
+        user,
+        session,
+        held,
+        delete_token.as_deref(),
+    )?;
+
+    let uri =
+        uri!(crate::routes::thread: board_name.as_str(), new_thread_id, _);
+
+    if wants_json.0 {
+        let created = PostCreated {
+            board: board_name,
+            thread: new_thread_id,
+            post: new_post_id,
+            delete_token,
+        };
+
+        // Posts that are waiting for approval can't be seen yet, so there's no
+        // location to give.
+        return Ok(if held {
+            PostedResponse::Accepted(Accepted(Some(created)))
+        } else {
+            PostedResponse::Created(Created(uri.to_string(), Some(created)))
+        });
+    }
+
+    if held {
+        return held_response(&board_name, &mut context);
+    }
+
+    Ok(PostedResponse::Redirect(Redirect::to(uri)))
+}
+
+/// Handle a request to create a new post.
+///
+/// The client is redirected to the new post, or sent the new post as JSON if
+/// it wants JSON (see `WantsJson`).
+#[post("/<board_name>/<thread_id>", data = "<entries>", rank = 1)]
+pub fn new_post(
+    board_name: String,
+    thread_id: ThreadId,
+    entries: Result<MultipartEntries>,
+    mut context: Context,
+    user: User,
+    session: Option<Session>,
+    wants_json: WantsJson,
+    thumbnails: State<Thumbnails>,
+    _not_blocked: NotBlocked,
+) -> Result<PostedResponse> {
+    let entries = entries?;
+    let db = &mut context.database;
+
+    db.thread_on_board(&board_name, thread_id)?;
+
+    let board = db.board(&board_name)?;
+    let held = db.should_hold(
+        &context.conf,
+        &user,
+        session.as_ref(),
+        &board,
+        entries.param("body").unwrap_or_default(),
+    )?;
+
+    // Only clients that read the response can be given a token.
+    let delete_token =
+        (wants_json.0 && context.conf.delete_tokens).then(new_delete_token);
+
+    let _thumbnail = thumbnails.start_for_post(&context.conf, &entries)?;
+
+    let new_post_id = db.create_post(
+        board_name.clone(),
+        thread_id,
+        entries,
+        context.conf.clone(),
+        user,
+        session,
+        delete_token.as_deref(),
+    )?;
+
+    if held {
+        if wants_json.0 {
+            let created = PostCreated {
+                board: board_name,
+                thread: thread_id,
+                post: new_post_id,
+                delete_token,
+            };
+
+            return Ok(PostedResponse::Accepted(Accepted(Some(created))));
+        }
+
+        return held_response(&board_name, &mut context);
+    }
+
+    // Send the poster straight to the page that their post is on.
+    let page_width = context.conf.posts_per_thread_page.max(1);
+    let page_num = context.database.thread_page_of_post(
+        thread_id,
+        new_post_id,
+        page_width,
+    )?;
+
+    let uri =
+        uri!(crate::routes::thread: board_name.as_str(), thread_id, page_num);
+
+    if wants_json.0 {
+        let location = format!("{}#{}", uri, new_post_id);
+        let created = PostCreated {
+            board: board_name,
+            thread: thread_id,
+            post: new_post_id,
+            delete_token,
+        };
+
+        return Ok(PostedResponse::Created(Created(location, Some(created))));
+    }
+
+    Ok(PostedResponse::Fragment(FragmentRedirect::to(uri, new_post_id)))
+}
+
+/// The body of a post to preview.
+#[derive(FromForm)]
+pub struct PreviewData {
+    body: String,
+}
+
+/// Render the body of a post without making it.
+///
+/// Along with the HTML, this returns a token to submit with the post, which
+/// shows that it was previewed. See `require_preview`.
+#[post("/<board_name>/preview", data = "<preview_data>", rank = 0)]
+pub fn preview_body(
+    board_name: String,
+    preview_data: Form<PreviewData>,
+    mut context: Context,
+    user: User,
+    session: Option<Session>,
+    previews: State<Previews>,
+    _not_blocked: NotBlocked,
+) -> Result<BodyPreview> {
+    let conf = &context.conf;
+    let db = &mut context.database;
+
+    let board = db.board(&board_name)?;
+
+    let body = preview_data.body.as_str();
+    if body.trim().is_empty() {
+        return Err(Error::MissingPostParam {
+            param: "body".into(),
+        });
+    }
+
+    if !is_exempt_staff(conf, session.as_ref()) {
+        previews.start(user.id, *conf.rate_limit_same_user, Utc::now())?;
+    }
+
+    let (html, _, _) = db.render_body(conf, &board, body)?;
+
+    Ok(BodyPreview {
+        html,
+        token: new_preview_token(conf.form_secret, body, Utc::now()),
+    })
+}
+
+impl<C, M> Connection<C, M>
+where
+    C: InnerConnection<M> + diesel::connection::LoadConnection,
+    M: diesel::connection::TransactionManager<C>,
+{
+    /// Whether a new post from `user` should be held for approval.
+    ///
+    /// If `hold_first_posts` is set, posts are held until staff have approved
+    /// one of the user's posts. Posts with links may also be held or rejected,
+    /// see `first_link_min_posts`. Posts made by staff are never held.
+    fn should_hold(
+        &mut self,
+        conf: &Conf,
+        user: &User,
+        session: Option<&Session>,
+        board: &Board,
+        body: &str,
+    ) -> Result<bool> {
+        if session.is_some() {
+            return Ok(false);
+        }
+
+        if conf.hold_first_posts && !self.user_has_approved_posts(user.id)? {
+            return Ok(true);
+        }
+
+        self.check_first_link(conf, user, board, body)
+    }
+
+    /// Whether a new post from `user` should be held because it has links and
+    /// the user has fewer than `first_link_min_posts` posts. If those posts are
+    /// rejected instead, this returns an error.
+    ///
+    /// Only posts that aren't waiting for approval are counted, so held posts
+    /// can't be used to get past the limit. Users on the allow list can always
+    /// post links.
+    fn check_first_link(
+        &mut self,
+        conf: &Conf,
+        user: &User,
+        board: &Board,
+        body: &str,
+    ) -> Result<bool> {
+        let min_posts = match conf.first_link_min_posts {
+            Some(min_posts) => min_posts,
+            None => return Ok(false),
+        };
+
+        let allow_listed = user
+            .ip
+            .parse::<IpAddr>()
+            .map(|ip| in_ip_list(conf, conf.allow_list, ip))
+            .unwrap_or(false);
+
+        if allow_listed
+            || body_link_count(conf, board, body)? == 0
+            || self.approved_post_count(user.id)? >= min_posts
+        {
+            return Ok(false);
+        }
+
+        match conf.first_link_action {
+            FirstLinkAction::Hold => Ok(true),
+            FirstLinkAction::Reject => {
+                Err(Error::FirstLinkRejected { min_posts })
+            }
+        }
+    }
+
+    /// Render the body of a new post on `board` to HTML, in the board's markup
+    /// mode. Along with the HTML, this returns the truncated HTML and the
+    /// serialized embeds of the post, if it has any.
+    fn render_body(
+        &mut self,
+        conf: &Conf,
+        board: &Board,
+        body_param: &str,
+    ) -> Result<(String, Option<String>, Option<String>)> {
+        match board.markup_mode()? {
+            MarkupMode::Longboard => {
+                let limits = LineLimits {
+                    max_lines: conf.max_lines,
+                    max_quote_lines: conf.max_quote_lines,
+                };
+
+                let mut body = PostBody::parse_limited(
+                    body_param,
+                    conf.filter_rules,
+                    limits,
+                )?;
+                body.apply_line_break_mode(conf.line_break_mode);
+                body.resolve_refs(
+                    self,
+                    conf.board_id_prefixes,
+                    conf.ref_snippets,
+                );
+
+                let options = RenderOptions {
+                    link_rel: conf.link_rel,
+                    link_target: conf.link_target,
+                    link_display_chars: conf.link_display_chars,
+                };
+
+                let truncated_html = body.render_truncated(
+                    CollapseLimits {
+                        lines: conf.post_collapse_lines,
+                        chars: conf.post_collapse_chars,
+                    },
+                    options,
+                );
+
+                let embeds = if board.allow_embeds {
+                    body.embeds(conf.embed_hosts)
+                } else {
+                    Vec::new()
+                };
+
+                let embeds = if embeds.is_empty() {
+                    None
+                } else {
+                    Some(
+                        serde_json::to_string(&embeds)
+                            .expect("could not serialize embeds"),
+                    )
+                };
+
+                Ok((body.into_html(options), truncated_html, embeds))
+            }
+            MarkupMode::SanitizedHtml => {
+                let body_html = sanitize_html(
+                    body_param,
+                    conf.filter_rules,
+                    conf.sanitizer_allowed_tags,
+                );
+
+                Ok((body_html, None, None))
+            }
+        }
+    }
+
+    /// Check that a post with `body_html` isn't the same as or similar to a
+    /// recent post. Exempt staff can always post.
+    fn check_content_rate_limit(
+        &mut self,
+        conf: &Conf,
+        body_html: &str,
+        exempt: bool,
+    ) -> Result<()> {
+        if exempt {
+            return Ok(());
+        }
+
+        let limit = *conf.rate_limit_same_content;
+        if self.content_rate_limit_exceeded(body_html, limit)? {
+            return Err(Error::ContentRateLimitExceeded);
+        }
+
+        if let Some(threshold) = conf.content_similarity {
+            if self
+                .similar_recent_content_exists(body_html, limit, threshold)?
+            {
+                return Err(Error::ContentRateLimitExceeded);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create a new thread.
+    ///
+    /// This function also creates a post, which will be the original post of
+    /// the new thread. If `held` is set, the thread isn't shown until its
+    /// first post is approved. Returns the IDs of the new thread and post.
+    fn create_thread(
+        &mut self,
+        board_name: String,
+        entries: MultipartEntries,
+        conf: Conf,
+        user: User,
+        session: Option<Session>,
+        held: bool,
+        delete_token: Option<&str>,
+    ) -> Result<(ThreadId, PostId)> {
+        let thread_limit = conf.thread_limit;
+
+        check_posting_hours(&conf, Utc::now())?;
+
+        let board = self.board(&board_name)?;
+        check_new_threads_allowed(&board)?;
+
+        check_form_token(&conf, entries.param("form-token"), Utc::now())?;
+
+        let honeypot =
+            conf.honeypot_field.and_then(|field| entries.param(field));
+        check_honeypot(&conf, honeypot)?;
+
+        if conf.min_seconds_between_threads.is_some() {
+            let newest_thread = self.newest_thread_time(&board_name)?;
+            check_thread_throttle(&conf, newest_thread, Utc::now())?;
+        }
+
+        let open_thread_limit = conf
+            .open_thread_limit(&board_name)
+            .filter(|_| !is_exempt_staff(&conf, session.as_ref()));
+
+        if let Some(max_open_threads) = open_thread_limit {
+            let open_threads =
+                self.user_thread_count_on_board(user.id, &board_name)?;
+
+            if open_threads >= max_open_threads {
+                return Err(Error::TooManyOpenThreads { max_open_threads });
+            }
+        }
+
+        check_op_file(&conf, entries.field("file").is_some())?;
+
+        // These are checked again for the post, but a thread shouldn't be made
+        // for a post that won't be.
+        check_upload(
+            &conf,
+            entries.field("file").is_some(),
+            entries.param("spoiler").is_some(),
+        )?;
+        check_preview_token(
+            &conf,
+            &board_name,
+            entries.param("body").unwrap_or_default(),
+            entries.param("preview-token"),
+            Utc::now(),
+        )?;
+
+        let subject = match (entries.param("subject"), entries.param("body")) {
+            (Some(subject), _) => subject.to_string(),
+            (None, Some(body)) if conf.subject_optional => {
+                fallback_subject(body, conf.subject_fallback_chars as usize)
+            }
+            _ => {
+                return Err(Error::MissingThreadParam {
+                    param: "subject".into(),
+                })
+            }
+        };
+
+        if let Some(cooldown) = conf.duplicate_subject_cooldown {
+            if let Some(thread) = self.recent_thread_with_subject(
+                &board_name,
+                &subject,
+                *cooldown,
+            )? {
+                return Err(Error::DuplicateThread {
+                    existing_uri: Some(thread.uri()),
+                });
+            }
+
+            if self.recently_deleted_subject(&board_name, &subject, *cooldown)?
+            {
+                return Err(Error::DuplicateThread { existing_uri: None });
+            }
+        }
+
+        if conf.dedup_threads && entries.param("force").is_none() {
+            if let Some(thread) =
+                self.find_similar_thread(&board_name, &subject)?
+            {
+                return Err(Error::SimilarThreadExists {
+                    thread_uri: thread.uri(),
+                });
+            }
+        }
+
+        // Like the preview token, the body is checked again when the post is
+        // made, but it has to pass before the thread is made.
+        let body_param = entries
+            .param("body")
+            .filter(|body| !body.trim().is_empty())
+            .ok_or(Error::MissingPostParam {
+                param: "body".into(),
+            })?;
+        let (body_html, _, _) = self.render_body(&conf, &board, body_param)?;
+        let exempt = is_exempt_staff(&conf, session.as_ref());
+        self.check_content_rate_limit(&conf, &body_html, exempt)?;
+
+        let cyclical =
+            conf.allow_cyclical && entries.param("cyclical").is_some();
 
         let new_thread_id = self.insert_thread(NewThread {
             subject,
             board: board_name.clone(),
             locked: false,
             pinned: false,
+            cyclical,
+            pending: held,
         })?;
 
-        self.create_post(
+        let new_post_id = self.create_post(
             board_name.clone(),
             new_thread_id,
             entries,
             conf,
             user,
             session,
+            delete_token,
+        )?;
+
+        // A held thread isn't shown yet, so it mustn't push shown threads off
+        // of the board.
+        if !held {
+            self.trim_board(&board_name, thread_limit)?;
+        }
+
+        Ok((new_thread_id, new_post_id))
+    }
+
+    /// Crate a new post.
+    ///
+    /// If the post has an attatched file, the file is also created. Posts from
+    /// users that need approval are held, see `should_hold`. If
+    /// `delete_token` is set, the post can be deleted with it as well as with
+    /// its delete password.
+    fn create_post(
+        &mut self,
+        board_name: String,
+        thread_id: ThreadId,
+        entries: MultipartEntries,
+        conf: Conf,
+        user: User,
+        session: Option<Session>,
+        delete_token: Option<&str>,
+    ) -> Result<PostId>
+    where
+        C: InnerConnection<M> + diesel::connection::LoadConnection,
+        M: diesel::connection::TransactionManager<C>,
+    {
+        check_posting_hours(&conf, Utc::now())?;
+
+        check_form_token(&conf, entries.param("form-token"), Utc::now())?;
+
+        let honeypot =
+            conf.honeypot_field.and_then(|field| entries.param(field));
+        check_honeypot(&conf, honeypot)?;
+
+        let exempt = is_exempt_staff(&conf, session.as_ref());
+
+        let limit = *conf.rate_limit_same_user;
+        if !exempt && self.user_rate_limit_exceeded(user.id, limit)? {
+            return Err(Error::UserRateLimitExceeded);
+        }
+
+        check_upload(
+            &conf,
+            entries.field("file").is_some(),
+            entries.param("spoiler").is_some(),
+        )?;
+
+        let body_param = entries
+            .param("body")
+            .filter(|body| !body.trim().is_empty())
+            .ok_or(Error::MissingPostParam {
+                param: "body".into(),
+            })?;
+
+        check_preview_token(
+            &conf,
+            &board_name,
+            body_param,
+            entries.param("preview-token"),
+            Utc::now(),
+        )?;
+
+        let board = self.board(&board_name)?;
+
+        let held = self.should_hold(
+            &conf,
+            &user,
+            session.as_ref(),
+            &board,
+            body_param,
+        )?;
+
+        let (body_html, truncated_html, embeds) =
+            self.render_body(&conf, &board, body_param)?;
+
+        self.check_content_rate_limit(&conf, &body_html, exempt)?;
+
+        let author_name = if let Some(param) = entries.param("author") {
+            param.to_string()
+        } else {
+            conf.choose_name()?
+        };
+
+        // TODO: actually parse if this is an email, domain, ...
+        let author_contact = entries.param("contact").map(ToString::to_string);
+
+        let (author_ident, author_ident_class) = match entries.param("ident") {
+            Some(ident) => {
+                let salt: [u8; 20] = thread_rng().gen();
+                let hash = hash_encoded(
+                    ident.as_bytes(),
+                    &salt,
+                    &argon2::Config::default(),
+                )
+                .expect("could not hash ident with Argon2");
+
+                (Some(hash), None)
+            }
+            None => match (session, entries.param("staff-ident")) {
+                (Some(session), Some(ident)) => {
+                    staff_ident(&conf, &session.staff, ident)?.unzip()
+                }
+                _ => (None, None),
+            },
+        };
+
+        let delete_hash = entries.param("delete-pass").map(|pass| {
+            let salt = b"longboard-delete";
+
+            hash_encoded(pass.as_bytes(), salt, &argon2::Config::default())
+                .expect("could not hash delete password with Argon2")
+        });
+        let delete_token_hash = delete_token.map(hash_delete_token);
+
+        let no_bump = entries.param("no-bump").is_some();
+
+        let new_post = NewPost {
+            body: body_html,
+            author_name,
+            author_contact,
+            author_ident,
+            delete_hash,
+            thread: thread_id,
+            board: board_name,
+            user_id: user.id,
+            no_bump,
+            system: false,
+            author_ident_class,
+            truncated_html,
+            embeds,
+            pending: false,
+            delete_token_hash,
+        };
+
+        if held {
+            let new_post_id = self.insert_pending_post(new_post)?;
+
+            if entries.field("file").is_some() {
+                self.create_file(new_post_id, entries, conf)?;
+            }
+
+            return Ok(new_post_id);
+        }
+
+        let new_post_id = self.insert_post(new_post)?;
+
+        if !no_bump {
+            self.bump_thread(thread_id)?;
+        }
+
+        if let Some(reply_cap) = conf.reply_cap {
+            self.apply_reply_cap(thread_id, reply_cap)?;
+        }
+
+        if entries.field("file").is_some() {
+            self.create_file(new_post_id, entries, conf)?;
+        };
+
+        Ok(new_post_id)
+    }
+
+    /// Trim the oldest replies from a cyclical thread, or lock any other thread
+    /// that has reached the reply cap and leave a system post saying so.
+    fn apply_reply_cap(
+        &mut self,
+        thread_id: ThreadId,
+        reply_cap: u32,
+    ) -> Result<()> {
+        let thread = self.thread(thread_id)?;
+        let post_count = thread.post_count.try_into().unwrap();
+
+        if thread.cyclical {
+            self.trim_thread(thread_id, reply_cap)?;
+        } else if !thread.locked && reply_cap_reached(reply_cap, post_count) {
+            self.lock_thread(thread_id)?;
+            self.insert_system_post(thread_id, REPLY_CAP_NOTICE)?;
+        }
+
+        Ok(())
+    }
+
+    /// Create a new file for a post.
+    fn create_file(
+        &mut self,
+        post_id: PostId,
+        entries: MultipartEntries,
+        conf: Conf,
+    ) -> Result<()> {
+        let field = entries.field("file").unwrap();
+
+        let mut content_type: Mime = match field.headers.content_type.as_ref() {
+            Some(content_type) => content_type.to_owned(),
+            None => return Err(Error::UploadMissingContentType),
+        };
+
+        if !conf.allow_file_types.contains(&content_type) {
+            return Err(Error::UploadBadContentType { content_type });
+        }
+
+        let mut orig_name = field.headers.filename.clone();
+
+        let mut save_path = save_file(
+            field,
+            &content_type,
+            orig_name.as_deref().filter(|_| conf.preserve_filename),
+            conf.upload_dir,
         )?;
 
-        self.trim_board(&board_name, thread_limit)?;
+        // This happens while the post holds a thumbnail slot, so conversions
+        // are limited along with thumbnails.
+        if let Some(format) = conf.transcode_format(&content_type) {
+            let transcoded_path = transcode_video(&save_path, format)?;
+
+            if !conf.keep_transcoded_originals {
+                std::fs::remove_file(&save_path).map_err(|cause| {
+                    Error::IoErrorMsg {
+                        cause,
+                        msg: format!(
+                            "Couldn't remove converted upload {}",
+                            save_path.display()
+                        ),
+                    }
+                })?;
+            }
+
+            orig_name = orig_name.map(|name| {
+                Path::new(&name)
+                    .with_extension(format.extension())
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            save_path = transcoded_path;
+            content_type = format.content_type();
+        }
+
+        let save_name = save_path
+            .file_name()
+            .expect("bad filename for save path")
+            .to_string_lossy()
+            .into_owned();
+
+        let thumb_path = create_thumbnail(
+            &save_path,
+            &content_type,
+            conf.thumbnail_format,
+            conf.auto_orient_images,
+        )?;
+        let thumb_name = thumb_path
+            .file_name()
+            .expect("bad thumb path")
+            .to_string_lossy()
+            .into_owned();
+
+        let catalog_thumb_name = match conf.catalog_thumb_size {
+            Some(max_size) => {
+                let catalog_path = create_catalog_thumbnail(
+                    &save_path,
+                    &thumb_path,
+                    &content_type,
+                    max_size,
+                    conf.thumbnail_format,
+                    conf.auto_orient_images,
+                )?;
+
+                Some(
+                    catalog_path
+                        .file_name()
+                        .expect("bad thumb path")
+                        .to_string_lossy()
+                        .into_owned(),
+                )
+            }
+            None => None,
+        };
+
+        let is_spoiler =
+            is_spoiler(&conf, entries.param("spoiler").is_some())?;
+
+        self.insert_file(NewFile {
+            save_name,
+            orig_name,
+            thumb_name,
+            catalog_thumb_name,
+            content_type: content_type.to_string(),
+            is_spoiler,
+            post: post_id,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use chrono::{Duration, Utc};
+
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    use tempfile::tempdir;
+
+    use super::{
+        check_form_token, check_honeypot, check_new_threads_allowed,
+        check_op_file, check_preview_token, check_thread_throttle,
+        check_upload, create_catalog_thumbnail, create_thumbnail,
+        fallback_subject, is_spoiler, new_form_token, new_preview_token,
+        reply_cap_reached, sanitize_file_stem, save_entries, save_stem,
+        staff_ident, transcode_video, MultipartEntries, Previews, Thumbnails,
+        REPLY_CAP_NOTICE,
+    };
+    use crate::config::{
+        Config, FirstLinkAction, Flair, GlobalConfig, ThumbnailFormat,
+        TranscodeFormat,
+    };
+    use crate::models::staff::{Role, Session, Staff};
+    use crate::models::{testing, Board};
+    use crate::{Error, Result};
+
+    const PNG: ThumbnailFormat = ThumbnailFormat::Png;
+
+    fn config() -> Config {
+        Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn spoiler_requested() -> Result<()> {
+        let config = config();
+        let conf = config.global();
+
+        assert!(is_spoiler(&conf, true)?);
+        assert!(!is_spoiler(&conf, false)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn spoiler_forced() -> Result<()> {
+        let mut config = config();
+        config.global_config.force_spoiler = true;
+        let conf = config.global();
+
+        assert!(is_spoiler(&conf, true)?);
+        assert!(is_spoiler(&conf, false)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn spoiler_forced_not_optional() -> Result<()> {
+        let mut config = config();
+        config.global_config.force_spoiler = true;
+        config.global_config.spoiler_optional = false;
+        let conf = config.global();
+
+        assert!(is_spoiler(&conf, false)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn spoiler_not_optional() {
+        let mut config = config();
+        config.global_config.spoiler_optional = false;
+        let conf = config.global();
+
+        assert!(matches!(
+            is_spoiler(&conf, false),
+            Err(Error::SpoilerRequired)
+        ));
+        assert!(matches!(is_spoiler(&conf, true), Ok(true)));
+    }
+
+    #[test]
+    fn reply_cap() {
+        // Just the original post.
+        assert!(!reply_cap_reached(2, 1));
+        assert!(!reply_cap_reached(2, 2));
+        assert!(reply_cap_reached(2, 3));
+        assert!(reply_cap_reached(2, 4));
+
+        assert!(reply_cap_reached(0, 1));
+    }
+
+    #[test]
+    fn op_file_required() -> Result<()> {
+        let mut config = config();
+        config.global_config.allow_uploads = true;
+        let conf = config.global();
+
+        assert!(matches!(
+            check_op_file(&conf, false),
+            Err(Error::MissingThreadParam { .. })
+        ));
+        check_op_file(&conf, true)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn op_file_not_required() -> Result<()> {
+        let mut config = config();
+        config.global_config.allow_uploads = true;
+        config.global_config.op_requires_file = false;
+        let conf = config.global();
+
+        check_op_file(&conf, false)?;
+        check_op_file(&conf, true)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn op_file_uploads_disabled() -> Result<()> {
+        let mut config = config();
+        config.global_config.allow_uploads = false;
+        let conf = config.global();
+
+        check_op_file(&conf, false)?;
+
+        Ok(())
+    }
 
-        Ok(new_thread_id)
+    #[test]
+    fn upload_checked() -> Result<()> {
+        let mut config = config();
+        config.global_config.allow_uploads = true;
+        config.global_config.spoiler_optional = false;
+        let conf = config.global();
+
+        check_upload(&conf, false, false)?;
+        check_upload(&conf, true, true)?;
+        assert!(matches!(
+            check_upload(&conf, true, false),
+            Err(Error::SpoilerRequired)
+        ));
+
+        config.global_config.allow_uploads = false;
+        let conf = config.global();
+
+        check_upload(&conf, false, false)?;
+        assert!(matches!(
+            check_upload(&conf, true, true),
+            Err(Error::FileUploadNotAllowed)
+        ));
+
+        Ok(())
     }
 
-    /// Crate a new post.
-    ///
-    /// If the post has an attatched file, the file is also created.
-    fn create_post(
-        &mut self,
-        board_name: String,
-        thread_id: ThreadId,
-        entries: MultipartEntries,
-        conf: Conf,
-        user: User,
-        session: Option<Session>,
-    ) -> Result<PostId>
-    where
-        C: InnerConnection<M> + diesel::connection::LoadConnection,
-        M: diesel::connection::TransactionManager<C>,
-    {
-        if self.user_rate_limit_exceeded(user.id, *conf.rate_limit_same_user)? {
-            return Err(Error::UserRateLimitExceeded);
-        }
+    #[test]
+    fn subject_from_body() {
+        let body = "\n  first line \nsecond line";
+        assert_eq!(fallback_subject(body, 20), "first line");
+        assert_eq!(fallback_subject("a long first line", 8), "a long …");
+        assert_eq!(fallback_subject("猫猫猫猫", 4), "猫猫猫猫");
+        assert_eq!(fallback_subject("猫猫猫猫猫", 4), "猫猫猫…");
+    }
 
-        if entries.field("file").is_some() && !conf.allow_uploads {
-            return Err(Error::FileUploadNotAllowed);
+    fn staff(role: Role) -> Staff {
+        Staff {
+            name: "alice".into(),
+            password_hash: String::new(),
+            role,
         }
+    }
 
-        let body_param = entries
-            .param("body")
-            .filter(|body| !body.trim().is_empty())
-            .ok_or(Error::MissingPostParam {
-                param: "body".into(),
-            })?;
+    #[test]
+    fn role_flair() -> Result<()> {
+        let mut config = config();
+        config.global_config.role_flair.administrator = Flair {
+            text: "## Admin".into(),
+            class: "flair-admin".into(),
+        };
+        let conf = config.global();
+
+        let admin = staff(Role::Administrator);
+
+        assert_eq!(
+            staff_ident(&conf, &admin, "Janitor")?,
+            Some(("Janitor".into(), "role-janitor".into()))
+        );
+        assert_eq!(
+            staff_ident(&conf, &admin, "Moderator")?,
+            Some(("Moderator".into(), "role-moderator".into()))
+        );
+        assert_eq!(
+            staff_ident(&conf, &admin, "Administrator")?,
+            Some(("## Admin".into(), "flair-admin".into()))
+        );
+        assert_eq!(
+            staff_ident(&conf, &admin, "alice (Administrator)")?,
+            Some(("alice (## Admin)".into(), "flair-admin".into()))
+        );
+        assert_eq!(staff_ident(&conf, &admin, "Anonymous")?, None);
+
+        Ok(())
+    }
 
-        let mut body = PostBody::parse(body_param, conf.filter_rules)?;
-        body.resolve_refs(self);
+    #[test]
+    fn role_flair_unauthorized() {
+        let config = config();
+        let conf = config.global();
 
-        let body_html = body.into_html();
+        let janitor = staff(Role::Janitor);
 
-        let limit = *conf.rate_limit_same_content;
-        if self.content_rate_limit_exceeded(&body_html, limit)? {
-            return Err(Error::ContentRateLimitExceeded);
-        }
+        assert!(matches!(
+            staff_ident(&conf, &janitor, "Moderator"),
+            Err(Error::UnauthorizedRole { .. })
+        ));
+    }
 
-        let author_name = if let Some(param) = entries.param("author") {
-            param.to_string()
-        } else {
-            conf.choose_name()?
+    /// A multipart body with one file of `file_size` bytes.
+    fn multipart_body(file_size: usize) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(b"--boundary\r\n");
+        body.extend_from_slice(
+            b"Content-Disposition: form-data; name=\"file\"; \
+              filename=\"big.bin\"\r\n",
+        );
+        body.extend_from_slice(
+            b"Content-Type: application/octet-stream\r\n\r\n",
+        );
+        body.extend_from_slice(&vec![0u8; file_size]);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+        body
+    }
+
+    #[test]
+    fn aborted_upload_removes_temp_files() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        let body = multipart_body(4096);
+
+        let result = save_entries(
+            &body[..],
+            "boundary",
+            1024,
+            u64::MAX,
+            Some(temp_dir.path()),
+        );
+        assert!(matches!(result, Err(Error::UploadTooBig { .. })));
+
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn upload_size_limits() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let temp_dir = Some(dir.path());
+
+        let body = multipart_body(4096);
+
+        // Under both limits.
+        let entries =
+            save_entries(&body[..], "boundary", 8192, 16384, temp_dir)?;
+        assert!(entries.fields.contains_key("file"));
+
+        // The file is too big, but the request isn't.
+        assert!(matches!(
+            save_entries(&body[..], "boundary", 1024, 16384, temp_dir),
+            Err(Error::UploadTooBig { size_limit: 1024 })
+        ));
+
+        // The file is small enough, but the request isn't.
+        assert!(matches!(
+            save_entries(&body[..], "boundary", 8192, 2048, temp_dir),
+            Err(Error::UploadTooBig { size_limit: 2048 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn form_token_accepted() -> Result<()> {
+        let mut config = config();
+        config.global_config.min_form_time = Some(Duration::seconds(5));
+        let conf = config.global();
+
+        let loaded_at = Utc::now();
+        let token = new_form_token(conf.form_secret, loaded_at);
+        let now = loaded_at + Duration::seconds(10);
+
+        check_form_token(&conf, Some(&token), now)
+    }
+
+    #[test]
+    fn form_token_too_fast() {
+        let mut config = config();
+        config.global_config.min_form_time = Some(Duration::seconds(5));
+        let conf = config.global();
+
+        let loaded_at = Utc::now();
+        let token = new_form_token(conf.form_secret, loaded_at);
+        let now = loaded_at + Duration::seconds(1);
+
+        assert!(matches!(
+            check_form_token(&conf, Some(&token), now),
+            Err(Error::PostedTooFast)
+        ));
+    }
+
+    #[test]
+    fn form_token_expired() {
+        let mut config = config();
+        config.global_config.min_form_time = Some(Duration::seconds(5));
+        let conf = config.global();
+
+        let loaded_at = Utc::now();
+        let token = new_form_token(conf.form_secret, loaded_at);
+        let now = loaded_at + Duration::days(2);
+
+        assert!(matches!(
+            check_form_token(&conf, Some(&token), now),
+            Err(Error::PostedTooFast)
+        ));
+    }
+
+    #[test]
+    fn form_token_tampered() {
+        let mut config = config();
+        config.global_config.min_form_time = Some(Duration::seconds(5));
+        let conf = config.global();
+
+        let now = Utc::now();
+        let token = new_form_token(conf.form_secret, now);
+        let (_, signature) = token.split_once('.').unwrap();
+        let earlier = now - Duration::seconds(10);
+        let forged = format!("{}.{}", earlier.timestamp(), signature);
+
+        assert!(matches!(
+            check_form_token(&conf, Some(&forged), now),
+            Err(Error::PostedTooFast)
+        ));
+        assert!(matches!(
+            check_form_token(&conf, None, now),
+            Err(Error::PostedTooFast)
+        ));
+    }
+
+    #[test]
+    fn form_token_not_required() -> Result<()> {
+        let config = config();
+        let conf = config.global();
+
+        check_form_token(&conf, None, Utc::now())
+    }
+
+    #[test]
+    fn preview_token() -> Result<()> {
+        let mut config = config();
+        config.global_config.require_preview = vec!["tech".to_string()];
+        let conf = config.global();
+
+        let body = "hi\nthere";
+        let previewed_at = Utc::now();
+        let token = new_preview_token(conf.form_secret, body, previewed_at);
+        let now = previewed_at + Duration::minutes(5);
+
+        // Line endings and surrounding whitespace don't matter.
+        let submitted = "hi\r\nthere\n";
+        check_preview_token(&conf, "tech", submitted, Some(&token), now)?;
+
+        assert!(matches!(
+            check_preview_token(&conf, "tech", "edited", Some(&token), now),
+            Err(Error::PreviewRequired)
+        ));
+        assert!(matches!(
+            check_preview_token(&conf, "tech", body, None, now),
+            Err(Error::PreviewRequired)
+        ));
+
+        let later = previewed_at + Duration::days(2);
+        assert!(matches!(
+            check_preview_token(&conf, "tech", body, Some(&token), later),
+            Err(Error::PreviewRequired)
+        ));
+
+        // Other boards don't need a preview.
+        check_preview_token(&conf, "meta", "hi", None, now)
+    }
+
+    #[test]
+    fn preview_rate_limit() {
+        let previews = Previews::default();
+        let limit = Duration::seconds(10);
+        let now = Utc::now();
+
+        assert!(previews.start(1, limit, now).is_ok());
+        assert!(matches!(
+            previews.start(1, limit, now + Duration::seconds(5)),
+            Err(Error::UserRateLimitExceeded)
+        ));
+
+        // Other users have their own limit.
+        assert!(previews.start(2, limit, now).is_ok());
+
+        assert!(previews.start(1, limit, now + Duration::seconds(10)).is_ok());
+
+        // Without a limit, previews can be made as often as users like.
+        assert!(previews.start(3, Duration::zero(), now).is_ok());
+        assert!(previews.start(3, Duration::zero(), now).is_ok());
+    }
+
+    #[test]
+    fn honeypot_filled() {
+        let mut config = config();
+        config.global_config.honeypot_field = Some("email2".to_string());
+        let conf = config.global();
+
+        assert!(check_honeypot(&conf, None).is_ok());
+        assert!(matches!(
+            check_honeypot(&conf, Some("spam@example.com")),
+            Err(Error::HoneypotFilled { .. })
+        ));
+    }
+
+    #[test]
+    fn thread_throttle() -> Result<()> {
+        let mut config = config();
+        config.global_config.min_seconds_between_threads = Some(60);
+        let conf = config.global();
+
+        let newest = Utc::now();
+
+        check_thread_throttle(&conf, None, newest)?;
+        let later = newest + Duration::hours(1);
+        check_thread_throttle(&conf, Some(newest), later)?;
+
+        assert!(matches!(
+            check_thread_throttle(
+                &conf,
+                Some(newest),
+                newest + Duration::seconds(15)
+            ),
+            Err(Error::ThreadsThrottled { wait_seconds: 45 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn thread_throttle_disabled() -> Result<()> {
+        let config = config();
+        let conf = config.global();
+
+        let now = Utc::now();
+
+        check_thread_throttle(&conf, Some(now), now)
+    }
+
+    #[test]
+    fn new_threads_closed() -> Result<()> {
+        let mut board = Board {
+            name: String::from("tech"),
+            description: String::from("Technology"),
+            markup_mode: String::from("longboard"),
+            allow_embeds: false,
+            allow_new_threads: true,
+            listed: true,
+            nsfw: false,
         };
 
-        // TODO: actually parse if this is an email, domain, ...
-        let author_contact = entries.param("contact").map(ToString::to_string);
+        check_new_threads_allowed(&board)?;
 
-        let author_ident = match entries.param("ident") {
-            Some(ident) => {
-                let salt: [u8; 20] = thread_rng().gen();
-                let hash = hash_encoded(
-                    ident.as_bytes(),
-                    &salt,
-                    &argon2::Config::default(),
+        board.allow_new_threads = false;
+
+        assert!(matches!(
+            check_new_threads_allowed(&board),
+            Err(Error::PostingClosed { .. })
+        ));
+
+        Ok(())
+    }
+
+    /// Form data with the given text fields.
+    fn form_entries(fields: &[(&str, &str)]) -> MultipartEntries {
+        let mut body = Vec::new();
+
+        for (name, value) in fields {
+            body.extend_from_slice(b"--boundary\r\n");
+            body.extend_from_slice(
+                format!(
+                    "Content-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                    name, value
                 )
-                .expect("could not hash ident with Argon2");
+                .as_bytes(),
+            );
+        }
 
-                Some(hash)
-            }
-            None => {
-                if let Some(session) = session {
-                    if let Some(ident) = entries.param("staff-ident") {
-                        if ident == "Anonymous" {
-                            None
-                        } else {
-                            let named_role = format!(
-                                "{} ({})",
-                                session.staff.name, session.staff.role,
-                            );
-
-                            if ident == named_role {
-                                Some(ident.to_string())
-                            } else {
-                                let role: Role = ident.parse()?;
-
-                                if session.staff.is_authorized(role) {
-                                    Some(ident.to_string())
-                                } else {
-                                    return Err(Error::UnauthorizedRole {
-                                        staff_name: session.staff.name,
-                                        role,
-                                    });
-                                }
-                            }
-                        }
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }
+        body.extend_from_slice(b"--boundary--\r\n");
+
+        let entries =
+            save_entries(&body[..], "boundary", u64::MAX, u64::MAX, None);
+        MultipartEntries(entries.unwrap())
+    }
+
+    #[test]
+    fn rejected_op_leaves_no_thread() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
         };
 
-        let delete_hash = entries.param("delete-pass").map(|pass| {
-            let salt = b"longboard-delete";
+        let mut config = config();
+        config.global_config.max_lines = Some(2);
+
+        testing::board(&mut db, "strict");
+
+        let thread = form_entries(&[
+            ("author", "Anon"),
+            ("subject", "Too long"),
+            ("body", "one\ntwo\nthree"),
+        ]);
+        let user = testing::user(&mut db);
+        assert!(matches!(
+            db.create_thread(
+                "strict".into(),
+                thread,
+                config.global(),
+                user,
+                None,
+                false,
+                None,
+            ),
+            Err(Error::TooManyLines { max_lines: 2 })
+        ));
 
-            hash_encoded(pass.as_bytes(), salt, &argon2::Config::default())
-                .expect("could not hash delete password with Argon2")
+        assert_eq!(db.thread_page_count("strict", 10).unwrap(), 0);
+    }
+
+    #[test]
+    fn similar_op_leaves_no_thread() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        let mut config = config();
+        config.global_config.rate_limit_same_content = Duration::minutes(2);
+        config.global_config.content_similarity = Some(0.5);
+
+        testing::board(&mut db, "spam");
+
+        let thread = form_entries(&[
+            ("author", "Anon"),
+            ("subject", "First"),
+            ("body", "the quick brown fox jumps over the lazy dog"),
+        ]);
+        let user = testing::user(&mut db);
+        db.create_thread(
+            "spam".into(),
+            thread,
+            config.global(),
+            user,
+            None,
+            false,
+            None,
+        )
+        .unwrap();
+
+        let thread = form_entries(&[
+            ("author", "Anon"),
+            ("subject", "Second"),
+            ("body", "the quick brown fox jumps over the lazy cat"),
+        ]);
+        let user = testing::user(&mut db);
+        assert!(matches!(
+            db.create_thread(
+                "spam".into(),
+                thread,
+                config.global(),
+                user,
+                None,
+                false,
+                None,
+            ),
+            Err(Error::ContentRateLimitExceeded)
+        ));
+
+        assert_eq!(db.thread_page_count("spam", 1).unwrap(), 1);
+    }
+
+    #[test]
+    fn replies_while_threads_closed() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        let config = config();
+
+        testing::board(&mut db, "event");
+        let thread_id = testing::thread(&mut db, "event", false);
+        db.update_board("event", "", None, None, Some(false), None)
+            .unwrap();
+
+        let reply = form_entries(&[("author", "Anon"), ("body", "Still open")]);
+        let user = testing::user(&mut db);
+        let post_id = db
+            .create_post(
+                "event".into(),
+                thread_id,
+                reply,
+                config.global(),
+                user,
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(db.post(post_id).unwrap().thread_id, thread_id);
+
+        let thread = form_entries(&[
+            ("author", "Anon"),
+            ("subject", "New thread"),
+            ("body", "Let me in"),
+        ]);
+        let user = testing::user(&mut db);
+        assert!(matches!(
+            db.create_thread(
+                "event".into(),
+                thread,
+                config.global(),
+                user,
+                None,
+                false,
+                None,
+            ),
+            Err(Error::PostingClosed { .. })
+        ));
+    }
+
+    #[test]
+    fn small_image_is_own_thumbnail() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let content_type = "image/png".parse().unwrap();
+
+        let small_path = dir.path().join("small.png");
+        RgbImage::new(100, 100).save(&small_path)?;
+
+        assert_eq!(
+            create_thumbnail(&small_path, &content_type, PNG, true)?,
+            small_path
+        );
+        assert!(!dir.path().join("small-thumb.png").exists());
+
+        let large_path = dir.path().join("large.png");
+        RgbImage::new(400, 300).save(&large_path)?;
+
+        let thumb_path =
+            create_thumbnail(&large_path, &content_type, PNG, true)?;
+        assert_eq!(thumb_path, dir.path().join("large-thumb.png"));
+        assert!(thumb_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn catalog_thumbnails() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let content_type = "image/png".parse().unwrap();
+
+        let large_path = dir.path().join("large.png");
+        RgbImage::new(400, 300).save(&large_path)?;
+
+        let thumb_path =
+            create_thumbnail(&large_path, &content_type, PNG, true)?;
+        let catalog_path = create_catalog_thumbnail(
+            &large_path,
+            &thumb_path,
+            &content_type,
+            100,
+            PNG,
+            true,
+        )?;
+
+        assert_eq!(thumb_path, dir.path().join("large-thumb.png"));
+        assert_eq!(catalog_path, dir.path().join("large-catalog.png"));
+        assert_eq!(image::image_dimensions(&thumb_path)?, (200, 150));
+        assert_eq!(image::image_dimensions(&catalog_path)?, (100, 75));
+
+        // An image that fits is its own catalog thumbnail too.
+        let small_path = dir.path().join("small.png");
+        RgbImage::new(80, 80).save(&small_path)?;
+
+        let thumb_path =
+            create_thumbnail(&small_path, &content_type, PNG, true)?;
+        assert_eq!(
+            create_catalog_thumbnail(
+                &small_path,
+                &thumb_path,
+                &content_type,
+                100,
+                PNG,
+                true,
+            )?,
+            small_path
+        );
+        assert!(!dir.path().join("small-catalog.png").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn webp_thumbnails() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let content_type = "image/png".parse().unwrap();
+        let webp = ThumbnailFormat::Webp;
+
+        let large_path = dir.path().join("large.png");
+        RgbImage::new(400, 300).save(&large_path)?;
+
+        let thumb_path =
+            create_thumbnail(&large_path, &content_type, webp, true)?;
+        let catalog_path = create_catalog_thumbnail(
+            &large_path,
+            &thumb_path,
+            &content_type,
+            100,
+            webp,
+            true,
+        )?;
+
+        assert_eq!(thumb_path, dir.path().join("large-thumb.webp"));
+        assert_eq!(catalog_path, dir.path().join("large-catalog.webp"));
+        assert_eq!(
+            ImageFormat::from_path(&thumb_path)?,
+            image::guess_format(&std::fs::read(&thumb_path)?)?
+        );
+        assert_eq!(image::image_dimensions(&thumb_path)?, (200, 150));
+        assert_eq!(image::image_dimensions(&catalog_path)?, (100, 75));
+
+        Ok(())
+    }
+
+    #[test]
+    fn oriented_thumbnails() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let content_type = "image/jpeg".parse().unwrap();
+
+        // A photo whose top half is red and bottom half is blue, taken with
+        // the camera turned so that it has to be rotated 90° clockwise.
+        let red = Rgb([255, 0, 0]);
+        let blue = Rgb([0, 0, 255]);
+        let photo = RgbImage::from_fn(400, 200, |_, y| {
+            if y < 100 {
+                red
+            } else {
+                blue
+            }
         });
 
-        let no_bump = entries.param("no-bump").is_some();
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg).encode_image(&photo)?;
 
-        let new_post_id = self.insert_post(NewPost {
-            body: body_html,
-            author_name,
-            author_contact,
-            author_ident,
-            delete_hash,
-            thread: thread_id,
-            board: board_name,
-            user_id: user.id,
-            no_bump,
-        })?;
+        // An APP1 segment with an EXIF orientation of 6, placed right after
+        // the start of image marker.
+        let exif = [
+            0xFF, 0xE1, 0x00, 0x22, b'E', b'x', b'i', b'f', 0x00, 0x00, b'M',
+            b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08, 0x00, 0x01, 0x01, 0x12,
+            0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let jpeg = [&jpeg[..2], &exif[..], &jpeg[2..]].concat();
 
-        if !no_bump {
-            self.bump_thread(thread_id)?;
+        let photo_path = dir.path().join("photo.jpg");
+        std::fs::write(&photo_path, &jpeg)?;
+
+        let thumb_path =
+            create_thumbnail(&photo_path, &content_type, PNG, true)?;
+        let thumb = image::open(&thumb_path)?.to_rgb8();
+
+        assert_eq!(thumb.dimensions(), (100, 200));
+        assert!(thumb.get_pixel(10, 100)[2] > 200);
+        assert!(thumb.get_pixel(90, 100)[0] > 200);
+
+        let thumb_path =
+            create_thumbnail(&photo_path, &content_type, PNG, false)?;
+        assert_eq!(image::image_dimensions(&thumb_path)?, (200, 100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn transcoded_videos() -> Result<()> {
+        // Videos can only be converted where ffmpeg is installed.
+        if Command::new("ffmpeg").arg("-version").output().is_err() {
+            return Ok(());
         }
 
-        if entries.field("file").is_some() {
-            self.create_file(new_post_id, entries, conf)?;
+        let dir = tempdir().unwrap();
+        let mov_path = dir.path().join("clip.mov");
+
+        let status = Command::new("ffmpeg")
+            .args(["-f", "lavfi", "-i", "testsrc=duration=1:size=64x64"])
+            .arg(&mov_path)
+            .output()?
+            .status;
+        assert!(status.success());
+
+        let mp4_path = transcode_video(&mov_path, TranscodeFormat::Mp4)?;
+        assert_eq!(mp4_path, dir.path().join("clip-web.mp4"));
+
+        let mp4 = std::fs::read(&mp4_path)?;
+        assert_eq!(&mp4[4..8], b"ftyp");
+
+        // Converting again doesn't overwrite the first video.
+        let second_path = transcode_video(&mov_path, TranscodeFormat::Mp4)?;
+        assert_eq!(second_path, dir.path().join("clip-web-1.mp4"));
+        assert_eq!(std::fs::read(&mp4_path)?, mp4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn other_files_are_own_thumbnail() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let content_type = "application/pdf".parse().unwrap();
+
+        let pdf_path = dir.path().join("paper.pdf");
+        std::fs::write(&pdf_path, b"%PDF-1.4")?;
+
+        assert_eq!(
+            create_thumbnail(&pdf_path, &content_type, PNG, true)?,
+            pdf_path
+        );
+        assert!(!dir.path().join("paper-thumb.pdf").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sanitized_file_stems() {
+        assert_eq!(sanitize_file_stem("cat.jpg").unwrap(), "cat");
+        assert_eq!(
+            sanitize_file_stem("My Holiday (2).png").unwrap(),
+            "My-Holiday-2"
+        );
+        assert_eq!(sanitize_file_stem("../../etc/passwd").unwrap(), "passwd");
+        assert_eq!(
+            sanitize_file_stem("C:\\Users\\me\\a.b.gif").unwrap(),
+            "a-b"
+        );
+        assert_eq!(sanitize_file_stem(".bashrc").unwrap(), "bashrc");
+        assert!(sanitize_file_stem("..").is_none());
+        assert!(sanitize_file_stem("日本.png").is_none());
+        assert!(sanitize_file_stem("dir/").is_none());
+
+        let long_name = format!("{}.png", "a".repeat(100));
+        assert_eq!(sanitize_file_stem(long_name).unwrap().len(), 64);
+    }
+
+    #[test]
+    fn save_stems_are_unique() {
+        let first = save_stem(Some("cat.jpg"));
+        let second = save_stem(Some("cat.jpg"));
+
+        assert!(first.starts_with("cat-"));
+        assert_eq!(first.len(), "cat-".len() + 8);
+        assert_ne!(first, second);
+
+        assert!(save_stem(Some("..")).chars().all(|c| c.is_ascii_digit()));
+        assert!(save_stem(None).chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn thumbnail_limit() {
+        let thumbnails = Thumbnails::default();
+        let timeout = std::time::Duration::from_millis(10);
+
+        let unlimited: Vec<_> = (0..3)
+            .map(|_| thumbnails.start(None, timeout).unwrap())
+            .collect();
+        assert_eq!(unlimited.len(), 3);
+
+        let first = thumbnails.start(Some(1), timeout).unwrap();
+        assert!(matches!(
+            thumbnails.start(Some(1), timeout),
+            Err(Error::ServerBusy)
+        ));
+
+        drop(first);
+        assert!(thumbnails.start(Some(1), timeout).is_ok());
+    }
+
+    #[test]
+    fn held_first_links() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
         };
 
-        Ok(new_post_id)
+        let mut config = config();
+        config.global_config.first_link_min_posts = Some(1);
+
+        testing::board(&mut db, "links");
+        let board = db.board("links").unwrap();
+        let thread_id = testing::thread(&mut db, "links", false);
+        let user = testing::user(&mut db);
+
+        let link = "see https://example.com";
+
+        let conf = config.global();
+        assert!(!db.check_first_link(&conf, &user, &board, "hi").unwrap());
+        assert!(db.check_first_link(&conf, &user, &board, link).unwrap());
+        assert!(!db.should_hold(&conf, &user, None, &board, "hi").unwrap());
+        assert!(db.should_hold(&conf, &user, None, &board, link).unwrap());
+
+        // Held posts don't count towards the limit.
+        let held = testing::post(&mut db, "links", thread_id, &user, true);
+        assert!(db.check_first_link(&conf, &user, &board, link).unwrap());
+
+        db.approve_post(held).unwrap();
+        assert!(!db.check_first_link(&conf, &user, &board, link).unwrap());
+
+        // New users on the allow list can post links right away.
+        let new_user = testing::user(&mut db);
+
+        config.global_config.allow_list = vec!["127.0.0.1".parse().unwrap()];
+        let conf = config.global();
+        assert!(!db.check_first_link(&conf, &new_user, &board, link).unwrap());
+
+        config.global_config.allow_list = Vec::new();
+        config.global_config.first_link_action = FirstLinkAction::Reject;
+        let conf = config.global();
+        assert!(matches!(
+            db.check_first_link(&conf, &new_user, &board, link),
+            Err(Error::FirstLinkRejected { min_posts: 1 })
+        ));
+        assert!(matches!(
+            db.should_hold(&conf, &new_user, None, &board, link),
+            Err(Error::FirstLinkRejected { min_posts: 1 })
+        ));
+
+        // Staff are never held.
+        let session = Session {
+            id: String::new(),
+            expires: Utc::now() + Duration::days(1),
+            staff: Staff {
+                name: "mod".into(),
+                password_hash: String::new(),
+                role: Role::Moderator,
+            },
+        };
+        let held =
+            db.should_hold(&conf, &new_user, Some(&session), &board, link);
+        assert!(!held.unwrap());
     }
 
-    /// Create a new file for a post.
-    fn create_file(
-        &mut self,
-        post_id: PostId,
-        entries: MultipartEntries,
-        conf: Conf,
-    ) -> Result<()> {
-        let field = entries.field("file").unwrap();
+    #[test]
+    fn held_first_posts() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
 
-        let content_type: Mime = match field.headers.content_type.as_ref() {
-            Some(content_type) => content_type.to_owned(),
-            None => return Err(Error::UploadMissingContentType),
+        let mut config = config();
+        config.global_config.hold_first_posts = true;
+        let conf = config.global();
+
+        testing::board(&mut db, "first");
+        let board = db.board("first").unwrap();
+        let thread_id = testing::thread(&mut db, "first", false);
+        let user = testing::user(&mut db);
+
+        assert!(db.should_hold(&conf, &user, None, &board, "hi").unwrap());
+
+        // Another held post doesn't count as approved.
+        let held = testing::post(&mut db, "first", thread_id, &user, true);
+        assert!(db.should_hold(&conf, &user, None, &board, "hi").unwrap());
+
+        db.approve_post(held).unwrap();
+        assert!(!db.should_hold(&conf, &user, None, &board, "hi").unwrap());
+    }
+
+    #[test]
+    fn reply_cap_locks_thread() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
         };
 
-        if !conf.allow_file_types.contains(&content_type) {
-            return Err(Error::UploadBadContentType { content_type });
+        testing::board(&mut db, "capped");
+        let thread_id = testing::thread(&mut db, "capped", false);
+        let user = testing::user(&mut db);
+        let new_post = || testing::new_post("capped", thread_id, &user);
+
+        // The original post and the first reply.
+        for _ in 0..2 {
+            db.insert_post(new_post()).unwrap();
+            db.apply_reply_cap(thread_id, 2).unwrap();
+            assert!(!db.thread(thread_id).unwrap().locked);
         }
 
-        let save_path = save_file(field, &content_type, conf.upload_dir)?;
-        let save_name = save_path
-            .file_name()
-            .expect("bad filename for save path")
-            .to_string_lossy()
-            .into_owned();
+        // The reply that reaches the cap is posted, then the thread is locked.
+        db.insert_post(new_post()).unwrap();
+        db.apply_reply_cap(thread_id, 2).unwrap();
+        assert!(db.thread(thread_id).unwrap().locked);
+
+        assert!(matches!(
+            db.insert_post(new_post()),
+            Err(Error::ThreadLocked)
+        ));
+        assert!(matches!(
+            db.insert_pending_post(new_post()),
+            Err(Error::ThreadLocked)
+        ));
+    }
+
+    #[test]
+    fn reply_cap_notice() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
 
-        let orig_name = field.headers.filename.clone();
+        testing::board(&mut db, "capped");
+        let thread_id = testing::thread(&mut db, "capped", false);
+        let user = testing::user(&mut db);
 
-        let thumb_path = create_thumbnail(&save_path, &content_type)?;
-        let thumb_name = thumb_path
-            .file_name()
-            .expect("bad thumb path")
-            .to_string_lossy()
-            .into_owned();
+        for _ in 0..3 {
+            testing::post(&mut db, "capped", thread_id, &user, false);
+        }
 
-        let is_spoiler = entries.param("spoiler").is_some();
+        let bump_date = db.thread(thread_id).unwrap().bump_date;
+        db.apply_reply_cap(thread_id, 2).unwrap();
+        assert!(db.thread(thread_id).unwrap().locked);
 
-        self.insert_file(NewFile {
-            save_name,
-            orig_name,
-            thumb_name,
-            content_type: content_type.to_string(),
-            is_spoiler,
-            post: post_id,
-        })?;
+        let posts = db.posts_in_thread(thread_id).unwrap();
+        let notice = posts.last().unwrap();
+        assert!(notice.system);
+        assert_eq!(notice.body, REPLY_CAP_NOTICE);
 
-        Ok(())
+        // System posts never bump, and an already locked thread doesn't get
+        // another notice.
+        db.apply_reply_cap(thread_id, 2).unwrap();
+        assert_eq!(db.posts_in_thread(thread_id).unwrap().len(), posts.len());
+        assert_eq!(db.thread(thread_id).unwrap().bump_date, bump_date);
     }
 }
@@ -1,19 +1,126 @@
 //! Routes for user options.
 
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Utc};
+
 use rocket::http::{hyper::header::Location, Cookie, Status};
 use rocket::request::{Form, FromForm, FromRequest, Outcome};
 use rocket::{get, post, uri, Request, Response};
 
 use serde::Serialize;
 
+use crate::config::{parse_utc_offset, utc_offset_zero};
 use crate::views::{Context, OptionsPage};
 use crate::{Error, Result};
 
+/// The default format for times shown to users.
+pub const DEFAULT_TIME_FORMAT: &str = "%F %R";
+
+/// The default timezone for times shown to users.
+pub const DEFAULT_TIMEZONE: &str = "UTC";
+
+/// Describe how long ago `time` was, like "2h ago".
+///
+/// Times less than a minute ago, or in the future because of clock skew, are
+/// "just now".
+pub fn relative_time(time: &DateTime<Utc>, now: &DateTime<Utc>) -> String {
+    let elapsed = now.signed_duration_since(*time);
+
+    let minutes = elapsed.num_minutes();
+    let hours = elapsed.num_hours();
+    let days = elapsed.num_days();
+
+    if minutes < 1 {
+        String::from("just now")
+    } else if hours < 1 {
+        format!("{}m ago", minutes)
+    } else if days < 1 {
+        format!("{}h ago", hours)
+    } else if days < 30 {
+        format!("{}d ago", days)
+    } else if days < 365 {
+        format!("{}mo ago", days / 30)
+    } else {
+        format!("{}y ago", days / 365)
+    }
+}
+
+/// How times are shown to a user.
+#[derive(Clone, Debug)]
+pub struct TimeFormat {
+    format: String,
+    offset: FixedOffset,
+    relative: bool,
+}
+
+impl TimeFormat {
+    /// Create a new time format from a `strftime`-style format string and a
+    /// timezone, which is either `UTC` or an offset like `+02:00`.
+    ///
+    /// Returns `None` if either is invalid.
+    pub fn new<S1, S2>(format: S1, timezone: S2) -> Option<TimeFormat>
+    where
+        S1: Into<String>,
+        S2: AsRef<str>,
+    {
+        let format = format.into();
+
+        let invalid = format.is_empty()
+            || StrftimeItems::new(&format).any(|item| item == Item::Error);
+
+        if invalid {
+            return None;
+        }
+
+        let offset = match timezone.as_ref().trim() {
+            tz if tz.eq_ignore_ascii_case("UTC") => utc_offset_zero(),
+            tz => parse_utc_offset(tz).ok()?,
+        };
+
+        Some(TimeFormat {
+            format,
+            offset,
+            relative: false,
+        })
+    }
+
+    /// Set whether times should be shown relative to the current time.
+    pub fn with_relative(mut self, relative: bool) -> TimeFormat {
+        self.relative = relative;
+        self
+    }
+
+    /// Format a time as an absolute time.
+    pub fn format(&self, time: &DateTime<Utc>) -> String {
+        time.with_timezone(&self.offset)
+            .format(&self.format)
+            .to_string()
+    }
+
+    /// Show a time, either relative to `now` or as an absolute time.
+    pub fn show(&self, time: &DateTime<Utc>, now: &DateTime<Utc>) -> String {
+        if self.relative {
+            relative_time(time, now)
+        } else {
+            self.format(time)
+        }
+    }
+}
+
+impl Default for TimeFormat {
+    fn default() -> TimeFormat {
+        TimeFormat::new(DEFAULT_TIME_FORMAT, DEFAULT_TIMEZONE).unwrap()
+    }
+}
+
 /// Form data for user options.
 #[derive(FromForm, Serialize, Clone, Debug)]
 pub struct UserOptions {
     pub style: String,
     pub code_highlighting: bool,
+    pub time_format: String,
+    pub timezone: String,
+    pub relative_time: bool,
 }
 
 impl UserOptions {
@@ -26,8 +133,30 @@ impl UserOptions {
             )
             .path("/")
             .finish(),
+            Cookie::build("option-time-format", self.time_format)
+                .path("/")
+                .finish(),
+            Cookie::build("option-timezone", self.timezone)
+                .path("/")
+                .finish(),
+            Cookie::build(
+                "option-relative-time",
+                self.relative_time.to_string(),
+            )
+            .path("/")
+            .finish(),
         ]
     }
+
+    /// How times should be shown to the user.
+    ///
+    /// If the user's chosen format or timezone is invalid, the default format
+    /// is used.
+    pub fn time_format(&self) -> TimeFormat {
+        TimeFormat::new(self.time_format.as_str(), &self.timezone)
+            .unwrap_or_default()
+            .with_relative(self.relative_time)
+    }
 }
 
 impl Default for UserOptions {
@@ -35,6 +164,9 @@ impl Default for UserOptions {
         UserOptions {
             style: "default".into(),
             code_highlighting: true,
+            time_format: DEFAULT_TIME_FORMAT.into(),
+            timezone: DEFAULT_TIMEZONE.into(),
+            relative_time: false,
         }
     }
 }
@@ -56,6 +188,21 @@ impl<'a, 'r> FromRequest<'a, 'r> for UserOptions {
                 .map(|cookie| cookie.value().to_string())
                 .and_then(|s| s.parse::<bool>().ok())
                 .unwrap_or(UserOptions::default().code_highlighting),
+            time_format: cookies
+                .get("option-time-format")
+                .map(|cookie| cookie.value().to_string())
+                .filter(|s| !s.is_empty())
+                .unwrap_or(UserOptions::default().time_format),
+            timezone: cookies
+                .get("option-timezone")
+                .map(|cookie| cookie.value().to_string())
+                .filter(|s| !s.is_empty())
+                .unwrap_or(UserOptions::default().timezone),
+            relative_time: cookies
+                .get("option-relative-time")
+                .map(|cookie| cookie.value().to_string())
+                .and_then(|s| s.parse::<bool>().ok())
+                .unwrap_or(UserOptions::default().relative_time),
         })
     }
 }
@@ -82,3 +229,58 @@ pub fn update_options<'r>(
 
     Ok(res.finalize())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{relative_time, TimeFormat, UserOptions};
+
+    #[test]
+    fn time_format() {
+        let time = Utc.with_ymd_and_hms(2020, 4, 20, 16, 20, 0).unwrap();
+
+        assert_eq!(TimeFormat::default().format(&time), "2020-04-20 16:20");
+
+        let format = TimeFormat::new("%d/%m/%Y %H:%M", "+02:00").unwrap();
+        assert_eq!(format.format(&time), "20/04/2020 18:20");
+
+        let format = TimeFormat::new("%R", "-05:30").unwrap();
+        assert_eq!(format.format(&time), "10:50");
+
+        assert!(TimeFormat::new("%Q", "UTC").is_none());
+        assert!(TimeFormat::new("", "UTC").is_none());
+        assert!(TimeFormat::new("%F", "Mars/Olympus").is_none());
+    }
+
+    #[test]
+    fn invalid_options_use_default_format() {
+        let time = Utc.with_ymd_and_hms(2020, 4, 20, 16, 20, 0).unwrap();
+
+        let options = UserOptions {
+            time_format: "%Q".into(),
+            ..UserOptions::default()
+        };
+
+        assert_eq!(options.time_format().format(&time), "2020-04-20 16:20");
+    }
+
+    #[test]
+    fn relative() {
+        let now = Utc.with_ymd_and_hms(2020, 4, 20, 16, 20, 0).unwrap();
+        let ago = |seconds| now - Duration::seconds(seconds);
+
+        assert_eq!(relative_time(&now, &now), "just now");
+        assert_eq!(relative_time(&ago(59), &now), "just now");
+        assert_eq!(relative_time(&ago(-600), &now), "just now");
+        assert_eq!(relative_time(&ago(3 * 60), &now), "3m ago");
+        assert_eq!(relative_time(&ago(2 * 3600 + 5), &now), "2h ago");
+        assert_eq!(relative_time(&ago(3 * 86400), &now), "3d ago");
+        assert_eq!(relative_time(&ago(61 * 86400), &now), "2mo ago");
+        assert_eq!(relative_time(&ago(800 * 86400), &now), "2y ago");
+
+        let format = TimeFormat::default().with_relative(true);
+        assert_eq!(format.show(&ago(3 * 60), &now), "3m ago");
+        assert_eq!(format.format(&now), "2020-04-20 16:20");
+    }
+}
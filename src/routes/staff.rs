@@ -1,5 +1,8 @@
 //! Routes for staff member pages and actions.
 
+use std::fs::{copy, remove_file};
+use std::path::Path;
+
 use argon2::verify_encoded;
 
 use chrono::{Duration, Utc};
@@ -11,12 +14,14 @@ use rocket::request::{
     Form, FromForm, FromFormValue, FromRequest, Outcome, Request,
 };
 use rocket::response::Response;
-use rocket::{get, post, uri};
+use rocket::{catch, get, post, uri, State};
 
+use crate::config::{Conf, Config};
 use crate::models::*;
+use crate::parse::MarkupMode;
 use crate::views::staff::*;
 use crate::views::{ActionSuccessPage, Context};
-use crate::{Error, Result};
+use crate::{ApiError, Error, Result};
 
 impl<'a, 'r> FromRequest<'a, 'r> for Session {
     type Error = Error;
@@ -37,6 +42,42 @@ impl<'a, 'r> FromRequest<'a, 'r> for Session {
     }
 }
 
+impl<'a, 'r> FromRequest<'a, 'r> for ApiKey {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let mut db = request
+            .guard::<PooledConnection>()
+            .expect("expected database to be initialized");
+
+        let err = (Status::Unauthorized, Error::MissingApiKey);
+        let header = request.headers().get_one("Authorization").ok_or(err)?;
+
+        let err = (Status::Unauthorized, Error::InvalidApiKey);
+        let key = header.strip_prefix("Bearer ").ok_or(err)?;
+
+        let err = (Status::Unauthorized, Error::InvalidApiKey);
+        let api_key = db.api_key(key.trim()).map_err(|_| err)?;
+
+        Outcome::Success(api_key)
+    }
+}
+
+/// Respond with JSON when a request to an API route has no valid API key.
+///
+/// Only the `ApiKey` guard fails with 401 Unauthorized, so this catcher never
+/// sees requests for the HTML pages.
+#[catch(401)]
+pub fn unauthorized(req: &Request) -> ApiError {
+    match req.headers().get_one("Authorization") {
+        Some(_) => ApiError(Error::InvalidApiKey),
+        None => ApiError(Error::MissingApiKey),
+    }
+}
+
+/// The result of an API route.
+pub type ApiResult = std::result::Result<ApiResponse, ApiError>;
+
 /// Serve the login page for staff members.
 #[get("/staff/login")]
 pub fn login(mut context: Context, _user: User) -> Result<LoginPage> {
@@ -134,6 +175,71 @@ pub fn history(
     HistoryPage::new(&mut context)
 }
 
+/// Check that a staff member is logged in, for pages that would otherwise be
+/// served to anyone.
+fn require_session(session: Option<&Session>) -> Result<()> {
+    match session {
+        Some(_) => Ok(()),
+        None => Err(Error::NotAuthenticated),
+    }
+}
+
+/// Serve the user search page.
+///
+/// Users are found by the start of their hash or by their note. If `banned`
+/// is set to 1, only banned users are shown.
+#[get("/staff/users?<q>&<banned>&<page>")]
+pub fn users(
+    q: Option<String>,
+    banned: Option<u8>,
+    page: Option<u32>,
+    mut context: Context,
+    session: Option<Session>,
+) -> Result<UserSearchPage> {
+    require_session(session.as_ref())?;
+
+    UserSearchPage::new(
+        q.unwrap_or_default(),
+        banned.map_or(false, |banned| banned != 0),
+        page.unwrap_or(1),
+        &mut context,
+    )
+}
+
+/// Serve the page with the posts a user has made, newest first.
+#[get("/staff/user/<id>?<page>")]
+pub fn user_posts(
+    id: UserId,
+    page: Option<u32>,
+    mut context: Context,
+    session: Option<Session>,
+) -> Result<UserPostsPage> {
+    require_session(session.as_ref())?;
+
+    UserPostsPage::new(id, page.unwrap_or(1), &mut context)
+}
+
+/// Serve the effective configuration for the extension `extension`, or for
+/// the current request if no extension is given.
+#[get("/staff/config?<extension>")]
+pub fn effective_config(
+    extension: Option<String>,
+    config: State<Config>,
+    mut context: Context,
+    session: Option<Session>,
+) -> Result<EffectiveConfigPage> {
+    require_session(session.as_ref())?;
+
+    let conf = match extension {
+        Some(name) => config
+            .extension(&name)
+            .ok_or(Error::ExtensionNotFound { name })?,
+        None => context.conf.clone(),
+    };
+
+    EffectiveConfigPage::new(&conf, &mut context)
+}
+
 /// Form data for closing a report.
 #[derive(FromForm)]
 pub struct CloseReportData {
@@ -150,7 +256,7 @@ pub fn close_report(
 ) -> Result<ActionSuccessPage> {
     let CloseReportData { id, reason } = close_data.into_inner();
 
-    context.database.delete_report(id)?;
+    context.database.close_report(id)?;
 
     context.database.insert_staff_action(NewStaffAction {
         done_by: session.staff.name,
@@ -166,11 +272,84 @@ pub fn close_report(
     )?)
 }
 
+/// Form data for approving or rejecting a post that's waiting for approval.
+#[derive(FromForm)]
+pub struct PendingPostData {
+    pub id: PostId,
+    pub reason: String,
+}
+
+/// Approve a post that's waiting for approval, showing it to everyone.
+#[post("/staff/approve-post", data = "<approve_data>")]
+pub fn approve_post(
+    approve_data: Form<PendingPostData>,
+    mut context: Context,
+    session: Session,
+) -> Result<ActionSuccessPage> {
+    let PendingPostData { id, reason } = approve_data.into_inner();
+
+    context.database.approve_post(id)?;
+
+    context.database.insert_staff_action(NewStaffAction {
+        done_by: session.staff.name,
+        action: format!("Approved post {}", id),
+        reason,
+    })?;
+
+    let msg = format!("Approved post {} successfully.", id);
+    Ok(ActionSuccessPage::new(
+        msg,
+        uri!(overview).to_string(),
+        &mut context,
+    )?)
+}
+
+/// Reject a post that's waiting for approval, deleting it.
+///
+/// If the post started a thread, the thread is deleted too.
+#[post("/staff/reject-post", data = "<reject_data>")]
+pub fn reject_post(
+    reject_data: Form<PendingPostData>,
+    mut context: Context,
+    session: Session,
+) -> Result<ActionSuccessPage> {
+    let PendingPostData { id, reason } = reject_data.into_inner();
+
+    if !context.database.post(id)?.pending {
+        return Err(Error::PostNotPending { post_id: id });
+    }
+
+    let (_thread, _deleted_thread, action) = delete_as_staff(
+        &mut context.database,
+        &context.conf,
+        id,
+        &session.staff.name,
+    )?;
+
+    context.database.insert_staff_action(NewStaffAction {
+        done_by: session.staff.name,
+        action: format!("Rejected post {}: {}", id, action),
+        reason,
+    })?;
+
+    let msg = format!("Rejected post {} successfully.", id);
+    Ok(ActionSuccessPage::new(
+        msg,
+        uri!(overview).to_string(),
+        &mut context,
+    )?)
+}
+
 /// Form data for creating a board.
 #[derive(FromForm)]
 pub struct CreateBoardData {
     pub name: String,
     pub description: String,
+    pub markup_mode: String,
+    pub allow_embeds: bool,
+    pub allow_new_threads: bool,
+    pub listed: bool,
+    pub nsfw: bool,
 }
 
 /// Create a board.
@@ -180,11 +359,33 @@ pub fn create_board(
     mut context: Context,
     _session: Session,
 ) -> Result<ActionSuccessPage> {
-    let CreateBoardData { name, description } = create_data.into_inner();
+    let CreateBoardData {
+        name,
+        description,
+        markup_mode,
+        allow_embeds,
+        allow_new_threads,
+        listed,
+        nsfw,
+    } = create_data.into_inner();
+    let name = name.trim().to_lowercase();
+    let markup_mode = markup_mode.parse::<MarkupMode>()?.to_string();
 
     let msg = format!("Created board \"{}\" successfully.", name);
 
-    context.database.insert_board(Board { name, description })?;
+    let max_boards = context.conf.max_boards;
+    context.database.insert_board(
+        Board {
+            name,
+            description,
+            markup_mode,
+            allow_embeds,
+            allow_new_threads,
+            listed,
+            nsfw,
+        },
+        max_boards,
+    )?;
 
     Ok(ActionSuccessPage::new(
         msg,
@@ -198,6 +399,10 @@ pub fn create_board(
 pub struct EditBoardData {
     pub name: String,
     pub description: String,
+    pub markup_mode: Option<String>,
+    pub allow_embeds: Option<String>,
+    pub allow_new_threads: Option<String>,
+    pub listed: Option<String>,
 }
 
 /// Edit a board.
@@ -207,11 +412,42 @@ pub fn edit_board(
     mut context: Context,
     _session: Session,
 ) -> Result<ActionSuccessPage> {
-    let EditBoardData { name, description } = edit_data.into_inner();
+    let EditBoardData {
+        name,
+        description,
+        markup_mode,
+        allow_embeds,
+        allow_new_threads,
+        listed,
+    } = edit_data.into_inner();
+
+    let markup_mode = markup_mode
+        .filter(|mode| !mode.is_empty())
+        .map(|mode| mode.parse::<MarkupMode>())
+        .transpose()?;
+
+    let allow_embeds = allow_embeds
+        .filter(|allow| !allow.is_empty())
+        .map(|allow| allow == "yes");
+
+    let allow_new_threads = allow_new_threads
+        .filter(|allow| !allow.is_empty())
+        .map(|allow| allow == "yes");
+
+    let listed = listed
+        .filter(|listed| !listed.is_empty())
+        .map(|listed| listed == "yes");
 
     let msg = format!("Edited board \"{}\" successfully.", name);
 
-    context.database.update_board(name, description)?;
+    context.database.update_board(
+        name,
+        description,
+        markup_mode,
+        allow_embeds,
+        allow_new_threads,
+        listed,
+    )?;
 
     Ok(ActionSuccessPage::new(
         msg,
@@ -220,6 +456,49 @@ pub fn edit_board(
     )?)
 }
 
+/// Form data for marking a board as safe or not safe for work.
+#[derive(FromForm)]
+pub struct SetBoardNsfwData {
+    pub name: String,
+    pub nsfw: String,
+    pub reason: String,
+}
+
+/// Mark a board as safe or not safe for work.
+#[post("/staff/set-board-nsfw", data = "<nsfw_data>")]
+pub fn set_board_nsfw(
+    nsfw_data: Form<SetBoardNsfwData>,
+    mut context: Context,
+    session: Session,
+) -> Result<ActionSuccessPage> {
+    let SetBoardNsfwData { name, nsfw, reason } = nsfw_data.into_inner();
+    let nsfw = nsfw == "yes";
+
+    // Check that the board exists, since updating a missing board isn't an
+    // error.
+    context.database.board(&name)?;
+    context.database.set_board_nsfw(&name, nsfw)?;
+
+    let action = if nsfw {
+        format!("Marked board /{}/ as NSFW", name)
+    } else {
+        format!("Marked board /{}/ as safe for work", name)
+    };
+
+    context.database.insert_staff_action(NewStaffAction {
+        done_by: session.staff.name,
+        action,
+        reason,
+    })?;
+
+    let msg = format!("Updated board \"{}\" successfully.", name);
+    Ok(ActionSuccessPage::new(
+        msg,
+        uri!(overview).to_string(),
+        &mut context,
+    )?)
+}
+
 /// Form data for deleting a board.
 #[derive(FromForm)]
 pub struct DeleteBoardData {
@@ -432,7 +711,8 @@ pub fn pin(
 ) -> Result<ActionSuccessPage> {
     let ReasonData { reason } = reason_data.into_inner();
 
-    let uri = uri!(crate::routes::thread: &board_name, thread_id).to_string();
+    let uri =
+        uri!(crate::routes::thread: &board_name, thread_id, _).to_string();
 
     context.database.pin_thread(thread_id)?;
 
@@ -457,7 +737,8 @@ pub fn unpin(
 ) -> Result<ActionSuccessPage> {
     let ReasonData { reason } = reason_data.into_inner();
 
-    let uri = uri!(crate::routes::thread: &board_name, thread_id).to_string();
+    let uri =
+        uri!(crate::routes::thread: &board_name, thread_id, _).to_string();
 
     context.database.unpin_thread(thread_id)?;
 
@@ -482,7 +763,8 @@ pub fn lock(
 ) -> Result<ActionSuccessPage> {
     let ReasonData { reason } = reason_data.into_inner();
 
-    let uri = uri!(crate::routes::thread: &board_name, thread_id).to_string();
+    let uri =
+        uri!(crate::routes::thread: &board_name, thread_id, _).to_string();
 
     context.database.lock_thread(thread_id)?;
 
@@ -507,7 +789,8 @@ pub fn unlock(
 ) -> Result<ActionSuccessPage> {
     let ReasonData { reason } = reason_data.into_inner();
 
-    let uri = uri!(crate::routes::thread: &board_name, thread_id).to_string();
+    let uri =
+        uri!(crate::routes::thread: &board_name, thread_id, _).to_string();
 
     context.database.unlock_thread(thread_id)?;
 
@@ -521,14 +804,123 @@ pub fn unlock(
     Ok(ActionSuccessPage::new(msg, uri, &mut context)?)
 }
 
+/// Move a file, even if the destination is on a different file system.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        copy(from, to).map_err(|cause| {
+            let msg = format!("Couldn't copy file to {}", to.display());
+            Error::from_io_error(cause, msg)
+        })?;
+
+        remove_file(from).map_err(|cause| {
+            let msg = format!("Couldn't remove file {}", from.display());
+            Error::from_io_error(cause, msg)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// If a quarantine directory is configured, move the files of some posts
+/// there and record them in the database, so that they are kept after the
+/// posts are deleted.
+///
+/// Returns a note about where the files were moved, to add to the description
+/// of the staff action.
+fn quarantine_files(
+    db: &mut PooledConnection,
+    conf: &Conf,
+    post_ids: &[PostId],
+    staff_name: &str,
+) -> Result<String> {
+    let quarantine_dir = match conf.quarantine_dir {
+        Some(dir) => dir,
+        None => return Ok(String::new()),
+    };
+
+    let mut deleted_files = Vec::new();
+
+    for post_id in post_ids {
+        for file in db.files_in_post(*post_id)? {
+            let names = [
+                Some(&file.save_name),
+                Some(&file.thumb_name),
+                file.catalog_thumb_name.as_ref(),
+            ];
+
+            for name in names.into_iter().flatten() {
+                let from = conf.upload_dir.join(name);
+
+                if from.exists() {
+                    move_file(&from, &quarantine_dir.join(name))?;
+                }
+            }
+
+            let quarantine_path = quarantine_dir.join(&file.save_name);
+            deleted_files.push(NewDeletedFile::new(
+                file,
+                quarantine_path,
+                staff_name,
+            ));
+        }
+    }
+
+    if deleted_files.is_empty() {
+        return Ok(String::new());
+    }
+
+    db.insert_deleted_files(&deleted_files)?;
+
+    let paths: Vec<&str> = deleted_files
+        .iter()
+        .map(|file| file.quarantine_path.as_ref())
+        .collect();
+
+    Ok(format!(" (files kept at {})", paths.join(", ")))
+}
+
+/// Delete a post as a staff member, keeping its files if a quarantine
+/// directory is configured.
+///
+/// If the post is the first post in a thread, the whole thread is deleted.
+/// Returns the parent thread of the post and a description of the action.
+fn delete_as_staff(
+    db: &mut PooledConnection,
+    conf: &Conf,
+    post_id: PostId,
+    staff_name: &str,
+) -> Result<(Thread, bool, String)> {
+    let thread = db.parent_thread(post_id)?;
+
+    if db.is_first_post(post_id)? {
+        let post_ids: Vec<PostId> = db
+            .posts_in_thread(thread.id)?
+            .into_iter()
+            .map(|post| post.id)
+            .collect();
+
+        let note = quarantine_files(db, conf, &post_ids, staff_name)?;
+        db.delete_thread(thread.id)?;
+
+        let action = format!("Deleted thread {}{}", thread.id, note);
+        Ok((thread, true, action))
+    } else {
+        let note = quarantine_files(db, conf, &[post_id], staff_name)?;
+        db.delete_post(post_id)?;
+
+        let action = format!("Deleted post {}{}", post_id, note);
+        Ok((thread, false, action))
+    }
+}
+
 /// Delete a post without needing a password.
 #[post(
-    "/<_board_name>/<thread_id>/staff-delete/<post_id>",
+    "/<_board_name>/<_thread_id>/staff-delete/<post_id>",
     data = "<reason_data>"
 )]
 pub fn staff_delete(
     _board_name: String,
-    thread_id: ThreadId,
+    _thread_id: ThreadId,
     post_id: PostId,
     reason_data: Form<ReasonData>,
     mut context: Context,
@@ -536,41 +928,172 @@ pub fn staff_delete(
 ) -> Result<ActionSuccessPage> {
     let ReasonData { reason } = reason_data.into_inner();
 
-    let thread = context.database.parent_thread(post_id)?;
+    let (thread, deleted_thread, action) = delete_as_staff(
+        &mut context.database,
+        &context.conf,
+        post_id,
+        &session.staff.name,
+    )?;
 
-    let msg: String;
-    let redirect_uri: String;
+    context.database.insert_staff_action(NewStaffAction {
+        done_by: session.staff.name,
+        action,
+        reason,
+    })?;
 
-    if context.database.is_first_post(post_id)? {
-        context.database.delete_thread(thread.id)?;
+    let (msg, redirect_uri) = if deleted_thread {
+        (
+            format!("Deleted thread {} successfully.", thread.id),
+            uri!(crate::routes::board: thread.board_name, 1).to_string(),
+        )
+    } else {
+        (
+            format!("Deleted post {} successfully.", post_id),
+            uri!(crate::routes::thread: thread.board_name, thread.id, _)
+                .to_string(),
+        )
+    };
 
-        context.database.insert_staff_action(NewStaffAction {
-            done_by: session.staff.name,
-            action: format!("Deleted thread {}", thread_id),
-            reason,
-        })?;
+    Ok(ActionSuccessPage::new(msg, redirect_uri, &mut context)?)
+}
 
-        msg = format!("Deleted thread {} successfully.", thread_id);
-        redirect_uri =
-            uri!(crate::routes::board: thread.board_name, 1).to_string();
-    } else {
-        context.database.delete_post(post_id)?;
+/// Form data for deleting a post with an API key.
+#[derive(FromForm)]
+pub struct ApiDeleteData {
+    pub id: PostId,
+    pub reason: String,
+}
 
-        context.database.insert_staff_action(NewStaffAction {
-            done_by: session.staff.name,
-            action: format!("Deleted post {}", post_id),
-            reason,
-        })?;
+/// Delete a post with an API key.
+///
+/// If the post is the first post in a thread, the whole thread is deleted.
+#[post("/api/staff/delete", data = "<delete_data>")]
+pub fn api_delete(
+    delete_data: Form<ApiDeleteData>,
+    mut context: Context,
+    api_key: ApiKey,
+) -> ApiResult {
+    api_key.authorize(ApiScope::Delete)?;
+
+    let ApiDeleteData { id, reason } = delete_data.into_inner();
+
+    let (thread, deleted_thread, action) = delete_as_staff(
+        &mut context.database,
+        &context.conf,
+        id,
+        &api_key.staff.name,
+    )?;
 
-        msg = format!("Deleted post {} successfully.", post_id);
-        redirect_uri =
-            uri!(crate::routes::thread: thread.board_name, thread.id)
-                .to_string();
+    context.database.insert_staff_action(NewStaffAction {
+        done_by: api_key.staff.name,
+        action,
+        reason,
+    })?;
+
+    let msg = if deleted_thread {
+        format!("Deleted thread {} successfully.", thread.id)
+    } else {
+        format!("Deleted post {} successfully.", id)
     };
 
-    Ok(ActionSuccessPage::new(
-        msg,
-        redirect_uri.to_string(),
-        &mut context,
-    )?)
+    Ok(ApiResponse::success(msg))
+}
+
+/// Ban a user with an API key.
+#[post("/api/staff/ban-user", data = "<ban_data>")]
+pub fn api_ban_user(
+    ban_data: Form<BanUserData>,
+    mut db: PooledConnection,
+    api_key: ApiKey,
+) -> ApiResult {
+    api_key.authorize(ApiScope::Ban)?;
+
+    let BanUserData {
+        id,
+        duration: BanDuration(duration),
+        reason,
+    } = ban_data.into_inner();
+
+    db.user_by_id(id)?;
+    db.ban_user(id, duration)?;
+
+    db.insert_staff_action(NewStaffAction {
+        done_by: api_key.staff.name,
+        action: format!("Banned user {}", id),
+        reason,
+    })?;
+
+    Ok(ApiResponse::success(format!("Banned user {} successfully.", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use rocket::local::Client;
+
+    use crate::config::{Config, GlobalConfig};
+    use crate::models::ApiScope;
+    use crate::Error;
+
+    #[test]
+    fn staff_routes_need_session() {
+        let database_uri = match std::env::var("LONGBOARD_TEST_DATABASE") {
+            Ok(uri) => uri,
+            Err(_) => return,
+        };
+
+        let mut config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        config.global_config.database_uri = database_uri;
+
+        let client = Client::new(crate::new_instance(config).unwrap()).unwrap();
+
+        // Pages that anyone could otherwise load send the client to log in.
+        let pages = [
+            "/staff",
+            "/staff/history",
+            "/staff/users",
+            "/staff/user/1",
+            "/staff/config",
+        ];
+
+        for path in pages {
+            let res = client.get(path).dispatch();
+            assert_eq!(res.status(), Status::SeeOther, "{}", path);
+            assert_eq!(res.headers().get_one("Location"), Some("/staff/login"));
+        }
+
+        // Actions are refused outright.
+        let res = client
+            .post("/staff/ban-user")
+            .header(ContentType::Form)
+            .body("id=1&duration=1d&reason=spam")
+            .dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn api_errors_have_client_statuses() {
+        assert_eq!(Error::MissingApiKey.api_status(), Status::Unauthorized);
+        assert_eq!(Error::InvalidApiKey.api_status(), Status::Unauthorized);
+
+        let err = Error::ApiKeyNotInScope {
+            scope: ApiScope::Delete,
+        };
+        assert_eq!(err.api_status(), Status::Forbidden);
+
+        let err = Error::PostNotFound { post_id: 1 };
+        assert_eq!(err.api_status(), Status::NotFound);
+
+        let err = Error::UserIdNotFound { user_id: 1 };
+        assert_eq!(err.api_status(), Status::NotFound);
+
+        let err = Error::CannotDeleteSystemPost;
+        assert_eq!(err.api_status(), Status::BadRequest);
+
+        let err = Error::ServerBusy;
+        assert_eq!(err.api_status(), Status::ServiceUnavailable);
+    }
 }
@@ -8,10 +8,48 @@ table! {
     }
 }
 
+table! {
+    api_key (id) {
+        id -> Int4,
+        key_hash -> Text,
+        staff_name -> Text,
+        scope -> Text,
+        created -> Timestamptz,
+    }
+}
+
 table! {
     board (name) {
         name -> Text,
         description -> Text,
+        markup_mode -> Text,
+        allow_embeds -> Bool,
+        allow_new_threads -> Bool,
+        listed -> Bool,
+        nsfw -> Bool,
+    }
+}
+
+table! {
+    deleted_file (save_name) {
+        save_name -> Text,
+        thumb_name -> Text,
+        orig_name -> Nullable<Text>,
+        content_type -> Text,
+        post -> Int4,
+        is_spoiler -> Bool,
+        quarantine_path -> Text,
+        deleted_by -> Text,
+        time_stamp -> Timestamptz,
+    }
+}
+
+table! {
+    deleted_thread (id) {
+        id -> Int4,
+        board -> Text,
+        subject -> Text,
+        time_stamp -> Timestamptz,
     }
 }
 
@@ -23,6 +61,14 @@ table! {
         content_type -> Text,
         post -> Int4,
         is_spoiler -> Bool,
+        catalog_thumb_name -> Nullable<Text>,
+    }
+}
+
+table! {
+    instance_secret (name) {
+        name -> Text,
+        value -> Text,
     }
 }
 
@@ -39,6 +85,12 @@ table! {
         board -> Text,
         user_id -> Int4,
         no_bump -> Bool,
+        system -> Bool,
+        author_ident_class -> Nullable<Text>,
+        truncated_html -> Nullable<Text>,
+        embeds -> Nullable<Text>,
+        pending -> Bool,
+        delete_token_hash -> Nullable<Text>,
     }
 }
 
@@ -49,6 +101,7 @@ table! {
         reason -> Text,
         post -> Int4,
         user_id -> Int4,
+        closed_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -87,9 +140,14 @@ table! {
         pinned -> Bool,
         locked -> Bool,
         bump_date -> Timestamptz,
+        post_count -> Int4,
+        file_count -> Int4,
+        cyclical -> Bool,
+        pending -> Bool,
     }
 }
 
+joinable!(api_key -> staff (staff_name));
 joinable!(file -> post (post));
 joinable!(post -> anon_user (user_id));
 joinable!(post -> board (board));
@@ -97,13 +155,16 @@ joinable!(post -> thread (thread));
 joinable!(report -> anon_user (user_id));
 joinable!(report -> post (post));
 joinable!(session -> staff (staff_name));
-joinable!(staff_action -> staff (done_by));
 joinable!(thread -> board (board));
 
 allow_tables_to_appear_in_same_query!(
     anon_user,
+    api_key,
     board,
+    deleted_file,
+    deleted_thread,
     file,
+    instance_secret,
     post,
     report,
     session,
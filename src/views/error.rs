@@ -50,6 +50,57 @@ impl SpamDetectedPage {
 
 impl_template_responder!(SpamDetectedPage, "pages/error/spam-detected");
 
+/// A page warning that a thread with a similar subject already exists.
+#[derive(Debug, Serialize)]
+pub struct SimilarThreadPage {
+    pub thread_uri: String,
+    pub page_info: PageInfo,
+    pub page_footer: PageFooter,
+}
+
+impl SimilarThreadPage {
+    pub fn new<S>(
+        thread_uri: S,
+        context: &mut Context,
+    ) -> Result<SimilarThreadPage>
+    where
+        S: Into<String>,
+    {
+        Ok(SimilarThreadPage {
+            thread_uri: thread_uri.into(),
+            page_info: PageInfo::new("Similar Thread", context),
+            page_footer: PageFooter::new(context)?,
+        })
+    }
+}
+
+impl_template_responder!(SimilarThreadPage, "pages/error/similar-thread");
+
+/// A page for a new thread with the same subject as a recent thread.
+///
+/// If the recent thread has since been deleted, there's no link to it.
+#[derive(Debug, Serialize)]
+pub struct DuplicateThreadPage {
+    pub thread_uri: Option<String>,
+    pub page_info: PageInfo,
+    pub page_footer: PageFooter,
+}
+
+impl DuplicateThreadPage {
+    pub fn new(
+        thread_uri: Option<String>,
+        context: &mut Context,
+    ) -> Result<DuplicateThreadPage> {
+        Ok(DuplicateThreadPage {
+            thread_uri,
+            page_info: PageInfo::new("Duplicate Thread", context),
+            page_footer: PageFooter::new(context)?,
+        })
+    }
+}
+
+impl_template_responder!(DuplicateThreadPage, "pages/error/duplicate-thread");
+
 /// A page for a resource that wasn't found.
 #[derive(Debug, Serialize)]
 pub struct NotFoundPage {
@@ -2,20 +2,29 @@
 //!
 //! Most of these types are meant to be returned from a route.
 
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+
 use maplit::hashmap;
 
+use pulldown_cmark::{html::push_html, Parser};
+
 use serde::{Serialize, Serializer};
 
-use serde_json::value::{to_value, Value as JsonValue};
+use serde_json::value::{to_value, Map as JsonMap, Value as JsonValue};
 
 use rocket::request::{FromRequest, Outcome};
 use rocket::response::Responder;
 use rocket::{uri, Request};
 
-use crate::config::{Banner, Conf, Page as ConfigPage};
+use crate::config::{Banner, Conf, FilterRule, Page as ConfigPage};
 use crate::models::staff::Staff;
 use crate::models::*;
-use crate::routes::UserOptions;
+use crate::parse::{filter_html_text, html_excerpt};
+use crate::routes::new::new_form_token;
+use crate::routes::options::TimeFormat;
+use crate::routes::{canonical_path, UserOptions};
 use crate::{Error, Result};
 
 pub mod error;
@@ -29,6 +38,10 @@ pub struct Context<'r> {
     pub conf: Conf<'r>,
     pub options: UserOptions,
     pub staff: Option<Staff>,
+    /// The canonical URI of the page being rendered. This starts out as just
+    /// the canonical path, and routes add the query parameters they use. See
+    /// `routes::canonical_path`.
+    pub canonical_uri: String,
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for Context<'r> {
@@ -46,6 +59,12 @@ impl<'a, 'r> FromRequest<'a, 'r> for Context<'r> {
         let staff =
             session.and_then(|session| database.staff(session.staff.name).ok());
 
+        // Routes add the query parameters they use themselves.
+        let path = req.uri().path();
+        let canonical_uri =
+            canonical_path(path, |name| database.board(name).is_ok())
+                .unwrap_or_else(|| path.to_string());
+
         Outcome::Success(Context {
             database,
             conf: req.guard::<Conf>().expect("couldn't load configuration"),
@@ -53,23 +72,109 @@ impl<'a, 'r> FromRequest<'a, 'r> for Context<'r> {
                 .guard::<UserOptions>()
                 .expect("couldn't load user options from cookies"),
             staff,
+            canonical_uri,
         })
     }
 }
 
+impl<'r> Context<'r> {
+    /// How posts and threads should be shown to the user.
+    pub fn display_options(&self) -> DisplayOptions {
+        DisplayOptions {
+            time_format: self.options.time_format(),
+            board_id_prefixes: self.conf.board_id_prefixes,
+            viewer: None,
+        }
+    }
+
+    /// Create a token for a posting form, if posting forms need one.
+    pub fn form_token(&self) -> Option<String> {
+        self.conf
+            .min_form_time
+            .map(|_| new_form_token(self.conf.form_secret, Utc::now()))
+    }
+}
+
+/// How posts and threads are shown, from the configuration and the options of
+/// the user viewing them.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOptions {
+    /// How times are shown.
+    pub time_format: TimeFormat,
+    /// Whether IDs and anchors are prefixed with the board name.
+    pub board_id_prefixes: bool,
+    /// The user viewing the posts, if known. Their own posts are marked.
+    pub viewer: Option<UserId>,
+}
+
+/// Insert a time into serialized data as `key`, shown the way the user chose.
+///
+/// The absolute time is also inserted as `<key>_title`, to be used as a title
+/// attribute when the time is shown relative to now.
+pub(crate) fn insert_time(
+    obj: &mut JsonMap<String, JsonValue>,
+    key: &str,
+    time: &DateTime<Utc>,
+    time_format: &TimeFormat,
+) {
+    let shown = time_format.show(time, &Utc::now());
+    let absolute = time_format.format(time);
+
+    obj.insert(key.into(), JsonValue::String(shown));
+    obj.insert(format!("{}_title", key), JsonValue::String(absolute));
+}
+
+/// The templates that are rendered by name, relative to the template directory
+/// and without their extension. See `Config::check_templates`.
+pub const TEMPLATES: &[&str] = &[
+    "models/post",
+    "pages/actions/action-success",
+    "pages/actions/delete-post",
+    "pages/actions/delete-thread",
+    "pages/actions/report",
+    "pages/custom-page",
+    "pages/error/400",
+    "pages/error/404",
+    "pages/error/500",
+    "pages/error/duplicate-thread",
+    "pages/error/similar-thread",
+    "pages/error/spam-detected",
+    "pages/form-help",
+    "pages/gallery",
+    "pages/home",
+    "pages/models/board",
+    "pages/models/board-catalog",
+    "pages/models/thread",
+    "pages/options",
+    "pages/staff/config",
+    "pages/staff/history",
+    "pages/staff/login",
+    "pages/staff/overview",
+    "pages/staff/user-posts",
+    "pages/staff/users",
+];
+
 /// Implement `Responder` for a type which implements `Serialize`, given a path
 /// to a template file that should be loaded.
 ///
 /// The type is serialized and passed directly into that template, which is
-/// rendered as HTML.
+/// rendered as HTML. If `noindex` is given, it's called with the value, and if
+/// it returns true, crawlers are asked not to index the page with an
+/// `X-Robots-Tag` header.
 #[macro_export]
 macro_rules! impl_template_responder {
     ($t:ty, $template:expr) => {
+        $crate::impl_template_responder!($t, $template, noindex = |_| false);
+    };
+    ($t:ty, $template:expr, noindex = $noindex:expr) => {
         impl<'r> ::rocket::response::Responder<'r> for $t {
             fn respond_to(
                 self,
                 req: &::rocket::request::Request,
             ) -> ::rocket::response::Result<'r> {
+                let noindex: fn(&$t) -> bool = $noindex;
+                let noindex = noindex(&self);
+
                 let data = ::serde_json::value::to_value(self)
                     .expect("could not serialize value");
                 let template = ::rocket_contrib::templates::Template::render(
@@ -78,7 +183,31 @@ macro_rules! impl_template_responder {
 
                 log::trace!("Rendering template at {}", $template);
 
-                template.respond_to(req)
+                let mut response = template.respond_to(req)?;
+
+                if noindex {
+                    response.set_raw_header("X-Robots-Tag", "noindex");
+                }
+
+                Ok(response)
+            }
+        }
+    };
+}
+
+/// Implement `Responder` for a type by serializing it as JSON.
+#[macro_export]
+macro_rules! impl_json_responder {
+    ($t:ty) => {
+        impl<'r> ::rocket::response::Responder<'r> for $t {
+            fn respond_to(
+                self,
+                req: &::rocket::request::Request,
+            ) -> ::rocket::response::Result<'r> {
+                let data = ::serde_json::to_string(&self)
+                    .expect("could not serialize value");
+
+                ::rocket::response::content::Json(data).respond_to(req)
             }
         }
     };
@@ -95,6 +224,10 @@ pub struct PageInfo {
     pub style: String,
     /// Whether to use javascript to do code highlighting.
     pub code_highlighting: bool,
+    /// Trusted HTML from the config to add to the page's head.
+    pub extra_head_html: Option<String>,
+    /// The canonical URI of the page.
+    pub canonical_uri: String,
 }
 
 impl PageInfo {
@@ -108,6 +241,12 @@ impl PageInfo {
             version: env!("CARGO_PKG_VERSION").to_string(),
             style: context.options.style.clone(),
             code_highlighting: context.options.code_highlighting,
+            extra_head_html: context
+                .conf
+                .extra_head_html
+                .filter(|_| context.conf.allow_extra_html)
+                .map(String::from),
+            canonical_uri: context.canonical_uri.clone(),
         }
     }
 }
@@ -117,13 +256,20 @@ impl PageInfo {
 pub struct PageFooter {
     /// A list of admin-created pages.
     pages: Vec<ConfigPage>,
+    /// Trusted HTML from the config to add to the end of the page.
+    extra_footer_html: Option<String>,
 }
 
 impl PageFooter {
     /// Create a new `PageFooter`.
     pub fn new(context: &mut Context) -> Result<PageFooter> {
         Ok(PageFooter {
-            pages: context.conf.pages()?,
+            pages: context.conf.pages.to_vec(),
+            extra_footer_html: context
+                .conf
+                .extra_footer_html
+                .filter(|_| context.conf.allow_extra_html)
+                .map(String::from),
         })
     }
 }
@@ -131,15 +277,20 @@ impl PageFooter {
 /// The board navigation at the top of the page.
 #[derive(Debug, Serialize)]
 pub struct PageNav {
-    /// A list of all site boards.
-    pub boards: Vec<Board>,
+    /// A list of the site's listed boards.
+    pub boards: Vec<BoardView>,
 }
 
 impl PageNav {
     /// Create a new `PageNav`.
     pub fn new(context: &mut Context) -> Result<PageNav> {
         Ok(PageNav {
-            boards: context.database.all_boards()?,
+            boards: context
+                .database
+                .listed_boards()?
+                .into_iter()
+                .map(BoardView)
+                .collect(),
         })
     }
 }
@@ -148,12 +299,37 @@ impl PageNav {
 #[derive(Debug, Serialize)]
 pub struct PageHeader {
     /// The board we're on.
-    pub board: Board,
+    pub board: BoardView,
     /// The banner to be displayed.
     pub banner: BannerView,
     /// A site notice to be displayed at the top of the page.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notice_html: Option<String>,
+    /// How the page is shown if the board isn't safe for work.
+    #[serde(flatten)]
+    pub nsfw: NsfwView,
+}
+
+/// How a page is shown if its board isn't safe for work.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NsfwView {
+    /// Whether crawlers are asked not to index the page.
+    pub noindex: bool,
+    /// Whether thumbnails are blurred until they're hovered over.
+    pub blur: bool,
+    /// Whether link previews of the page are shown without an image.
+    pub hide_share_image: bool,
+}
+
+impl NsfwView {
+    /// Create a new `NsfwView` for a page on `board`.
+    fn new(board: &Board, conf: &Conf) -> NsfwView {
+        NsfwView {
+            noindex: board.nsfw && conf.nsfw_noindex,
+            blur: board.nsfw && conf.nsfw_blur_thumbnails,
+            hide_share_image: board.nsfw && conf.nsfw_hide_share_image,
+        }
+    }
 }
 
 impl PageHeader {
@@ -162,14 +338,44 @@ impl PageHeader {
     where
         S: Into<String>,
     {
+        let board = context.database.board(board_name)?;
+
         Ok(PageHeader {
-            board: context.database.board(board_name)?,
+            nsfw: NsfwView::new(&board, &context.conf),
+            board: BoardView(board),
             banner: BannerView(context.conf.choose_banner()?),
             notice_html: context.conf.notice()?,
         })
     }
 }
 
+/// A wrapper for a board that can be passed into a template.
+#[derive(Debug)]
+pub struct BoardView(Board);
+
+impl Serialize for BoardView {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut description_html = String::new();
+        push_html(&mut description_html, Parser::new(&self.0.description));
+
+        let mut data = to_value(&self.0).expect("could not serialize board");
+
+        let obj = data.as_object_mut().unwrap();
+        obj.insert(
+            "description_html".into(),
+            JsonValue::String(ammonia::clean(&description_html)),
+        );
+
+        data.serialize(serializer)
+    }
+}
+
 /// A wrapper for a banner that can be passed into a template.
 #[derive(Debug)]
 pub struct BannerView(Banner);
@@ -192,8 +398,13 @@ impl Serialize for BannerView {
 }
 
 /// A wrapper for file that can be passed into a template.
+///
+/// The second field is whether the thumbnail should be loaded lazily. See
+/// `lazy_thumbnails`. The last field is whether the thumbnail is blurred
+/// because its board isn't safe for work, for pages that show files from other
+/// boards. A board's own pages are blurred as a whole, see `NsfwView`.
 #[derive(Debug)]
-pub struct FileView(File);
+pub struct FileView(File, bool, bool);
 
 impl Serialize for FileView {
     fn serialize<S>(
@@ -205,22 +416,39 @@ impl Serialize for FileView {
     {
         let uri = self.0.uri();
         let thumb_uri = self.0.thumb_uri();
+        let catalog_thumb_uri = self.0.catalog_thumb_uri();
         let is_spoiler = self.0.is_spoiler;
+        let is_media = self.0.is_media();
         let content_type = self.0.content_type.clone();
+        let type_label = self.0.type_label();
 
         let mut data = to_value(&self.0).expect("could not serialize file");
 
         let obj = data.as_object_mut().unwrap();
 
         obj.insert("uri".into(), JsonValue::String(uri));
+        obj.insert("lazy".into(), JsonValue::Bool(self.1));
+        obj.insert("blur".into(), JsonValue::Bool(self.2));
 
         if is_spoiler {
             obj.insert(
                 "thumb_uri".into(),
                 JsonValue::from("/file/spoiler.png"),
             );
+            obj.insert(
+                "catalog_thumb_uri".into(),
+                JsonValue::from("/file/spoiler.png"),
+            );
+        } else if !is_media {
+            let icon_uri = uri!(crate::routes::file_icon).to_string();
+            obj.insert("thumb_uri".into(), JsonValue::String(icon_uri.clone()));
+            obj.insert("catalog_thumb_uri".into(), JsonValue::String(icon_uri));
         } else {
             obj.insert("thumb_uri".into(), JsonValue::String(thumb_uri));
+            obj.insert(
+                "catalog_thumb_uri".into(),
+                JsonValue::String(catalog_thumb_uri),
+            );
         }
 
         match content_type.type_() {
@@ -231,7 +459,8 @@ impl Serialize for FileView {
                 obj.insert("is_video".into(), JsonValue::Bool(true));
             }
             _ => {
-                log::warn!("Unknown content type {} for upload", content_type);
+                obj.insert("is_file".into(), JsonValue::Bool(true));
+                obj.insert("type_label".into(), JsonValue::String(type_label));
             }
         }
 
@@ -240,8 +469,10 @@ impl Serialize for FileView {
 }
 
 /// A wrapper for post that can be passed into a template.
+///
+/// The post is shown according to the given display options.
 #[derive(Debug)]
-pub struct PostView(Post);
+pub struct PostView(Post, DisplayOptions);
 
 impl Serialize for PostView {
     fn serialize<S>(
@@ -251,15 +482,24 @@ impl Serialize for PostView {
     where
         S: Serializer,
     {
-        let time_stamp = self.0.time_stamp.format("%F %R").to_string();
-
-        let hash = self
-            .0
-            .author_ident
-            .as_ref()
-            .map(|ident| ident.split('$').last().unwrap().to_owned());
+        // Staff identities are stored as-is, and user identities are stored as
+        // Argon2 hashes, of which we only show the hash itself.
+        let hash = match self.0.author_ident_class {
+            Some(_) => self.0.author_ident.clone(),
+            None => self
+                .0
+                .author_ident
+                .as_ref()
+                .map(|ident| ident.split('$').last().unwrap().to_owned()),
+        };
 
         let uri = self.0.uri();
+        let anchor = self.0.anchor();
+        let display_id = display_id(
+            &self.0.board_name,
+            self.0.id,
+            self.1.board_id_prefixes,
+        );
 
         let pin_uri = uri!(
             crate::routes::staff::pin:
@@ -317,8 +557,16 @@ impl Serialize for PostView {
 
         let obj = data.as_object_mut().unwrap();
 
-        obj.insert("time_stamp".into(), JsonValue::String(time_stamp));
+        let time_format = &self.1.time_format;
+        insert_time(obj, "time_stamp", &self.0.time_stamp, time_format);
         obj.insert("uri".into(), JsonValue::String(uri));
+        obj.insert("anchor".into(), JsonValue::String(anchor));
+        obj.insert("display_id".into(), JsonValue::String(display_id));
+        obj.insert(
+            "is_you".into(),
+            JsonValue::Bool(self.1.viewer == Some(self.0.user_id)),
+        );
+        obj.insert("replies_to".into(), JsonValue::from(self.0.replies_to()));
         obj.insert("pin_uri".into(), JsonValue::String(pin_uri));
         obj.insert("unpin_uri".into(), JsonValue::String(unpin_uri));
         obj.insert("lock_uri".into(), JsonValue::String(lock_uri));
@@ -330,6 +578,23 @@ impl Serialize for PostView {
             JsonValue::String(staff_delete_uri),
         );
 
+        let embeds = self
+            .0
+            .embeds()
+            .iter()
+            .map(|embed| {
+                let mut embed_obj = JsonMap::new();
+                embed_obj.insert(
+                    "provider".into(),
+                    JsonValue::String(embed.provider.to_string()),
+                );
+                embed_obj
+                    .insert("uri".into(), JsonValue::String(embed.embed_uri()));
+                JsonValue::Object(embed_obj)
+            })
+            .collect();
+        obj.insert("embeds".into(), JsonValue::Array(embeds));
+
         if let Some(ident) = hash {
             obj.insert("author_ident".into(), JsonValue::String(ident));
         }
@@ -342,20 +607,19 @@ impl Serialize for PostView {
 #[derive(Debug)]
 pub struct ThreadView {
     pub thread: Thread,
-    pub post_count: u32,
-    pub file_count: u32,
+    /// How to show the thread.
+    pub display: DisplayOptions,
 }
 
 impl ThreadView {
     /// Create a new `ThreadView`.
     pub fn new(
         thread_id: ThreadId,
-        db: &mut PooledConnection,
+        context: &mut Context,
     ) -> Result<ThreadView> {
         Ok(ThreadView {
-            thread: db.thread(thread_id)?,
-            post_count: db.thread_post_count(thread_id)?,
-            file_count: db.thread_file_count(thread_id)?,
+            thread: context.database.thread(thread_id)?,
+            display: context.display_options(),
         })
     }
 }
@@ -375,26 +639,69 @@ impl Serialize for ThreadView {
 
         let obj = data.as_object_mut().unwrap();
         obj.insert("uri".into(), JsonValue::String(uri));
-        obj.insert("post_count".into(), JsonValue::from(self.post_count));
-        obj.insert("file_count".into(), JsonValue::from(self.file_count));
+        let thread = &self.thread;
+        let time_format = &self.display.time_format;
+        let display_id = display_id(
+            &thread.board_name,
+            thread.id,
+            self.display.board_id_prefixes,
+        );
+
+        insert_time(obj, "time_stamp", &thread.time_stamp, time_format);
+        insert_time(obj, "bump_date", &thread.bump_date, time_format);
+        obj.insert("display_id".into(), JsonValue::String(display_id));
 
         data.serialize(serializer)
     }
 }
 
 /// A post and it's file, if it has one.
+///
+/// The last field is whether the post is new to the user viewing it.
 #[derive(Debug)]
-pub struct DeepPost(PostView, Option<FileView>);
+pub struct DeepPost(PostView, Option<FileView>, bool);
 
 impl DeepPost {
-    /// Create a new `DeepPost`.
-    fn new(post_id: PostId, db: &mut PooledConnection) -> Result<DeepPost> {
-        let post = PostView(db.post(post_id)?);
-        let file = db.files_in_post(post_id)?.pop().map(FileView);
-        Ok(DeepPost(post, file))
+    /// Create a new `DeepPost`. If `lazy` is set, the post's thumbnail is
+    /// marked to be loaded lazily.
+    ///
+    /// Posts that are waiting for approval are only shown to staff. Anyone else
+    /// gets `PostNotFound`.
+    fn new(
+        post_id: PostId,
+        lazy: bool,
+        context: &mut Context,
+    ) -> Result<DeepPost> {
+        let db = &mut context.database;
+        let post = db.post(post_id)?;
+
+        if post.pending && context.staff.is_none() {
+            return Err(Error::PostNotFound { post_id });
+        }
+
+        // Previews of posts can be shown on other boards' pages.
+        let blur = context.conf.nsfw_blur_thumbnails
+            && db.board(&post.board_name)?.nsfw;
+
+        let post = PostView(post, context.display_options());
+        let file = db
+            .files_in_post(post_id)?
+            .pop()
+            .map(|file| FileView(file, lazy, blur));
+        Ok(DeepPost(post, file, false))
     }
 }
 
+/// Apply the filter rules for previews to a post shown in a board's thread
+/// previews or its catalog.
+fn filter_preview(post: &mut Post, rules: &[FilterRule]) {
+    post.body = filter_html_text(&post.body, rules);
+    post.truncated_html = post
+        .truncated_html
+        .as_ref()
+        .map(|html| filter_html_text(html, rules));
+}
+
 impl Serialize for DeepPost {
     fn serialize<S>(
         &self,
@@ -403,10 +710,15 @@ impl Serialize for DeepPost {
     where
         S: Serializer,
     {
-        let DeepPost(post, file) = self;
+        let DeepPost(post, file, is_new) = self;
 
         let mut post_data = to_value(post).expect("could not serialize post");
 
+        post_data
+            .as_object_mut()
+            .unwrap()
+            .insert(String::from("is_new"), JsonValue::Bool(*is_new));
+
         if let Some(file) = file {
             let file_data = to_value(file).expect("could not serialize file");
 
@@ -425,40 +737,76 @@ impl Serialize for DeepPost {
 pub struct DeepThread(ThreadView, Vec<DeepPost>);
 
 impl DeepThread {
-    /// Load a thread and its posts from the database.
+    /// Load a thread, its original post, and some of its replies from the
+    /// database.
+    ///
+    /// Posts made by `viewer` are marked as the viewer's own.
     fn new(
         thread_id: ThreadId,
-        db: &mut PooledConnection,
+        slice: ThreadSlice,
+        viewer: &User,
+        context: &mut Context,
     ) -> Result<DeepThread> {
-        let thread = ThreadView::new(thread_id, db)?;
-        let posts = db.posts_in_thread(thread_id)?;
+        let thread = context.database.thread(thread_id)?;
+        let posts = match slice {
+            ThreadSlice::Page(num) => {
+                let page = Page {
+                    num,
+                    width: context.conf.posts_per_thread_page.max(1),
+                };
+
+                context.database.posts_in_thread_page(thread_id, page)?
+            }
+            ThreadSlice::Last(count) => {
+                context.database.last_posts_in_thread(thread_id, count)?
+            }
+        };
+        let display = DisplayOptions {
+            viewer: Some(viewer.id),
+            ..context.display_options()
+        };
 
         let deep_posts = posts
             .into_iter()
-            .map(|post| {
-                let file = db.files_in_post(post.id)?.pop();
-                Ok(DeepPost(PostView(post), file.map(FileView)))
+            .map(|(post, mut files)| {
+                let post = PostView(post, display.clone());
+                let file =
+                    files.pop().map(|file| FileView(file, false, false));
+                DeepPost(post, file, false)
             })
-            .collect::<Result<_>>()?;
+            .collect();
+
+        let thread = ThreadView { thread, display };
 
         Ok(DeepThread(thread, deep_posts))
     }
 
+    /// Mark the posts made after `last_seen` as new.
+    fn mark_new(&mut self, last_seen: PostId) {
+        for DeepPost(post, _, is_new) in self.1.iter_mut() {
+            *is_new = post.0.id > last_seen;
+        }
+    }
+
     /// Load a thread and a few of its posts from the database, as a preview.
     fn new_preview(
         thread_id: ThreadId,
         context: &mut Context,
     ) -> Result<DeepThread> {
-        let thread = ThreadView::new(thread_id, &mut context.database)?;
+        let thread = ThreadView::new(thread_id, context)?;
         let posts = context
             .database
             .preview_thread(thread_id, context.conf.preview_limit)?;
+        let lazy = context.conf.lazy_thumbnails;
 
         let deep_posts = posts
             .into_iter()
-            .map(|post| {
+            .map(|mut post| {
                 let file = context.database.files_in_post(post.id)?.pop();
-                Ok(DeepPost(PostView(post), file.map(FileView)))
+                filter_preview(&mut post, context.conf.preview_filter_rules);
+                let post = PostView(post, thread.display.clone());
+                let file = file.map(|file| FileView(file, lazy, false));
+                Ok(DeepPost(post, file, false))
             })
             .collect::<Result<_>>()?;
 
@@ -479,10 +827,35 @@ impl Serialize for DeepThread {
         let mut thread_data =
             to_value(thread).expect("could not serialize thread");
 
-        thread_data.as_object_mut().unwrap().insert(
-            "posts".into(),
-            to_value(posts).expect("could not serialize posts"),
-        );
+        // The first post is the original post, so replies to it can be found
+        // without looking it up.
+        let op_id = posts.first().map(|DeepPost(post, ..)| post.0.id);
+
+        let posts_data: Vec<JsonValue> = posts
+            .iter()
+            .map(|post| {
+                let mut post_data =
+                    to_value(post).expect("could not serialize post");
+
+                let replies_to_op = op_id.map_or(false, |op_id| {
+                    post_data["replies_to"]
+                        .as_array()
+                        .map_or(false, |ids| ids.contains(&op_id.into()))
+                });
+
+                post_data
+                    .as_object_mut()
+                    .unwrap()
+                    .insert("replies_to_op".into(), replies_to_op.into());
+
+                post_data
+            })
+            .collect();
+
+        thread_data
+            .as_object_mut()
+            .unwrap()
+            .insert("posts".into(), JsonValue::Array(posts_data));
 
         thread_data.serialize(serializer)
     }
@@ -496,13 +869,17 @@ pub struct RecentPost {
 }
 
 impl RecentPost {
-    fn load(db: &mut PooledConnection, limit: u32) -> Result<Vec<RecentPost>> {
+    fn load(
+        db: &mut PooledConnection,
+        display: &DisplayOptions,
+        limit: u32,
+    ) -> Result<Vec<RecentPost>> {
         db.recent_posts(limit)?
             .into_iter()
             .map(|post| {
                 Ok(RecentPost {
                     thread_subject: db.thread(post.thread_id)?.subject,
-                    post: PostView(post),
+                    post: PostView(post, display.clone()),
                 })
             })
             .collect()
@@ -536,17 +913,42 @@ pub struct RecentFile {
 }
 
 impl RecentFile {
-    fn load(db: &mut PooledConnection, limit: u32) -> Result<Vec<RecentFile>> {
-        Ok(db
-            .recent_files(limit)?
+    fn load(context: &mut Context) -> Result<Vec<RecentFile>> {
+        let limit = context.conf.num_recent_files;
+        let files = context.database.recent_files(limit)?;
+        RecentFile::from_files(context, files)
+    }
+
+    /// Files from boards that aren't safe for work are blurred, since these
+    /// pages show files from every board.
+    fn from_files(
+        context: &mut Context,
+        files: Vec<File>,
+    ) -> Result<Vec<RecentFile>> {
+        let display = context.display_options();
+        let db = &mut context.database;
+
+        let mut nsfw_boards = HashSet::new();
+        if context.conf.nsfw_blur_thumbnails {
+            for board in db.all_boards()? {
+                if board.nsfw {
+                    nsfw_boards.insert(board.name);
+                }
+            }
+        }
+
+        files
             .into_iter()
             .map(|file| {
+                let post = db.post(file.post_id)?;
+                let blur = nsfw_boards.contains(&post.board_name);
+
                 Ok(RecentFile {
-                    post: PostView(db.post(file.post_id)?),
-                    file: FileView(file),
+                    post: PostView(post, display.clone()),
+                    file: FileView(file, false, blur),
                 })
             })
-            .collect::<Result<_>>()?)
+            .collect()
     }
 }
 
@@ -568,6 +970,8 @@ impl HomePage {
         site_description: Option<String>,
         context: &mut Context,
     ) -> Result<HomePage> {
+        let display = context.display_options();
+
         Ok(HomePage {
             page_info: PageInfo::new(context.conf.site_name, context),
             page_nav: PageNav::new(context)?,
@@ -576,18 +980,56 @@ impl HomePage {
             site_description,
             recent_posts: RecentPost::load(
                 &mut context.database,
+                &display,
                 context.conf.num_recent_posts,
             )?,
-            recent_files: RecentFile::load(
-                &mut context.database,
-                context.conf.num_recent_files,
-            )?,
+            recent_files: RecentFile::load(context)?,
         })
     }
 }
 
 impl_template_responder!(HomePage, "pages/home");
 
+/// A page of recently uploaded files from the whole site.
+#[derive(Debug, Serialize)]
+pub struct GalleryPage {
+    page_info: PageInfo,
+    page_nav: PageNav,
+    page_footer: PageFooter,
+    files: Vec<RecentFile>,
+    /// The URI of the next page of files, if there are any more files.
+    next_uri: Option<String>,
+}
+
+impl GalleryPage {
+    /// Create a new gallery page, showing files from posts older than the
+    /// post `before`.
+    pub fn new(
+        before: Option<PostId>,
+        context: &mut Context,
+    ) -> Result<GalleryPage> {
+        let limit = context.conf.gallery_page_size;
+        let files = context.database.recent_files_before(before, limit)?;
+
+        let next_uri = match files.last() {
+            Some(last) if files.len() as u32 == limit => {
+                Some(uri!(crate::routes::gallery: last.post_id).to_string())
+            }
+            _ => None,
+        };
+
+        Ok(GalleryPage {
+            page_info: PageInfo::new("Gallery", context),
+            page_nav: PageNav::new(context)?,
+            page_footer: PageFooter::new(context)?,
+            files: RecentFile::from_files(context, files)?,
+            next_uri,
+        })
+    }
+}
+
+impl_template_responder!(GalleryPage, "pages/gallery");
+
 /// A style that the user can select.
 #[derive(Debug, Serialize)]
 pub struct StyleOption {
@@ -649,6 +1091,16 @@ impl PageNumLink {
     }
 }
 
+/// The URI that posting forms on a board get previews from, if posts there
+/// must be previewed.
+fn preview_uri(board_name: &str, context: &Context) -> Option<String> {
+    if context.conf.preview_required(board_name) {
+        Some(uri!(crate::routes::new::preview_body: board_name).to_string())
+    } else {
+        None
+    }
+}
+
 /// A page for a board.
 #[derive(Debug, Serialize)]
 pub struct BoardPage {
@@ -658,8 +1110,15 @@ pub struct BoardPage {
     page_footer: PageFooter,
     threads: Vec<DeepThread>,
     page_num_links: Vec<PageNumLink>,
+    last_posts_count: u32,
     catalog_uri: String,
     staff: Option<StaffView>,
+    allow_uploads: bool,
+    dedup_threads: bool,
+    allow_cyclical: bool,
+    form_token: Option<String>,
+    honeypot_field: Option<String>,
+    preview_uri: Option<String>,
 }
 
 impl BoardPage {
@@ -674,6 +1133,7 @@ impl BoardPage {
     {
         let board_name = board_name.as_ref();
         let page_width = context.conf.threads_per_page;
+        let sort_mode = context.conf.sort_mode(board_name);
 
         let threads = context
             .database
@@ -683,6 +1143,8 @@ impl BoardPage {
                     num: page_num,
                     width: page_width,
                 },
+                sort_mode,
+                context.conf.max_pinned_on_page,
             )?
             .into_iter()
             .map(|thread| DeepThread::new_preview(thread.id, context))
@@ -701,13 +1163,24 @@ impl BoardPage {
             page_footer: PageFooter::new(context)?,
             threads,
             page_num_links: PageNumLink::generate(page_count, page_num),
+            last_posts_count: context.conf.last_posts_count,
             catalog_uri,
             staff: context.staff.clone().map(StaffView),
+            allow_uploads: context.conf.allow_uploads,
+            dedup_threads: context.conf.dedup_threads,
+            allow_cyclical: context.conf.allow_cyclical,
+            form_token: context.form_token(),
+            honeypot_field: context.conf.honeypot_field.map(String::from),
+            preview_uri: preview_uri(board_name, context),
         })
     }
 }
 
-impl_template_responder!(BoardPage, "pages/models/board");
+impl_template_responder!(
+    BoardPage,
+    "pages/models/board",
+    noindex = |page| page.page_header.nsfw.noindex
+);
 
 /// A catalog item.
 #[derive(Debug, Serialize)]
@@ -716,6 +1189,8 @@ pub struct CatalogItem {
     first_post: DeepPost,
     num_posts: u32,
     num_files: u32,
+    /// The number of different users that posted in the thread.
+    unique_posters: u32,
 }
 
 /// A page for a board catalog.
@@ -726,6 +1201,8 @@ pub struct BoardCatalogPage {
     page_header: PageHeader,
     page_footer: PageFooter,
     items: Vec<CatalogItem>,
+    /// How many columns the catalog is laid out in, if it's fixed.
+    columns: Option<u32>,
 }
 
 impl BoardCatalogPage {
@@ -739,22 +1216,39 @@ impl BoardCatalogPage {
     {
         let board_name = board_name.as_ref();
 
-        let first_posts = context.database.first_posts(board_name)?;
+        let sort_mode = context.conf.sort_mode(board_name);
+        let first_posts =
+            context.database.first_posts(board_name, sort_mode)?;
+
+        let thread_ids: Vec<ThreadId> =
+            first_posts.iter().map(|post| post.thread_id).collect();
+        let unique_posters =
+            context.database.unique_poster_counts(&thread_ids)?;
 
         let items = first_posts
             .into_iter()
             .map(|post| {
                 let thread = context.database.thread(post.thread_id)?;
 
+                let lazy = context.conf.lazy_thumbnails;
+                let mut first_post = DeepPost::new(post.id, lazy, context)?;
+                filter_preview(
+                    &mut first_post.0 .0,
+                    context.conf.preview_filter_rules,
+                );
+
                 Ok(CatalogItem {
-                    thread: ThreadView::new(thread.id, &mut context.database)?,
-                    first_post: DeepPost::new(post.id, &mut context.database)?,
-                    num_posts: context
-                        .database
-                        .thread_post_count(post.thread_id)?,
-                    num_files: context
-                        .database
-                        .thread_file_count(post.thread_id)?,
+                    num_posts: thread.post_count.try_into().unwrap(),
+                    num_files: thread.file_count.try_into().unwrap(),
+                    unique_posters: unique_posters
+                        .get(&thread.id)
+                        .copied()
+                        .unwrap_or(0),
+                    thread: ThreadView {
+                        thread,
+                        display: context.display_options(),
+                    },
+                    first_post,
                 })
             })
             .collect::<Result<_>>()?;
@@ -765,11 +1259,25 @@ impl BoardCatalogPage {
             page_header: PageHeader::new(board_name, context)?,
             page_footer: PageFooter::new(context)?,
             items,
+            columns: context.conf.catalog_columns,
         })
     }
 }
 
-impl_template_responder!(BoardCatalogPage, "pages/models/board-catalog");
+impl_template_responder!(
+    BoardCatalogPage,
+    "pages/models/board-catalog",
+    noindex = |page| page.page_header.nsfw.noindex
+);
+
+/// Which of a thread's replies are shown on a thread page.
+#[derive(Debug, Clone, Copy)]
+pub enum ThreadSlice {
+    /// One page of replies, by page number.
+    Page(u32),
+    /// The given number of newest replies.
+    Last(u32),
+}
 
 /// A page for a thread.
 #[derive(Debug, Serialize)]
@@ -779,34 +1287,159 @@ pub struct ThreadPage {
     page_header: PageHeader,
     page_footer: PageFooter,
     thread: DeepThread,
+    summary: ThreadSummary,
+    page_num_links: Vec<PageNumLink>,
+    last_posts: Option<u32>,
+    last_posts_count: u32,
     staff: Option<StaffView>,
+    allow_uploads: bool,
+    form_token: Option<String>,
+    honeypot_field: Option<String>,
+    preview_uri: Option<String>,
 }
 
 impl ThreadPage {
     /// Create a new thread page.
+    ///
+    /// Only the replies in `slice` are shown, after the original post. If
+    /// `last_seen` is given, posts after it are marked as new. Posts made by
+    /// `viewer` are marked as theirs.
     pub fn new<S>(
         board_name: S,
         thread_id: ThreadId,
+        slice: ThreadSlice,
+        last_seen: Option<PostId>,
+        viewer: &User,
         context: &mut Context,
     ) -> Result<ThreadPage>
     where
         S: AsRef<str>,
     {
-        let thread = DeepThread::new(thread_id, &mut context.database)?;
+        let page_count = match slice {
+            ThreadSlice::Page(page_num) => {
+                let page_width = context.conf.posts_per_thread_page.max(1);
+                let page_count = context
+                    .database
+                    .thread_reply_page_count(thread_id, page_width)?;
+
+                if page_num == 0 || page_num > page_count {
+                    return Err(Error::PageOutOfRange { page: page_num });
+                }
+
+                page_count
+            }
+            ThreadSlice::Last(_) => 1,
+        };
+
+        let mut thread = DeepThread::new(thread_id, slice, viewer, context)?;
+
+        if let Some(last_seen) = last_seen {
+            thread.mark_new(last_seen);
+        }
+
         let subject = thread.0.thread.subject.clone();
 
+        let (page_num_links, last_posts) = match slice {
+            // A single page doesn't need any links to other pages.
+            ThreadSlice::Page(page_num) if page_count > 1 => {
+                (PageNumLink::generate(page_count, page_num), None)
+            }
+            ThreadSlice::Page(_) => (Vec::new(), None),
+            ThreadSlice::Last(count) => (Vec::new(), Some(count)),
+        };
+
+        let page_header = PageHeader::new(board_name.as_ref(), context)?;
+        let summary = ThreadSummary::new(
+            &thread,
+            context.conf.base_url,
+            !page_header.nsfw.hide_share_image,
+        );
+
         Ok(ThreadPage {
             page_info: PageInfo::new(subject, context),
             page_nav: PageNav::new(context)?,
-            page_header: PageHeader::new(board_name.as_ref(), context)?,
+            page_header,
             page_footer: PageFooter::new(context)?,
             thread,
+            summary,
+            page_num_links,
+            last_posts,
+            last_posts_count: context.conf.last_posts_count,
             staff: context.staff.clone().map(StaffView),
+            allow_uploads: context.conf.allow_uploads,
+            form_token: context.form_token(),
+            honeypot_field: context.conf.honeypot_field.map(String::from),
+            preview_uri: preview_uri(board_name.as_ref(), context),
         })
     }
+
+    /// The ID of the newest post shown on the page.
+    pub fn newest_post_id(&self) -> Option<PostId> {
+        self.thread.1.iter().map(|post| post.0 .0.id).max()
+    }
+}
+
+impl_template_responder!(
+    ThreadPage,
+    "pages/models/thread",
+    noindex = |page| page.page_header.nsfw.noindex
+);
+
+/// The most characters of the original post used in a thread's summary.
+const SUMMARY_MAX_CHARS: usize = 200;
+
+/// A summary of a thread for link previews, such as OpenGraph tags.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ThreadSummary {
+    /// The subject of the thread.
+    pub title: String,
+    /// The start of the original post, as plain text.
+    pub description: String,
+    /// The absolute URL of the thread.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The absolute URL of the thumbnail of the original post's file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_uri: Option<String>,
 }
 
-impl_template_responder!(ThreadPage, "pages/models/thread");
+impl ThreadSummary {
+    /// Create a new `ThreadSummary` from a thread whose original post has
+    /// been loaded.
+    ///
+    /// Link previews need absolute URLs, so the URL and image are only given
+    /// if `base_url` is set. Spoilered files and files without a real
+    /// thumbnail are never used as the image.
+    fn new(
+        thread: &DeepThread,
+        base_url: Option<&str>,
+        show_image: bool,
+    ) -> ThreadSummary {
+        let op = thread.1.first();
+
+        let description = op
+            .map(|DeepPost(post, _, _)| {
+                html_excerpt(&post.0.body, SUMMARY_MAX_CHARS)
+            })
+            .unwrap_or_default();
+
+        let image_uri = op
+            .and_then(|DeepPost(_, file, _)| file.as_ref())
+            .map(|file| &file.0)
+            .filter(|file| show_image && file.is_media() && !file.is_spoiler)
+            .and_then(|file| {
+                base_url.map(|base| format!("{}{}", base, file.thumb_uri()))
+            });
+
+        ThreadSummary {
+            title: thread.0.thread.subject.clone(),
+            description,
+            url: base_url
+                .map(|base| format!("{}{}", base, thread.0.thread.uri())),
+            image_uri,
+        }
+    }
+}
 
 /// A post preview.
 ///
@@ -822,13 +1455,106 @@ impl PostPreview {
     /// Create a new post preview.
     pub fn new(post_id: PostId, context: &mut Context) -> Result<PostPreview> {
         Ok(PostPreview {
-            post: DeepPost::new(post_id, &mut context.database)?,
+            post: DeepPost::new(post_id, false, context)?,
         })
     }
 }
 
 impl_template_responder!(PostPreview, "models/post");
 
+/// A post preview, serialized as JSON.
+///
+/// The response is an object with the following keys:
+///
+/// - `id`, `thread_id`: the IDs of the post and its thread.
+/// - `board_name`: the board the post was made on.
+/// - `time_stamp`: when the post was made, in the user's chosen time format.
+/// - `time_stamp_title`: when the post was made, as an absolute time.
+/// - `body`: the rendered HTML body of the post.
+/// - `author_name`, `author_contact`, `author_ident`: information about the
+///   author. `author_contact` and `author_ident` may be `null`.
+/// - `author_ident_class`: the CSS class for a staff member's identity, or
+///   `null` if the author isn't a staff member.
+/// - `no_bump`: whether the post bumped its thread.
+/// - `system`: whether the post is a message from the site itself.
+/// - `uri`, `report_uri`, `delete_uri`: links for the post.
+/// - `file`: the attached file, if any. This is an object with the keys
+///   `save_name`, `thumb_name`, `catalog_thumb_name` (may be `null`),
+///   `orig_name` (may be `null`), `content_type`, `post_id`, `is_spoiler`,
+///   `uri`, `thumb_uri`, `catalog_thumb_uri`, `lazy`, and either `is_image`
+///   or `is_video`.
+#[derive(Debug)]
+pub struct PostPreviewJson(DeepPost);
+
+impl PostPreviewJson {
+    /// Create a new post preview.
+    pub fn new(
+        post_id: PostId,
+        context: &mut Context,
+    ) -> Result<PostPreviewJson> {
+        Ok(PostPreviewJson(DeepPost::new(post_id, false, context)?))
+    }
+}
+
+impl Serialize for PostPreviewJson {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut data = to_value(&self.0).expect("could not serialize post");
+
+        let obj = data.as_object_mut().unwrap();
+
+        // Don't leak anything that's only meant for staff or templates.
+        for key in &[
+            "delete_hash",
+            "delete_token_hash",
+            "user_id",
+            "pin_uri",
+            "unpin_uri",
+            "lock_uri",
+            "unlock_uri",
+            "staff_delete_uri",
+        ] {
+            obj.remove(*key);
+        }
+
+        data.serialize(serializer)
+    }
+}
+
+impl_json_responder!(PostPreviewJson);
+
+/// A preview of the body of a post that hasn't been made yet.
+#[derive(Debug, Serialize)]
+pub struct BodyPreview {
+    /// The body rendered as HTML.
+    pub html: String,
+    /// The token to submit with the post, to show that it was previewed.
+    pub token: String,
+}
+
+impl_json_responder!(BodyPreview);
+
+/// A post that was just made, for clients that post with JSON responses.
+#[derive(Debug, Serialize)]
+pub struct PostCreated {
+    /// The board the post was made on.
+    pub board: String,
+    /// The thread the post was made in.
+    pub thread: ThreadId,
+    /// The new post.
+    pub post: PostId,
+    /// The token for deleting the new post, if delete tokens are enabled.
+    /// This is only ever sent once.
+    pub delete_token: Option<String>,
+}
+
+impl_json_responder!(PostCreated);
+
 /// A page for reporting a post.
 #[derive(Debug, Serialize)]
 pub struct ReportPage {
@@ -935,3 +1661,434 @@ impl ActionSuccessPage {
 }
 
 impl_template_responder!(ActionSuccessPage, "pages/actions/action-success");
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use serde_json::value::{to_value, Value as JsonValue};
+
+    use super::{
+        Context, DeepPost, DeepThread, DisplayOptions, FileView, NsfwView,
+        PostPreview, PostView, ThreadSummary, ThreadView,
+    };
+    use crate::config::{Config, GlobalConfig, SortMode};
+    use crate::models::{
+        testing, Board, File, Post, PostId, Role, Staff, Thread,
+    };
+    use crate::routes::UserOptions;
+    use crate::Error;
+
+    fn post() -> Post {
+        Post {
+            id: 123,
+            time_stamp: Utc.with_ymd_and_hms(2020, 4, 20, 16, 20, 0).unwrap(),
+            body: String::new(),
+            author_name: String::from("Anonymous"),
+            author_contact: None,
+            author_ident: None,
+            thread_id: 45,
+            delete_hash: None,
+            board_name: String::from("tech"),
+            user_id: 1,
+            no_bump: false,
+            system: false,
+            author_ident_class: None,
+            truncated_html: None,
+            embeds: None,
+            pending: false,
+            delete_token_hash: None,
+        }
+    }
+
+    fn thread() -> Thread {
+        let time = Utc.with_ymd_and_hms(2020, 4, 20, 16, 20, 0).unwrap();
+
+        Thread {
+            id: 45,
+            time_stamp: time,
+            subject: String::from("Rust"),
+            board_name: String::from("tech"),
+            pinned: false,
+            locked: false,
+            bump_date: time,
+            post_count: 1,
+            file_count: 0,
+            cyclical: false,
+            pending: false,
+        }
+    }
+
+    fn file(orig_name: &str, content_type: &str) -> File {
+        File {
+            save_name: String::from("1587399600.bin"),
+            thumb_name: String::from("1587399600-thumb.bin"),
+            orig_name: Some(String::from(orig_name)),
+            content_type: content_type.parse().unwrap(),
+            post_id: 123,
+            is_spoiler: false,
+            catalog_thumb_name: None,
+        }
+    }
+
+    fn display(board_id_prefixes: bool) -> DisplayOptions {
+        DisplayOptions {
+            board_id_prefixes,
+            ..DisplayOptions::default()
+        }
+    }
+
+    #[test]
+    fn post_anchors() {
+        let data = to_value(PostView(post(), display(false))).unwrap();
+
+        assert_eq!(data["anchor"], "123");
+        assert_eq!(data["display_id"], "#123");
+        assert_eq!(data["uri"], "/tech/45#123");
+
+        // Only the ID that's shown changes, so links to posts keep working
+        // when the option is changed.
+        let data = to_value(PostView(post(), display(true))).unwrap();
+
+        assert_eq!(data["anchor"], "123");
+        assert_eq!(data["display_id"], "/tech/#123");
+        assert_eq!(data["uri"], "/tech/45#123");
+    }
+
+    #[test]
+    fn thread_ids() {
+        let view = ThreadView {
+            thread: thread(),
+            display: display(false),
+        };
+        assert_eq!(to_value(view).unwrap()["display_id"], "#45");
+
+        let view = ThreadView {
+            thread: thread(),
+            display: display(true),
+        };
+        assert_eq!(to_value(view).unwrap()["display_id"], "/tech/#45");
+    }
+
+    #[test]
+    fn post_embeds() {
+        let data = to_value(PostView(post(), display(false))).unwrap();
+        assert_eq!(data["embeds"], JsonValue::Array(Vec::new()));
+
+        let post = Post {
+            embeds: Some(
+                r#"[{"provider":"vimeo","host":"vimeo.com","id":"76979871"}]"#
+                    .to_string(),
+            ),
+            ..post()
+        };
+
+        let data = to_value(PostView(post, display(false))).unwrap();
+        assert_eq!(data["embeds"][0]["provider"], "Vimeo");
+        assert_eq!(
+            data["embeds"][0]["uri"],
+            "https://player.vimeo.com/video/76979871"
+        );
+    }
+
+    #[test]
+    fn post_is_you() {
+        let data = to_value(PostView(post(), display(false))).unwrap();
+        assert_eq!(data["is_you"], false);
+
+        let viewer = DisplayOptions {
+            viewer: Some(1),
+            ..DisplayOptions::default()
+        };
+        let data = to_value(PostView(post(), viewer)).unwrap();
+        assert_eq!(data["is_you"], true);
+
+        let other = DisplayOptions {
+            viewer: Some(2),
+            ..DisplayOptions::default()
+        };
+        let data = to_value(PostView(post(), other)).unwrap();
+        assert_eq!(data["is_you"], false);
+    }
+
+    #[test]
+    fn replies_to_op() {
+        let reply = |id, body: &str| {
+            let post = Post {
+                id,
+                body: body.to_string(),
+                ..post()
+            };
+            DeepPost(PostView(post, display(false)), None, false)
+        };
+
+        let thread = DeepThread(
+            ThreadView {
+                thread: thread(),
+                display: display(false),
+            },
+            vec![
+                reply(123, "<p>first</p>"),
+                reply(
+                    124,
+                    r#"<p><a class="post-ref" href="/tech/45#123">123</a></p>"#,
+                ),
+                reply(
+                    125,
+                    r#"<p><a class="post-ref" href="/tech/45#124">124</a></p>"#,
+                ),
+            ],
+        );
+
+        let data = to_value(thread).unwrap();
+        let posts = &data["posts"];
+
+        assert_eq!(posts[0]["replies_to_op"], false);
+        assert_eq!(posts[1]["replies_to"], JsonValue::from(vec![123]));
+        assert_eq!(posts[1]["replies_to_op"], true);
+        assert_eq!(posts[2]["replies_to"], JsonValue::from(vec![124]));
+        assert_eq!(posts[2]["replies_to_op"], false);
+    }
+
+    #[test]
+    fn non_media_files() {
+        let cat = FileView(file("cat.png", "image/png"), false, false);
+        let data = to_value(cat).unwrap();
+
+        assert_eq!(data["is_image"], true);
+        assert_eq!(data["is_file"], JsonValue::Null);
+        assert_eq!(data["thumb_uri"], "/file/upload/1587399600-thumb.bin");
+
+        let pdf = file("paper.pdf", "application/pdf");
+        let data = to_value(FileView(pdf, false, false)).unwrap();
+
+        assert_eq!(data["is_file"], true);
+        assert_eq!(data["type_label"], "PDF");
+        assert_eq!(data["thumb_uri"], "/file/file-icon.png");
+
+        let zip = file("archive", "application/zip");
+        assert_eq!(zip.type_label(), "ZIP");
+    }
+
+    #[test]
+    fn lazy_thumbnails() {
+        let cat = FileView(file("cat.png", "image/png"), true, false);
+        let data = to_value(cat).unwrap();
+        assert_eq!(data["lazy"], true);
+
+        let cat = FileView(file("cat.png", "image/png"), false, false);
+        let data = to_value(cat).unwrap();
+        assert_eq!(data["lazy"], false);
+    }
+
+    #[test]
+    fn blurred_thumbnails() {
+        let cat = FileView(file("cat.png", "image/png"), false, true);
+        let data = to_value(cat).unwrap();
+        assert_eq!(data["blur"], true);
+
+        let cat = FileView(file("cat.png", "image/png"), false, false);
+        let data = to_value(cat).unwrap();
+        assert_eq!(data["blur"], false);
+    }
+
+    #[test]
+    fn catalog_thumb_uris() {
+        let cat = FileView(file("cat.png", "image/png"), false, false);
+        let data = to_value(cat).unwrap();
+        assert_eq!(
+            data["catalog_thumb_uri"],
+            "/file/upload/1587399600-thumb.bin"
+        );
+
+        let with_catalog_thumb = File {
+            catalog_thumb_name: Some(String::from("1587399600-catalog.png")),
+            ..file("cat.png", "image/png")
+        };
+        let data =
+            to_value(FileView(with_catalog_thumb, false, false)).unwrap();
+        assert_eq!(
+            data["catalog_thumb_uri"],
+            "/file/upload/1587399600-catalog.png"
+        );
+
+        let pdf = file("paper.pdf", "application/pdf");
+        let data = to_value(FileView(pdf, false, false)).unwrap();
+        assert_eq!(data["catalog_thumb_uri"], "/file/file-icon.png");
+    }
+
+    #[test]
+    fn nsfw_boards() {
+        let mut config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        config.global_config.nsfw_noindex = true;
+        config.global_config.nsfw_blur_thumbnails = true;
+        let conf = config.global();
+
+        let mut board = Board {
+            name: String::from("b"),
+            description: String::from("Random"),
+            markup_mode: String::from("longboard"),
+            allow_embeds: false,
+            allow_new_threads: true,
+            listed: true,
+            nsfw: false,
+        };
+
+        assert_eq!(NsfwView::new(&board, &conf), NsfwView::default());
+
+        board.nsfw = true;
+        assert_eq!(
+            NsfwView::new(&board, &conf),
+            NsfwView {
+                noindex: true,
+                blur: true,
+                hide_share_image: false,
+            }
+        );
+
+        config.global_config.nsfw_blur_thumbnails = false;
+        config.global_config.nsfw_hide_share_image = true;
+        let conf = config.global();
+        assert!(!NsfwView::new(&board, &conf).blur);
+        assert!(NsfwView::new(&board, &conf).hide_share_image);
+    }
+
+    #[test]
+    fn thread_summaries() {
+        let body = "<p>Post your <em>crabs</em></p><p>&gt;.&lt;</p>";
+        let op = |file: Option<File>| {
+            let post = Post {
+                body: String::from(body),
+                ..post()
+            };
+            let file = file.map(|file| FileView(file, false, false));
+            DeepPost(PostView(post, display(false)), file, false)
+        };
+        let deep_thread = |file| {
+            DeepThread(
+                ThreadView {
+                    thread: thread(),
+                    display: display(false),
+                },
+                vec![op(file)],
+            )
+        };
+
+        let base = Some("https://example.com");
+
+        let thread = deep_thread(Some(file("crab.png", "image/png")));
+        assert_eq!(
+            ThreadSummary::new(&thread, base, true),
+            ThreadSummary {
+                title: String::from("Rust"),
+                description: String::from("Post your crabs >.<"),
+                url: Some(String::from("https://example.com/tech/45")),
+                image_uri: Some(String::from(
+                    "https://example.com/file/upload/1587399600-thumb.bin"
+                )),
+            }
+        );
+        assert_eq!(ThreadSummary::new(&thread, base, false).image_uri, None);
+
+        // Relative URLs aren't used in link previews.
+        let summary = ThreadSummary::new(&thread, None, true);
+        assert_eq!(summary.url, None);
+        assert_eq!(summary.image_uri, None);
+
+        let spoiler = File {
+            is_spoiler: true,
+            ..file("crab.png", "image/png")
+        };
+        let thread = deep_thread(Some(spoiler));
+        assert_eq!(ThreadSummary::new(&thread, base, true).image_uri, None);
+
+        let thread = deep_thread(Some(file("paper.pdf", "application/pdf")));
+        assert_eq!(ThreadSummary::new(&thread, base, true).image_uri, None);
+
+        let thread = deep_thread(None);
+        assert_eq!(ThreadSummary::new(&thread, base, true).image_uri, None);
+    }
+
+    #[test]
+    fn held_posts_hidden() {
+        let mut db = match testing::connect() {
+            Some(db) => db,
+            None => return,
+        };
+
+        testing::board(&mut db, "held");
+        let user = testing::user(&mut db);
+
+        // A held thread, started by a held post.
+        let thread_id = testing::thread(&mut db, "held", true);
+        let op_id = testing::post(&mut db, "held", thread_id, &user, true);
+
+        let config = Config {
+            global_config: GlobalConfig::default(),
+            extension_configs: Vec::new(),
+        };
+        let mut context = Context {
+            database: db,
+            conf: config.global(),
+            options: UserOptions::default(),
+            staff: None,
+            canonical_uri: String::new(),
+        };
+
+        let first_posts = |context: &mut Context| -> Vec<PostId> {
+            let posts = context
+                .database
+                .first_posts("held", SortMode::Bump)
+                .unwrap();
+            posts.iter().map(|post| post.id).collect()
+        };
+        let thread_posts = |context: &mut Context| -> Vec<PostId> {
+            let (_, posts) =
+                context.database.thread_with_posts(thread_id).unwrap();
+            posts.iter().map(|(post, _)| post.id).collect()
+        };
+
+        assert!(first_posts(&mut context).is_empty());
+        assert!(matches!(
+            PostPreview::new(op_id, &mut context),
+            Err(Error::PostNotFound { .. })
+        ));
+
+        context.database.approve_post(op_id).unwrap();
+
+        assert_eq!(first_posts(&mut context), vec![op_id]);
+        assert!(PostPreview::new(op_id, &mut context).is_ok());
+
+        // A held reply to the approved thread.
+        let reply_id = testing::post(
+            &mut context.database,
+            "held",
+            thread_id,
+            &user,
+            true,
+        );
+
+        assert_eq!(thread_posts(&mut context), vec![op_id]);
+        assert!(matches!(
+            PostPreview::new(reply_id, &mut context),
+            Err(Error::PostNotFound { .. })
+        ));
+
+        // Staff can see held posts, so that they can review them.
+        context.staff = Some(Staff {
+            name: "admin".into(),
+            password_hash: String::new(),
+            role: Role::Janitor,
+        });
+        assert!(PostPreview::new(reply_id, &mut context).is_ok());
+        context.staff = None;
+
+        context.database.approve_post(reply_id).unwrap();
+
+        assert_eq!(thread_posts(&mut context), vec![op_id, reply_id]);
+        assert!(PostPreview::new(reply_id, &mut context).is_ok());
+    }
+}
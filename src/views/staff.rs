@@ -1,13 +1,23 @@
 //! Views for staff pages.
 
+use chrono::Utc;
+
+use rocket::uri;
+
 use serde::{Serialize, Serializer};
 
 use serde_json::value::{to_value, Value as JsonValue};
 
-use crate::impl_template_responder;
-use crate::models::staff::{Role, Staff, StaffAction, User};
+use crate::{impl_json_responder, impl_template_responder};
+use crate::models::staff::{
+    Role, Staff, StaffAction, User, UserId, USERS_PER_PAGE, USER_POSTS_PER_PAGE,
+};
 use crate::models::{Board, PooledConnection, Report};
-use crate::views::{Context, PageFooter, PageInfo};
+use crate::config::Conf;
+use crate::views::{
+    insert_time, Context, DeepPost, DisplayOptions, FileView, PageFooter,
+    PageInfo, PostView,
+};
 use crate::Result;
 
 /// A wrapper type for using a `Staff` in a template.
@@ -42,14 +52,23 @@ impl Serialize for StaffView {
 pub struct ReportView {
     report: Report,
     post_uri: String,
+    display: DisplayOptions,
 }
 
 impl ReportView {
     /// Create a new `ReportView`.
-    fn new(report_id: i32, db: &mut PooledConnection) -> Result<ReportView> {
+    fn new(
+        report_id: i32,
+        db: &mut PooledConnection,
+        display: DisplayOptions,
+    ) -> Result<ReportView> {
         let report = db.report(report_id)?;
         let post_uri = db.post(report.post_id)?.uri();
-        Ok(ReportView { report, post_uri })
+        Ok(ReportView {
+            report,
+            post_uri,
+            display,
+        })
     }
 }
 
@@ -61,14 +80,14 @@ impl Serialize for ReportView {
     where
         S: Serializer,
     {
-        let time_stamp = self.report.time_stamp.format("%F %R").to_string();
         let uri = self.post_uri.clone();
 
         let mut data =
             to_value(&self.report).expect("could not serialize report");
 
         let obj = data.as_object_mut().unwrap();
-        obj.insert("time_stamp".into(), JsonValue::String(time_stamp));
+        let time_stamp = &self.report.time_stamp;
+        insert_time(obj, "time_stamp", time_stamp, &self.display.time_format);
         obj.insert("post_uri".into(), JsonValue::String(uri));
 
         data.serialize(serializer)
@@ -95,18 +114,33 @@ impl Serialize for UserView {
             .ban_expires
             .as_ref()
             .map(|time| time.format("%F %R").to_string());
-        let hash = self.user.hash.split('$').last().unwrap().to_string();
+        let hash = self.user.short_hash().to_string();
+
+        // Bans that have run out are kept until they're cleared, so say
+        // whether the ban is still in effect.
+        let ban_status = ban_expires.as_ref().map(|ban_expires| {
+            if self.user.ban_has_expired(&Utc::now()) {
+                String::from("expired")
+            } else {
+                format!("active until {}", ban_expires)
+            }
+        });
 
         let mut data = to_value(&self.user).expect("could not serialize user");
 
         let obj = data.as_object_mut().unwrap();
         obj.insert("hash".into(), JsonValue::String(hash));
         obj.insert("post_count".into(), JsonValue::from(self.post_count));
+        obj.insert("is_banned".into(), JsonValue::Bool(self.user.is_banned()));
 
         if let Some(ban_expires) = ban_expires {
             obj.insert("ban_expires".into(), JsonValue::String(ban_expires));
         }
 
+        if let Some(ban_status) = ban_status {
+            obj.insert("ban_status".into(), JsonValue::String(ban_status));
+        }
+
         data.serialize(serializer)
     }
 }
@@ -118,6 +152,7 @@ pub struct OverviewPage {
     page_footer: PageFooter,
     staff: StaffView,
     reports: Vec<ReportView>,
+    pending_posts: Vec<PostView>,
     boards: Vec<Board>,
     users: Vec<UserView>,
 }
@@ -132,6 +167,8 @@ impl OverviewPage {
             .map(|(user, post_count)| Ok(UserView { user, post_count }))
             .collect::<Result<_>>()?;
 
+        let display = context.display_options();
+
         Ok(OverviewPage {
             page_info: PageInfo::new("Overview", context),
             page_footer: PageFooter::new(context)?,
@@ -140,8 +177,20 @@ impl OverviewPage {
                 .database
                 .all_reports()?
                 .into_iter()
-                .map(|report| ReportView::new(report.id, &mut context.database))
+                .map(|report| {
+                    ReportView::new(
+                        report.id,
+                        &mut context.database,
+                        display.clone(),
+                    )
+                })
                 .collect::<Result<_>>()?,
+            pending_posts: context
+                .database
+                .pending_posts()?
+                .into_iter()
+                .map(|post| PostView(post, display.clone()))
+                .collect(),
             boards: context.database.all_boards()?,
             users,
         })
@@ -168,6 +217,158 @@ impl LoginPage {
 
 impl_template_responder!(LoginPage, "pages/staff/login");
 
+/// The page showing the effective configuration for an extension.
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfigPage {
+    page_info: PageInfo,
+    page_footer: PageFooter,
+    extension_name: Option<String>,
+    config: String,
+}
+
+impl EffectiveConfigPage {
+    /// Create a new page showing `conf`, with its secrets redacted.
+    pub fn new(conf: &Conf, context: &mut Context) -> Result<Self> {
+        Ok(EffectiveConfigPage {
+            page_info: PageInfo::new("Configuration", context),
+            page_footer: PageFooter::new(context)?,
+            extension_name: conf.extension_name.map(String::from),
+            config: format!("{:#?}", conf.redacted()),
+        })
+    }
+}
+
+impl_template_responder!(EffectiveConfigPage, "pages/staff/config");
+
+/// The page for searching users.
+#[derive(Debug, Serialize)]
+pub struct UserSearchPage {
+    page_info: PageInfo,
+    page_footer: PageFooter,
+    query: String,
+    only_banned: bool,
+    users: Vec<UserView>,
+    prev_page_uri: Option<String>,
+    next_page_uri: Option<String>,
+}
+
+impl UserSearchPage {
+    /// Create a new page with the users found by a search. See
+    /// `Connection::search_users`.
+    pub fn new(
+        query: String,
+        only_banned: bool,
+        page: u32,
+        context: &mut Context,
+    ) -> Result<UserSearchPage> {
+        let page = page.max(1);
+
+        let users: Vec<_> = context
+            .database
+            .search_users(&query, only_banned, page)?
+            .into_iter()
+            .map(|(user, post_count)| UserView { user, post_count })
+            .collect();
+
+        let page_uri = |page: u32| {
+            uri!(
+                crate::routes::staff::users:
+                &query,
+                u8::from(only_banned),
+                page
+            )
+            .to_string()
+        };
+
+        let prev_page_uri = if page > 1 {
+            Some(page_uri(page - 1))
+        } else {
+            None
+        };
+
+        let next_page_uri = if users.len() == USERS_PER_PAGE as usize {
+            Some(page_uri(page + 1))
+        } else {
+            None
+        };
+
+        Ok(UserSearchPage {
+            page_info: PageInfo::new("Users", context),
+            page_footer: PageFooter::new(context)?,
+            query,
+            only_banned,
+            users,
+            prev_page_uri,
+            next_page_uri,
+        })
+    }
+}
+
+impl_template_responder!(UserSearchPage, "pages/staff/users");
+
+/// The page with the posts a user has made.
+#[derive(Debug, Serialize)]
+pub struct UserPostsPage {
+    page_info: PageInfo,
+    page_footer: PageFooter,
+    user: UserView,
+    posts: Vec<DeepPost>,
+    prev_page_uri: Option<String>,
+    next_page_uri: Option<String>,
+}
+
+impl UserPostsPage {
+    /// Create a new page with a page of the posts a user has made. See
+    /// `Connection::posts_by_user`.
+    pub fn new(
+        user_id: UserId,
+        page: u32,
+        context: &mut Context,
+    ) -> Result<UserPostsPage> {
+        let page = page.max(1);
+
+        let user = context.database.user_by_id(user_id)?;
+        let post_count = context.database.user_post_count(user_id)?;
+
+        let display_options = context.display_options();
+        let mut posts = Vec::new();
+
+        for post in context.database.posts_by_user(user_id, page)? {
+            let file = context.database.files_in_post(post.id)?.pop();
+            let post = PostView(post, display_options.clone());
+
+            posts.push(DeepPost(post, file.map(FileView), false));
+        }
+
+        let page_uri = |page: u32| {
+            uri!(crate::routes::staff::user_posts: user_id, page).to_string()
+        };
+
+        let prev_page_uri = if page > 1 {
+            Some(page_uri(page - 1))
+        } else {
+            None
+        };
+
+        let next_page_uri = if posts.len() == USER_POSTS_PER_PAGE as usize {
+            Some(page_uri(page + 1))
+        } else {
+            None
+        };
+
+        Ok(UserPostsPage {
+            page_info: PageInfo::new(format!("User {}", user_id), context),
+            page_footer: PageFooter::new(context)?,
+            user: UserView { user, post_count },
+            posts,
+            prev_page_uri,
+            next_page_uri,
+        })
+    }
+}
+
+impl_template_responder!(UserPostsPage, "pages/staff/user-posts");
+
 /// The page with a history of staff actions.
 #[derive(Debug, Serialize)]
 pub struct HistoryPage {
@@ -187,3 +388,38 @@ impl HistoryPage {
 }
 
 impl_template_responder!(HistoryPage, "pages/staff/history");
+
+/// The JSON response for an API route.
+#[derive(Debug, Serialize)]
+pub struct ApiResponse {
+    /// Whether the action succeeded.
+    pub ok: bool,
+    /// A description of what was done, or of what went wrong.
+    pub message: String,
+}
+
+impl ApiResponse {
+    /// Create a response for an action that succeeded.
+    pub fn success<S>(message: S) -> ApiResponse
+    where
+        S: Into<String>,
+    {
+        ApiResponse {
+            ok: true,
+            message: message.into(),
+        }
+    }
+
+    /// Create a response for an action that failed.
+    pub fn error<S>(message: S) -> ApiResponse
+    where
+        S: Into<String>,
+    {
+        ApiResponse {
+            ok: false,
+            message: message.into(),
+        }
+    }
+}
+
+impl_json_responder!(ApiResponse);